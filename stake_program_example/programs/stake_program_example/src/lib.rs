@@ -1,297 +1,702 @@
-use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, Token, TokenAccount};
+use anchor_lang::{prelude::*, system_program, Discriminator};
+use anchor_spl::{
+  token::Token,
+  token_2022::{
+    spl_token_2022::{
+      self,
+      extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+    },
+    Token2022,
+  },
+  token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked},
+};
 
 declare_id!("8Bv63d7LKuxYipEWycEHz263LKgq1qTwE6PaxsmE2Vmx");
 
-#[program]
-pub mod stake_program_example {
+// anchor 0.31's `#[program]` generates calls to the deprecated `AccountInfo::realloc`
+#[allow(deprecated)]
+mod processor {
   use super::*;
 
-  pub fn initialize_pool(
-    ctx: Context<InitializePool>,
-    reward_rate: u64,
-    lock_period: i64,
-  ) -> Result<()> {
-    let pool = &mut ctx.accounts.pool;
-    pool.authority = ctx.accounts.authority.key();
-    pool.staking_mint = ctx.accounts.staking_mint.key();
-    pool.reward_mint = ctx.accounts.reward_mint.key();
-    pool.staking_vault = ctx.accounts.staking_vault.key();
-    pool.reward_vault = ctx.accounts.reward_vault.key();
-    pool.reward_rate = reward_rate;
-    pool.lock_period = lock_period;
-    pool.total_staked = 0;
-    pool.last_update_time = Clock::get()?.unix_timestamp;
-    pool.bump = ctx.bumps.pool;
-
-    Ok(())
+  #[program]
+  pub mod stake_program_example {
+    use super::*;
+
+    pub fn initialize_pool(
+      ctx: Context<InitializePool>,
+      reward_rate: u64,
+      lock_period: i64,
+    ) -> Result<()> {
+      check_mint_extensions(&ctx.accounts.staking_mint)?;
+      check_mint_extensions(&ctx.accounts.reward_mint)?;
+      require!(
+        (0 ..= MAX_LOCK_PERIOD).contains(&lock_period),
+        ErrorCode::InvalidLockPeriod
+      );
+
+      let pool = &mut ctx.accounts.pool;
+      pool.authority = ctx.accounts.authority.key();
+      pool.staking_mint = ctx.accounts.staking_mint.key();
+      pool.staking_vault = ctx.accounts.staking_vault.key();
+      pool.lock_period = lock_period;
+      pool.total_staked = 0;
+      pool.last_update_time = Clock::get()?.unix_timestamp;
+      pool.reward_streams = vec![RewardStream::new(
+        ctx.accounts.reward_mint.key(),
+        ctx.accounts.reward_vault.key(),
+        reward_rate,
+        pool.last_update_time,
+      )];
+      pool.pending_authority = None;
+      pool.paused = false;
+      pool.bump = ctx.bumps.pool;
+
+      Ok(())
+    }
+
+    /// Adds a reward stream paying `reward_mint`, funded separately through `fund_reward_pool`.
+    pub fn add_reward_stream(ctx: Context<AddRewardStream>, reward_rate: u64) -> Result<()> {
+      check_mint_extensions(&ctx.accounts.reward_mint)?;
+
+      let pool = &mut ctx.accounts.pool;
+      let reward_mint = ctx.accounts.reward_mint.key();
+
+      require!(
+        pool.reward_streams.len() < MAX_REWARD_STREAMS,
+        ErrorCode::TooManyRewardStreams
+      );
+      require!(
+        pool
+          .reward_streams
+          .iter()
+          .all(|stream| stream.reward_mint != reward_mint),
+        ErrorCode::DuplicateRewardMint
+      );
+
+      // Bring the other streams up to now, which is where the new one starts
+      update_pool_rewards(pool, Clock::get()?.unix_timestamp)?;
+      let stream = RewardStream::new(
+        reward_mint,
+        ctx.accounts.reward_vault.key(),
+        reward_rate,
+        pool.last_update_time,
+      );
+      pool.reward_streams.push(stream);
+
+      Ok(())
+    }
+
+    pub fn initialize_user_stake(ctx: Context<InitializeUserStake>) -> Result<()> {
+      let user_stake = &mut ctx.accounts.user_stake;
+      user_stake.owner = ctx.accounts.owner.key();
+      user_stake.pool = ctx.accounts.pool.key();
+      user_stake.staked_amount = 0;
+      user_stake.rewards = Vec::new();
+      user_stake.last_stake_time = 0;
+      user_stake.unlock_time = 0;
+      user_stake.bump = ctx.bumps.user_stake;
+
+      Ok(())
+    }
+
+    pub fn stake(ctx: Context<Stake>, amount: u64) -> Result<()> {
+      let pool = &mut ctx.accounts.pool;
+      let user_stake = &mut ctx.accounts.user_stake;
+      let clock = Clock::get()?;
+
+      require!(!pool.paused, ErrorCode::PoolPaused);
+
+      // Update pool rewards and move the user's share into pending rewards
+      update_pool_rewards(pool, clock.unix_timestamp)?;
+      settle_user_rewards(pool, user_stake)?;
+
+      // Transfer tokens from user to vault, crediting what arrives after any transfer fee
+      let received = transfer_to_pool(
+        ctx.accounts.user_token_account.to_account_info(),
+        &ctx.accounts.staking_mint,
+        &mut ctx.accounts.staking_vault,
+        ctx.accounts.owner.to_account_info(),
+        ctx.accounts.token_program.to_account_info(),
+        amount,
+      )?;
+
+      // Update user stake
+      user_stake.staked_amount = user_stake
+        .staked_amount
+        .checked_add(received)
+        .ok_or(ErrorCode::Overflow)?;
+      reset_reward_debts(pool, user_stake)?;
+      // The whole stake is locked again, for the lock period in force now
+      user_stake.last_stake_time = clock.unix_timestamp;
+      user_stake.unlock_time = clock
+        .unix_timestamp
+        .checked_add(pool.lock_period)
+        .ok_or(ErrorCode::Overflow)?;
+
+      // Update pool
+      pool.total_staked = pool
+        .total_staked
+        .checked_add(received)
+        .ok_or(ErrorCode::Overflow)?;
+
+      emit!(StakeEvent {
+        user: ctx.accounts.owner.key(),
+        amount: received,
+        timestamp: clock.unix_timestamp,
+      });
+
+      Ok(())
+    }
+
+    pub fn unstake(ctx: Context<Unstake>, amount: u64) -> Result<()> {
+      let clock = Clock::get()?;
+
+      // Check lock period
+      require!(
+        clock.unix_timestamp >= ctx.accounts.user_stake.unlock_time,
+        ErrorCode::StillLocked
+      );
+
+      // Check sufficient balance
+      require!(
+        ctx.accounts.user_stake.staked_amount >= amount,
+        ErrorCode::InsufficientBalance
+      );
+
+      // Update pool rewards and move the user's share into pending rewards
+      update_pool_rewards(&mut ctx.accounts.pool, clock.unix_timestamp)?;
+      settle_user_rewards(&ctx.accounts.pool, &mut ctx.accounts.user_stake)?;
+
+      // Transfer tokens from vault to user
+      transfer_from_pool(
+        &ctx.accounts.pool,
+        ctx.accounts.staking_vault.to_account_info(),
+        &ctx.accounts.staking_mint,
+        ctx.accounts.user_token_account.to_account_info(),
+        ctx.accounts.token_program.to_account_info(),
+        amount,
+      )?;
+
+      // Update user stake
+      ctx.accounts.user_stake.staked_amount = ctx
+        .accounts
+        .user_stake
+        .staked_amount
+        .checked_sub(amount)
+        .ok_or(ErrorCode::Underflow)?;
+      reset_reward_debts(&ctx.accounts.pool, &mut ctx.accounts.user_stake)?;
+
+      // Update pool
+      ctx.accounts.pool.total_staked = ctx
+        .accounts
+        .pool
+        .total_staked
+        .checked_sub(amount)
+        .ok_or(ErrorCode::Underflow)?;
+
+      emit!(UnstakeEvent {
+        user: ctx.accounts.owner.key(),
+        amount,
+        timestamp: clock.unix_timestamp,
+      });
+
+      Ok(())
+    }
+
+    /// Claims everything earned from one reward stream.
+    pub fn claim_reward(ctx: Context<ClaimReward>, stream_index: u8) -> Result<()> {
+      let clock = Clock::get()?;
+
+      // Update pool rewards and move the user's share into pending rewards
+      update_pool_rewards(&mut ctx.accounts.pool, clock.unix_timestamp)?;
+      settle_user_rewards(&ctx.accounts.pool, &mut ctx.accounts.user_stake)?;
+
+      let amount = ctx.accounts.user_stake.rewards[usize::from(stream_index)].pending_reward;
+      require!(amount > 0, ErrorCode::NoRewardsToClaim);
+
+      transfer_from_pool(
+        &ctx.accounts.pool,
+        ctx.accounts.reward_vault.to_account_info(),
+        &ctx.accounts.reward_mint,
+        ctx.accounts.user_reward_account.to_account_info(),
+        ctx.accounts.token_program.to_account_info(),
+        amount,
+      )?;
+      ctx.accounts.user_stake.rewards[usize::from(stream_index)].pending_reward = 0;
+
+      emit!(ClaimRewardEvent {
+        user: ctx.accounts.owner.key(),
+        reward_mint: ctx.accounts.reward_mint.key(),
+        amount,
+        timestamp: clock.unix_timestamp,
+      });
+
+      Ok(())
+    }
+
+    /// Claims every reward stream at once. `remaining_accounts` holds the stream's reward mint, its
+    /// reward vault and the owner's token account for the mint, for each stream in order.
+    pub fn claim_all_rewards<'info>(
+      ctx: Context<'_, '_, 'info, 'info, ClaimAllRewards<'info>>,
+    ) -> Result<()> {
+      let clock = Clock::get()?;
+
+      // Update pool rewards and move the user's share into pending rewards
+      update_pool_rewards(&mut ctx.accounts.pool, clock.unix_timestamp)?;
+      settle_user_rewards(&ctx.accounts.pool, &mut ctx.accounts.user_stake)?;
+
+      let pool = &ctx.accounts.pool;
+      require!(
+        ctx.remaining_accounts.len() == 3 * pool.reward_streams.len(),
+        ErrorCode::InvalidRewardStream
+      );
+
+      let mut claimed = false;
+      for (index, (stream, accounts)) in pool
+        .reward_streams
+        .iter()
+        .zip(ctx.remaining_accounts.chunks(3))
+        .enumerate()
+      {
+        let (reward_mint, reward_vault, user_reward_account) =
+          (&accounts[0], &accounts[1], &accounts[2]);
+        let reward_mint = InterfaceAccount::<Mint>::try_from(reward_mint)?;
+        let destination = InterfaceAccount::<TokenAccount>::try_from(user_reward_account)?;
+        require!(
+          reward_mint.key() == stream.reward_mint
+            && reward_vault.key() == stream.reward_vault
+            && destination.mint == stream.reward_mint
+            && destination.owner == ctx.accounts.owner.key(),
+          ErrorCode::InvalidRewardStream
+        );
+
+        let amount = ctx.accounts.user_stake.rewards[index].pending_reward;
+        if amount == 0 {
+          continue;
+        }
+        // Streams can pay out in tokens from either token program
+        let token_program = if *reward_mint.to_account_info().owner == Token2022::id() {
+          ctx.accounts.token_2022_program.to_account_info()
+        } else {
+          ctx.accounts.token_program.to_account_info()
+        };
+        transfer_from_pool(
+          pool,
+          reward_vault.clone(),
+          &reward_mint,
+          user_reward_account.clone(),
+          token_program,
+          amount,
+        )?;
+        ctx.accounts.user_stake.rewards[index].pending_reward = 0;
+        claimed = true;
+
+        emit!(ClaimRewardEvent {
+          user: ctx.accounts.owner.key(),
+          reward_mint: stream.reward_mint,
+          amount,
+          timestamp: clock.unix_timestamp,
+        });
+      }
+
+      require!(claimed, ErrorCode::NoRewardsToClaim);
+
+      Ok(())
+    }
+
+    pub fn fund_reward_pool(
+      ctx: Context<FundRewardPool>,
+      stream_index: u8,
+      amount: u64,
+    ) -> Result<()> {
+      let clock = Clock::get()?;
+
+      // Settle emission up to now so the new funds only extend it from here
+      update_pool_rewards(&mut ctx.accounts.pool, clock.unix_timestamp)?;
+
+      // Only what reaches the vault after any transfer fee can be emitted
+      let received = transfer_to_pool(
+        ctx.accounts.funder_token_account.to_account_info(),
+        &ctx.accounts.reward_mint,
+        &mut ctx.accounts.reward_vault,
+        ctx.accounts.funder.to_account_info(),
+        ctx.accounts.token_program.to_account_info(),
+        amount,
+      )?;
+
+      let pool = &mut ctx.accounts.pool;
+      let last_update_time = pool.last_update_time;
+      let stream = pool.reward_stream_mut(stream_index)?;
+      stream.unemitted_rewards = stream
+        .unemitted_rewards
+        .checked_add(received)
+        .ok_or(ErrorCode::Overflow)?;
+      stream.reward_end_time = projected_reward_end_time(stream, last_update_time);
+
+      emit!(FundRewardPoolEvent {
+        funder: ctx.accounts.funder.key(),
+        reward_mint: stream.reward_mint,
+        amount: received,
+        reward_end_time: stream.reward_end_time,
+        timestamp: clock.unix_timestamp,
+      });
+
+      Ok(())
+    }
+
+    pub fn set_reward_rate(
+      ctx: Context<UpdatePool>,
+      stream_index: u8,
+      reward_rate: u64,
+    ) -> Result<()> {
+      let pool = &mut ctx.accounts.pool;
+
+      // Settle everything accrued at the old rate first
+      update_pool_rewards(pool, Clock::get()?.unix_timestamp)?;
+      let last_update_time = pool.last_update_time;
+      let stream = pool.reward_stream_mut(stream_index)?;
+      stream.reward_rate = reward_rate;
+      stream.reward_end_time = projected_reward_end_time(stream, last_update_time);
+
+      Ok(())
+    }
+
+    /// Applies to stakes made from now on; existing stakes keep the unlock time they were made
+    /// with.
+    pub fn set_lock_period(ctx: Context<UpdatePool>, lock_period: i64) -> Result<()> {
+      require!(
+        (0 ..= MAX_LOCK_PERIOD).contains(&lock_period),
+        ErrorCode::InvalidLockPeriod
+      );
+      ctx.accounts.pool.lock_period = lock_period;
+
+      Ok(())
+    }
+
+    /// Stops new stakes; unstaking and claiming keep working.
+    pub fn pause(ctx: Context<UpdatePool>) -> Result<()> {
+      ctx.accounts.pool.paused = true;
+
+      Ok(())
+    }
+
+    pub fn unpause(ctx: Context<UpdatePool>) -> Result<()> {
+      ctx.accounts.pool.paused = false;
+
+      Ok(())
+    }
+
+    /// First step of an authority transfer, completed by `accept_authority`.
+    pub fn propose_authority(ctx: Context<UpdatePool>, new_authority: Pubkey) -> Result<()> {
+      ctx.accounts.pool.pending_authority = Some(new_authority);
+
+      Ok(())
+    }
+
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+      let pool = &mut ctx.accounts.pool;
+      pool.authority = ctx.accounts.new_authority.key();
+      pool.pending_authority = None;
+
+      Ok(())
+    }
+
+    /// Moves a pool created before the u128 reward accumulator onto the current layout. The
+    /// reward vault's balance is left to pay what stakers earned before the migration, so the
+    /// migrated stream emits nothing until `fund_reward_pool`.
+    pub fn migrate_pool(ctx: Context<MigratePool>) -> Result<()> {
+      migrate_account(
+        &ctx.accounts.pool,
+        &ctx.accounts.payer,
+        &ctx.accounts.system_program,
+        LegacyStakePool::INIT_SPACE,
+        StakePool::INIT_SPACE,
+        |legacy: LegacyStakePool| Ok(StakePool::from(legacy)),
+      )
+    }
+
+    /// Moves a user stake created before the u128 reward debt onto the current layout. Its pool
+    /// has to be migrated first, since the unlock time comes from the pool's lock period.
+    pub fn migrate_user_stake(ctx: Context<MigrateUserStake>) -> Result<()> {
+      let pool_key = ctx.accounts.pool.key();
+      let lock_period = ctx.accounts.pool.lock_period;
+      migrate_account(
+        &ctx.accounts.user_stake,
+        &ctx.accounts.payer,
+        &ctx.accounts.system_program,
+        LegacyUserStake::INIT_SPACE,
+        UserStake::INIT_SPACE,
+        |legacy: LegacyUserStake| {
+          require_keys_eq!(
+            legacy.pool,
+            pool_key,
+            anchor_lang::error::ErrorCode::ConstraintHasOne
+          );
+          let unlock_time = legacy
+            .last_stake_time
+            .checked_add(lock_period)
+            .ok_or(ErrorCode::Overflow)?;
+          Ok(UserStake {
+            unlock_time,
+            ..legacy.into()
+          })
+        },
+      )
+    }
   }
+}
+pub use processor::*;
 
-  pub fn initialize_user_stake(ctx: Context<InitializeUserStake>) -> Result<()> {
-    let user_stake = &mut ctx.accounts.user_stake;
-    user_stake.owner = ctx.accounts.owner.key();
-    user_stake.pool = ctx.accounts.pool.key();
-    user_stake.staked_amount = 0;
-    user_stake.reward_debt = 0;
-    user_stake.last_stake_time = 0;
-    user_stake.bump = ctx.bumps.user_stake;
+// Helper functions
+fn update_pool_rewards(pool: &mut StakePool, current_time: i64) -> Result<()> {
+  let time_elapsed = current_time
+    .checked_sub(pool.last_update_time)
+    .ok_or(ErrorCode::Underflow)? as u64;
 
-    Ok(())
+  for stream in pool.reward_streams.iter_mut() {
+    // Nothing is emitted while the pool is empty, so the funded rewards carry over
+    if pool.total_staked > 0 {
+      accrue_stream_rewards(stream, time_elapsed, pool.total_staked)?;
+    }
+    stream.reward_end_time = projected_reward_end_time(stream, current_time);
   }
+  pool.last_update_time = current_time;
 
-  pub fn stake(ctx: Context<Stake>, amount: u64) -> Result<()> {
-    let pool = &mut ctx.accounts.pool;
-    let user_stake = &mut ctx.accounts.user_stake;
-    let clock = Clock::get()?;
+  Ok(())
+}
 
-    // Update pool rewards
-    update_pool_rewards(pool, clock.unix_timestamp)?;
+fn accrue_stream_rewards(
+  stream: &mut RewardStream,
+  time_elapsed: u64,
+  total_staked: u64,
+) -> Result<()> {
+  // Emission stops once the funded rewards run out
+  let reward_amount = u128::from(stream.reward_rate)
+    .checked_mul(u128::from(time_elapsed))
+    .ok_or(ErrorCode::Overflow)?
+    .min(u128::from(stream.unemitted_rewards));
 
-    // Calculate pending rewards for user
-    let pending_reward = calculate_pending_reward(
-      user_stake.staked_amount,
-      pool.accumulated_reward_per_share,
-      user_stake.reward_debt,
-    );
+  let reward_per_share = reward_amount
+    .checked_mul(PRECISION)
+    .ok_or(ErrorCode::Overflow)?
+    .checked_div(u128::from(total_staked))
+    .ok_or(ErrorCode::DivisionByZero)?;
 
-    // Transfer tokens from user to vault
-    let cpi_accounts = token::Transfer {
-      from: ctx.accounts.user_token_account.to_account_info(),
-      to: ctx.accounts.staking_vault.to_account_info(),
-      authority: ctx.accounts.owner.to_account_info(),
-    };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-    token::transfer(cpi_ctx, amount)?;
-
-    // Update user stake
-    user_stake.staked_amount = user_stake
-      .staked_amount
-      .checked_add(amount)
-      .ok_or(ErrorCode::Overflow)?;
-    user_stake.reward_debt =
-      calculate_reward_debt(user_stake.staked_amount, pool.accumulated_reward_per_share);
-    user_stake.pending_reward = user_stake
-      .pending_reward
-      .checked_add(pending_reward)
-      .ok_or(ErrorCode::Overflow)?;
-    user_stake.last_stake_time = clock.unix_timestamp;
+  stream.accumulated_reward_per_share = stream
+    .accumulated_reward_per_share
+    .checked_add(reward_per_share)
+    .ok_or(ErrorCode::Overflow)?;
 
-    // Update pool
-    pool.total_staked = pool
-      .total_staked
-      .checked_add(amount)
-      .ok_or(ErrorCode::Overflow)?;
+  // `reward_amount` is at most `unemitted_rewards`, so it fits
+  stream.unemitted_rewards -= reward_amount as u64;
 
-    emit!(StakeEvent {
-      user: ctx.accounts.owner.key(),
-      amount,
-      timestamp: clock.unix_timestamp,
-    });
+  Ok(())
+}
 
-    Ok(())
+/// When `unemitted_rewards` runs out at the current rate, counting from the pool's last update.
+/// A stream that emits nothing has already ended.
+fn projected_reward_end_time(stream: &RewardStream, last_update_time: i64) -> i64 {
+  if stream.reward_rate == 0 {
+    return last_update_time;
   }
+  // Rounded up so the final, partial second of emission is included
+  let seconds = stream.unemitted_rewards.div_ceil(stream.reward_rate);
+  last_update_time.saturating_add(i64::try_from(seconds).unwrap_or(i64::MAX))
+}
 
-  pub fn unstake(ctx: Context<Unstake>, amount: u64) -> Result<()> {
-    let clock = Clock::get()?;
-
-    // Check lock period
-    require!(
-      clock.unix_timestamp
-        >= ctx.accounts.user_stake.last_stake_time + ctx.accounts.pool.lock_period,
-      ErrorCode::StillLocked
-    );
-
-    // Check sufficient balance
-    require!(
-      ctx.accounts.user_stake.staked_amount >= amount,
-      ErrorCode::InsufficientBalance
-    );
-
-    // Update pool rewards
-    update_pool_rewards(&mut ctx.accounts.pool, clock.unix_timestamp)?;
-
-    // Calculate pending rewards
+/// Moves what the user earned on every stream since their last update into `pending_reward`.
+fn settle_user_rewards(pool: &StakePool, user_stake: &mut UserStake) -> Result<()> {
+  // Streams added since the user's last update start with no debt, like the stream itself
+  user_stake
+    .rewards
+    .resize(pool.reward_streams.len(), UserReward::default());
+
+  let staked_amount = user_stake.staked_amount;
+  for (stream, reward) in pool
+    .reward_streams
+    .iter()
+    .zip(user_stake.rewards.iter_mut())
+  {
     let pending_reward = calculate_pending_reward(
-      ctx.accounts.user_stake.staked_amount,
-      ctx.accounts.pool.accumulated_reward_per_share,
-      ctx.accounts.user_stake.reward_debt,
-    );
-
-    // Transfer tokens from vault to user
-    let pool_seeds = &[
-      b"pool",
-      ctx.accounts.pool.staking_mint.as_ref(),
-      &[ctx.accounts.pool.bump],
-    ];
-    let signer_seeds = &[&pool_seeds[..]];
-
-    let cpi_accounts = token::Transfer {
-      from: ctx.accounts.staking_vault.to_account_info(),
-      to: ctx.accounts.user_token_account.to_account_info(),
-      authority: ctx.accounts.pool.to_account_info(),
-    };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-    token::transfer(cpi_ctx, amount)?;
-
-    // Update user stake
-    ctx.accounts.user_stake.staked_amount = ctx
-      .accounts
-      .user_stake
-      .staked_amount
-      .checked_sub(amount)
-      .ok_or(ErrorCode::Underflow)?;
-    ctx.accounts.user_stake.reward_debt = calculate_reward_debt(
-      ctx.accounts.user_stake.staked_amount,
-      ctx.accounts.pool.accumulated_reward_per_share,
-    );
-    ctx.accounts.user_stake.pending_reward = ctx
-      .accounts
-      .user_stake
+      staked_amount,
+      stream.accumulated_reward_per_share,
+      reward.reward_debt,
+    )?;
+    reward.pending_reward = reward
       .pending_reward
       .checked_add(pending_reward)
       .ok_or(ErrorCode::Overflow)?;
-
-    // Update pool
-    ctx.accounts.pool.total_staked = ctx
-      .accounts
-      .pool
-      .total_staked
-      .checked_sub(amount)
-      .ok_or(ErrorCode::Underflow)?;
-
-    emit!(UnstakeEvent {
-      user: ctx.accounts.owner.key(),
-      amount,
-      timestamp: clock.unix_timestamp,
-    });
-
-    Ok(())
+    reward.reward_debt = calculate_reward_debt(staked_amount, stream.accumulated_reward_per_share)?;
   }
 
-  pub fn claim_reward(ctx: Context<ClaimReward>) -> Result<()> {
-    let clock = Clock::get()?;
-
-    // Update pool rewards
-    update_pool_rewards(&mut ctx.accounts.pool, clock.unix_timestamp)?;
-
-    // Calculate total rewards
-    let pending_reward = calculate_pending_reward(
-      ctx.accounts.user_stake.staked_amount,
-      ctx.accounts.pool.accumulated_reward_per_share,
-      ctx.accounts.user_stake.reward_debt,
-    );
-    let total_reward = ctx
-      .accounts
-      .user_stake
-      .pending_reward
-      .checked_add(pending_reward)
-      .ok_or(ErrorCode::Overflow)?;
-
-    require!(total_reward > 0, ErrorCode::NoRewardsToClaim);
+  Ok(())
+}
 
-    // Transfer rewards
-    let pool_seeds = &[
-      b"pool",
-      ctx.accounts.pool.staking_mint.as_ref(),
-      &[ctx.accounts.pool.bump],
-    ];
-    let signer_seeds = &[&pool_seeds[..]];
+/// Marks everything accrued so far as accounted for, after the user's stake changed.
+fn reset_reward_debts(pool: &StakePool, user_stake: &mut UserStake) -> Result<()> {
+  let staked_amount = user_stake.staked_amount;
+  for (stream, reward) in pool
+    .reward_streams
+    .iter()
+    .zip(user_stake.rewards.iter_mut())
+  {
+    reward.reward_debt = calculate_reward_debt(staked_amount, stream.accumulated_reward_per_share)?;
+  }
 
-    let cpi_accounts = token::Transfer {
-      from: ctx.accounts.reward_vault.to_account_info(),
-      to: ctx.accounts.user_reward_account.to_account_info(),
-      authority: ctx.accounts.pool.to_account_info(),
-    };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-    token::transfer(cpi_ctx, total_reward)?;
-
-    // Update user state
-    ctx.accounts.user_stake.reward_debt = calculate_reward_debt(
-      ctx.accounts.user_stake.staked_amount,
-      ctx.accounts.pool.accumulated_reward_per_share,
-    );
-    ctx.accounts.user_stake.pending_reward = 0;
+  Ok(())
+}
 
-    emit!(ClaimRewardEvent {
-      user: ctx.accounts.owner.key(),
-      amount: total_reward,
-      timestamp: clock.unix_timestamp,
-    });
+fn calculate_pending_reward(
+  staked_amount: u64,
+  accumulated_reward_per_share: u128,
+  reward_debt: u128,
+) -> Result<u64> {
+  let pending = calculate_reward_debt(staked_amount, accumulated_reward_per_share)?
+    .checked_sub(reward_debt)
+    .ok_or(ErrorCode::Underflow)?
+    / PRECISION;
+
+  Ok(u64::try_from(pending).map_err(|_| ErrorCode::Overflow)?)
+}
 
-    Ok(())
-  }
+// Reward debt keeps the `PRECISION` scaling, so only the final division in
+// `calculate_pending_reward` truncates
+fn calculate_reward_debt(staked_amount: u64, accumulated_reward_per_share: u128) -> Result<u128> {
+  Ok(
+    u128::from(staked_amount)
+      .checked_mul(accumulated_reward_per_share)
+      .ok_or(ErrorCode::Overflow)?,
+  )
+}
 
-  pub fn fund_reward_pool(ctx: Context<FundRewardPool>, amount: u64) -> Result<()> {
-    let cpi_accounts = token::Transfer {
-      from: ctx.accounts.funder_token_account.to_account_info(),
-      to: ctx.accounts.reward_vault.to_account_info(),
-      authority: ctx.accounts.funder.to_account_info(),
-    };
-    let cpi_program = ctx.accounts.token_program.to_account_info();
-    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-    token::transfer(cpi_ctx, amount)?;
+/// Transfers `amount` into one of the pool's vaults and returns what the vault actually received,
+/// which is less than `amount` when the mint charges a transfer fee.
+fn transfer_to_pool<'info>(
+  from: AccountInfo<'info>,
+  mint: &InterfaceAccount<'info, Mint>,
+  vault: &mut InterfaceAccount<'info, TokenAccount>,
+  authority: AccountInfo<'info>,
+  token_program: AccountInfo<'info>,
+  amount: u64,
+) -> Result<u64> {
+  let balance_before = vault.amount;
+
+  let cpi_accounts = TransferChecked {
+    from,
+    mint: mint.to_account_info(),
+    to: vault.to_account_info(),
+    authority,
+  };
+  let cpi_ctx = CpiContext::new(token_program, cpi_accounts);
+  token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)?;
+
+  vault.reload()?;
+  Ok(
+    vault
+      .amount
+      .checked_sub(balance_before)
+      .ok_or(ErrorCode::Underflow)?,
+  )
+}
 
-    Ok(())
-  }
+/// Transfers `amount` out of one of the pool's vaults, signed by the pool.
+fn transfer_from_pool<'info>(
+  pool: &Account<'info, StakePool>,
+  from: AccountInfo<'info>,
+  mint: &InterfaceAccount<'info, Mint>,
+  to: AccountInfo<'info>,
+  token_program: AccountInfo<'info>,
+  amount: u64,
+) -> Result<()> {
+  let pool_seeds = &[b"pool", pool.staking_mint.as_ref(), &[pool.bump]];
+  let signer_seeds = &[&pool_seeds[..]];
+
+  let cpi_accounts = TransferChecked {
+    from,
+    mint: mint.to_account_info(),
+    to,
+    authority: pool.to_account_info(),
+  };
+  let cpi_ctx = CpiContext::new_with_signer(token_program, cpi_accounts, signer_seeds);
+  token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)
 }
 
-// Helper functions
-fn update_pool_rewards(pool: &mut StakePool, current_time: i64) -> Result<()> {
-  if pool.total_staked == 0 {
-    pool.last_update_time = current_time;
+/// Rejects Token-2022 mints whose extensions would break the vault accounting: tokens that can't
+/// be transferred out again, a delegate that can move them out of the vaults, or transfer hooks
+/// needing accounts the pool doesn't pass. Transfer fees are handled by crediting what arrives.
+fn check_mint_extensions(mint: &InterfaceAccount<Mint>) -> Result<()> {
+  let mint_info = mint.to_account_info();
+  if *mint_info.owner != Token2022::id() {
     return Ok(());
   }
 
-  let time_elapsed = current_time
-    .checked_sub(pool.last_update_time)
-    .ok_or(ErrorCode::Underflow)? as u64;
-
-  let reward_amount = pool
-    .reward_rate
-    .checked_mul(time_elapsed)
-    .ok_or(ErrorCode::Overflow)?;
+  let mint_data = mint_info.try_borrow_data()?;
+  let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+  for extension in mint_state.get_extension_types()? {
+    require!(
+      !matches!(
+        extension,
+        ExtensionType::NonTransferable
+          | ExtensionType::PermanentDelegate
+          | ExtensionType::TransferHook
+      ),
+      ErrorCode::UnsupportedMintExtension
+    );
+  }
 
-  let reward_per_share = reward_amount
-    .checked_mul(PRECISION)
-    .ok_or(ErrorCode::Overflow)?
-    .checked_div(pool.total_staked)
-    .ok_or(ErrorCode::DivisionByZero)?;
+  Ok(())
+}
 
-  pool.accumulated_reward_per_share = pool
-    .accumulated_reward_per_share
-    .checked_add(reward_per_share)
-    .ok_or(ErrorCode::Overflow)?;
+/// Rewrites a program account from its `Legacy` layout into `T` with `migrate`, topping up rent
+/// from `payer` for the extra space.
+fn migrate_account<'info, Legacy, T>(
+  account: &AccountInfo<'info>,
+  payer: &Signer<'info>,
+  system_program: &Program<'info, System>,
+  legacy_space: usize,
+  space: usize,
+  migrate: impl FnOnce(Legacy) -> Result<T>,
+) -> Result<()>
+where
+  Legacy: AnchorDeserialize,
+  T: AccountSerialize + Discriminator,
+{
+  let migrated: T = {
+    let data = account.try_borrow_data()?;
+    require!(
+      data.starts_with(T::DISCRIMINATOR),
+      anchor_lang::error::ErrorCode::AccountDiscriminatorMismatch
+    );
+    require!(data.len() == 8 + legacy_space, ErrorCode::AlreadyMigrated);
+    migrate(Legacy::deserialize(&mut &data[8 ..])?)?
+  };
+
+  let rent_exempt_minimum = Rent::get()?.minimum_balance(8 + space);
+  let shortfall = rent_exempt_minimum.saturating_sub(account.lamports());
+  if shortfall > 0 {
+    let cpi_accounts = system_program::Transfer {
+      from: payer.to_account_info(),
+      to: account.clone(),
+    };
+    let cpi_ctx = CpiContext::new(system_program.to_account_info(), cpi_accounts);
+    system_program::transfer(cpi_ctx, shortfall)?;
+  }
 
-  pool.last_update_time = current_time;
+  account.resize(8 + space)?;
+  migrated.try_serialize(&mut &mut account.try_borrow_mut_data()?[..])?;
 
   Ok(())
 }
 
-fn calculate_pending_reward(
-  staked_amount: u64,
-  accumulated_reward_per_share: u64,
-  reward_debt: u64,
-) -> u64 {
-  staked_amount
-    .checked_mul(accumulated_reward_per_share)
-    .unwrap_or(0)
-    .checked_div(PRECISION)
-    .unwrap_or(0)
-    .saturating_sub(reward_debt)
-}
+const PRECISION: u128 = 1_000_000_000_000;
 
-fn calculate_reward_debt(staked_amount: u64, accumulated_reward_per_share: u64) -> u64 {
-  staked_amount
-    .checked_mul(accumulated_reward_per_share)
-    .unwrap_or(0)
-    .checked_div(PRECISION)
-    .unwrap_or(0)
-}
+// Scaling factor of `accumulated_reward_per_share` before it moved to u128
+const LEGACY_PRECISION: u128 = 1_000_000;
+
+pub const MAX_REWARD_STREAMS: usize = 4;
 
-const PRECISION: u64 = 1_000_000;
+/// Longest lock the authority can set, one year in seconds
+pub const MAX_LOCK_PERIOD: i64 = 365 * 24 * 60 * 60;
 
 // Contexts
 #[derive(Accounts)]
@@ -308,30 +713,62 @@ pub struct InitializePool<'info> {
   #[account(mut)]
   pub authority: Signer<'info>,
 
-  pub staking_mint: Account<'info, Mint>,
-  pub reward_mint: Account<'info, Mint>,
+  pub staking_mint: InterfaceAccount<'info, Mint>,
+  pub reward_mint: InterfaceAccount<'info, Mint>,
 
   #[account(
         init,
         payer = authority,
         token::mint = staking_mint,
         token::authority = pool,
+        token::token_program = staking_token_program,
         seeds = [b"staking_vault", pool.key().as_ref()],
         bump
     )]
-  pub staking_vault: Account<'info, TokenAccount>,
+  pub staking_vault: InterfaceAccount<'info, TokenAccount>,
 
   #[account(
         init,
         payer = authority,
         token::mint = reward_mint,
         token::authority = pool,
-        seeds = [b"reward_vault", pool.key().as_ref()],
+        token::token_program = reward_token_program,
+        seeds = [b"reward_vault", pool.key().as_ref(), reward_mint.key().as_ref()],
         bump
     )]
-  pub reward_vault: Account<'info, TokenAccount>,
+  pub reward_vault: InterfaceAccount<'info, TokenAccount>,
 
-  pub token_program: Program<'info, Token>,
+  // The two mints may belong to different token programs
+  pub staking_token_program: Interface<'info, TokenInterface>,
+  pub reward_token_program: Interface<'info, TokenInterface>,
+  pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AddRewardStream<'info> {
+  #[account(
+        mut,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+  pub pool: Account<'info, StakePool>,
+
+  #[account(mut)]
+  pub authority: Signer<'info>,
+
+  pub reward_mint: InterfaceAccount<'info, Mint>,
+
+  #[account(
+        init,
+        payer = authority,
+        token::mint = reward_mint,
+        token::authority = pool,
+        token::token_program = token_program,
+        seeds = [b"reward_vault", pool.key().as_ref(), reward_mint.key().as_ref()],
+        bump
+    )]
+  pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+  pub token_program: Interface<'info, TokenInterface>,
   pub system_program: Program<'info, System>,
 }
 
@@ -371,16 +808,19 @@ pub struct Stake<'info> {
         constraint = user_token_account.mint == pool.staking_mint,
         constraint = user_token_account.owner == owner.key(),
     )]
-  pub user_token_account: Account<'info, TokenAccount>,
+  pub user_token_account: InterfaceAccount<'info, TokenAccount>,
 
   #[account(
         mut,
         constraint = staking_vault.key() == pool.staking_vault,
     )]
-  pub staking_vault: Account<'info, TokenAccount>,
+  pub staking_vault: InterfaceAccount<'info, TokenAccount>,
+
+  #[account(address = pool.staking_mint)]
+  pub staking_mint: InterfaceAccount<'info, Mint>,
 
   pub owner: Signer<'info>,
-  pub token_program: Program<'info, Token>,
+  pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
@@ -400,19 +840,23 @@ pub struct Unstake<'info> {
         constraint = user_token_account.mint == pool.staking_mint,
         constraint = user_token_account.owner == owner.key(),
     )]
-  pub user_token_account: Account<'info, TokenAccount>,
+  pub user_token_account: InterfaceAccount<'info, TokenAccount>,
 
   #[account(
         mut,
         constraint = staking_vault.key() == pool.staking_vault,
     )]
-  pub staking_vault: Account<'info, TokenAccount>,
+  pub staking_vault: InterfaceAccount<'info, TokenAccount>,
+
+  #[account(address = pool.staking_mint)]
+  pub staking_mint: InterfaceAccount<'info, Mint>,
 
   pub owner: Signer<'info>,
-  pub token_program: Program<'info, Token>,
+  pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
+#[instruction(stream_index: u8)]
 pub struct ClaimReward<'info> {
   #[account(mut)]
   pub pool: Account<'info, StakePool>,
@@ -426,45 +870,229 @@ pub struct ClaimReward<'info> {
 
   #[account(
         mut,
-        constraint = user_reward_account.mint == pool.reward_mint,
+        constraint = user_reward_account.mint == pool.reward_stream(stream_index)?.reward_mint,
         constraint = user_reward_account.owner == owner.key(),
     )]
-  pub user_reward_account: Account<'info, TokenAccount>,
+  pub user_reward_account: InterfaceAccount<'info, TokenAccount>,
+
+  #[account(
+        mut,
+        constraint = reward_vault.key() == pool.reward_stream(stream_index)?.reward_vault,
+    )]
+  pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+  #[account(address = pool.reward_stream(stream_index)?.reward_mint)]
+  pub reward_mint: InterfaceAccount<'info, Mint>,
+
+  pub owner: Signer<'info>,
+  pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimAllRewards<'info> {
+  #[account(mut)]
+  pub pool: Account<'info, StakePool>,
 
   #[account(
         mut,
-        constraint = reward_vault.key() == pool.reward_vault,
+        has_one = owner,
+        has_one = pool,
     )]
-  pub reward_vault: Account<'info, TokenAccount>,
+  pub user_stake: Account<'info, UserStake>,
 
   pub owner: Signer<'info>,
+  // Reward streams may pay out from either token program
   pub token_program: Program<'info, Token>,
+  pub token_2022_program: Program<'info, Token2022>,
 }
 
 #[derive(Accounts)]
+#[instruction(stream_index: u8)]
 pub struct FundRewardPool<'info> {
+  #[account(mut)]
   pub pool: Account<'info, StakePool>,
 
   #[account(
         mut,
-        constraint = funder_token_account.mint == pool.reward_mint,
+        constraint = funder_token_account.mint == pool.reward_stream(stream_index)?.reward_mint,
     )]
-  pub funder_token_account: Account<'info, TokenAccount>,
+  pub funder_token_account: InterfaceAccount<'info, TokenAccount>,
 
   #[account(
         mut,
-        constraint = reward_vault.key() == pool.reward_vault,
+        constraint = reward_vault.key() == pool.reward_stream(stream_index)?.reward_vault,
     )]
-  pub reward_vault: Account<'info, TokenAccount>,
+  pub reward_vault: InterfaceAccount<'info, TokenAccount>,
+
+  #[account(address = pool.reward_stream(stream_index)?.reward_mint)]
+  pub reward_mint: InterfaceAccount<'info, Mint>,
 
   pub funder: Signer<'info>,
-  pub token_program: Program<'info, Token>,
+  pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePool<'info> {
+  #[account(
+        mut,
+        has_one = authority @ ErrorCode::Unauthorized,
+    )]
+  pub pool: Account<'info, StakePool>,
+
+  pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+  #[account(
+        mut,
+        constraint = pool.pending_authority == Some(new_authority.key()) @ ErrorCode::Unauthorized,
+    )]
+  pub pool: Account<'info, StakePool>,
+
+  pub new_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePool<'info> {
+  /// CHECK: still on the legacy layout, which `migrate_account` checks and decodes
+  #[account(mut, owner = crate::ID)]
+  pub pool: UncheckedAccount<'info>,
+
+  #[account(mut)]
+  pub payer: Signer<'info>,
+
+  pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateUserStake<'info> {
+  /// CHECK: still on the legacy layout, which `migrate_account` checks and decodes
+  #[account(mut, owner = crate::ID)]
+  pub user_stake: UncheckedAccount<'info>,
+
+  pub pool: Account<'info, StakePool>,
+
+  #[account(mut)]
+  pub payer: Signer<'info>,
+
+  pub system_program: Program<'info, System>,
 }
 
 // State
 #[account]
 #[derive(InitSpace)]
 pub struct StakePool {
+  pub authority: Pubkey,
+  pub staking_mint: Pubkey,
+  pub staking_vault: Pubkey,
+  pub lock_period: i64,
+  pub total_staked: u64,
+  /// Every reward stream has been updated up to here
+  pub last_update_time: i64,
+  #[max_len(MAX_REWARD_STREAMS)]
+  pub reward_streams: Vec<RewardStream>,
+  /// Proposed by the authority, takes over once it accepts
+  pub pending_authority: Option<Pubkey>,
+  pub paused: bool,
+  pub bump: u8,
+}
+
+impl StakePool {
+  pub fn reward_stream(&self, index: u8) -> Result<&RewardStream> {
+    Ok(
+      self
+        .reward_streams
+        .get(usize::from(index))
+        .ok_or(ErrorCode::InvalidRewardStream)?,
+    )
+  }
+
+  fn reward_stream_mut(&mut self, index: u8) -> Result<&mut RewardStream> {
+    Ok(
+      self
+        .reward_streams
+        .get_mut(usize::from(index))
+        .ok_or(ErrorCode::InvalidRewardStream)?,
+    )
+  }
+
+  /// The rewards each stream has left to emit and the seconds until they run out, as of `now`.
+  pub fn reward_runway(&self, now: i64) -> Result<Vec<RewardRunway>> {
+    let mut pool = self.clone();
+    update_pool_rewards(&mut pool, now)?;
+
+    Ok(
+      pool
+        .reward_streams
+        .iter()
+        .map(|stream| RewardRunway {
+          reward_mint: stream.reward_mint,
+          remaining_rewards: stream.unemitted_rewards,
+          seconds: stream.reward_end_time.saturating_sub(now).max(0),
+        })
+        .collect(),
+    )
+  }
+}
+
+/// One token paid out to stakers at its own rate, from its own vault.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq, InitSpace)]
+pub struct RewardStream {
+  pub reward_mint: Pubkey,
+  pub reward_vault: Pubkey,
+  pub reward_rate: u64,
+  pub accumulated_reward_per_share: u128,
+  /// Funded rewards not yet emitted to stakers
+  pub unemitted_rewards: u64,
+  /// When emission stops at the current rate unless the stream is funded again
+  pub reward_end_time: i64,
+}
+
+impl RewardStream {
+  fn new(reward_mint: Pubkey, reward_vault: Pubkey, reward_rate: u64, now: i64) -> Self {
+    Self {
+      reward_mint,
+      reward_vault,
+      reward_rate,
+      accumulated_reward_per_share: 0,
+      unemitted_rewards: 0,
+      reward_end_time: now,
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RewardRunway {
+  pub reward_mint: Pubkey,
+  pub remaining_rewards: u64,
+  pub seconds: i64,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct UserStake {
+  pub owner: Pubkey,
+  pub pool: Pubkey,
+  pub staked_amount: u64,
+  /// Indexed like the pool's `reward_streams`; streams added later are appended on the next update
+  #[max_len(MAX_REWARD_STREAMS)]
+  pub rewards: Vec<UserReward>,
+  pub last_stake_time: i64,
+  /// Set from the pool's lock period at every stake, so later changes to it don't apply
+  pub unlock_time: i64,
+  pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq, Eq, InitSpace)]
+pub struct UserReward {
+  pub reward_debt: u128,
+  pub pending_reward: u64,
+}
+
+/// `StakePool` as laid out before `accumulated_reward_per_share` moved to u128.
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub struct LegacyStakePool {
   pub authority: Pubkey,
   pub staking_mint: Pubkey,
   pub reward_mint: Pubkey,
@@ -478,9 +1106,38 @@ pub struct StakePool {
   pub bump: u8,
 }
 
-#[account]
-#[derive(InitSpace)]
-pub struct UserStake {
+impl From<LegacyStakePool> for StakePool {
+  fn from(legacy: LegacyStakePool) -> Self {
+    Self {
+      authority: legacy.authority,
+      staking_mint: legacy.staking_mint,
+      staking_vault: legacy.staking_vault,
+      // Legacy pools accepted any lock period
+      lock_period: legacy.lock_period.clamp(0, MAX_LOCK_PERIOD),
+      total_staked: legacy.total_staked,
+      last_update_time: legacy.last_update_time,
+      // Legacy pools never tracked their funding, and the vault may owe all of its balance to
+      // stakers already, so emission resumes with the next `fund_reward_pool`
+      reward_streams: vec![RewardStream {
+        accumulated_reward_per_share: u128::from(legacy.accumulated_reward_per_share) * PRECISION
+          / LEGACY_PRECISION,
+        ..RewardStream::new(
+          legacy.reward_mint,
+          legacy.reward_vault,
+          legacy.reward_rate,
+          legacy.last_update_time,
+        )
+      }],
+      pending_authority: None,
+      paused: false,
+      bump: legacy.bump,
+    }
+  }
+}
+
+/// `UserStake` as laid out before `reward_debt` moved to u128.
+#[derive(AnchorSerialize, AnchorDeserialize, InitSpace)]
+pub struct LegacyUserStake {
   pub owner: Pubkey,
   pub pool: Pubkey,
   pub staked_amount: u64,
@@ -490,6 +1147,26 @@ pub struct UserStake {
   pub bump: u8,
 }
 
+impl From<LegacyUserStake> for UserStake {
+  fn from(legacy: LegacyUserStake) -> Self {
+    Self {
+      owner: legacy.owner,
+      pool: legacy.pool,
+      staked_amount: legacy.staked_amount,
+      rewards: vec![UserReward {
+        // The legacy debt was stored in whole reward tokens
+        reward_debt: u128::from(legacy.reward_debt) * PRECISION,
+        pending_reward: legacy.pending_reward,
+      }],
+      last_stake_time: legacy.last_stake_time,
+      // Legacy stakes were locked until `last_stake_time` plus the pool's lock period, which
+      // `migrate_user_stake` fills in
+      unlock_time: legacy.last_stake_time,
+      bump: legacy.bump,
+    }
+  }
+}
+
 // Events
 #[event]
 pub struct StakeEvent {
@@ -508,7 +1185,17 @@ pub struct UnstakeEvent {
 #[event]
 pub struct ClaimRewardEvent {
   pub user: Pubkey,
+  pub reward_mint: Pubkey,
+  pub amount: u64,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct FundRewardPoolEvent {
+  pub funder: Pubkey,
+  pub reward_mint: Pubkey,
   pub amount: u64,
+  pub reward_end_time: i64,
   pub timestamp: i64,
 }
 
@@ -527,4 +1214,254 @@ pub enum ErrorCode {
   StillLocked,
   #[msg("No rewards to claim")]
   NoRewardsToClaim,
+  #[msg("Account is already on the current layout")]
+  AlreadyMigrated,
+  #[msg("Signer is not authorized to manage this pool")]
+  Unauthorized,
+  #[msg("Pool is paused")]
+  PoolPaused,
+  #[msg("Lock period must be between zero and MAX_LOCK_PERIOD seconds")]
+  InvalidLockPeriod,
+  #[msg("Reward stream does not exist or does not match the accounts given")]
+  InvalidRewardStream,
+  #[msg("Pool already has the maximum number of reward streams")]
+  TooManyRewardStreams,
+  #[msg("Pool already has a reward stream for this mint")]
+  DuplicateRewardMint,
+  #[msg("Mint has an extension the pool does not support")]
+  UnsupportedMintExtension,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pool(reward_rate: u64, total_staked: u64) -> StakePool {
+    StakePool {
+      authority: Pubkey::default(),
+      staking_mint: Pubkey::default(),
+      staking_vault: Pubkey::default(),
+      lock_period: 0,
+      total_staked,
+      last_update_time: 0,
+      reward_streams: vec![RewardStream {
+        unemitted_rewards: u64::MAX,
+        reward_end_time: i64::MAX,
+        ..RewardStream::new(Pubkey::default(), Pubkey::default(), reward_rate, 0)
+      }],
+      pending_authority: None,
+      paused: false,
+      bump: 0,
+    }
+  }
+
+  fn user_stake(staked_amount: u64) -> UserStake {
+    UserStake {
+      owner: Pubkey::default(),
+      pool: Pubkey::default(),
+      staked_amount,
+      rewards: Vec::new(),
+      last_stake_time: 0,
+      unlock_time: 0,
+      bump: 0,
+    }
+  }
+
+  #[test]
+  fn small_stakers_earn_against_nine_decimal_supply() {
+    // 1 token/s against a billion 9-decimal tokens staked rounded down to nothing at 1e6
+    let mut pool = pool(1_000_000_000, 1_000_000_000 * 1_000_000_000);
+    update_pool_rewards(&mut pool, 1).unwrap();
+
+    let pending = calculate_pending_reward(
+      1_000_000_000_000,
+      pool.reward_streams[0].accumulated_reward_per_share,
+      0,
+    );
+    assert_eq!(pending.unwrap(), 1_000);
+  }
+
+  #[test]
+  fn high_reward_rates_do_not_overflow() {
+    // `reward_rate * elapsed * PRECISION` exceeds u64 after a few seconds at this rate
+    let mut pool = pool(u64::MAX / 2, 1);
+    update_pool_rewards(&mut pool, 2).unwrap();
+    assert_eq!(
+      calculate_pending_reward(1, pool.reward_streams[0].accumulated_reward_per_share, 0).unwrap(),
+      u64::MAX - 1
+    );
+  }
+
+  #[test]
+  fn overflow_is_an_error() {
+    assert!(calculate_reward_debt(u64::MAX, u128::MAX / 2).is_err());
+    // More pending than fits in a u64 amount
+    assert!(calculate_pending_reward(2, u128::from(u64::MAX) * PRECISION, 0).is_err());
+    // Debt recorded above the accrued amount
+    assert!(calculate_pending_reward(1, PRECISION, PRECISION + 1).is_err());
+  }
+
+  #[test]
+  fn pending_reward_truncates_once() {
+    // 0.4 + 0.4 tokens per share accrued since the debt was recorded
+    let debt = calculate_reward_debt(5, PRECISION * 4 / 10).unwrap();
+    assert_eq!(
+      calculate_pending_reward(5, PRECISION * 8 / 10, debt).unwrap(),
+      2
+    );
+  }
+
+  #[test]
+  fn migration_preserves_pending_rewards() {
+    let legacy_pool = LegacyStakePool {
+      authority: Pubkey::new_unique(),
+      staking_mint: Pubkey::new_unique(),
+      reward_mint: Pubkey::new_unique(),
+      staking_vault: Pubkey::new_unique(),
+      reward_vault: Pubkey::new_unique(),
+      reward_rate: 100,
+      lock_period: 86_400,
+      total_staked: 5_000,
+      accumulated_reward_per_share: 2_500_000,
+      last_update_time: 1_700_000_000,
+      bump: 254,
+    };
+    let legacy_user_stake = LegacyUserStake {
+      owner: Pubkey::new_unique(),
+      pool: Pubkey::new_unique(),
+      staked_amount: 1_000,
+      // Staked when the accumulator was at 1.5 tokens per share
+      reward_debt: 1_500,
+      pending_reward: 7,
+      last_stake_time: 1_699_990_000,
+      bump: 253,
+    };
+    let (reward_mint, reward_vault) = (legacy_pool.reward_mint, legacy_pool.reward_vault);
+
+    let pool: StakePool = legacy_pool.into();
+    let mut user_stake: UserStake = legacy_user_stake.into();
+    let stream = &pool.reward_streams[0];
+    assert_eq!(pool.reward_streams.len(), 1);
+    assert_eq!(
+      (stream.reward_mint, stream.reward_vault),
+      (reward_mint, reward_vault)
+    );
+    assert_eq!(stream.accumulated_reward_per_share, 2_500_000_000_000);
+
+    settle_user_rewards(&pool, &mut user_stake).unwrap();
+    assert_eq!(user_stake.rewards[0].pending_reward, 1_007);
+  }
+
+  #[test]
+  fn migration_bounds_the_lock_period() {
+    let legacy_pool = LegacyStakePool {
+      authority: Pubkey::new_unique(),
+      staking_mint: Pubkey::new_unique(),
+      reward_mint: Pubkey::new_unique(),
+      staking_vault: Pubkey::new_unique(),
+      reward_vault: Pubkey::new_unique(),
+      reward_rate: 100,
+      lock_period: i64::MAX,
+      total_staked: 0,
+      accumulated_reward_per_share: 0,
+      last_update_time: 1_700_000_000,
+      bump: 254,
+    };
+
+    let pool: StakePool = legacy_pool.into();
+    assert_eq!(pool.lock_period, MAX_LOCK_PERIOD);
+  }
+
+  // `migrate_account` tells the layouts apart by their length
+  const _: () = assert!(StakePool::INIT_SPACE > LegacyStakePool::INIT_SPACE);
+  const _: () = assert!(UserStake::INIT_SPACE > LegacyUserStake::INIT_SPACE);
+
+  #[test]
+  fn emission_stops_when_funding_runs_out() {
+    let mut pool = pool(10, 100);
+    pool.reward_streams[0].unemitted_rewards = 95;
+    update_pool_rewards(&mut pool, 20).unwrap();
+
+    let stream = &pool.reward_streams[0];
+    assert_eq!(
+      calculate_pending_reward(100, stream.accumulated_reward_per_share, 0).unwrap(),
+      95
+    );
+    assert_eq!(stream.unemitted_rewards, 0);
+    assert_eq!(stream.reward_end_time, 20);
+  }
+
+  #[test]
+  fn empty_pool_keeps_its_funding() {
+    let mut pool = pool(10, 0);
+    pool.reward_streams[0].unemitted_rewards = 100;
+    update_pool_rewards(&mut pool, 50).unwrap();
+
+    assert_eq!(pool.reward_streams[0].unemitted_rewards, 100);
+    assert_eq!(pool.reward_streams[0].reward_end_time, 60);
+  }
+
+  #[test]
+  fn end_time_covers_the_last_partial_second() {
+    let mut pool = pool(10, 100);
+    let stream = &mut pool.reward_streams[0];
+    stream.unemitted_rewards = 25;
+    assert_eq!(projected_reward_end_time(stream, 0), 3);
+
+    stream.reward_rate = 0;
+    assert_eq!(projected_reward_end_time(stream, 0), 0);
+  }
+
+  #[test]
+  fn runway_counts_down_to_the_end_time() {
+    let mut pool = pool(10, 100);
+    pool.reward_streams[0].unemitted_rewards = 100;
+
+    assert_eq!(
+      pool.reward_runway(4).unwrap(),
+      [RewardRunway {
+        reward_mint: Pubkey::default(),
+        remaining_rewards: 60,
+        seconds: 6,
+      }]
+    );
+    assert_eq!(
+      pool.reward_runway(15).unwrap(),
+      [RewardRunway {
+        reward_mint: Pubkey::default(),
+        remaining_rewards: 0,
+        seconds: 0,
+      }]
+    );
+  }
+
+  #[test]
+  fn streams_accrue_independently() {
+    let mut pool = pool(10, 100);
+    pool.reward_streams[0].unemitted_rewards = 1_000;
+    let mut user_stake = user_stake(25);
+    settle_user_rewards(&pool, &mut user_stake).unwrap();
+
+    update_pool_rewards(&mut pool, 10).unwrap();
+    let mut partner = RewardStream::new(Pubkey::new_unique(), Pubkey::new_unique(), 4, 10);
+    partner.unemitted_rewards = 1_000;
+    pool.reward_streams.push(partner);
+    update_pool_rewards(&mut pool, 20).unwrap();
+
+    // The partner stream only pays for the 10 seconds since it was added
+    settle_user_rewards(&pool, &mut user_stake).unwrap();
+    assert_eq!(
+      user_stake.rewards,
+      [
+        UserReward {
+          reward_debt: 25 * pool.reward_streams[0].accumulated_reward_per_share,
+          pending_reward: 50,
+        },
+        UserReward {
+          reward_debt: 25 * pool.reward_streams[1].accumulated_reward_per_share,
+          pending_reward: 10,
+        },
+      ]
+    );
+  }
 }
@@ -0,0 +1,1109 @@
+//! End-to-end tests of the staking program running as a native builtin in `solana-program-test`.
+
+use anchor_lang::{
+  prelude::{AccountInfo, AccountMeta, Clock, Pubkey, Rent},
+  solana_program::{
+    instruction::{Instruction, InstructionError},
+    program_pack::Pack,
+  },
+  system_program, AccountDeserialize, AnchorSerialize, Discriminator, InstructionData,
+  ToAccountMetas,
+};
+use anchor_spl::{
+  token::spl_token,
+  token_2022::spl_token_2022::{
+    self,
+    extension::{
+      non_transferable::NonTransferable,
+      permanent_delegate::PermanentDelegate,
+      transfer_fee::{TransferFee, TransferFeeAmount, TransferFeeConfig},
+      BaseStateWithExtensionsMut, ExtensionType, StateWithExtensionsMut,
+    },
+  },
+  token_interface::TokenAccount,
+};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+  account::Account,
+  entrypoint::ProgramResult,
+  native_token::LAMPORTS_PER_SOL,
+  program_option::COption,
+  signature::{Keypair, Signer},
+  transaction::{Transaction, TransactionError},
+};
+use stake_program_example::{
+  accounts, instruction, ErrorCode, LegacyStakePool, LegacyUserStake, RewardRunway, StakePool,
+  UserStake, MAX_LOCK_PERIOD, MAX_REWARD_STREAMS,
+};
+
+const START: i64 = 1_700_000_000;
+const DECIMALS: u8 = 9;
+const ONE_TOKEN: u64 = 1_000_000_000;
+const FUNDER_BALANCE: u64 = 1_000_000 * ONE_TOKEN;
+
+// `entry` ties the account slice to the lifetime of the accounts in it, which the builtin
+// signature doesn't; a leaked copy of the slice satisfies both
+fn process_instruction(
+  program_id: &Pubkey,
+  accounts: &[AccountInfo],
+  data: &[u8],
+) -> ProgramResult {
+  let accounts = Box::leak(accounts.to_vec().into_boxed_slice());
+  stake_program_example::entry(program_id, accounts, data)
+}
+
+fn program_address(seeds: &[&[u8]]) -> Pubkey {
+  Pubkey::find_program_address(seeds, &stake_program_example::ID).0
+}
+
+fn mint_account() -> Account {
+  let mut data = vec![0; spl_token::state::Mint::LEN];
+  spl_token::state::Mint {
+    mint_authority: COption::Some(Pubkey::new_unique()),
+    supply: u64::MAX,
+    decimals: DECIMALS,
+    is_initialized: true,
+    freeze_authority: COption::None,
+  }
+  .pack_into_slice(&mut data);
+  program_account(data, spl_token::ID)
+}
+
+fn token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Account {
+  let mut data = vec![0; spl_token::state::Account::LEN];
+  spl_token::state::Account {
+    mint,
+    owner,
+    amount,
+    delegate: COption::None,
+    state: spl_token::state::AccountState::Initialized,
+    is_native: COption::None,
+    delegated_amount: 0,
+    close_authority: COption::None,
+  }
+  .pack_into_slice(&mut data);
+  program_account(data, spl_token::ID)
+}
+
+/// A Token-2022 mint carrying `extensions`, which `init_extensions` sets up.
+fn token_2022_mint_account(
+  extensions: &[ExtensionType],
+  init_extensions: impl FnOnce(&mut StateWithExtensionsMut<spl_token_2022::state::Mint>),
+) -> Account {
+  let len =
+    ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(extensions).unwrap();
+  let mut data = vec![0; len];
+  let mut state =
+    StateWithExtensionsMut::<spl_token_2022::state::Mint>::unpack_uninitialized(&mut data).unwrap();
+  init_extensions(&mut state);
+  state.base = spl_token_2022::state::Mint {
+    mint_authority: COption::Some(Pubkey::new_unique()),
+    supply: u64::MAX,
+    decimals: DECIMALS,
+    is_initialized: true,
+    freeze_authority: COption::None,
+  };
+  state.pack_base();
+  state.init_account_type().unwrap();
+  program_account(data, spl_token_2022::ID)
+}
+
+fn transfer_fee_mint_account(basis_points: u16) -> Account {
+  token_2022_mint_account(&[ExtensionType::TransferFeeConfig], |state| {
+    let fee = TransferFee {
+      epoch: 0.into(),
+      maximum_fee: u64::MAX.into(),
+      transfer_fee_basis_points: basis_points.into(),
+    };
+    let config = state.init_extension::<TransferFeeConfig>(true).unwrap();
+    config.older_transfer_fee = fee;
+    config.newer_transfer_fee = fee;
+  })
+}
+
+/// A token account for a `transfer_fee_mint_account` mint.
+fn transfer_fee_token_account(mint: Pubkey, owner: Pubkey, amount: u64) -> Account {
+  let len = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Account>(&[
+    ExtensionType::TransferFeeAmount,
+  ])
+  .unwrap();
+  let mut data = vec![0; len];
+  let mut state =
+    StateWithExtensionsMut::<spl_token_2022::state::Account>::unpack_uninitialized(&mut data)
+      .unwrap();
+  state.init_extension::<TransferFeeAmount>(true).unwrap();
+  state.base = spl_token_2022::state::Account {
+    mint,
+    owner,
+    amount,
+    delegate: COption::None,
+    state: spl_token_2022::state::AccountState::Initialized,
+    is_native: COption::None,
+    delegated_amount: 0,
+    close_authority: COption::None,
+  };
+  state.pack_base();
+  state.init_account_type().unwrap();
+  program_account(data, spl_token_2022::ID)
+}
+
+fn program_account(data: Vec<u8>, owner: Pubkey) -> Account {
+  Account {
+    lamports: Rent::default().minimum_balance(data.len()),
+    data,
+    owner,
+    executable: false,
+    rent_epoch: 0,
+  }
+}
+
+/// Account data as the program wrote it before the u128 reward accumulator.
+fn legacy_account(discriminator: &[u8], legacy: impl AnchorSerialize) -> Account {
+  let mut data = discriminator.to_vec();
+  legacy.serialize(&mut data).unwrap();
+  program_account(data, stake_program_example::ID)
+}
+
+/// Instruction accounts followed by `remaining_accounts`.
+struct WithRemaining<A>(A, Vec<AccountMeta>);
+
+impl<A: ToAccountMetas> ToAccountMetas for WithRemaining<A> {
+  fn to_account_metas(&self, is_signer: Option<bool>) -> Vec<AccountMeta> {
+    let mut metas = self.0.to_account_metas(is_signer);
+    metas.extend_from_slice(&self.1);
+    metas
+  }
+}
+
+fn assert_program_error(result: Result<(), BanksClientError>, expected: ErrorCode) {
+  match result.map_err(|error| error.unwrap()) {
+    Err(TransactionError::InstructionError(_, InstructionError::Custom(code))) => {
+      assert_eq!(code, u32::from(expected), "expected {expected:?}")
+    }
+    other => panic!("expected {expected:?}, got {other:?}"),
+  }
+}
+
+struct TestEnv {
+  context: ProgramTestContext,
+  now: i64,
+  staking_mint: Pubkey,
+  // One more reward mint than a pool can hold streams for, each with a user and funder account
+  reward_mints: Vec<Pubkey>,
+  pool: Pubkey,
+  // Owns every mint
+  token_program: Pubkey,
+  user: Keypair,
+  user_staking_account: Pubkey,
+  user_reward_accounts: Vec<Pubkey>,
+  funder: Keypair,
+  funder_reward_accounts: Vec<Pubkey>,
+}
+
+impl TestEnv {
+  /// Starts a cluster with all the mints, a user holding `user_balance` staking tokens, a funder
+  /// holding `FUNDER_BALANCE` of every reward token and any `extra_accounts` already in place.
+  async fn start(user_balance: u64, extra_accounts: Vec<(Pubkey, Account)>) -> Self {
+    Self::start_with_mints(user_balance, extra_accounts, None).await
+  }
+
+  /// Like `start`, but every mint is a Token-2022 mint charging `basis_points` on transfers.
+  async fn start_with_transfer_fee(user_balance: u64, basis_points: u16) -> Self {
+    Self::start_with_mints(user_balance, Vec::new(), Some(basis_points)).await
+  }
+
+  async fn start_with_mints(
+    user_balance: u64,
+    extra_accounts: Vec<(Pubkey, Account)>,
+    transfer_fee_basis_points: Option<u16>,
+  ) -> Self {
+    let (token_program, mint_account, token_account): (_, _, fn(_, _, _) -> _) =
+      match transfer_fee_basis_points {
+        None => (spl_token::ID, mint_account(), token_account),
+        Some(basis_points) => (
+          spl_token_2022::ID,
+          transfer_fee_mint_account(basis_points),
+          transfer_fee_token_account,
+        ),
+      };
+    let mut program_test = ProgramTest::new(
+      "stake_program_example",
+      stake_program_example::ID,
+      processor!(process_instruction),
+    );
+
+    let (staking_mint, reward_mints) = Self::mints();
+    let pool = program_address(&[b"pool", staking_mint.as_ref()]);
+    let (user, funder) = (Keypair::new(), Keypair::new());
+    let user_staking_account = Pubkey::new_unique();
+    program_test.add_account(staking_mint, mint_account.clone());
+    program_test.add_account(
+      user_staking_account,
+      token_account(staking_mint, user.pubkey(), user_balance),
+    );
+    let (mut user_reward_accounts, mut funder_reward_accounts) = (Vec::new(), Vec::new());
+    for &reward_mint in &reward_mints {
+      let (user_reward_account, funder_reward_account) =
+        (Pubkey::new_unique(), Pubkey::new_unique());
+      program_test.add_account(reward_mint, mint_account.clone());
+      program_test.add_account(
+        user_reward_account,
+        token_account(reward_mint, user.pubkey(), 0),
+      );
+      program_test.add_account(
+        funder_reward_account,
+        token_account(reward_mint, funder.pubkey(), FUNDER_BALANCE),
+      );
+      user_reward_accounts.push(user_reward_account);
+      funder_reward_accounts.push(funder_reward_account);
+    }
+    // Enough to pay for the user stake account
+    program_test.add_account(
+      user.pubkey(),
+      Account::new(LAMPORTS_PER_SOL, 0, &system_program::ID),
+    );
+    for (address, account) in extra_accounts {
+      program_test.add_account(address, account);
+    }
+
+    Self {
+      context: program_test.start_with_context().await,
+      now: START,
+      staking_mint,
+      reward_mints,
+      pool,
+      token_program,
+      user,
+      user_staking_account,
+      user_reward_accounts,
+      funder,
+      funder_reward_accounts,
+    }
+  }
+
+  /// The same mints for every test, so legacy accounts can be laid out before starting.
+  fn mints() -> (Pubkey, Vec<Pubkey>) {
+    let reward_mints = (0 ..= MAX_REWARD_STREAMS as u8)
+      .map(|index| Pubkey::new_from_array([index + 2; 32]))
+      .collect();
+    (Pubkey::new_from_array([1; 32]), reward_mints)
+  }
+
+  fn user_stake(&self) -> Pubkey {
+    program_address(&[
+      b"user_stake",
+      self.pool.as_ref(),
+      self.user.pubkey().as_ref(),
+    ])
+  }
+
+  fn staking_vault(&self) -> Pubkey {
+    program_address(&[b"staking_vault", self.pool.as_ref()])
+  }
+
+  fn new_reward_vault(&self, reward_mint: Pubkey) -> Pubkey {
+    program_address(&[b"reward_vault", self.pool.as_ref(), reward_mint.as_ref()])
+  }
+
+  async fn reward_vault(&mut self, stream_index: u8) -> Pubkey {
+    let pool: StakePool = self.deserialize(self.pool).await;
+    pool.reward_streams[usize::from(stream_index)].reward_vault
+  }
+
+  async fn process(
+    &mut self,
+    accounts: impl ToAccountMetas,
+    data: impl InstructionData,
+    signers: &[&Keypair],
+  ) -> Result<(), BanksClientError> {
+    let instruction = Instruction {
+      program_id: stake_program_example::ID,
+      accounts: accounts.to_account_metas(None),
+      data: data.data(),
+    };
+    let payer = self.context.payer.insecure_clone();
+    let mut all_signers = vec![&payer];
+    all_signers.extend_from_slice(signers);
+
+    // A fresh blockhash keeps a retried instruction from being rejected as already processed
+    let blockhash = self.context.get_new_latest_blockhash().await.unwrap();
+    let mut clock: Clock = self.context.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = self.now;
+    self.context.set_sysvar(&clock);
+
+    let transaction = Transaction::new_signed_with_payer(
+      &[instruction],
+      Some(&payer.pubkey()),
+      &all_signers,
+      blockhash,
+    );
+    self
+      .context
+      .banks_client
+      .process_transaction(transaction)
+      .await
+  }
+
+  async fn deserialize<T: AccountDeserialize>(&mut self, address: Pubkey) -> T {
+    let account = self
+      .context
+      .banks_client
+      .get_account(address)
+      .await
+      .unwrap()
+      .expect("account exists");
+    T::try_deserialize(&mut account.data.as_slice()).unwrap()
+  }
+
+  async fn token_balance(&mut self, address: Pubkey) -> u64 {
+    self.deserialize::<TokenAccount>(address).await.amount
+  }
+
+  async fn initialize_pool(&mut self, reward_rate: u64) {
+    let authority = self.context.payer.pubkey();
+    self
+      .process(
+        accounts::InitializePool {
+          pool: self.pool,
+          authority,
+          staking_mint: self.staking_mint,
+          reward_mint: self.reward_mints[0],
+          staking_vault: self.staking_vault(),
+          reward_vault: self.new_reward_vault(self.reward_mints[0]),
+          staking_token_program: self.token_program,
+          reward_token_program: self.token_program,
+          system_program: system_program::ID,
+        },
+        instruction::InitializePool {
+          reward_rate,
+          lock_period: 0,
+        },
+        &[],
+      )
+      .await
+      .unwrap();
+
+    let user = self.user.insecure_clone();
+    self
+      .process(
+        accounts::InitializeUserStake {
+          user_stake: self.user_stake(),
+          pool: self.pool,
+          owner: user.pubkey(),
+          system_program: system_program::ID,
+        },
+        instruction::InitializeUserStake {},
+        &[&user],
+      )
+      .await
+      .unwrap();
+  }
+
+  /// Adds a stream paying the reward mint at `mint_index`, signed by `authority`.
+  async fn add_reward_stream(
+    &mut self,
+    mint_index: usize,
+    reward_rate: u64,
+    authority: &Keypair,
+  ) -> Result<(), BanksClientError> {
+    let reward_mint = self.reward_mints[mint_index];
+    self
+      .process(
+        accounts::AddRewardStream {
+          pool: self.pool,
+          authority: authority.pubkey(),
+          reward_mint,
+          reward_vault: self.new_reward_vault(reward_mint),
+          token_program: self.token_program,
+          system_program: system_program::ID,
+        },
+        instruction::AddRewardStream { reward_rate },
+        &[authority],
+      )
+      .await
+  }
+
+  async fn fund_reward_pool(
+    &mut self,
+    stream_index: u8,
+    amount: u64,
+  ) -> Result<(), BanksClientError> {
+    let funder = self.funder.insecure_clone();
+    let reward_vault = self.reward_vault(stream_index).await;
+    self
+      .process(
+        accounts::FundRewardPool {
+          pool: self.pool,
+          funder_token_account: self.funder_reward_accounts[usize::from(stream_index)],
+          reward_vault,
+          reward_mint: self.reward_mints[usize::from(stream_index)],
+          funder: funder.pubkey(),
+          token_program: self.token_program,
+        },
+        instruction::FundRewardPool {
+          stream_index,
+          amount,
+        },
+        &[&funder],
+      )
+      .await
+  }
+
+  async fn stake(&mut self, amount: u64) -> Result<(), BanksClientError> {
+    let user = self.user.insecure_clone();
+    self
+      .process(
+        accounts::Stake {
+          pool: self.pool,
+          user_stake: self.user_stake(),
+          user_token_account: self.user_staking_account,
+          staking_vault: self.staking_vault(),
+          staking_mint: self.staking_mint,
+          owner: user.pubkey(),
+          token_program: self.token_program,
+        },
+        instruction::Stake { amount },
+        &[&user],
+      )
+      .await
+  }
+
+  async fn unstake(&mut self, amount: u64) -> Result<(), BanksClientError> {
+    let user = self.user.insecure_clone();
+    self
+      .process(
+        accounts::Unstake {
+          pool: self.pool,
+          user_stake: self.user_stake(),
+          user_token_account: self.user_staking_account,
+          staking_vault: self.staking_vault(),
+          staking_mint: self.staking_mint,
+          owner: user.pubkey(),
+          token_program: self.token_program,
+        },
+        instruction::Unstake { amount },
+        &[&user],
+      )
+      .await
+  }
+
+  /// Sends one of the authority-gated pool instructions, signed by `authority`.
+  async fn update_pool(
+    &mut self,
+    data: impl InstructionData,
+    authority: &Keypair,
+  ) -> Result<(), BanksClientError> {
+    self
+      .process(
+        accounts::UpdatePool {
+          pool: self.pool,
+          authority: authority.pubkey(),
+        },
+        data,
+        &[authority],
+      )
+      .await
+  }
+
+  async fn accept_authority(&mut self, new_authority: &Keypair) -> Result<(), BanksClientError> {
+    self
+      .process(
+        accounts::AcceptAuthority {
+          pool: self.pool,
+          new_authority: new_authority.pubkey(),
+        },
+        instruction::AcceptAuthority {},
+        &[new_authority],
+      )
+      .await
+  }
+
+  async fn claim_reward(&mut self, stream_index: u8) -> Result<(), BanksClientError> {
+    let user = self.user.insecure_clone();
+    let reward_vault = self.reward_vault(stream_index).await;
+    self
+      .process(
+        accounts::ClaimReward {
+          pool: self.pool,
+          user_stake: self.user_stake(),
+          user_reward_account: self.user_reward_accounts[usize::from(stream_index)],
+          reward_vault,
+          reward_mint: self.reward_mints[usize::from(stream_index)],
+          owner: user.pubkey(),
+          token_program: self.token_program,
+        },
+        instruction::ClaimReward { stream_index },
+        &[&user],
+      )
+      .await
+  }
+
+  /// Claims every stream, passing each stream's mint and vault and the user's account for the mint.
+  async fn claim_all_rewards(&mut self) -> Result<(), BanksClientError> {
+    let user = self.user.insecure_clone();
+    let pool: StakePool = self.deserialize(self.pool).await;
+    let remaining_accounts = pool
+      .reward_streams
+      .iter()
+      .zip(&self.user_reward_accounts)
+      .flat_map(|(stream, &user_reward_account)| {
+        [
+          AccountMeta::new_readonly(stream.reward_mint, false),
+          AccountMeta::new(stream.reward_vault, false),
+          AccountMeta::new(user_reward_account, false),
+        ]
+      })
+      .collect();
+    self
+      .process(
+        WithRemaining(
+          accounts::ClaimAllRewards {
+            pool: self.pool,
+            user_stake: self.user_stake(),
+            owner: user.pubkey(),
+            token_program: spl_token::ID,
+            token_2022_program: spl_token_2022::ID,
+          },
+          remaining_accounts,
+        ),
+        instruction::ClaimAllRewards {},
+        &[&user],
+      )
+      .await
+  }
+}
+
+#[tokio::test]
+async fn small_stake_earns_rewards_on_nine_decimal_mints() {
+  let mut env = TestEnv::start(1_000 * ONE_TOKEN, Vec::new()).await;
+  env.initialize_pool(ONE_TOKEN).await;
+  env.fund_reward_pool(0, 1_000 * ONE_TOKEN).await.unwrap();
+
+  // Other stakers hold the rest of a billion tokens, next to which the user's thousand earned
+  // nothing under the old precision
+  let mut pool: StakePool = env.deserialize(env.pool).await;
+  pool.total_staked = (1_000_000_000 - 1_000) * ONE_TOKEN;
+  let mut data = StakePool::DISCRIMINATOR.to_vec();
+  pool.serialize(&mut data).unwrap();
+  let pool_address = env.pool;
+  env.context.set_account(
+    &pool_address,
+    &program_account(data, stake_program_example::ID).into(),
+  );
+
+  env.stake(1_000 * ONE_TOKEN).await.unwrap();
+  env.now += 10;
+  env.claim_reward(0).await.unwrap();
+
+  // 10 tokens emitted, a millionth of which is the user's
+  assert_eq!(env.token_balance(env.user_reward_accounts[0]).await, 10_000);
+}
+
+#[tokio::test]
+async fn emission_stops_when_funding_runs_out() {
+  let mut env = TestEnv::start(1_000, Vec::new()).await;
+  env.initialize_pool(10).await;
+  env.fund_reward_pool(0, 100).await.unwrap();
+
+  // Funding while nobody is staked doesn't start the clock
+  env.now += 100;
+  env.stake(1_000).await.unwrap();
+  let pool: StakePool = env.deserialize(env.pool).await;
+  assert_eq!(pool.reward_streams[0].reward_end_time, env.now + 10);
+
+  // Twice the funded period has passed, but only the funded 100 tokens were emitted
+  env.now += 20;
+  env.claim_reward(0).await.unwrap();
+  assert_eq!(env.token_balance(env.user_reward_accounts[0]).await, 100);
+  let reward_vault = env.reward_vault(0).await;
+  assert_eq!(env.token_balance(reward_vault).await, 0);
+  assert_program_error(env.claim_reward(0).await, ErrorCode::NoRewardsToClaim);
+
+  // Funding again picks up from now rather than paying out the unfunded gap
+  env.fund_reward_pool(0, 50).await.unwrap();
+  let pool: StakePool = env.deserialize(env.pool).await;
+  assert_eq!(pool.reward_streams[0].reward_end_time, env.now + 5);
+  assert_eq!(
+    pool.reward_runway(env.now + 2).unwrap(),
+    [RewardRunway {
+      reward_mint: env.reward_mints[0],
+      remaining_rewards: 30,
+      seconds: 3,
+    }]
+  );
+  env.now += 60;
+  env.claim_reward(0).await.unwrap();
+  assert_eq!(env.token_balance(env.user_reward_accounts[0]).await, 150);
+}
+
+#[tokio::test]
+async fn migrates_legacy_accounts() {
+  let (staking_mint, reward_mints) = TestEnv::mints();
+  let reward_mint = reward_mints[0];
+  let (pool, pool_bump) = Pubkey::find_program_address(
+    &[b"pool", staking_mint.as_ref()],
+    &stake_program_example::ID,
+  );
+  let staking_vault = program_address(&[b"staking_vault", pool.as_ref()]);
+  // Before reward streams the vault's address didn't include the mint
+  let reward_vault = program_address(&[b"reward_vault", pool.as_ref()]);
+  let authority = Keypair::new();
+  let legacy_pool = LegacyStakePool {
+    authority: authority.pubkey(),
+    staking_mint,
+    reward_mint,
+    staking_vault,
+    reward_vault,
+    reward_rate: 10,
+    lock_period: 60,
+    total_staked: 1_000,
+    // 2 reward tokens per staked token at the old 1e6 precision
+    accumulated_reward_per_share: 2_000_000,
+    last_update_time: START,
+    bump: pool_bump,
+  };
+
+  let mut env = TestEnv::start(
+    0,
+    vec![
+      (pool, legacy_account(StakePool::DISCRIMINATOR, legacy_pool)),
+      (staking_vault, token_account(staking_mint, pool, 1_000)),
+      // Exactly what the legacy stakers are owed
+      (reward_vault, token_account(reward_mint, pool, 500)),
+      (
+        authority.pubkey(),
+        Account::new(LAMPORTS_PER_SOL, 0, &system_program::ID),
+      ),
+    ],
+  )
+  .await;
+  let user = env.user.pubkey();
+  let (user_stake, user_stake_bump) = Pubkey::find_program_address(
+    &[b"user_stake", pool.as_ref(), user.as_ref()],
+    &stake_program_example::ID,
+  );
+  env.context.set_account(
+    &user_stake,
+    &legacy_account(
+      UserStake::DISCRIMINATOR,
+      LegacyUserStake {
+        owner: user,
+        pool,
+        staked_amount: 1_000,
+        // Staked at 1.5 reward tokens per share, so 500 tokens are pending
+        reward_debt: 1_500,
+        pending_reward: 0,
+        last_stake_time: START,
+        bump: user_stake_bump,
+      },
+    )
+    .into(),
+  );
+
+  // The current layout can't read the legacy accounts
+  let user_reward_account = env.user_reward_accounts[0];
+  let owner = env.user.insecure_clone();
+  assert!(env
+    .process(
+      accounts::ClaimReward {
+        pool,
+        user_stake,
+        user_reward_account,
+        reward_vault,
+        reward_mint,
+        owner: owner.pubkey(),
+        token_program: spl_token::ID,
+      },
+      instruction::ClaimReward { stream_index: 0 },
+      &[&owner],
+    )
+    .await
+    .is_err());
+
+  let payer = env.context.payer.pubkey();
+  env
+    .process(
+      accounts::MigratePool {
+        pool,
+        payer,
+        system_program: system_program::ID,
+      },
+      instruction::MigratePool {},
+      &[],
+    )
+    .await
+    .unwrap();
+  env
+    .process(
+      accounts::MigrateUserStake {
+        user_stake,
+        pool,
+        payer,
+        system_program: system_program::ID,
+      },
+      instruction::MigrateUserStake {},
+      &[],
+    )
+    .await
+    .unwrap();
+  assert_program_error(
+    env
+      .process(
+        accounts::MigratePool {
+          pool,
+          payer,
+          system_program: system_program::ID,
+        },
+        instruction::MigratePool {},
+        &[],
+      )
+      .await,
+    ErrorCode::AlreadyMigrated,
+  );
+
+  let migrated: StakePool = env.deserialize(pool).await;
+  assert_eq!(migrated.reward_streams[0].reward_vault, reward_vault);
+  assert_eq!(
+    migrated.reward_streams[0].accumulated_reward_per_share,
+    2_000_000_000_000
+  );
+  assert_eq!(migrated.total_staked, 1_000);
+  let migrated: UserStake = env.deserialize(user_stake).await;
+  assert_eq!(migrated.rewards[0].reward_debt, 1_500_000_000_000_000);
+  assert_eq!(migrated.unlock_time, START + 60);
+
+  // The vault's balance is owed to the legacy stakers, so nothing is emitted until funding
+  let stream = &env.deserialize::<StakePool>(pool).await.reward_streams[0];
+  assert_eq!(stream.unemitted_rewards, 0);
+  assert_eq!(stream.reward_end_time, START);
+
+  // 100 tokens over 10 seconds, all of them the user's
+  env.fund_reward_pool(0, 100).await.unwrap();
+  let stream = &env.deserialize::<StakePool>(pool).await.reward_streams[0];
+  assert_eq!(stream.reward_end_time, START + 10);
+
+  // Claimed after the migrated emission ran out, the 500 pending still come out of the vault
+  env.now += 30;
+  env.claim_reward(0).await.unwrap();
+  assert_eq!(env.token_balance(env.user_reward_accounts[0]).await, 600);
+  assert_eq!(env.token_balance(reward_vault).await, 0);
+
+  // The migrated stream keeps paying from the old vault, so its mint can't get a second one
+  assert_program_error(
+    env.add_reward_stream(0, 10, &authority).await,
+    ErrorCode::DuplicateRewardMint,
+  );
+}
+
+#[tokio::test]
+async fn rate_changes_settle_accrued_rewards() {
+  let mut env = TestEnv::start(1_000, Vec::new()).await;
+  env.initialize_pool(10).await;
+  env.fund_reward_pool(0, 1_000).await.unwrap();
+  env.stake(1_000).await.unwrap();
+  let (authority, stranger) = (
+    env.context.payer.insecure_clone(),
+    env.funder.insecure_clone(),
+  );
+
+  assert_program_error(
+    env
+      .update_pool(
+        instruction::SetRewardRate {
+          stream_index: 0,
+          reward_rate: 20,
+        },
+        &stranger,
+      )
+      .await,
+    ErrorCode::Unauthorized,
+  );
+
+  // 5 seconds at 10 per second, then 5 at 20
+  env.now += 5;
+  env
+    .update_pool(
+      instruction::SetRewardRate {
+        stream_index: 0,
+        reward_rate: 20,
+      },
+      &authority,
+    )
+    .await
+    .unwrap();
+  let pool: StakePool = env.deserialize(env.pool).await;
+  assert_eq!(pool.reward_streams[0].reward_end_time, env.now + 48);
+  env.now += 5;
+  env.claim_reward(0).await.unwrap();
+  assert_eq!(env.token_balance(env.user_reward_accounts[0]).await, 150);
+}
+
+#[tokio::test]
+async fn lock_period_applies_from_the_next_stake() {
+  let mut env = TestEnv::start(1_000, Vec::new()).await;
+  env.initialize_pool(10).await;
+  env.stake(500).await.unwrap();
+  let authority = env.context.payer.insecure_clone();
+
+  for lock_period in [-1, MAX_LOCK_PERIOD + 1, i64::MAX] {
+    assert_program_error(
+      env
+        .update_pool(instruction::SetLockPeriod { lock_period }, &authority)
+        .await,
+      ErrorCode::InvalidLockPeriod,
+    );
+  }
+
+  // The stake made without a lock stays unlocked
+  env
+    .update_pool(instruction::SetLockPeriod { lock_period: 60 }, &authority)
+    .await
+    .unwrap();
+  env.unstake(100).await.unwrap();
+
+  // Staking again locks the whole stake for the new period, which later changes don't extend
+  env.stake(100).await.unwrap();
+  let user_stake: UserStake = env.deserialize(env.user_stake()).await;
+  assert_eq!(user_stake.unlock_time, env.now + 60);
+  env
+    .update_pool(
+      instruction::SetLockPeriod {
+        lock_period: MAX_LOCK_PERIOD,
+      },
+      &authority,
+    )
+    .await
+    .unwrap();
+  assert_program_error(env.unstake(500).await, ErrorCode::StillLocked);
+  env.now += 60;
+  env.unstake(500).await.unwrap();
+  assert_eq!(env.token_balance(env.user_staking_account).await, 1_000);
+}
+
+#[tokio::test]
+async fn pause_only_blocks_staking() {
+  let mut env = TestEnv::start(1_000, Vec::new()).await;
+  env.initialize_pool(10).await;
+  env.fund_reward_pool(0, 1_000).await.unwrap();
+  env.stake(500).await.unwrap();
+  let authority = env.context.payer.insecure_clone();
+
+  env
+    .update_pool(instruction::Pause {}, &authority)
+    .await
+    .unwrap();
+  assert_program_error(env.stake(500).await, ErrorCode::PoolPaused);
+
+  env.now += 10;
+  env.claim_reward(0).await.unwrap();
+  env.unstake(500).await.unwrap();
+  assert_eq!(env.token_balance(env.user_reward_accounts[0]).await, 100);
+  assert_eq!(env.token_balance(env.user_staking_account).await, 1_000);
+
+  env
+    .update_pool(instruction::Unpause {}, &authority)
+    .await
+    .unwrap();
+  env.stake(1_000).await.unwrap();
+}
+
+#[tokio::test]
+async fn authority_transfer_takes_two_steps() {
+  let mut env = TestEnv::start(0, Vec::new()).await;
+  env.initialize_pool(10).await;
+  let (authority, new_authority) = (env.context.payer.insecure_clone(), Keypair::new());
+  let stranger = env.funder.insecure_clone();
+
+  env
+    .update_pool(
+      instruction::ProposeAuthority {
+        new_authority: new_authority.pubkey(),
+      },
+      &authority,
+    )
+    .await
+    .unwrap();
+  // Until accepted, the current authority stays in charge
+  env
+    .update_pool(instruction::Pause {}, &authority)
+    .await
+    .unwrap();
+
+  assert_program_error(
+    env.accept_authority(&stranger).await,
+    ErrorCode::Unauthorized,
+  );
+  env.accept_authority(&new_authority).await.unwrap();
+
+  let pool: StakePool = env.deserialize(env.pool).await;
+  assert_eq!(pool.authority, new_authority.pubkey());
+  assert_eq!(pool.pending_authority, None);
+  assert_program_error(
+    env.update_pool(instruction::Unpause {}, &authority).await,
+    ErrorCode::Unauthorized,
+  );
+  env
+    .update_pool(instruction::Unpause {}, &new_authority)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn reward_streams_pay_out_separately() {
+  let mut env = TestEnv::start(1_000, Vec::new()).await;
+  env.initialize_pool(10).await;
+  env.fund_reward_pool(0, 1_000).await.unwrap();
+  env.stake(1_000).await.unwrap();
+  let authority = env.context.payer.insecure_clone();
+
+  // A partner stream added later only pays from when it was added
+  env.now += 10;
+  env.add_reward_stream(1, 5, &authority).await.unwrap();
+  env.fund_reward_pool(1, 1_000).await.unwrap();
+  env.now += 10;
+  env.claim_reward(1).await.unwrap();
+  assert_eq!(env.token_balance(env.user_reward_accounts[0]).await, 0);
+  assert_eq!(env.token_balance(env.user_reward_accounts[1]).await, 50);
+
+  // Streams with nothing pending are skipped
+  env.claim_all_rewards().await.unwrap();
+  assert_eq!(env.token_balance(env.user_reward_accounts[0]).await, 200);
+  assert_eq!(env.token_balance(env.user_reward_accounts[1]).await, 50);
+  assert_program_error(env.claim_all_rewards().await, ErrorCode::NoRewardsToClaim);
+
+  let user = env.user.insecure_clone();
+  let reward_vault = env.reward_vault(0).await;
+  assert_program_error(
+    env
+      .process(
+        accounts::ClaimReward {
+          pool: env.pool,
+          user_stake: env.user_stake(),
+          user_reward_account: env.user_reward_accounts[0],
+          reward_vault,
+          reward_mint: env.reward_mints[0],
+          owner: user.pubkey(),
+          token_program: spl_token::ID,
+        },
+        instruction::ClaimReward { stream_index: 2 },
+        &[&user],
+      )
+      .await,
+    ErrorCode::InvalidRewardStream,
+  );
+
+  env.add_reward_stream(2, 1, &authority).await.unwrap();
+  env.add_reward_stream(3, 1, &authority).await.unwrap();
+  assert_program_error(
+    env.add_reward_stream(4, 1, &authority).await,
+    ErrorCode::TooManyRewardStreams,
+  );
+}
+
+#[tokio::test]
+async fn transfer_fees_credit_what_arrives() {
+  // Every transfer withholds 1%
+  let mut env = TestEnv::start_with_transfer_fee(1_000, 100).await;
+  env.initialize_pool(99).await;
+  env.fund_reward_pool(0, 1_000).await.unwrap();
+  env.stake(1_000).await.unwrap();
+
+  let pool: StakePool = env.deserialize(env.pool).await;
+  assert_eq!(pool.reward_streams[0].unemitted_rewards, 990);
+  assert_eq!(pool.total_staked, 990);
+  let user_stake: UserStake = env.deserialize(env.user_stake()).await;
+  assert_eq!(user_stake.staked_amount, 990);
+
+  // Emission runs out exactly at the funded 990, and the user receives that less the fee
+  assert_eq!(pool.reward_streams[0].reward_end_time, env.now + 10);
+  env.now += 10;
+  env.claim_all_rewards().await.unwrap();
+  assert_eq!(env.token_balance(env.user_reward_accounts[0]).await, 980);
+  let reward_vault = env.reward_vault(0).await;
+  assert_eq!(env.token_balance(reward_vault).await, 0);
+
+  env.unstake(990).await.unwrap();
+  assert_eq!(env.token_balance(env.user_staking_account).await, 980);
+  let staking_vault = env.staking_vault();
+  assert_eq!(env.token_balance(staking_vault).await, 0);
+}
+
+#[tokio::test]
+async fn rejects_mints_that_break_vault_accounting() {
+  let (permanent_delegate_mint, non_transferable_mint) =
+    (Pubkey::new_unique(), Pubkey::new_unique());
+  let mut env = TestEnv::start(
+    0,
+    vec![
+      (
+        permanent_delegate_mint,
+        token_2022_mint_account(&[ExtensionType::PermanentDelegate], |state| {
+          state
+            .init_extension::<PermanentDelegate>(true)
+            .unwrap()
+            .delegate
+            .0 = Pubkey::new_unique();
+        }),
+      ),
+      (
+        non_transferable_mint,
+        token_2022_mint_account(&[ExtensionType::NonTransferable], |state| {
+          state.init_extension::<NonTransferable>(true).unwrap();
+        }),
+      ),
+    ],
+  )
+  .await;
+  let authority = env.context.payer.pubkey();
+
+  // A permanent delegate could drain the staking vault
+  let pool = program_address(&[b"pool", permanent_delegate_mint.as_ref()]);
+  assert_program_error(
+    env
+      .process(
+        accounts::InitializePool {
+          pool,
+          authority,
+          staking_mint: permanent_delegate_mint,
+          reward_mint: env.reward_mints[0],
+          staking_vault: program_address(&[b"staking_vault", pool.as_ref()]),
+          reward_vault: program_address(&[
+            b"reward_vault",
+            pool.as_ref(),
+            env.reward_mints[0].as_ref(),
+          ]),
+          staking_token_program: spl_token_2022::ID,
+          reward_token_program: spl_token::ID,
+          system_program: system_program::ID,
+        },
+        instruction::InitializePool {
+          reward_rate: 10,
+          lock_period: 0,
+        },
+        &[],
+      )
+      .await,
+    ErrorCode::UnsupportedMintExtension,
+  );
+
+  // Non-transferable rewards could never be claimed out of the vault
+  env.initialize_pool(10).await;
+  let reward_vault = env.new_reward_vault(non_transferable_mint);
+  assert_program_error(
+    env
+      .process(
+        accounts::AddRewardStream {
+          pool: env.pool,
+          authority,
+          reward_mint: non_transferable_mint,
+          reward_vault,
+          token_program: spl_token_2022::ID,
+          system_program: system_program::ID,
+        },
+        instruction::AddRewardStream { reward_rate: 10 },
+        &[],
+      )
+      .await,
+    ErrorCode::UnsupportedMintExtension,
+  );
+}
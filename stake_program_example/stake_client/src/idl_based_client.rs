@@ -81,22 +81,27 @@ impl IdlStakeClient {
         Pubkey::find_program_address(&[b"staking_vault", pool.as_ref()], &self.program_id)
     }
 
-    pub fn derive_reward_vault_pda(&self, pool: &Pubkey) -> (Pubkey, u8) {
-        Pubkey::find_program_address(&[b"reward_vault", pool.as_ref()], &self.program_id)
+    pub fn derive_reward_vault_pda(&self, pool: &Pubkey, reward_mint: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[b"reward_vault", pool.as_ref(), reward_mint.as_ref()],
+            &self.program_id,
+        )
     }
 
     /// Initialize pool using raw instruction data
     pub async fn initialize_pool_raw(
         &self,
         staking_mint: Pubkey,
+        staking_token_program: Pubkey,
         reward_mint: Pubkey,
+        reward_token_program: Pubkey,
         reward_rate: u64,
         lock_period: i64,
     ) -> Result<String> {
         let discriminator = self.get_instruction_discriminator("initialize_pool")?;
         let (pool, _) = self.derive_pool_pda(&staking_mint);
         let (staking_vault, _) = self.derive_staking_vault_pda(&pool);
-        let (reward_vault, _) = self.derive_reward_vault_pda(&pool);
+        let (reward_vault, _) = self.derive_reward_vault_pda(&pool, &reward_mint);
 
         // Construct instruction data: discriminator + args
         let mut instruction_data = discriminator;
@@ -110,7 +115,8 @@ impl IdlStakeClient {
             AccountMeta::new_readonly(reward_mint, false),
             AccountMeta::new(staking_vault, false),
             AccountMeta::new(reward_vault, false),
-            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(staking_token_program, false),
+            AccountMeta::new_readonly(reward_token_program, false),
             AccountMeta::new_readonly(system_program::id(), false),
         ];
 
@@ -168,6 +174,8 @@ impl IdlStakeClient {
     pub async fn stake_raw(
         &self,
         pool: Pubkey,
+        staking_mint: Pubkey,
+        token_program: Pubkey,
         user: Pubkey,
         user_token_account: Pubkey,
         amount: u64,
@@ -184,8 +192,9 @@ impl IdlStakeClient {
             AccountMeta::new(user_stake, false),
             AccountMeta::new(user_token_account, false),
             AccountMeta::new(staking_vault, false),
+            AccountMeta::new_readonly(staking_mint, false),
             AccountMeta::new_readonly(user, true),
-            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(token_program, false),
         ];
 
         let instruction = Instruction {
@@ -210,6 +219,8 @@ impl IdlStakeClient {
     pub async fn unstake_raw(
         &self,
         pool: Pubkey,
+        staking_mint: Pubkey,
+        token_program: Pubkey,
         user: Pubkey,
         user_token_account: Pubkey,
         amount: u64,
@@ -226,8 +237,9 @@ impl IdlStakeClient {
             AccountMeta::new(user_stake, false),
             AccountMeta::new(user_token_account, false),
             AccountMeta::new(staking_vault, false),
+            AccountMeta::new_readonly(staking_mint, false),
             AccountMeta::new_readonly(user, true),
-            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(token_program, false),
         ];
 
         let instruction = Instruction {
@@ -253,21 +265,26 @@ impl IdlStakeClient {
         &self,
         pool: Pubkey,
         user: Pubkey,
+        stream_index: u8,
+        reward_vault: Pubkey,
+        reward_mint: Pubkey,
+        token_program: Pubkey,
         user_reward_account: Pubkey,
     ) -> Result<String> {
         let discriminator = self.get_instruction_discriminator("claim_reward")?;
         let (user_stake, _) = self.derive_user_stake_pda(&pool, &user);
-        let (reward_vault, _) = self.derive_reward_vault_pda(&pool);
 
-        let instruction_data = discriminator; // No additional args
+        let mut instruction_data = discriminator;
+        instruction_data.push(stream_index);
 
         let accounts = vec![
             AccountMeta::new(pool, false),
             AccountMeta::new(user_stake, false),
             AccountMeta::new(user_reward_account, false),
             AccountMeta::new(reward_vault, false),
+            AccountMeta::new_readonly(reward_mint, false),
             AccountMeta::new_readonly(user, true),
-            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(token_program, false),
         ];
 
         let instruction = Instruction {
@@ -292,22 +309,27 @@ impl IdlStakeClient {
     pub async fn fund_reward_pool_raw(
         &self,
         pool: Pubkey,
+        stream_index: u8,
+        reward_vault: Pubkey,
+        reward_mint: Pubkey,
+        token_program: Pubkey,
         funder: Pubkey,
         funder_token_account: Pubkey,
         amount: u64,
     ) -> Result<String> {
         let discriminator = self.get_instruction_discriminator("fund_reward_pool")?;
-        let (reward_vault, _) = self.derive_reward_vault_pda(&pool);
 
         let mut instruction_data = discriminator;
+        instruction_data.push(stream_index);
         instruction_data.extend_from_slice(&amount.to_le_bytes());
 
         let accounts = vec![
-            AccountMeta::new_readonly(pool, false),
+            AccountMeta::new(pool, false),
             AccountMeta::new(funder_token_account, false),
             AccountMeta::new(reward_vault, false),
+            AccountMeta::new_readonly(reward_mint, false),
             AccountMeta::new_readonly(funder, true),
-            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(token_program, false),
         ];
 
         let instruction = Instruction {
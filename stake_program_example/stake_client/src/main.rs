@@ -9,13 +9,27 @@ use anchor_client::{
   },
   Client, Cluster,
 };
+use anchor_lang::AccountDeserialize;
+use anchor_spl::token_2022::spl_token_2022;
 use anyhow::Result;
 use solana_system_interface::program as system_program;
-use spl_associated_token_account::get_associated_token_address;
+use spl_associated_token_account::{
+  get_associated_token_address, get_associated_token_address_with_program_id,
+};
+use stake_program_example::{RewardRunway, StakePool};
 
 // Program ID from the IDL
 const PROGRAM_ID: &str = "8Bv63d7LKuxYipEWycEHz263LKgq1qTwE6PaxsmE2Vmx";
 
+/// A reward stream's accounts as recorded in the pool, and the token program of its mint
+#[derive(Clone, Copy, Debug)]
+pub struct RewardStreamAccounts {
+  pub index: u8,
+  pub reward_mint: Pubkey,
+  pub reward_vault: Pubkey,
+  pub token_program: Pubkey,
+}
+
 /// Simplified client for demonstrating IDL-based interaction
 pub struct IdlStakeClient {
   pub client: Client<Rc<Keypair>>,
@@ -52,21 +66,26 @@ impl IdlStakeClient {
     Pubkey::find_program_address(&[b"staking_vault", pool.as_ref()], &self.program_id)
   }
 
-  pub fn derive_reward_vault_pda(&self, pool: &Pubkey) -> (Pubkey, u8) {
-    Pubkey::find_program_address(&[b"reward_vault", pool.as_ref()], &self.program_id)
+  pub fn derive_reward_vault_pda(&self, pool: &Pubkey, reward_mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+      &[b"reward_vault", pool.as_ref(), reward_mint.as_ref()],
+      &self.program_id,
+    )
   }
 
   /// Build instruction for initialize_pool using IDL discriminator
   pub fn build_initialize_pool_instruction(
     &self,
     staking_mint: Pubkey,
+    staking_token_program: Pubkey,
     reward_mint: Pubkey,
+    reward_token_program: Pubkey,
     reward_rate: u64,
     lock_period: i64,
   ) -> Result<Instruction> {
     let (pool, _) = self.derive_pool_pda(&staking_mint);
     let (staking_vault, _) = self.derive_staking_vault_pda(&pool);
-    let (reward_vault, _) = self.derive_reward_vault_pda(&pool);
+    let (reward_vault, _) = self.derive_reward_vault_pda(&pool, &reward_mint);
 
     // Instruction discriminator for initialize_pool (from IDL)
     let discriminator = [95, 180, 10, 172, 84, 174, 232, 40];
@@ -81,7 +100,8 @@ impl IdlStakeClient {
       AccountMeta::new_readonly(reward_mint, false),
       AccountMeta::new(staking_vault, false),
       AccountMeta::new(reward_vault, false),
-      AccountMeta::new_readonly(spl_token::id(), false),
+      AccountMeta::new_readonly(staking_token_program, false),
+      AccountMeta::new_readonly(reward_token_program, false),
       AccountMeta::new_readonly(system_program::id(), false),
     ];
 
@@ -121,11 +141,13 @@ impl IdlStakeClient {
   /// Build instruction for stake using IDL discriminator
   pub fn build_stake_instruction(
     &self,
-    pool: Pubkey,
+    staking_mint: Pubkey,
+    token_program: Pubkey,
     user: Pubkey,
     user_token_account: Pubkey,
     amount: u64,
   ) -> Result<Instruction> {
+    let (pool, _) = self.derive_pool_pda(&staking_mint);
     let (user_stake, _) = self.derive_user_stake_pda(&pool, &user);
     let (staking_vault, _) = self.derive_staking_vault_pda(&pool);
 
@@ -139,8 +161,9 @@ impl IdlStakeClient {
       AccountMeta::new(user_stake, false),
       AccountMeta::new(user_token_account, false),
       AccountMeta::new(staking_vault, false),
+      AccountMeta::new_readonly(staking_mint, false),
       AccountMeta::new_readonly(user, true),
-      AccountMeta::new_readonly(spl_token::id(), false),
+      AccountMeta::new_readonly(token_program, false),
     ];
 
     Ok(Instruction {
@@ -153,11 +176,13 @@ impl IdlStakeClient {
   /// Build instruction for unstake using IDL discriminator
   pub fn build_unstake_instruction(
     &self,
-    pool: Pubkey,
+    staking_mint: Pubkey,
+    token_program: Pubkey,
     user: Pubkey,
     user_token_account: Pubkey,
     amount: u64,
   ) -> Result<Instruction> {
+    let (pool, _) = self.derive_pool_pda(&staking_mint);
     let (user_stake, _) = self.derive_user_stake_pda(&pool, &user);
     let (staking_vault, _) = self.derive_staking_vault_pda(&pool);
 
@@ -171,8 +196,40 @@ impl IdlStakeClient {
       AccountMeta::new(user_stake, false),
       AccountMeta::new(user_token_account, false),
       AccountMeta::new(staking_vault, false),
+      AccountMeta::new_readonly(staking_mint, false),
       AccountMeta::new_readonly(user, true),
-      AccountMeta::new_readonly(spl_token::id(), false),
+      AccountMeta::new_readonly(token_program, false),
+    ];
+
+    Ok(Instruction {
+      program_id: self.program_id,
+      accounts,
+      data: instruction_data,
+    })
+  }
+
+  /// Build instruction for add_reward_stream using IDL discriminator
+  pub fn build_add_reward_stream_instruction(
+    &self,
+    pool: Pubkey,
+    reward_mint: Pubkey,
+    token_program: Pubkey,
+    reward_rate: u64,
+  ) -> Result<Instruction> {
+    let (reward_vault, _) = self.derive_reward_vault_pda(&pool, &reward_mint);
+
+    // Instruction discriminator for add_reward_stream (from IDL)
+    let discriminator = [126, 23, 174, 31, 202, 0, 137, 186];
+    let mut instruction_data = discriminator.to_vec();
+    instruction_data.extend_from_slice(&reward_rate.to_le_bytes());
+
+    let accounts = vec![
+      AccountMeta::new(pool, false),
+      AccountMeta::new(self.payer.pubkey(), true),
+      AccountMeta::new_readonly(reward_mint, false),
+      AccountMeta::new(reward_vault, false),
+      AccountMeta::new_readonly(token_program, false),
+      AccountMeta::new_readonly(system_program::id(), false),
     ];
 
     Ok(Instruction {
@@ -182,27 +239,248 @@ impl IdlStakeClient {
     })
   }
 
-  /// Build instruction for claim_reward using IDL discriminator
+  /// Build instruction for claim_reward using IDL discriminator. The stream's vault is the one
+  /// recorded in the pool, which for migrated pools predates the per-mint seeds.
   pub fn build_claim_reward_instruction(
     &self,
     pool: Pubkey,
     user: Pubkey,
+    stream: RewardStreamAccounts,
     user_reward_account: Pubkey,
   ) -> Result<Instruction> {
     let (user_stake, _) = self.derive_user_stake_pda(&pool, &user);
-    let (reward_vault, _) = self.derive_reward_vault_pda(&pool);
 
     // Instruction discriminator for claim_reward (from IDL)
     let discriminator = [149, 95, 181, 242, 94, 90, 158, 162];
-    let instruction_data = discriminator.to_vec(); // No additional args
+    let mut instruction_data = discriminator.to_vec();
+    instruction_data.push(stream.index);
 
     let accounts = vec![
       AccountMeta::new(pool, false),
       AccountMeta::new(user_stake, false),
       AccountMeta::new(user_reward_account, false),
-      AccountMeta::new(reward_vault, false),
+      AccountMeta::new(stream.reward_vault, false),
+      AccountMeta::new_readonly(stream.reward_mint, false),
+      AccountMeta::new_readonly(user, true),
+      AccountMeta::new_readonly(stream.token_program, false),
+    ];
+
+    Ok(Instruction {
+      program_id: self.program_id,
+      accounts,
+      data: instruction_data,
+    })
+  }
+
+  /// Build instruction for claim_all_rewards using IDL discriminator. `streams` holds every
+  /// stream with the user's token account for its mint, in the pool's stream order.
+  pub fn build_claim_all_rewards_instruction(
+    &self,
+    pool: Pubkey,
+    user: Pubkey,
+    streams: &[(RewardStreamAccounts, Pubkey)],
+  ) -> Result<Instruction> {
+    let (user_stake, _) = self.derive_user_stake_pda(&pool, &user);
+
+    // Instruction discriminator for claim_all_rewards (from IDL)
+    let discriminator = [132, 203, 246, 173, 206, 240, 85, 120];
+    let instruction_data = discriminator.to_vec(); // No additional args
+
+    let mut accounts = vec![
+      AccountMeta::new(pool, false),
+      AccountMeta::new(user_stake, false),
       AccountMeta::new_readonly(user, true),
       AccountMeta::new_readonly(spl_token::id(), false),
+      AccountMeta::new_readonly(spl_token_2022::id(), false),
+    ];
+    for &(stream, user_reward_account) in streams {
+      accounts.push(AccountMeta::new_readonly(stream.reward_mint, false));
+      accounts.push(AccountMeta::new(stream.reward_vault, false));
+      accounts.push(AccountMeta::new(user_reward_account, false));
+    }
+
+    Ok(Instruction {
+      program_id: self.program_id,
+      accounts,
+      data: instruction_data,
+    })
+  }
+
+  /// Build instruction for fund_reward_pool using IDL discriminator
+  pub fn build_fund_reward_pool_instruction(
+    &self,
+    pool: Pubkey,
+    stream: RewardStreamAccounts,
+    funder: Pubkey,
+    funder_token_account: Pubkey,
+    amount: u64,
+  ) -> Result<Instruction> {
+    // Instruction discriminator for fund_reward_pool (from IDL)
+    let discriminator = [85, 49, 108, 245, 204, 70, 243, 3];
+    let mut instruction_data = discriminator.to_vec();
+    instruction_data.push(stream.index);
+    instruction_data.extend_from_slice(&amount.to_le_bytes());
+
+    let accounts = vec![
+      AccountMeta::new(pool, false),
+      AccountMeta::new(funder_token_account, false),
+      AccountMeta::new(stream.reward_vault, false),
+      AccountMeta::new_readonly(stream.reward_mint, false),
+      AccountMeta::new_readonly(funder, true),
+      AccountMeta::new_readonly(stream.token_program, false),
+    ];
+
+    Ok(Instruction {
+      program_id: self.program_id,
+      accounts,
+      data: instruction_data,
+    })
+  }
+
+  /// Fetch how many reward tokens each of the pool's streams has left to emit, and for how long
+  pub fn fetch_reward_runway(&self, staking_mint: &Pubkey) -> Result<Vec<RewardRunway>> {
+    let (pool, _) = self.derive_pool_pda(staking_mint);
+    let rpc = self.client.program(self.program_id)?.rpc();
+
+    let data = rpc.get_account_data(&pool)?;
+    let pool = StakePool::try_deserialize(&mut data.as_slice())?;
+    let now = rpc.get_block_time(rpc.get_slot()?)?;
+
+    Ok(pool.reward_runway(now)?)
+  }
+
+  /// Build instruction for set_reward_rate using IDL discriminator
+  pub fn build_set_reward_rate_instruction(
+    &self,
+    pool: Pubkey,
+    stream_index: u8,
+    reward_rate: u64,
+  ) -> Result<Instruction> {
+    // Instruction discriminator for set_reward_rate (from IDL)
+    let discriminator = [253, 201, 190, 20, 48, 38, 120, 34];
+    let mut args = vec![stream_index];
+    args.extend_from_slice(&reward_rate.to_le_bytes());
+    Ok(self.build_update_pool_instruction(pool, discriminator, &args))
+  }
+
+  /// Build instruction for set_lock_period using IDL discriminator
+  pub fn build_set_lock_period_instruction(
+    &self,
+    pool: Pubkey,
+    lock_period: i64,
+  ) -> Result<Instruction> {
+    // Instruction discriminator for set_lock_period (from IDL)
+    let discriminator = [29, 201, 151, 32, 97, 69, 176, 195];
+    Ok(self.build_update_pool_instruction(pool, discriminator, &lock_period.to_le_bytes()))
+  }
+
+  /// Build instruction for pause using IDL discriminator
+  pub fn build_pause_instruction(&self, pool: Pubkey) -> Result<Instruction> {
+    // Instruction discriminator for pause (from IDL)
+    let discriminator = [211, 22, 221, 251, 74, 121, 193, 47];
+    Ok(self.build_update_pool_instruction(pool, discriminator, &[]))
+  }
+
+  /// Build instruction for unpause using IDL discriminator
+  pub fn build_unpause_instruction(&self, pool: Pubkey) -> Result<Instruction> {
+    // Instruction discriminator for unpause (from IDL)
+    let discriminator = [169, 144, 4, 38, 10, 141, 188, 255];
+    Ok(self.build_update_pool_instruction(pool, discriminator, &[]))
+  }
+
+  /// Build instruction for propose_authority using IDL discriminator
+  pub fn build_propose_authority_instruction(
+    &self,
+    pool: Pubkey,
+    new_authority: Pubkey,
+  ) -> Result<Instruction> {
+    // Instruction discriminator for propose_authority (from IDL)
+    let discriminator = [20, 148, 236, 198, 76, 119, 99, 142];
+    Ok(self.build_update_pool_instruction(pool, discriminator, new_authority.as_ref()))
+  }
+
+  /// Build instruction for accept_authority using IDL discriminator
+  pub fn build_accept_authority_instruction(
+    &self,
+    pool: Pubkey,
+    new_authority: Pubkey,
+  ) -> Result<Instruction> {
+    // Instruction discriminator for accept_authority (from IDL)
+    let discriminator = [107, 86, 198, 91, 33, 12, 107, 160];
+    let instruction_data = discriminator.to_vec(); // No additional args
+
+    let accounts = vec![
+      AccountMeta::new(pool, false),
+      AccountMeta::new_readonly(new_authority, true),
+    ];
+
+    Ok(Instruction {
+      program_id: self.program_id,
+      accounts,
+      data: instruction_data,
+    })
+  }
+
+  /// The authority-gated instructions share their accounts; the payer signs as the authority
+  fn build_update_pool_instruction(
+    &self,
+    pool: Pubkey,
+    discriminator: [u8; 8],
+    args: &[u8],
+  ) -> Instruction {
+    let mut instruction_data = discriminator.to_vec();
+    instruction_data.extend_from_slice(args);
+
+    let accounts = vec![
+      AccountMeta::new(pool, false),
+      AccountMeta::new_readonly(self.payer.pubkey(), true),
+    ];
+
+    Instruction {
+      program_id: self.program_id,
+      accounts,
+      data: instruction_data,
+    }
+  }
+
+  /// Build instruction for migrate_pool using IDL discriminator
+  pub fn build_migrate_pool_instruction(&self, staking_mint: Pubkey) -> Result<Instruction> {
+    let (pool, _) = self.derive_pool_pda(&staking_mint);
+
+    // Instruction discriminator for migrate_pool (from IDL)
+    let discriminator = [55, 170, 171, 123, 210, 69, 39, 172];
+    let instruction_data = discriminator.to_vec(); // No additional args
+
+    let accounts = vec![
+      AccountMeta::new(pool, false),
+      AccountMeta::new(self.payer.pubkey(), true),
+      AccountMeta::new_readonly(system_program::id(), false),
+    ];
+
+    Ok(Instruction {
+      program_id: self.program_id,
+      accounts,
+      data: instruction_data,
+    })
+  }
+
+  /// Build instruction for migrate_user_stake using IDL discriminator
+  pub fn build_migrate_user_stake_instruction(
+    &self,
+    pool: Pubkey,
+    user: Pubkey,
+  ) -> Result<Instruction> {
+    let (user_stake, _) = self.derive_user_stake_pda(&pool, &user);
+
+    // Instruction discriminator for migrate_user_stake (from IDL)
+    let discriminator = [29, 38, 213, 47, 115, 141, 135, 106];
+    let instruction_data = discriminator.to_vec(); // No additional args
+
+    let accounts = vec![
+      AccountMeta::new(user_stake, false),
+      AccountMeta::new_readonly(pool, false),
+      AccountMeta::new(self.payer.pubkey(), true),
+      AccountMeta::new_readonly(system_program::id(), false),
     ];
 
     Ok(Instruction {
@@ -229,11 +507,21 @@ impl IdlStakeClient {
 
     println!("\nInstructions:");
     println!("  - initialize_pool: [95, 180, 10, 172, 84, 174, 232, 40]");
+    println!("  - add_reward_stream: [126, 23, 174, 31, 202, 0, 137, 186]");
     println!("  - initialize_user_stake: [248, 96, 76, 185, 77, 56, 18, 0]");
     println!("  - stake: [206, 176, 202, 18, 200, 209, 179, 108]");
     println!("  - unstake: [90, 95, 107, 42, 205, 124, 50, 225]");
     println!("  - claim_reward: [149, 95, 181, 242, 94, 90, 158, 162]");
+    println!("  - claim_all_rewards: [132, 203, 246, 173, 206, 240, 85, 120]");
     println!("  - fund_reward_pool: [85, 49, 108, 245, 204, 70, 243, 3]");
+    println!("  - set_reward_rate: [253, 201, 190, 20, 48, 38, 120, 34]");
+    println!("  - set_lock_period: [29, 201, 151, 32, 97, 69, 176, 195]");
+    println!("  - pause: [211, 22, 221, 251, 74, 121, 193, 47]");
+    println!("  - unpause: [169, 144, 4, 38, 10, 141, 188, 255]");
+    println!("  - propose_authority: [20, 148, 236, 198, 76, 119, 99, 142]");
+    println!("  - accept_authority: [107, 86, 198, 91, 33, 12, 107, 160]");
+    println!("  - migrate_pool: [55, 170, 171, 123, 210, 69, 39, 172]");
+    println!("  - migrate_user_stake: [29, 38, 213, 47, 115, 141, 135, 106]");
 
     println!("\nAccount Types:");
     println!("  - StakePool: [121, 34, 206, 21, 79, 127, 255, 28]");
@@ -243,6 +531,7 @@ impl IdlStakeClient {
     println!("  - StakeEvent: [226, 134, 188, 173, 19, 33, 75, 175]");
     println!("  - UnstakeEvent: [162, 104, 137, 228, 81, 3, 79, 197]");
     println!("  - ClaimRewardEvent: [207, 16, 14, 170, 176, 71, 40, 53]");
+    println!("  - FundRewardPoolEvent: [156, 104, 28, 170, 129, 171, 22, 172]");
 
     println!("\nErrors:");
     println!("  - 6000: Overflow (Arithmetic overflow)");
@@ -251,6 +540,23 @@ impl IdlStakeClient {
     println!("  - 6003: InsufficientBalance (Insufficient balance)");
     println!("  - 6004: StillLocked (Tokens are still locked)");
     println!("  - 6005: NoRewardsToClaim (No rewards to claim)");
+    println!("  - 6006: AlreadyMigrated (Account is already on the current layout)");
+    println!("  - 6007: Unauthorized (Signer is not authorized to manage this pool)");
+    println!("  - 6008: PoolPaused (Pool is paused)");
+    println!(
+      "  - 6009: InvalidLockPeriod (Lock period must be between zero and MAX_LOCK_PERIOD seconds)"
+    );
+    println!(
+      "  - 6010: InvalidRewardStream (Reward stream does not exist or does not match the accounts \
+       given)"
+    );
+    println!(
+      "  - 6011: TooManyRewardStreams (Pool already has the maximum number of reward streams)"
+    );
+    println!("  - 6012: DuplicateRewardMint (Pool already has a reward stream for this mint)");
+    println!(
+      "  - 6013: UnsupportedMintExtension (Mint has an extension the pool does not support)"
+    );
   }
 }
 
@@ -270,6 +576,7 @@ async fn main() -> Result<()> {
   // Example token mints (you would use real mint addresses)
   let staking_mint = Pubkey::from_str("So11111111111111111111111111111111111111112")?; // SOL mint
   let reward_mint = Pubkey::from_str("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v")?; // USDC mint
+  let partner_reward_mint = Pubkey::from_str("2b1kV6DkPAnxd5ixfnxCpjxmKwqjjaYmCZfHsFu24GXo")?; // PYUSD mint (Token-2022)
 
   println!("🚀 Initializing IDL-based stake client...");
   let client = IdlStakeClient::new(Cluster::Localnet, &keypair_path)?;
@@ -283,7 +590,20 @@ async fn main() -> Result<()> {
   let user = client.payer.pubkey();
   let (user_stake, user_stake_bump) = client.derive_user_stake_pda(&pool, &user);
   let (staking_vault, staking_vault_bump) = client.derive_staking_vault_pda(&pool);
-  let (reward_vault, reward_vault_bump) = client.derive_reward_vault_pda(&pool);
+  let (reward_vault, reward_vault_bump) = client.derive_reward_vault_pda(&pool, &reward_mint);
+  let (partner_reward_vault, _) = client.derive_reward_vault_pda(&pool, &partner_reward_mint);
+  let reward_stream = RewardStreamAccounts {
+    index: 0,
+    reward_mint,
+    reward_vault,
+    token_program: spl_token::id(),
+  };
+  let partner_reward_stream = RewardStreamAccounts {
+    index: 1,
+    reward_mint: partner_reward_mint,
+    reward_vault: partner_reward_vault,
+    token_program: spl_token_2022::id(),
+  };
 
   println!("\n🔍 Derived Addresses:");
   println!("  Program ID: {}", client.program_id);
@@ -304,7 +624,14 @@ async fn main() -> Result<()> {
   println!("\n🛠️ Building Instructions:");
 
   // Build initialize pool instruction
-  match client.build_initialize_pool_instruction(staking_mint, reward_mint, 100, 86400) {
+  match client.build_initialize_pool_instruction(
+    staking_mint,
+    spl_token::id(),
+    reward_mint,
+    spl_token::id(),
+    100,
+    86400,
+  ) {
     Ok(instruction) => {
       println!("✅ Initialize Pool instruction built successfully");
       println!("   - Program ID: {}", instruction.program_id);
@@ -331,6 +658,11 @@ async fn main() -> Result<()> {
   // Create token account addresses for demo
   let user_staking_token_account = get_associated_token_address(&user, &staking_mint);
   let user_reward_token_account = get_associated_token_address(&user, &reward_mint);
+  let user_partner_reward_token_account = get_associated_token_address_with_program_id(
+    &user,
+    &partner_reward_mint,
+    &spl_token_2022::id(),
+  );
 
   println!(
     "   - User Staking Token Account: {}",
@@ -342,7 +674,13 @@ async fn main() -> Result<()> {
   );
 
   // Build stake instruction
-  match client.build_stake_instruction(pool, user, user_staking_token_account, 1000) {
+  match client.build_stake_instruction(
+    staking_mint,
+    spl_token::id(),
+    user,
+    user_staking_token_account,
+    1000,
+  ) {
     Ok(instruction) => {
       println!("✅ Stake instruction built successfully");
       println!("   - Program ID: {}", instruction.program_id);
@@ -353,7 +691,13 @@ async fn main() -> Result<()> {
   }
 
   // Build unstake instruction
-  match client.build_unstake_instruction(pool, user, user_staking_token_account, 500) {
+  match client.build_unstake_instruction(
+    staking_mint,
+    spl_token::id(),
+    user,
+    user_staking_token_account,
+    500,
+  ) {
     Ok(instruction) => {
       println!("✅ Unstake instruction built successfully");
       println!("   - Program ID: {}", instruction.program_id);
@@ -363,8 +707,24 @@ async fn main() -> Result<()> {
     Err(e) => println!("❌ Failed to build unstake instruction: {}", e),
   }
 
-  // Build claim reward instruction
-  match client.build_claim_reward_instruction(pool, user, user_reward_token_account) {
+  // Build add reward stream instruction for a second reward token
+  match client.build_add_reward_stream_instruction(
+    pool,
+    partner_reward_mint,
+    spl_token_2022::id(),
+    50,
+  ) {
+    Ok(instruction) => {
+      println!("✅ Add Reward Stream instruction built successfully");
+      println!("   - Accounts: {} accounts", instruction.accounts.len());
+      println!("   - Data: {} bytes", instruction.data.len());
+    }
+    Err(e) => println!("❌ Failed to build add reward stream instruction: {}", e),
+  }
+
+  // Build claim reward instruction for the first stream
+  match client.build_claim_reward_instruction(pool, user, reward_stream, user_reward_token_account)
+  {
     Ok(instruction) => {
       println!("✅ Claim Reward instruction built successfully");
       println!("   - Program ID: {}", instruction.program_id);
@@ -374,6 +734,102 @@ async fn main() -> Result<()> {
     Err(e) => println!("❌ Failed to build claim reward instruction: {}", e),
   }
 
+  // Build claim all rewards instruction across both streams
+  match client.build_claim_all_rewards_instruction(
+    pool,
+    user,
+    &[
+      (reward_stream, user_reward_token_account),
+      (partner_reward_stream, user_partner_reward_token_account),
+    ],
+  ) {
+    Ok(instruction) => {
+      println!("✅ Claim All Rewards instruction built successfully");
+      println!("   - Accounts: {} accounts", instruction.accounts.len());
+      println!("   - Data: {} bytes", instruction.data.len());
+    }
+    Err(e) => println!("❌ Failed to build claim all rewards instruction: {}", e),
+  }
+
+  // Build fund reward pool instruction
+  match client.build_fund_reward_pool_instruction(
+    pool,
+    reward_stream,
+    user,
+    user_reward_token_account,
+    10_000,
+  ) {
+    Ok(instruction) => {
+      println!("✅ Fund Reward Pool instruction built successfully");
+      println!("   - Program ID: {}", instruction.program_id);
+      println!("   - Accounts: {} accounts", instruction.accounts.len());
+      println!("   - Data: {} bytes", instruction.data.len());
+    }
+    Err(e) => println!("❌ Failed to build fund reward pool instruction: {}", e),
+  }
+
+  // Build authority-gated pool management instructions
+  let management_instructions = [
+    (
+      "Set Reward Rate",
+      client.build_set_reward_rate_instruction(pool, 0, 200),
+    ),
+    (
+      "Set Lock Period",
+      client.build_set_lock_period_instruction(pool, 172800),
+    ),
+    ("Pause", client.build_pause_instruction(pool)),
+    ("Unpause", client.build_unpause_instruction(pool)),
+    (
+      "Propose Authority",
+      client.build_propose_authority_instruction(pool, user),
+    ),
+    (
+      "Accept Authority",
+      client.build_accept_authority_instruction(pool, user),
+    ),
+  ];
+  for (name, instruction) in management_instructions {
+    match instruction {
+      Ok(instruction) => {
+        println!("✅ {} instruction built successfully", name);
+        println!("   - Accounts: {} accounts", instruction.accounts.len());
+        println!("   - Data: {} bytes", instruction.data.len());
+      }
+      Err(e) => println!("❌ Failed to build {} instruction: {}", name, e),
+    }
+  }
+
+  // Build migration instructions for accounts created before the u128 reward accumulator
+  match client.build_migrate_pool_instruction(staking_mint) {
+    Ok(instruction) => {
+      println!("✅ Migrate Pool instruction built successfully");
+      println!("   - Accounts: {} accounts", instruction.accounts.len());
+    }
+    Err(e) => println!("❌ Failed to build migrate pool instruction: {}", e),
+  }
+  match client.build_migrate_user_stake_instruction(pool, user) {
+    Ok(instruction) => {
+      println!("✅ Migrate User Stake instruction built successfully");
+      println!("   - Accounts: {} accounts", instruction.accounts.len());
+    }
+    Err(e) => println!("❌ Failed to build migrate user stake instruction: {}", e),
+  }
+
+  // Query how long the pool keeps emitting rewards (needs a running validator)
+  println!("\n⏳ Reward Runway:");
+  match client.fetch_reward_runway(&staking_mint) {
+    Ok(runways) => {
+      for runway in runways {
+        println!(
+          "  {}: {} reward tokens left to emit, running out in {} seconds",
+          runway.reward_mint, runway.remaining_rewards, runway.seconds
+        );
+      }
+    }
+    Err(e) => println!("❌ Failed to fetch reward runway: {}", e),
+  }
+
   println!("\n💡 Key Concepts Demonstrated:");
   println!("  ✓ IDL-based instruction discriminators");
   println!("  ✓ PDA derivation using program seeds");
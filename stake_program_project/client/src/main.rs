@@ -1,18 +1,42 @@
-use std::{rc::Rc, str::FromStr};
+use std::{collections::BTreeMap, ops::Deref, rc::Rc, str::FromStr, sync::Arc};
 
 use anchor_client::{
+  anchor_lang::{AccountDeserialize, Discriminator},
+  solana_account_decoder::UiAccountEncoding,
+  solana_client::{
+    nonce_utils,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+  },
   solana_sdk::{
+    account::from_account,
+    clock::Clock,
     commitment_config::CommitmentConfig,
+    hash::Hash,
+    message::Message,
     pubkey::Pubkey,
-    signature::{Keypair, Signer},
+    signature::{read_keypair_file, Keypair, Signature, Signer},
+    signer::null_signer::NullSigner,
+    sysvar::SysvarId,
+    transaction::Transaction,
   },
-  Client, Cluster, Program,
+  Client, Cluster, DynSigner, Program, RequestBuilder,
 };
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use clap::{Parser, Subcommand};
-use solana_system_interface::program as system_program;
+use serde::Serialize;
+use solana_stake_interface::stake_history::StakeHistory;
+use solana_system_interface::{instruction as system_instruction, program as system_program};
 use stake_program_project::{
-  accounts, instruction, Authorized, Lockup, LockupArgs, StakeAuthorize,
+  accounts, instruction,
+  lamports::{staked_lamports, withdrawable_lamports},
+  program_data_address,
+  rewards::PointValue,
+  split::SPLIT_SEED,
+  AuthorizeWithSeedArgs, Authorized, Lockup, LockupArgs, LockupCheckedArgs, StakeAccount,
+  StakeAuthorize, StakeConfig, StakeConfigArgs, UpdateConfigArgs, DEFAULT_MINIMUM_DELEGATION,
+  DEFAULT_SLASH_PENALTY, NEW_RATE_ACTIVATION_EPOCH, NEW_WARMUP_COOLDOWN_RATE, REWARD_RESERVE_SEED,
+  STAKE_CONFIG_SEED,
 };
 
 #[derive(Parser)]
@@ -25,8 +49,30 @@ struct Cli {
   #[arg(short, long, default_value = "http://localhost:8899")]
   rpc_url: String,
 
+  /// Fee payer and authority keypair; with --sign-only a pubkey may be given instead
   #[arg(short, long)]
   keypair_path: Option<String>,
+
+  #[command(flatten)]
+  tx_options: TxOptions,
+}
+
+#[derive(clap::Args)]
+struct TxOptions {
+  /// Sign with the keypairs at hand and print the transaction instead of sending it. Needs
+  /// --blockhash, so nothing is fetched from the cluster
+  #[arg(long, global = true, requires = "blockhash")]
+  sign_only: bool,
+  /// Blockhash to sign with instead of fetching one from the cluster; with --nonce, the blockhash
+  /// stored in the nonce account
+  #[arg(long, global = true)]
+  blockhash: Option<String>,
+  /// Durable nonce account whose stored blockhash the transaction uses
+  #[arg(long, global = true)]
+  nonce: Option<String>,
+  /// Keypair authorized to advance the nonce; defaults to the fee payer
+  #[arg(long, global = true, requires = "nonce")]
+  nonce_authority: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -42,6 +88,15 @@ enum Commands {
     lockup_epoch: u64,
     #[arg(long)]
     custodian: Option<String>,
+    /// Require the withdrawer to sign, so a mistyped key is rejected
+    #[arg(
+      long,
+      requires = "withdrawer_keypair",
+      conflicts_with_all = ["lockup_timestamp", "lockup_epoch", "custodian"]
+    )]
+    checked: bool,
+    #[arg(long, requires = "checked")]
+    withdrawer_keypair: Option<String>,
   },
   Authorize {
     #[arg(long)]
@@ -50,6 +105,30 @@ enum Commands {
     new_authority: String,
     #[arg(long, value_enum)]
     stake_authorize: AuthorizeType,
+    /// Require the new authority to sign, so a mistyped key is rejected
+    #[arg(long, requires = "new_authority_keypair")]
+    checked: bool,
+    #[arg(long, requires = "checked")]
+    new_authority_keypair: Option<String>,
+    /// Lockup custodian keypair, needed to change the withdrawer while the lockup is in force
+    #[arg(long)]
+    custodian_keypair: Option<String>,
+  },
+  /// Authorize on behalf of an authority derived from the payer, a seed and an owner program
+  AuthorizeWithSeed {
+    #[arg(long)]
+    stake_account: String,
+    #[arg(long)]
+    new_authority: String,
+    #[arg(long, value_enum)]
+    stake_authorize: AuthorizeType,
+    #[arg(long)]
+    authority_seed: String,
+    #[arg(long)]
+    authority_owner: String,
+    /// Lockup custodian keypair, needed to change the withdrawer while the lockup is in force
+    #[arg(long)]
+    custodian_keypair: Option<String>,
   },
   Delegate {
     #[arg(long)]
@@ -62,6 +141,12 @@ enum Commands {
     source_account: String,
     #[arg(long)]
     lamports: u64,
+    /// Split into the program address derived from the source account and this index
+    #[arg(long, conflicts_with = "seed")]
+    index: Option<u64>,
+    /// Split into the address derived from the payer and this seed
+    #[arg(long)]
+    seed: Option<String>,
   },
   Withdraw {
     #[arg(long)]
@@ -70,11 +155,40 @@ enum Commands {
     to: String,
     #[arg(long)]
     lamports: u64,
+    /// Lockup custodian keypair, needed to withdraw while the lockup is in force
+    #[arg(long)]
+    custodian_keypair: Option<String>,
+  },
+  /// Withdraw the whole balance of a fully inactive stake account, closing it
+  Close {
+    #[arg(long)]
+    stake_account: String,
+    /// Recipient of the balance, defaults to the payer
+    #[arg(long)]
+    to: Option<String>,
+    /// The account's balance; fetched from the cluster unless signing with --sign-only
+    #[arg(long)]
+    lamports: Option<u64>,
+    /// Lockup custodian keypair, needed to close while the lockup is in force
+    #[arg(long)]
+    custodian_keypair: Option<String>,
   },
   Deactivate {
     #[arg(long)]
     stake_account: String,
   },
+  /// Deactivate stake delegated to a validator that stopped voting, proven against a reference
+  /// validator that kept voting
+  DeactivateDelinquent {
+    #[arg(long)]
+    stake_account: String,
+    /// Vote account the stake is delegated to; fetched from the cluster unless signing with
+    /// --sign-only
+    #[arg(long)]
+    vote_account: Option<String>,
+    #[arg(long)]
+    reference_vote_account: String,
+  },
   SetLockup {
     #[arg(long)]
     stake_account: String,
@@ -84,6 +198,11 @@ enum Commands {
     epoch: Option<u64>,
     #[arg(long)]
     custodian: Option<String>,
+    /// Require a new custodian to sign, so a mistyped key is rejected
+    #[arg(long)]
+    checked: bool,
+    #[arg(long, requires = "checked")]
+    custodian_keypair: Option<String>,
   },
   Merge {
     #[arg(long)]
@@ -91,11 +210,87 @@ enum Commands {
     #[arg(long)]
     dest_account: String,
   },
+  MoveStake {
+    #[arg(long)]
+    source_account: String,
+    #[arg(long)]
+    dest_account: String,
+    #[arg(long)]
+    lamports: u64,
+  },
+  MoveLamports {
+    #[arg(long)]
+    source_account: String,
+    #[arg(long)]
+    dest_account: String,
+    #[arg(long)]
+    lamports: u64,
+  },
   GetMinimumDelegation,
   Info {
+    #[arg(long, required_unless_present = "all_by_authority")]
+    stake_account: Option<String>,
+    /// List every stake account this key is the staker or withdrawer of
+    #[arg(long, conflicts_with = "stake_account")]
+    all_by_authority: Option<String>,
+    #[arg(long, value_enum, default_value_t = OutputFormat::Display)]
+    output: OutputFormat,
+  },
+  /// Create the stake config, with the payer as its admin (program upgrade authority only)
+  InitConfig {
+    #[arg(long, default_value_t = DEFAULT_MINIMUM_DELEGATION)]
+    minimum_delegation: u64,
+    #[arg(long, default_value_t = NEW_WARMUP_COOLDOWN_RATE)]
+    warmup_cooldown_rate: f64,
+    #[arg(long, default_value_t = DEFAULT_SLASH_PENALTY)]
+    slash_penalty: u8,
+  },
+  /// Change stake config parameters (config admin only)
+  UpdateConfig {
+    #[arg(long)]
+    admin: Option<String>,
+    #[arg(long)]
+    minimum_delegation: Option<u64>,
+    #[arg(long)]
+    warmup_cooldown_rate: Option<f64>,
+    #[arg(long)]
+    slash_penalty: Option<u8>,
+  },
+  InitRewardReserve {
+    #[arg(long)]
+    rewards: u64,
+    #[arg(long)]
+    points: u128,
+  },
+  SetPointValue {
+    #[arg(long)]
+    rewards: u64,
+    #[arg(long)]
+    points: u128,
+  },
+  FundRewardReserve {
+    #[arg(long)]
+    lamports: u64,
+  },
+  RedeemRewards {
     #[arg(long)]
     stake_account: String,
+    /// Vote account the stake is delegated to; fetched from the cluster unless signing with
+    /// --sign-only
+    #[arg(long)]
+    vote_account: Option<String>,
   },
+  /// Combine the signatures of --sign-only transactions and broadcast the result
+  Submit {
+    #[arg(long, required = true, num_args = 1..)]
+    transaction: Vec<String>,
+  },
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq)]
+enum OutputFormat {
+  Display,
+  Json,
 }
 
 #[derive(clap::ValueEnum, Clone)]
@@ -117,12 +312,16 @@ impl From<AuthorizeType> for StakeAuthorize {
 async fn main() -> Result<()> {
   let cli = Cli::parse();
 
-  let payer = if let Some(path) = cli.keypair_path {
-    let keypair_bytes = std::fs::read(&path)?;
-    Keypair::try_from(&keypair_bytes[..])?
+  let sign_only = cli.tx_options.sign_only;
+  let payer: Arc<dyn Signer> = if let Some(path) = cli.keypair_path {
+    signer_from_source(&path, sign_only)?.into()
   } else {
     println!("Warning: Using new random keypair. Specify --keypair-path for a real keypair.");
-    Keypair::new()
+    Arc::new(Keypair::new())
+  };
+  let nonce_authority = match cli.tx_options.nonce_authority {
+    Some(path) => signer_from_source(&path, sign_only)?.into(),
+    None => payer.clone(),
   };
 
   let cluster_url = if cli.rpc_url.starts_with("http") {
@@ -136,18 +335,47 @@ async fn main() -> Result<()> {
     }
   };
 
-  let client = Client::new_with_options(cluster_url, Rc::new(payer), CommitmentConfig::confirmed());
+  let client = Client::new_with_options(
+    cluster_url,
+    Rc::new(DynSigner(payer.clone())),
+    CommitmentConfig::confirmed(),
+  );
 
   let program_id = stake_program_project::ID;
-  let program = client.program(program_id)?;
+  let program = StakeClient {
+    program: client.program(program_id)?,
+    payer,
+    sign_only,
+    blockhash: cli
+      .tx_options
+      .blockhash
+      .map(|blockhash| Hash::from_str(&blockhash))
+      .transpose()?,
+    nonce: cli
+      .tx_options
+      .nonce
+      .map(|nonce| Pubkey::from_str(&nonce))
+      .transpose()?,
+    nonce_authority,
+  };
 
   match cli.command {
+    Commands::Initialize {
+      staker,
+      withdrawer,
+      checked: true,
+      withdrawer_keypair: Some(withdrawer_keypair),
+      ..
+    } => {
+      initialize_stake_account_checked(&program, staker, withdrawer, withdrawer_keypair).await?;
+    }
     Commands::Initialize {
       staker,
       withdrawer,
       lockup_timestamp,
       lockup_epoch,
       custodian,
+      ..
     } => {
       initialize_stake_account(
         &program,
@@ -163,12 +391,52 @@ async fn main() -> Result<()> {
       stake_account,
       new_authority,
       stake_authorize,
+      checked: true,
+      new_authority_keypair: Some(new_authority_keypair),
+      custodian_keypair,
+    } => {
+      authorize_stake_account_checked(
+        &program,
+        stake_account,
+        new_authority,
+        new_authority_keypair,
+        stake_authorize.into(),
+        custodian_keypair,
+      )
+      .await?;
+    }
+    Commands::Authorize {
+      stake_account,
+      new_authority,
+      stake_authorize,
+      custodian_keypair,
+      ..
     } => {
       authorize_stake_account(
         &program,
         stake_account,
         new_authority,
         stake_authorize.into(),
+        custodian_keypair,
+      )
+      .await?;
+    }
+    Commands::AuthorizeWithSeed {
+      stake_account,
+      new_authority,
+      stake_authorize,
+      authority_seed,
+      authority_owner,
+      custodian_keypair,
+    } => {
+      authorize_with_seed(
+        &program,
+        stake_account,
+        new_authority,
+        stake_authorize.into(),
+        authority_seed,
+        authority_owner,
+        custodian_keypair,
       )
       .await?;
     }
@@ -181,24 +449,67 @@ async fn main() -> Result<()> {
     Commands::Split {
       source_account,
       lamports,
+      index,
+      seed,
     } => {
-      split_stake(&program, source_account, lamports).await?;
+      split_stake(&program, source_account, lamports, index, seed).await?;
     }
     Commands::Withdraw {
       stake_account,
       to,
       lamports,
+      custodian_keypair,
+    } => {
+      withdraw_stake(&program, stake_account, to, lamports, custodian_keypair).await?;
+    }
+    Commands::Close {
+      stake_account,
+      to,
+      lamports,
+      custodian_keypair,
     } => {
-      withdraw_stake(&program, stake_account, to, lamports).await?;
+      close_stake_account(&program, stake_account, to, lamports, custodian_keypair).await?;
     }
     Commands::Deactivate { stake_account } => {
       deactivate_stake(&program, stake_account).await?;
     }
+    Commands::DeactivateDelinquent {
+      stake_account,
+      vote_account,
+      reference_vote_account,
+    } => {
+      deactivate_delinquent(
+        &program,
+        stake_account,
+        vote_account,
+        reference_vote_account,
+      )
+      .await?;
+    }
+    Commands::SetLockup {
+      stake_account,
+      unix_timestamp,
+      epoch,
+      custodian,
+      checked: true,
+      custodian_keypair,
+    } => {
+      set_lockup_checked(
+        &program,
+        stake_account,
+        unix_timestamp,
+        epoch,
+        custodian,
+        custodian_keypair,
+      )
+      .await?;
+    }
     Commands::SetLockup {
       stake_account,
       unix_timestamp,
       epoch,
       custodian,
+      ..
     } => {
       set_lockup(&program, stake_account, unix_timestamp, epoch, custodian).await?;
     }
@@ -208,11 +519,87 @@ async fn main() -> Result<()> {
     } => {
       merge_stake(&program, source_account, dest_account).await?;
     }
+    Commands::MoveStake {
+      source_account,
+      dest_account,
+      lamports,
+    } => {
+      move_stake(&program, source_account, dest_account, lamports).await?;
+    }
+    Commands::MoveLamports {
+      source_account,
+      dest_account,
+      lamports,
+    } => {
+      move_lamports(&program, source_account, dest_account, lamports).await?;
+    }
     Commands::GetMinimumDelegation => {
       get_minimum_delegation(&program).await?;
     }
-    Commands::Info { stake_account } => {
-      show_stake_info(&program, stake_account).await?;
+    Commands::Info {
+      all_by_authority: Some(authority),
+      output,
+      ..
+    } => {
+      show_stake_accounts_by_authority(&program, authority, output).await?;
+    }
+    Commands::Info {
+      stake_account,
+      output,
+      ..
+    } => {
+      let stake_account = stake_account.ok_or_else(|| anyhow!("--stake-account is required"))?;
+      show_stake_info(&program, stake_account, output).await?;
+    }
+    Commands::InitConfig {
+      minimum_delegation,
+      warmup_cooldown_rate,
+      slash_penalty,
+    } => {
+      init_config(
+        &program,
+        StakeConfigArgs {
+          minimum_delegation,
+          warmup_cooldown_rate,
+          slash_penalty,
+        },
+      )
+      .await?;
+    }
+    Commands::UpdateConfig {
+      admin,
+      minimum_delegation,
+      warmup_cooldown_rate,
+      slash_penalty,
+    } => {
+      update_config(
+        &program,
+        UpdateConfigArgs {
+          admin: admin.map(|admin| Pubkey::from_str(&admin)).transpose()?,
+          minimum_delegation,
+          warmup_cooldown_rate,
+          slash_penalty,
+        },
+      )
+      .await?;
+    }
+    Commands::InitRewardReserve { rewards, points } => {
+      init_reward_reserve(&program, PointValue { rewards, points }).await?;
+    }
+    Commands::SetPointValue { rewards, points } => {
+      set_point_value(&program, PointValue { rewards, points }).await?;
+    }
+    Commands::FundRewardReserve { lamports } => {
+      fund_reward_reserve(&program, lamports).await?;
+    }
+    Commands::RedeemRewards {
+      stake_account,
+      vote_account,
+    } => {
+      redeem_rewards(&program, stake_account, vote_account).await?;
+    }
+    Commands::Submit { transaction } => {
+      submit_transactions(&program, transaction).await?;
     }
   }
 
@@ -220,7 +607,7 @@ async fn main() -> Result<()> {
 }
 
 async fn initialize_stake_account(
-  program: &Program<Rc<Keypair>>,
+  program: &StakeClient,
   staker: String,
   withdrawer: String,
   lockup_timestamp: i64,
@@ -233,7 +620,7 @@ async fn initialize_stake_account(
   let custodian_pubkey = custodian
     .map(|c| Pubkey::from_str(&c))
     .transpose()?
-    .unwrap_or_else(|| Pubkey::new_unique());
+    .unwrap_or_else(Pubkey::new_unique);
 
   let authorized = Authorized {
     staker: staker_pubkey,
@@ -248,7 +635,7 @@ async fn initialize_stake_account(
 
   println!("Initializing stake account: {}", stake_account.pubkey());
 
-  let sig = program
+  let request = program
     .request()
     .accounts(accounts::Initialize {
       stake_account: stake_account.pubkey(),
@@ -256,48 +643,148 @@ async fn initialize_stake_account(
       rent: anchor_client::solana_sdk::sysvar::rent::id(),
       system_program: system_program::id(),
     })
-    .args(instruction::Initialize { authorized, lockup })
-    .signer(&stake_account)
-    .send()?;
+    .args(instruction::Initialize { authorized, lockup });
+  program.send(request, &[&stake_account])?;
+  println!("Stake account created: {}", stake_account.pubkey());
 
-  println!("Transaction signature: {}", sig);
+  Ok(())
+}
+
+async fn initialize_stake_account_checked(
+  program: &StakeClient,
+  staker: String,
+  withdrawer: String,
+  withdrawer_keypair: String,
+) -> Result<()> {
+  let stake_account = Keypair::new();
+  let staker_pubkey = Pubkey::from_str(&staker)?;
+  let withdrawer = program.read_signer(&withdrawer_keypair, &Pubkey::from_str(&withdrawer)?)?;
+
+  println!("Initializing stake account: {}", stake_account.pubkey());
+
+  let request = program
+    .request()
+    .accounts(accounts::InitializeChecked {
+      stake_account: stake_account.pubkey(),
+      payer: program.payer(),
+      staker: staker_pubkey,
+      withdrawer: withdrawer.pubkey(),
+      rent: anchor_client::solana_sdk::sysvar::rent::id(),
+      system_program: system_program::id(),
+    })
+    .args(instruction::InitializeChecked {});
+  program.send(request, &[&stake_account, &withdrawer])?;
   println!("Stake account created: {}", stake_account.pubkey());
 
   Ok(())
 }
 
 async fn authorize_stake_account(
-  program: &Program<Rc<Keypair>>,
+  program: &StakeClient,
   stake_account: String,
   new_authority: String,
   stake_authorize: StakeAuthorize,
+  custodian_keypair: Option<String>,
 ) -> Result<()> {
   let stake_account_pubkey = Pubkey::from_str(&stake_account)?;
   let new_authority_pubkey = Pubkey::from_str(&new_authority)?;
+  let custodian = program.read_custodian(custodian_keypair)?;
 
   println!("Authorizing stake account: {}", stake_account_pubkey);
   println!("New authority: {}", new_authority_pubkey);
 
-  let sig = program
+  let request = program
     .request()
     .accounts(accounts::Authorize {
       stake_account: stake_account_pubkey,
       authority: program.payer(),
       clock: anchor_client::solana_sdk::sysvar::clock::id(),
+      custodian: custodian.as_ref().map(|custodian| custodian.pubkey()),
     })
     .args(instruction::Authorize {
       new_authority: new_authority_pubkey,
       stake_authorize,
+    });
+  program.send(request, custodian.as_deref().as_slice())?;
+
+  Ok(())
+}
+
+async fn authorize_stake_account_checked(
+  program: &StakeClient,
+  stake_account: String,
+  new_authority: String,
+  new_authority_keypair: String,
+  stake_authorize: StakeAuthorize,
+  custodian_keypair: Option<String>,
+) -> Result<()> {
+  let stake_account_pubkey = Pubkey::from_str(&stake_account)?;
+  let new_authority =
+    program.read_signer(&new_authority_keypair, &Pubkey::from_str(&new_authority)?)?;
+  let custodian = program.read_custodian(custodian_keypair)?;
+
+  println!("Authorizing stake account: {}", stake_account_pubkey);
+  println!("New authority: {}", new_authority.pubkey());
+
+  let request = program
+    .request()
+    .accounts(accounts::AuthorizeChecked {
+      stake_account: stake_account_pubkey,
+      authority: program.payer(),
+      new_authority: new_authority.pubkey(),
+      clock: anchor_client::solana_sdk::sysvar::clock::id(),
+      custodian: custodian.as_ref().map(|custodian| custodian.pubkey()),
     })
-    .send()?;
+    .args(instruction::AuthorizeChecked { stake_authorize });
+  let mut signers: Vec<&dyn Signer> = vec![&new_authority];
+  signers.extend(custodian.as_deref());
+  program.send(request, &signers)?;
 
-  println!("Transaction signature: {}", sig);
+  Ok(())
+}
+
+async fn authorize_with_seed(
+  program: &StakeClient,
+  stake_account: String,
+  new_authority: String,
+  stake_authorize: StakeAuthorize,
+  authority_seed: String,
+  authority_owner: String,
+  custodian_keypair: Option<String>,
+) -> Result<()> {
+  let stake_account_pubkey = Pubkey::from_str(&stake_account)?;
+  let new_authority_pubkey = Pubkey::from_str(&new_authority)?;
+  let authority_owner = Pubkey::from_str(&authority_owner)?;
+  let authority = Pubkey::create_with_seed(&program.payer(), &authority_seed, &authority_owner)?;
+  let custodian = program.read_custodian(custodian_keypair)?;
+
+  println!("Authorizing stake account: {}", stake_account_pubkey);
+  println!("Current authority: {}", authority);
+  println!("New authority: {}", new_authority_pubkey);
+
+  let request = program
+    .request()
+    .accounts(accounts::AuthorizeWithSeed {
+      stake_account: stake_account_pubkey,
+      base: program.payer(),
+      clock: anchor_client::solana_sdk::sysvar::clock::id(),
+      custodian: custodian.as_ref().map(|custodian| custodian.pubkey()),
+    })
+    .args(instruction::AuthorizeWithSeed {
+      args: AuthorizeWithSeedArgs {
+        new_authority: new_authority_pubkey,
+        stake_authorize,
+        authority_seed,
+        authority_owner,
+      },
+    });
+  program.send(request, custodian.as_deref().as_slice())?;
 
   Ok(())
 }
 
 async fn delegate_stake(
-  program: &Program<Rc<Keypair>>,
+  program: &StakeClient,
   stake_account: String,
   vote_account: String,
 ) -> Result<()> {
@@ -307,104 +794,190 @@ async fn delegate_stake(
   println!("Delegating stake account: {}", stake_account_pubkey);
   println!("To vote account: {}", vote_account_pubkey);
 
-  let sig = program
+  let request = program
     .request()
     .accounts(accounts::DelegateStake {
       stake_account: stake_account_pubkey,
       vote_account: vote_account_pubkey,
       staker: program.payer(),
       clock: anchor_client::solana_sdk::sysvar::clock::id(),
+      stake_history: StakeHistory::id(),
+      config: stake_config_address(program),
     })
-    .args(instruction::DelegateStake {})
-    .send()?;
-
-  println!("Transaction signature: {}", sig);
+    .args(instruction::DelegateStake {});
+  program.send(request, &[])?;
 
   Ok(())
 }
 
 async fn split_stake(
-  program: &Program<Rc<Keypair>>,
+  program: &StakeClient,
   source_account: String,
   lamports: u64,
+  index: Option<u64>,
+  seed: Option<String>,
 ) -> Result<()> {
   let source_account_pubkey = Pubkey::from_str(&source_account)?;
-  let split_account = Keypair::new();
+  let split_keypair = Keypair::new();
+  let split_account = match (index, &seed) {
+    (Some(index), _) => split_account_address(program, &source_account_pubkey, index),
+    (None, Some(seed)) => Pubkey::create_with_seed(&program.payer(), seed, &program.id())?,
+    (None, None) => split_keypair.pubkey(),
+  };
 
   println!("Splitting stake account: {}", source_account_pubkey);
-  println!("New split account: {}", split_account.pubkey());
+  println!("New split account: {}", split_account);
   println!("Amount: {} lamports", lamports);
 
-  let sig = program
-    .request()
-    .accounts(accounts::Split {
-      source_account: source_account_pubkey,
-      split_account: split_account.pubkey(),
-      staker: program.payer(),
-      rent: anchor_client::solana_sdk::sysvar::rent::id(),
-      system_program: system_program::id(),
-    })
-    .args(instruction::Split { lamports })
-    .signer(&split_account)
-    .send()?;
-
-  println!("Transaction signature: {}", sig);
-  println!("Split account created: {}", split_account.pubkey());
+  let request = program.request();
+  let request = match (index, seed) {
+    (Some(index), _) => request
+      .accounts(accounts::SplitToPda {
+        source_account: source_account_pubkey,
+        split_account,
+        staker: program.payer(),
+        rent: anchor_client::solana_sdk::sysvar::rent::id(),
+        system_program: system_program::id(),
+        config: stake_config_address(program),
+      })
+      .args(instruction::SplitToPda {
+        _index: index,
+        lamports,
+      }),
+    (None, Some(seed)) => request
+      .accounts(accounts::SplitWithSeed {
+        source_account: source_account_pubkey,
+        split_account,
+        base: program.payer(),
+        staker: program.payer(),
+        rent: anchor_client::solana_sdk::sysvar::rent::id(),
+        system_program: system_program::id(),
+        config: stake_config_address(program),
+      })
+      .args(instruction::SplitWithSeed { seed, lamports }),
+    (None, None) => request
+      .accounts(accounts::Split {
+        source_account: source_account_pubkey,
+        split_account,
+        staker: program.payer(),
+        rent: anchor_client::solana_sdk::sysvar::rent::id(),
+        system_program: system_program::id(),
+        config: stake_config_address(program),
+      })
+      .args(instruction::Split { lamports }),
+  };
+  if split_account == split_keypair.pubkey() {
+    program.send(request, &[&split_keypair])?;
+  } else {
+    program.send(request, &[])?;
+  }
+  println!("Split account created: {}", split_account);
 
   Ok(())
 }
 
 async fn withdraw_stake(
-  program: &Program<Rc<Keypair>>,
+  program: &StakeClient,
   stake_account: String,
   to: String,
   lamports: u64,
+  custodian_keypair: Option<String>,
 ) -> Result<()> {
   let stake_account_pubkey = Pubkey::from_str(&stake_account)?;
   let to_pubkey = Pubkey::from_str(&to)?;
+  let custodian = program.read_custodian(custodian_keypair)?;
 
   println!("Withdrawing from stake account: {}", stake_account_pubkey);
   println!("To: {}", to_pubkey);
   println!("Amount: {} lamports", lamports);
 
-  let sig = program
+  let request = program
     .request()
     .accounts(accounts::Withdraw {
       stake_account: stake_account_pubkey,
       withdrawer: program.payer(),
       to: to_pubkey,
       clock: anchor_client::solana_sdk::sysvar::clock::id(),
+      stake_history: StakeHistory::id(),
+      custodian: custodian.as_ref().map(|custodian| custodian.pubkey()),
     })
-    .args(instruction::Withdraw { lamports })
-    .send()?;
-
-  println!("Transaction signature: {}", sig);
+    .args(instruction::Withdraw { lamports });
+  program.send(request, custodian.as_deref().as_slice())?;
 
   Ok(())
 }
 
-async fn deactivate_stake(program: &Program<Rc<Keypair>>, stake_account: String) -> Result<()> {
+async fn close_stake_account(
+  program: &StakeClient,
+  stake_account: String,
+  to: Option<String>,
+  lamports: Option<u64>,
+  custodian_keypair: Option<String>,
+) -> Result<()> {
+  let stake_account_pubkey = Pubkey::from_str(&stake_account)?;
+  let lamports = match lamports {
+    Some(lamports) => lamports,
+    None if program.sign_only => bail!("--sign-only needs the account's balance as --lamports"),
+    None => program.rpc().get_balance(&stake_account_pubkey)?,
+  };
+  let to = to.unwrap_or_else(|| program.payer().to_string());
+
+  println!("Closing stake account: {}", stake_account_pubkey);
+
+  withdraw_stake(program, stake_account, to, lamports, custodian_keypair).await
+}
+
+async fn deactivate_stake(program: &StakeClient, stake_account: String) -> Result<()> {
   let stake_account_pubkey = Pubkey::from_str(&stake_account)?;
 
   println!("Deactivating stake account: {}", stake_account_pubkey);
 
-  let sig = program
+  let request = program
     .request()
     .accounts(accounts::Deactivate {
       stake_account: stake_account_pubkey,
       staker: program.payer(),
       clock: anchor_client::solana_sdk::sysvar::clock::id(),
+      stake_history: StakeHistory::id(),
     })
-    .args(instruction::Deactivate {})
-    .send()?;
+    .args(instruction::Deactivate {});
+  program.send(request, &[])?;
 
-  println!("Transaction signature: {}", sig);
+  Ok(())
+}
+
+async fn deactivate_delinquent(
+  program: &StakeClient,
+  stake_account: String,
+  vote_account: Option<String>,
+  reference_vote_account: String,
+) -> Result<()> {
+  let stake_account_pubkey = Pubkey::from_str(&stake_account)?;
+  let reference_vote_account = Pubkey::from_str(&reference_vote_account)?;
+  let vote_account = delegated_vote_account(program, &stake_account_pubkey, vote_account)?;
+
+  println!(
+    "Deactivating delinquent stake account: {}",
+    stake_account_pubkey
+  );
+  println!("Vote account: {}", vote_account);
+
+  let request = program
+    .request()
+    .accounts(accounts::DeactivateDelinquent {
+      stake_account: stake_account_pubkey,
+      vote_account,
+      reference_vote_account,
+      clock: anchor_client::solana_sdk::sysvar::clock::id(),
+    })
+    .args(instruction::DeactivateDelinquent {});
+  program.send(request, &[])?;
 
   Ok(())
 }
 
 async fn set_lockup(
-  program: &Program<Rc<Keypair>>,
+  program: &StakeClient,
   stake_account: String,
   unix_timestamp: Option<i64>,
   epoch: Option<u64>,
@@ -421,7 +994,7 @@ async fn set_lockup(
 
   println!("Setting lockup for stake account: {}", stake_account_pubkey);
 
-  let sig = program
+  let request = program
     .request()
     .accounts(accounts::SetLockup {
       stake_account: stake_account_pubkey,
@@ -429,16 +1002,51 @@ async fn set_lockup(
     })
     .args(instruction::SetLockup {
       lockup: lockup_args,
-    })
-    .send()?;
+    });
+  program.send(request, &[])?;
 
-  println!("Transaction signature: {}", sig);
+  Ok(())
+}
+
+async fn set_lockup_checked(
+  program: &StakeClient,
+  stake_account: String,
+  unix_timestamp: Option<i64>,
+  epoch: Option<u64>,
+  custodian: Option<String>,
+  custodian_keypair: Option<String>,
+) -> Result<()> {
+  let stake_account_pubkey = Pubkey::from_str(&stake_account)?;
+  let new_custodian = match (custodian, custodian_keypair) {
+    (Some(custodian), Some(path)) => {
+      Some(program.read_signer(&path, &Pubkey::from_str(&custodian)?)?)
+    }
+    (Some(_), None) => bail!("--checked requires --custodian-keypair to set a new custodian"),
+    (None, _) => None,
+  };
+
+  println!("Setting lockup for stake account: {}", stake_account_pubkey);
+
+  let request = program
+    .request()
+    .accounts(accounts::SetLockupChecked {
+      stake_account: stake_account_pubkey,
+      custodian: program.payer(),
+      new_custodian: new_custodian.as_ref().map(|custodian| custodian.pubkey()),
+    })
+    .args(instruction::SetLockupChecked {
+      lockup: LockupCheckedArgs {
+        unix_timestamp,
+        epoch,
+      },
+    });
+  program.send(request, new_custodian.as_deref().as_slice())?;
 
   Ok(())
 }
 
 async fn merge_stake(
-  program: &Program<Rc<Keypair>>,
+  program: &StakeClient,
   source_account: String,
   dest_account: String,
 ) -> Result<()> {
@@ -449,77 +1057,856 @@ async fn merge_stake(
   println!("Source: {}", source_account_pubkey);
   println!("Destination: {}", dest_account_pubkey);
 
-  let sig = program
+  let request = program
     .request()
     .accounts(accounts::Merge {
       source_account: source_account_pubkey,
       dest_account: dest_account_pubkey,
       staker: program.payer(),
       clock: anchor_client::solana_sdk::sysvar::clock::id(),
+      stake_history: StakeHistory::id(),
     })
-    .args(instruction::Merge {})
-    .send()?;
+    .args(instruction::Merge {});
+  program.send(request, &[])?;
 
-  println!("Transaction signature: {}", sig);
+  Ok(())
+}
+
+async fn move_stake(
+  program: &StakeClient,
+  source_account: String,
+  dest_account: String,
+  lamports: u64,
+) -> Result<()> {
+  let source_account_pubkey = Pubkey::from_str(&source_account)?;
+  let dest_account_pubkey = Pubkey::from_str(&dest_account)?;
+
+  println!("Moving active stake:");
+  println!("Source: {}", source_account_pubkey);
+  println!("Destination: {}", dest_account_pubkey);
+  println!("Amount: {} lamports", lamports);
+
+  let request = program
+    .request()
+    .accounts(accounts::MoveStake {
+      source_account: source_account_pubkey,
+      dest_account: dest_account_pubkey,
+      staker: program.payer(),
+      clock: anchor_client::solana_sdk::sysvar::clock::id(),
+      stake_history: StakeHistory::id(),
+      config: stake_config_address(program),
+    })
+    .args(instruction::MoveStake { lamports });
+  program.send(request, &[])?;
+
+  Ok(())
+}
+
+async fn move_lamports(
+  program: &StakeClient,
+  source_account: String,
+  dest_account: String,
+  lamports: u64,
+) -> Result<()> {
+  let source_account_pubkey = Pubkey::from_str(&source_account)?;
+  let dest_account_pubkey = Pubkey::from_str(&dest_account)?;
+
+  println!("Moving free lamports:");
+  println!("Source: {}", source_account_pubkey);
+  println!("Destination: {}", dest_account_pubkey);
+  println!("Amount: {} lamports", lamports);
+
+  let request = program
+    .request()
+    .accounts(accounts::MoveLamports {
+      source_account: source_account_pubkey,
+      dest_account: dest_account_pubkey,
+      staker: program.payer(),
+      clock: anchor_client::solana_sdk::sysvar::clock::id(),
+      stake_history: StakeHistory::id(),
+    })
+    .args(instruction::MoveLamports { lamports });
+  program.send(request, &[])?;
 
   Ok(())
 }
 
-async fn get_minimum_delegation(program: &Program<Rc<Keypair>>) -> Result<()> {
+async fn get_minimum_delegation(program: &StakeClient) -> Result<()> {
   println!("Getting minimum delegation amount...");
 
-  let sig = program
+  let config_address = stake_config_address(program);
+  let request = program
     .request()
-    .accounts(accounts::GetMinimumDelegation {})
-    .args(instruction::GetMinimumDelegation {})
-    .send()?;
+    .accounts(accounts::GetMinimumDelegation {
+      config: config_address,
+    })
+    .args(instruction::GetMinimumDelegation {});
+  program.send(request, &[])?;
 
-  println!("Transaction signature: {}", sig);
-  println!(
-    "Note: Check the return data in the transaction logs for the minimum delegation amount."
-  );
+  // Nothing was sent, so there is no cluster to read the config from
+  if !program.sign_only {
+    let config: StakeConfig = program.account(config_address)?;
+    println!("Minimum delegation: {} lamports", config.minimum_delegation);
+  }
 
   Ok(())
 }
 
-async fn show_stake_info(program: &Program<Rc<Keypair>>, stake_account: String) -> Result<()> {
+/// Offset of `Meta.authorized.staker` in a stake account: the account discriminator, then
+/// `Meta.rent_exempt_reserve`.
+const AUTHORIZED_STAKER_OFFSET: usize = 8 + 8;
+const AUTHORIZED_WITHDRAWER_OFFSET: usize = AUTHORIZED_STAKER_OFFSET + 32;
+
+#[derive(Serialize)]
+struct StakeInfo {
+  address: String,
+  epoch: u64,
+  balance: u64,
+  rent_exempt_reserve: u64,
+  staker: String,
+  withdrawer: String,
+  lockup: LockupInfo,
+  delegation: Option<DelegationInfo>,
+  /// What `withdraw` accepts right now, lockup aside; the whole balance once nothing is staked
+  withdrawable: u64,
+}
+
+#[derive(Serialize)]
+struct LockupInfo {
+  unix_timestamp: i64,
+  epoch: u64,
+  custodian: String,
+  in_force: bool,
+  seconds_remaining: u64,
+  epochs_remaining: u64,
+}
+
+#[derive(Serialize)]
+struct DelegationInfo {
+  voter: String,
+  stake: u64,
+  activation_epoch: u64,
+  deactivation_epoch: u64,
+  warmup_cooldown_rate: f64,
+  credits_observed: u64,
+  effective: u64,
+  activating: u64,
+  deactivating: u64,
+}
+
+impl StakeInfo {
+  fn new(
+    address: &Pubkey,
+    account: &StakeAccount,
+    balance: u64,
+    clock: &Clock,
+    stake_history: &StakeHistory,
+  ) -> Self {
+    let lockup = &account.meta.lockup;
+    let staked = staked_lamports(account.stake.as_ref(), clock.epoch, stake_history);
+    let withdrawable = if staked == 0 {
+      balance
+    } else {
+      withdrawable_lamports(balance, staked, account.meta.rent_exempt_reserve).unwrap_or(0)
+    };
+
+    Self {
+      address: address.to_string(),
+      epoch: clock.epoch,
+      balance,
+      rent_exempt_reserve: account.meta.rent_exempt_reserve,
+      staker: account.meta.authorized.staker.to_string(),
+      withdrawer: account.meta.authorized.withdrawer.to_string(),
+      lockup: LockupInfo {
+        unix_timestamp: lockup.unix_timestamp,
+        epoch: lockup.epoch,
+        custodian: lockup.custodian.to_string(),
+        in_force: lockup.is_in_force(clock, None),
+        seconds_remaining: lockup
+          .unix_timestamp
+          .saturating_sub(clock.unix_timestamp)
+          .max(0) as u64,
+        epochs_remaining: lockup.epoch.saturating_sub(clock.epoch),
+      },
+      delegation: account.stake.as_ref().map(|stake| {
+        let status = stake.delegation.stake_activating_and_deactivating(
+          clock.epoch,
+          stake_history,
+          NEW_RATE_ACTIVATION_EPOCH,
+        );
+        DelegationInfo {
+          voter: stake.delegation.voter_pubkey.to_string(),
+          stake: stake.delegation.stake,
+          activation_epoch: stake.delegation.activation_epoch,
+          deactivation_epoch: stake.delegation.deactivation_epoch,
+          warmup_cooldown_rate: stake.delegation.warmup_cooldown_rate,
+          credits_observed: stake.credits_observed,
+          effective: status.effective,
+          activating: status.activating,
+          deactivating: status.deactivating,
+        }
+      }),
+      withdrawable,
+    }
+  }
+
+  fn print(&self) {
+    println!("\nStake Account {}", self.address);
+    println!("==================");
+    println!("Balance: {} lamports", self.balance);
+    println!("Rent Exempt Reserve: {} lamports", self.rent_exempt_reserve);
+    println!("Staker: {}", self.staker);
+    println!("Withdrawer: {}", self.withdrawer);
+    println!("Lockup:");
+    println!("  Unix Timestamp: {}", self.lockup.unix_timestamp);
+    println!("  Epoch: {}", self.lockup.epoch);
+    println!("  Custodian: {}", self.lockup.custodian);
+    if self.lockup.in_force {
+      println!(
+        "  In force for another {} seconds / {} epochs",
+        self.lockup.seconds_remaining, self.lockup.epochs_remaining
+      );
+    } else {
+      println!("  Expired");
+    }
+
+    if let Some(delegation) = &self.delegation {
+      println!("\nStake:");
+      println!("  Voter: {}", delegation.voter);
+      println!("  Stake: {} lamports", delegation.stake);
+      println!("  Activation Epoch: {}", delegation.activation_epoch);
+      println!("  Deactivation Epoch: {}", delegation.deactivation_epoch);
+      println!(
+        "  Warmup/Cooldown Rate: {}",
+        delegation.warmup_cooldown_rate
+      );
+      println!("  Credits Observed: {}", delegation.credits_observed);
+
+      println!("\nActivation (epoch {}):", self.epoch);
+      println!("  Effective: {} lamports", delegation.effective);
+      println!("  Activating: {} lamports", delegation.activating);
+      println!("  Deactivating: {} lamports", delegation.deactivating);
+    } else {
+      println!("\nNo active stake delegation");
+    }
+
+    println!("\nWithdrawable: {} lamports", self.withdrawable);
+    if self.lockup.in_force && self.withdrawable > 0 {
+      println!("  (needs the custodian's signature while the lockup is in force)");
+    }
+  }
+}
+
+/// Clock and stake history the program would see, for computing activation and lockup status.
+fn cluster_state(program: &StakeClient) -> Result<(Clock, StakeHistory)> {
+  let rpc = program.rpc();
+  let clock: Clock = from_account(&rpc.get_account(&Clock::id())?)
+    .ok_or_else(|| anyhow!("Failed to deserialize the clock sysvar"))?;
+  let stake_history: StakeHistory = from_account(&rpc.get_account(&StakeHistory::id())?)
+    .ok_or_else(|| anyhow!("Failed to deserialize the stake history sysvar"))?;
+  Ok((clock, stake_history))
+}
+
+async fn show_stake_info(
+  program: &StakeClient,
+  stake_account: String,
+  output: OutputFormat,
+) -> Result<()> {
   let stake_account_pubkey = Pubkey::from_str(&stake_account)?;
 
-  println!("Fetching stake account info: {}", stake_account_pubkey);
+  if output == OutputFormat::Display {
+    println!("Fetching stake account info: {}", stake_account_pubkey);
+  }
+
+  let account: StakeAccount = program.account(stake_account_pubkey)?;
+  let balance = program.rpc().get_balance(&stake_account_pubkey)?;
+  let (clock, stake_history) = cluster_state(program)?;
+  let info = StakeInfo::new(
+    &stake_account_pubkey,
+    &account,
+    balance,
+    &clock,
+    &stake_history,
+  );
+
+  match output {
+    OutputFormat::Display => info.print(),
+    OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&info)?),
+  }
+
+  Ok(())
+}
+
+async fn show_stake_accounts_by_authority(
+  program: &StakeClient,
+  authority: String,
+  output: OutputFormat,
+) -> Result<()> {
+  let authority = Pubkey::from_str(&authority)?;
+  let rpc = program.rpc();
+
+  // Staker and withdrawer can't be matched in one query, so look up each and merge
+  let mut stake_accounts = BTreeMap::new();
+  for offset in [AUTHORIZED_STAKER_OFFSET, AUTHORIZED_WITHDRAWER_OFFSET] {
+    let config = RpcProgramAccountsConfig {
+      filters: Some(vec![
+        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(0, StakeAccount::DISCRIMINATOR)),
+        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(offset, authority.as_ref())),
+      ]),
+      account_config: RpcAccountInfoConfig {
+        encoding: Some(UiAccountEncoding::Base64),
+        ..RpcAccountInfoConfig::default()
+      },
+      ..RpcProgramAccountsConfig::default()
+    };
+    stake_accounts.extend(rpc.get_program_accounts_with_config(&program.id(), config)?);
+  }
+
+  let (clock, stake_history) = cluster_state(program)?;
+  let infos = stake_accounts
+    .iter()
+    .map(|(address, account)| {
+      let stake_account = StakeAccount::try_deserialize(&mut account.data.as_slice())?;
+      Ok(StakeInfo::new(
+        address,
+        &stake_account,
+        account.lamports,
+        &clock,
+        &stake_history,
+      ))
+    })
+    .collect::<Result<Vec<_>>>()?;
+
+  match output {
+    OutputFormat::Display => {
+      println!(
+        "Found {} stake accounts for authority {}",
+        infos.len(),
+        authority
+      );
+      infos.iter().for_each(StakeInfo::print);
+    }
+    OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&infos)?),
+  }
 
-  let account: stake_program_project::StakeAccount = program.account(stake_account_pubkey)?;
+  Ok(())
+}
 
-  println!("\nStake Account Info:");
-  println!("==================");
-  println!("Meta:");
+/// The stake program client along with the options that decide how its transactions are signed
+/// and whether they are sent.
+struct StakeClient {
+  program: Program<Rc<DynSigner>>,
+  payer: Arc<dyn Signer>,
+  sign_only: bool,
+  blockhash: Option<Hash>,
+  nonce: Option<Pubkey>,
+  nonce_authority: Arc<dyn Signer>,
+}
+
+impl Deref for StakeClient {
+  type Target = Program<Rc<DynSigner>>;
+
+  fn deref(&self) -> &Self::Target {
+    &self.program
+  }
+}
+
+impl StakeClient {
+  /// Signs the request with the fee payer and `signers`, then sends it, or with `--sign-only`
+  /// prints it so the absent signers can add their signatures elsewhere.
+  fn send<'a>(
+    &self,
+    request: RequestBuilder<'a, Rc<DynSigner>, Box<dyn Signer + 'a>>,
+    signers: &[&dyn Signer],
+  ) -> Result<()> {
+    let mut instructions = request.instructions()?;
+    let blockhash = match (self.blockhash, self.nonce) {
+      (Some(blockhash), _) => blockhash,
+      (None, Some(nonce)) => {
+        let account = nonce_utils::get_account_with_commitment(
+          &self.rpc(),
+          &nonce,
+          CommitmentConfig::confirmed(),
+        )?;
+        nonce_utils::data_from_account(&account)?.blockhash()
+      }
+      (None, None) => self.rpc().get_latest_blockhash()?,
+    };
+    if let Some(nonce) = self.nonce {
+      instructions.insert(
+        0,
+        system_instruction::advance_nonce_account(&nonce, &self.nonce_authority.pubkey()),
+      );
+    }
+
+    let message =
+      Message::new_with_blockhash(&instructions, Some(&self.payer.pubkey()), &blockhash);
+    let mut transaction = Transaction::new_unsigned(message);
+    let message_data = transaction.message_data();
+    let signer_keys = transaction.message.signer_keys();
+    let mut all_signers: Vec<&dyn Signer> = vec![&*self.payer, &*self.nonce_authority];
+    all_signers.extend(signers);
+    for signer in all_signers {
+      // Keys that are only present as a pubkey sign with the default signature; keep whatever
+      // another signer for the same key already produced.
+      let Some(position) = signer_keys.iter().position(|key| **key == signer.pubkey()) else {
+        continue;
+      };
+      let signature = signer.try_sign_message(&message_data)?;
+      if signature != Signature::default() {
+        transaction.signatures[position] = signature;
+      }
+    }
+
+    if self.sign_only {
+      print_sign_only_transaction(&transaction)
+    } else {
+      send_transaction(self, &transaction)
+    }
+  }
+
+  /// Loads a signer that must co-sign a checked instruction, making sure it belongs to the pubkey
+  /// that was typed on the command line.
+  fn read_signer(&self, source: &str, expected: &Pubkey) -> Result<Box<dyn Signer>> {
+    let signer = signer_from_source(source, self.sign_only)?;
+    if signer.pubkey() != *expected {
+      bail!(
+        "Keypair {} is for {}, not {}",
+        source,
+        signer.pubkey(),
+        expected
+      );
+    }
+    Ok(signer)
+  }
+
+  fn read_custodian(&self, source: Option<String>) -> Result<Option<Box<dyn Signer>>> {
+    source
+      .map(|source| signer_from_source(&source, self.sign_only))
+      .transpose()
+  }
+}
+
+/// Reads a keypair file, or with `--sign-only` also a bare pubkey whose signature is left for
+/// another machine to provide.
+fn signer_from_source(source: &str, sign_only: bool) -> Result<Box<dyn Signer>> {
+  if sign_only {
+    if let Ok(pubkey) = Pubkey::from_str(source) {
+      return Ok(Box::new(NullSigner::new(&pubkey)));
+    }
+  }
+  let keypair = read_keypair_file(source)
+    .map_err(|err| anyhow!("Failed to read keypair {}: {}", source, err))?;
+  Ok(Box::new(keypair))
+}
+
+fn absent_signers(transaction: &Transaction) -> Vec<Pubkey> {
+  transaction
+    .message
+    .signer_keys()
+    .into_iter()
+    .zip(&transaction.signatures)
+    .filter(|(_, signature)| **signature == Signature::default())
+    .map(|(key, _)| *key)
+    .collect()
+}
+
+fn print_sign_only_transaction(transaction: &Transaction) -> Result<()> {
+  println!("Blockhash: {}", transaction.message.recent_blockhash);
+  for (key, signature) in transaction
+    .message
+    .signer_keys()
+    .into_iter()
+    .zip(&transaction.signatures)
+  {
+    if *signature == Signature::default() {
+      println!("Absent signer: {}", key);
+    } else {
+      println!("Signer: {}={}", key, signature);
+    }
+  }
   println!(
-    "  Rent Exempt Reserve: {} lamports",
-    account.meta.rent_exempt_reserve
+    "Transaction: {}",
+    bs58::encode(bincode::serialize(transaction)?).into_string()
   );
-  println!("  Staker: {}", account.meta.authorized.staker);
-  println!("  Withdrawer: {}", account.meta.authorized.withdrawer);
-  println!("  Lockup:");
-  println!("    Unix Timestamp: {}", account.meta.lockup.unix_timestamp);
-  println!("    Epoch: {}", account.meta.lockup.epoch);
-  println!("    Custodian: {}", account.meta.lockup.custodian);
-
-  if let Some(stake) = &account.stake {
-    println!("\nStake:");
-    println!("  Voter: {}", stake.delegation.voter_pubkey);
-    println!("  Stake: {} lamports", stake.delegation.stake);
-    println!("  Activation Epoch: {}", stake.delegation.activation_epoch);
-    println!(
-      "  Deactivation Epoch: {}",
-      stake.delegation.deactivation_epoch
-    );
-    println!(
-      "  Warmup/Cooldown Rate: {}",
-      stake.delegation.warmup_cooldown_rate
+
+  Ok(())
+}
+
+fn send_transaction(program: &StakeClient, transaction: &Transaction) -> Result<()> {
+  let absent = absent_signers(transaction);
+  if !absent.is_empty() {
+    let absent: Vec<String> = absent.iter().map(Pubkey::to_string).collect();
+    bail!(
+      "Transaction is missing signatures from {}; use --sign-only and submit",
+      absent.join(", ")
     );
-    println!("  Credits Observed: {}", stake.credits_observed);
+  }
+  transaction.verify()?;
+
+  let sig = program.rpc().send_and_confirm_transaction(transaction)?;
+
+  println!("Transaction signature: {}", sig);
+
+  Ok(())
+}
+
+/// Merges the signatures of transactions printed by `--sign-only` for the same message and sends
+/// the result once every signer is accounted for.
+async fn submit_transactions(program: &StakeClient, transactions: Vec<String>) -> Result<()> {
+  let transaction = combine_transactions(transactions)?;
+
+  if program.sign_only {
+    print_sign_only_transaction(&transaction)
   } else {
-    println!("\nNo active stake delegation");
+    send_transaction(program, &transaction)
+  }
+}
+
+/// Decodes transactions printed by `--sign-only` and merges their signatures into one.
+fn combine_transactions(transactions: Vec<String>) -> Result<Transaction> {
+  let mut combined: Option<Transaction> = None;
+  for encoded in transactions {
+    let bytes = bs58::decode(&encoded)
+      .into_vec()
+      .map_err(|err| anyhow!("Invalid transaction encoding: {}", err))?;
+    let transaction: Transaction = bincode::deserialize(&bytes)?;
+    match &mut combined {
+      None => combined = Some(transaction),
+      Some(combined) => {
+        if combined.message != transaction.message {
+          bail!("Transactions were not signed over the same message");
+        }
+        for (signature, other) in combined.signatures.iter_mut().zip(transaction.signatures) {
+          if *signature == Signature::default() {
+            *signature = other;
+          }
+        }
+      }
+    }
+  }
+  combined.ok_or_else(|| anyhow!("No transactions to submit"))
+}
+
+fn split_account_address(program: &StakeClient, source: &Pubkey, index: u64) -> Pubkey {
+  Pubkey::find_program_address(
+    &[SPLIT_SEED, source.as_ref(), &index.to_le_bytes()],
+    &program.id(),
+  )
+  .0
+}
+
+fn stake_config_address(program: &StakeClient) -> Pubkey {
+  Pubkey::find_program_address(&[STAKE_CONFIG_SEED], &program.id()).0
+}
+
+/// The vote account `stake_account` is delegated to: `vote_account` if given, otherwise read
+/// from the cluster, which `--sign-only` doesn't talk to.
+fn delegated_vote_account(
+  program: &StakeClient,
+  stake_account: &Pubkey,
+  vote_account: Option<String>,
+) -> Result<Pubkey> {
+  if let Some(vote_account) = vote_account {
+    return Ok(Pubkey::from_str(&vote_account)?);
+  }
+  if program.sign_only {
+    bail!("--sign-only needs the vote account the stake is delegated to as --vote-account");
   }
+  let account: StakeAccount = program.account(*stake_account)?;
+  let stake = account
+    .stake
+    .ok_or_else(|| anyhow!("Stake account is not delegated"))?;
+  Ok(stake.delegation.voter_pubkey)
+}
+
+async fn init_config(program: &StakeClient, args: StakeConfigArgs) -> Result<()> {
+  let config = stake_config_address(program);
+
+  println!("Initializing stake config: {}", config);
+
+  let request = program
+    .request()
+    .accounts(accounts::InitializeConfig {
+      config,
+      admin: program.payer(),
+      program_data: program_data_address(),
+      system_program: system_program::id(),
+    })
+    .args(instruction::InitializeConfig { args });
+  program.send(request, &[])?;
+
+  Ok(())
+}
+
+async fn update_config(program: &StakeClient, args: UpdateConfigArgs) -> Result<()> {
+  let config = stake_config_address(program);
+
+  println!("Updating stake config: {}", config);
+
+  let request = program
+    .request()
+    .accounts(accounts::UpdateConfig {
+      config,
+      admin: program.payer(),
+    })
+    .args(instruction::UpdateConfig { args });
+  program.send(request, &[])?;
+
+  Ok(())
+}
+
+fn reward_reserve_address(program: &StakeClient) -> Pubkey {
+  Pubkey::find_program_address(&[REWARD_RESERVE_SEED], &program.id()).0
+}
+
+async fn init_reward_reserve(program: &StakeClient, point_value: PointValue) -> Result<()> {
+  let reward_reserve = reward_reserve_address(program);
+
+  println!("Initializing reward reserve: {}", reward_reserve);
+
+  let request = program
+    .request()
+    .accounts(accounts::InitializeRewardReserve {
+      reward_reserve,
+      authority: program.payer(),
+      program_data: program_data_address(),
+      system_program: system_program::id(),
+    })
+    .args(instruction::InitializeRewardReserve { point_value });
+  program.send(request, &[])?;
+
+  Ok(())
+}
+
+async fn set_point_value(program: &StakeClient, point_value: PointValue) -> Result<()> {
+  let reward_reserve = reward_reserve_address(program);
+
+  println!("Setting point value for reward reserve: {}", reward_reserve);
+
+  let request = program
+    .request()
+    .accounts(accounts::SetPointValue {
+      reward_reserve,
+      authority: program.payer(),
+    })
+    .args(instruction::SetPointValue { point_value });
+  program.send(request, &[])?;
 
   Ok(())
 }
+
+async fn fund_reward_reserve(program: &StakeClient, lamports: u64) -> Result<()> {
+  let reward_reserve = reward_reserve_address(program);
+
+  println!("Funding reward reserve: {}", reward_reserve);
+  println!("Amount: {} lamports", lamports);
+
+  let request = program.request().instruction(system_instruction::transfer(
+    &program.payer(),
+    &reward_reserve,
+    lamports,
+  ));
+  program.send(request, &[])?;
+
+  Ok(())
+}
+
+async fn redeem_rewards(
+  program: &StakeClient,
+  stake_account: String,
+  vote_account: Option<String>,
+) -> Result<()> {
+  let stake_account_pubkey = Pubkey::from_str(&stake_account)?;
+  let vote_account = delegated_vote_account(program, &stake_account_pubkey, vote_account)?;
+
+  println!(
+    "Redeeming rewards for stake account: {}",
+    stake_account_pubkey
+  );
+  println!("Vote account: {}", vote_account);
+
+  let request = program
+    .request()
+    .accounts(accounts::RedeemRewards {
+      stake_account: stake_account_pubkey,
+      vote_account,
+      reward_reserve: reward_reserve_address(program),
+      clock: anchor_client::solana_sdk::sysvar::clock::id(),
+      stake_history: StakeHistory::id(),
+    })
+    .args(instruction::RedeemRewards {});
+  program.send(request, &[])?;
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use anchor_client::solana_sdk::instruction::{AccountMeta, Instruction};
+
+  use super::*;
+
+  /// A transaction that needs both `payer` and `custodian` to sign
+  fn unsigned_transaction(payer: &Pubkey, custodian: &Pubkey, blockhash: Hash) -> Transaction {
+    let instruction = Instruction::new_with_bytes(
+      stake_program_project::ID,
+      &[],
+      vec![AccountMeta::new_readonly(*custodian, true)],
+    );
+    Transaction::new_unsigned(Message::new_with_blockhash(
+      &[instruction],
+      Some(payer),
+      &blockhash,
+    ))
+  }
+
+  fn encode(transaction: &Transaction) -> String {
+    bs58::encode(bincode::serialize(transaction).unwrap()).into_string()
+  }
+
+  /// A `--sign-only` client whose RPC endpoint refuses connections, so any read from the cluster
+  /// fails the command
+  fn offline_client() -> StakeClient {
+    let payer: Arc<dyn Signer> = Arc::new(Keypair::new());
+    let client = Client::new_with_options(
+      Cluster::Custom("http://127.0.0.1:1".into(), "ws://127.0.0.1:1".into()),
+      Rc::new(DynSigner(payer.clone())),
+      CommitmentConfig::confirmed(),
+    );
+    StakeClient {
+      program: client.program(stake_program_project::ID).unwrap(),
+      payer: payer.clone(),
+      sign_only: true,
+      blockhash: Some(Hash::new_unique()),
+      nonce: None,
+      nonce_authority: payer,
+    }
+  }
+
+  /// Runs a command outside of any async context, since the blocking RPC client owns a runtime
+  /// that can't be dropped inside one
+  fn block_on(command: impl std::future::Future<Output = Result<()>>) -> Result<()> {
+    tokio::runtime::Builder::new_current_thread()
+      .enable_all()
+      .build()
+      .unwrap()
+      .block_on(command)
+  }
+
+  fn assert_needs_argument(result: Result<()>, argument: &str) {
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains(argument), "{}", err);
+  }
+
+  #[test]
+  fn submit_combines_signatures_from_two_signers() {
+    let (payer, custodian) = (Keypair::new(), Keypair::new());
+    let blockhash = Hash::new_unique();
+
+    let mut signed_by_payer = unsigned_transaction(&payer.pubkey(), &custodian.pubkey(), blockhash);
+    signed_by_payer.partial_sign(&[&payer], blockhash);
+    let mut signed_by_custodian =
+      unsigned_transaction(&payer.pubkey(), &custodian.pubkey(), blockhash);
+    signed_by_custodian.partial_sign(&[&custodian], blockhash);
+    assert_eq!(absent_signers(&signed_by_payer), vec![custodian.pubkey()]);
+
+    let combined =
+      combine_transactions(vec![encode(&signed_by_payer), encode(&signed_by_custodian)]).unwrap();
+
+    assert!(absent_signers(&combined).is_empty());
+    combined.verify().unwrap();
+  }
+
+  #[test]
+  fn submit_rejects_transactions_for_different_messages() {
+    let (payer, custodian) = (Keypair::new(), Keypair::new());
+
+    let mut first = unsigned_transaction(&payer.pubkey(), &custodian.pubkey(), Hash::new_unique());
+    first.partial_sign(&[&payer], first.message.recent_blockhash);
+    let mut second = unsigned_transaction(&payer.pubkey(), &custodian.pubkey(), Hash::new_unique());
+    second.partial_sign(&[&custodian], second.message.recent_blockhash);
+
+    assert!(combine_transactions(vec![encode(&first), encode(&second)]).is_err());
+    assert!(combine_transactions(vec![]).is_err());
+  }
+
+  #[test]
+  fn sign_only_close_takes_the_balance() {
+    let program = offline_client();
+    let stake_account = Pubkey::new_unique().to_string();
+
+    assert_needs_argument(
+      block_on(close_stake_account(
+        &program,
+        stake_account.clone(),
+        None,
+        None,
+        None,
+      )),
+      "--lamports",
+    );
+    block_on(close_stake_account(
+      &program,
+      stake_account,
+      None,
+      Some(1_000_000),
+      None,
+    ))
+    .unwrap();
+  }
+
+  #[test]
+  fn sign_only_deactivate_delinquent_takes_the_vote_account() {
+    let program = offline_client();
+    let stake_account = Pubkey::new_unique().to_string();
+    let reference = Pubkey::new_unique().to_string();
+
+    assert_needs_argument(
+      block_on(deactivate_delinquent(
+        &program,
+        stake_account.clone(),
+        None,
+        reference.clone(),
+      )),
+      "--vote-account",
+    );
+    let vote_account = Pubkey::new_unique().to_string();
+    block_on(deactivate_delinquent(
+      &program,
+      stake_account,
+      Some(vote_account),
+      reference,
+    ))
+    .unwrap();
+  }
+
+  #[test]
+  fn sign_only_redeem_rewards_takes_the_vote_account() {
+    let program = offline_client();
+    let stake_account = Pubkey::new_unique().to_string();
+
+    assert_needs_argument(
+      block_on(redeem_rewards(&program, stake_account.clone(), None)),
+      "--vote-account",
+    );
+    let vote_account = Pubkey::new_unique().to_string();
+    block_on(redeem_rewards(&program, stake_account, Some(vote_account))).unwrap();
+  }
+
+  #[test]
+  fn sign_only_get_minimum_delegation_skips_the_config_read() {
+    let program = offline_client();
+    block_on(get_minimum_delegation(&program)).unwrap();
+  }
+
+  #[test]
+  fn sign_only_requires_a_blockhash() {
+    let args = ["stake-client", "get-minimum-delegation", "--sign-only"];
+    assert!(Cli::try_parse_from(args).is_err());
+
+    let blockhash = Hash::new_unique().to_string();
+    let args = [&args[..], &["--blockhash", &blockhash]].concat();
+    assert!(Cli::try_parse_from(args).is_ok());
+  }
+}
@@ -0,0 +1,288 @@
+use anchor_lang::{system_program, AccountDeserialize, InstructionData, ToAccountMetas};
+use anyhow::{anyhow, Result};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+  account::from_account,
+  clock::Clock,
+  instruction::{AccountMeta, Instruction},
+  pubkey::Pubkey,
+  signature::{Keypair, Signature, Signer},
+  sysvar::{self, SysvarId},
+  transaction::Transaction,
+};
+use solana_stake_interface::stake_history::StakeHistory;
+use stake_pool::{
+  transient_stake_address, validator_stake_address, StakePool, AUTHORITY_SEED, RESERVE_SEED,
+};
+use stake_program_project::{
+  StakeAccount, StakeConfig, NEW_RATE_ACTIVATION_EPOCH, STAKE_CONFIG_SEED,
+};
+
+use crate::plan::{Operation, PoolState, PoolValidator, StakeStatus, TransientStake};
+
+/// The pool account and its stake, with the activation of every stake account as of the current
+/// epoch.
+pub fn pool_state(rpc: &RpcClient, pool_address: &Pubkey) -> Result<(StakePool, PoolState)> {
+  let pool = StakePool::try_deserialize(&mut rpc.get_account(pool_address)?.data.as_slice())?;
+
+  let clock: Clock = from_account(&rpc.get_account(&Clock::id())?)
+    .ok_or_else(|| anyhow!("Failed to deserialize the clock sysvar"))?;
+  let stake_history: StakeHistory = from_account(&rpc.get_account(&StakeHistory::id())?)
+    .ok_or_else(|| anyhow!("Failed to deserialize the stake history sysvar"))?;
+
+  let reserve = rpc
+    .get_balance(&reserve_address(pool_address))?
+    .saturating_sub(rpc.get_minimum_balance_for_rent_exemption(0)?);
+
+  let addresses: Vec<Pubkey> = pool
+    .validators
+    .iter()
+    .flat_map(|vote_account| {
+      [
+        validator_stake_address(pool_address, vote_account),
+        transient_stake_address(pool_address, vote_account),
+      ]
+    })
+    .collect();
+  let accounts = rpc.get_multiple_accounts(&addresses)?;
+
+  let activation = |data: &[u8]| -> Result<(u64, StakeStatus)> {
+    let stake_account = StakeAccount::try_deserialize(&mut &data[..])?;
+    let Some(stake) = stake_account.stake else {
+      return Ok((0, StakeStatus::Inactive));
+    };
+    let activation = stake.delegation.stake_activating_and_deactivating(
+      clock.epoch,
+      &stake_history,
+      NEW_RATE_ACTIVATION_EPOCH,
+    );
+    let status = if activation.deactivating > 0 {
+      StakeStatus::Deactivating
+    } else if activation.activating > 0 {
+      StakeStatus::Activating
+    } else if activation.effective > 0 {
+      StakeStatus::Active
+    } else {
+      StakeStatus::Inactive
+    };
+    Ok((stake.delegation.stake, status))
+  };
+
+  let validators = pool
+    .validators
+    .iter()
+    .zip(accounts.chunks_exact(2))
+    .map(|(vote_account, accounts)| {
+      let validator_stake = accounts[0]
+        .as_ref()
+        .ok_or_else(|| anyhow!("Stake account of validator {} not found", vote_account))?;
+      let (stake, status) = activation(&validator_stake.data)?;
+      // The transient account only exists while stake is in flight. Lamports sent to its
+      // address in between leave a system account, which the pool sweeps to the reserve
+      let transient = accounts[1]
+        .as_ref()
+        .filter(|account| account.owner == stake_program_project::ID)
+        .map(|account| activation(&account.data))
+        .transpose()?
+        .map(|(stake, status)| TransientStake { stake, status });
+      Ok(PoolValidator {
+        vote_account: *vote_account,
+        stake,
+        status,
+        transient,
+      })
+    })
+    .collect::<Result<Vec<_>>>()?;
+
+  let state = PoolState {
+    epoch: clock.epoch,
+    last_update_epoch: pool.last_update_epoch,
+    reserve,
+    validators,
+  };
+  Ok((pool, state))
+}
+
+pub fn stake_config_address() -> Pubkey {
+  Pubkey::find_program_address(&[STAKE_CONFIG_SEED], &stake_program_project::ID).0
+}
+
+pub fn minimum_delegation(rpc: &RpcClient) -> Result<u64> {
+  let account = rpc.get_account(&stake_config_address())?;
+  let config = StakeConfig::try_deserialize(&mut account.data.as_slice())?;
+  Ok(config.minimum_delegation)
+}
+
+/// Rent of a stake account, which the reserve pays for every validator and transient account.
+pub fn stake_rent(rpc: &RpcClient) -> Result<u64> {
+  Ok(rpc.get_minimum_balance_for_rent_exemption(8 + StakeAccount::LEN)?)
+}
+
+fn withdraw_authority_address(pool_address: &Pubkey) -> Pubkey {
+  Pubkey::find_program_address(&[AUTHORITY_SEED, pool_address.as_ref()], &stake_pool::ID).0
+}
+
+fn reserve_address(pool_address: &Pubkey) -> Pubkey {
+  Pubkey::find_program_address(&[RESERVE_SEED, pool_address.as_ref()], &stake_pool::ID).0
+}
+
+/// Sends the transaction carrying out `operation`, with `manager` signing and paying for it.
+pub fn execute(
+  rpc: &RpcClient,
+  pool_address: &Pubkey,
+  pool: &StakePool,
+  manager: &Keypair,
+  operation: &Operation,
+) -> Result<Signature> {
+  let pool_key = *pool_address;
+  let withdraw_authority = withdraw_authority_address(&pool_key);
+  let reserve = reserve_address(&pool_key);
+  let stake_accounts = |vote_account: &Pubkey| {
+    (
+      validator_stake_address(&pool_key, vote_account),
+      transient_stake_address(&pool_key, vote_account),
+    )
+  };
+
+  let (accounts, data) = match *operation {
+    Operation::UpdatePoolBalance => {
+      let mut accounts = stake_pool::accounts::UpdatePoolBalance {
+        pool: pool_key,
+        reserve,
+        withdraw_authority,
+        pool_mint: pool.pool_mint,
+        manager_fee_account: pool.manager_fee_account,
+        token_program: anchor_spl::token::ID,
+      }
+      .to_account_metas(None);
+      for vote_account in &pool.validators {
+        let (validator_stake, transient_stake) = stake_accounts(vote_account);
+        accounts.push(AccountMeta::new_readonly(validator_stake, false));
+        accounts.push(AccountMeta::new_readonly(transient_stake, false));
+      }
+      (
+        accounts,
+        stake_pool::instruction::UpdatePoolBalance {}.data(),
+      )
+    }
+    Operation::MergeTransientStake { vote_account } => {
+      let (validator_stake, transient_stake) = stake_accounts(&vote_account);
+      (
+        stake_pool::accounts::MergeTransientStake {
+          pool: pool_key,
+          withdraw_authority,
+          reserve,
+          validator_stake,
+          transient_stake,
+          vote_account,
+          clock: sysvar::clock::ID,
+          stake_history: sysvar::stake_history::ID,
+          stake_program: stake_program_project::ID,
+        }
+        .to_account_metas(None),
+        stake_pool::instruction::MergeTransientStake {}.data(),
+      )
+    }
+    Operation::RemoveValidator { vote_account } => {
+      let (validator_stake, transient_stake) = stake_accounts(&vote_account);
+      (
+        stake_pool::accounts::RemoveValidator {
+          pool: pool_key,
+          manager: manager.pubkey(),
+          withdraw_authority,
+          reserve,
+          validator_stake,
+          transient_stake,
+          vote_account,
+          clock: sysvar::clock::ID,
+          stake_history: sysvar::stake_history::ID,
+          stake_program: stake_program_project::ID,
+          system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        stake_pool::instruction::RemoveValidator {}.data(),
+      )
+    }
+    Operation::AddValidator { vote_account } => (
+      stake_pool::accounts::AddValidator {
+        pool: pool_key,
+        manager: manager.pubkey(),
+        withdraw_authority,
+        reserve,
+        validator_stake: validator_stake_address(&pool_key, &vote_account),
+        vote_account,
+        stake_config: stake_config_address(),
+        clock: sysvar::clock::ID,
+        stake_history: sysvar::stake_history::ID,
+        stake_program: stake_program_project::ID,
+        rent: sysvar::rent::ID,
+        system_program: system_program::ID,
+      }
+      .to_account_metas(None),
+      stake_pool::instruction::AddValidator {}.data(),
+    ),
+    Operation::DecreaseValidatorStake {
+      vote_account,
+      lamports,
+    } => {
+      let (validator_stake, transient_stake) = stake_accounts(&vote_account);
+      (
+        stake_pool::accounts::DecreaseValidatorStake {
+          pool: pool_key,
+          manager: manager.pubkey(),
+          withdraw_authority,
+          reserve,
+          validator_stake,
+          transient_stake,
+          vote_account,
+          stake_config: stake_config_address(),
+          clock: sysvar::clock::ID,
+          stake_history: sysvar::stake_history::ID,
+          stake_program: stake_program_project::ID,
+          rent: sysvar::rent::ID,
+          system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        stake_pool::instruction::DecreaseValidatorStake { lamports }.data(),
+      )
+    }
+    Operation::IncreaseValidatorStake {
+      vote_account,
+      lamports,
+    } => {
+      let (validator_stake, transient_stake) = stake_accounts(&vote_account);
+      (
+        stake_pool::accounts::IncreaseValidatorStake {
+          pool: pool_key,
+          manager: manager.pubkey(),
+          withdraw_authority,
+          reserve,
+          validator_stake,
+          transient_stake,
+          vote_account,
+          stake_config: stake_config_address(),
+          clock: sysvar::clock::ID,
+          stake_history: sysvar::stake_history::ID,
+          stake_program: stake_program_project::ID,
+          rent: sysvar::rent::ID,
+          system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        stake_pool::instruction::IncreaseValidatorStake { lamports }.data(),
+      )
+    }
+  };
+
+  let instruction = Instruction {
+    program_id: stake_pool::ID,
+    accounts,
+    data,
+  };
+  let transaction = Transaction::new_signed_with_payer(
+    &[instruction],
+    Some(&manager.pubkey()),
+    &[manager],
+    rpc.get_latest_blockhash()?,
+  );
+  Ok(rpc.send_and_confirm_transaction(&transaction)?)
+}
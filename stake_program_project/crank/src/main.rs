@@ -0,0 +1,107 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+  commitment_config::CommitmentConfig,
+  pubkey::Pubkey,
+  signature::{read_keypair_file, Signer},
+};
+
+mod chain;
+mod plan;
+mod weights;
+
+#[derive(Parser)]
+#[command(name = "stake-crank")]
+#[command(
+  about = "Manages a stake pool's validators and rebalances its stake by target weight",
+  long_about = None
+)]
+struct Cli {
+  #[arg(short, long, default_value = "http://localhost:8899")]
+  rpc_url: String,
+
+  /// Manager of the pool; also pays for the transactions
+  #[arg(short, long)]
+  keypair_path: String,
+
+  /// Stake pool account to manage
+  #[arg(short, long)]
+  pool: String,
+
+  /// TOML file with a `[[validators]]` table (`vote_account`, `weight`) per validator
+  #[arg(short, long, default_value = "weights.toml")]
+  weights: String,
+
+  /// Print the planned operations without sending them
+  #[arg(long)]
+  dry_run: bool,
+}
+
+fn main() -> Result<()> {
+  let cli = Cli::parse();
+
+  let pool_address = Pubkey::from_str(&cli.pool)?;
+  let manager = read_keypair_file(&cli.keypair_path)
+    .map_err(|error| anyhow!("Failed to read keypair {}: {}", cli.keypair_path, error))?;
+  let targets = weights::load(&cli.weights)?;
+  let rpc = RpcClient::new_with_commitment(cli.rpc_url, CommitmentConfig::confirmed());
+
+  let minimum_delegation = chain::minimum_delegation(&rpc)?;
+  let stake_rent = chain::stake_rent(&rpc)?;
+  let (pool, state) = chain::pool_state(&rpc, &pool_address)?;
+  if pool.manager != manager.pubkey() && !cli.dry_run {
+    return Err(anyhow!(
+      "{} is not the manager of pool {}",
+      manager.pubkey(),
+      pool_address
+    ));
+  }
+  let plan = plan::plan(&state, &targets, minimum_delegation, stake_rent);
+
+  println!(
+    "Epoch {}: pool {} with {} validators, {} lamports in total ({} in the reserve)",
+    state.epoch,
+    pool_address,
+    state.validators.len(),
+    plan.total,
+    state.reserve
+  );
+  println!("Minimum delegation: {} lamports", minimum_delegation);
+  println!(
+    "\n{:<44} {:>6} {:>20} {:>20}",
+    "Vote account", "Weight", "Current", "Target"
+  );
+  for allocation in &plan.allocations {
+    println!(
+      "{:<44} {:>6} {:>20} {:>20}",
+      allocation.vote_account.to_string(),
+      allocation.weight,
+      allocation.current,
+      allocation.target
+    );
+  }
+
+  if plan.operations.is_empty() {
+    println!("\nNothing to rebalance");
+    return Ok(());
+  }
+  println!("\nPlanned operations:");
+  for (index, operation) in plan.operations.iter().enumerate() {
+    println!("  {}. {}", index + 1, operation);
+  }
+  if cli.dry_run {
+    println!("\nDry run, no transactions sent");
+    return Ok(());
+  }
+
+  println!();
+  for operation in &plan.operations {
+    let signature = chain::execute(&rpc, &pool_address, &pool, &manager, operation)?;
+    println!("{}: {}", operation, signature);
+  }
+
+  Ok(())
+}
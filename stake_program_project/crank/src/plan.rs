@@ -0,0 +1,582 @@
+use std::{
+  cmp::Reverse,
+  collections::{BTreeMap, BTreeSet},
+  fmt,
+};
+
+use solana_sdk::pubkey::Pubkey;
+use stake_pool::MAX_VALIDATORS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StakeStatus {
+  /// Never delegated, or fully cooled down
+  Inactive,
+  Activating,
+  Active,
+  Deactivating,
+}
+
+/// Stake on its way between the reserve and a validator's stake account.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransientStake {
+  pub stake: u64,
+  /// Activating or active while it is being added to the validator, deactivating or inactive
+  /// while it is being taken back to the reserve
+  pub status: StakeStatus,
+}
+
+impl TransientStake {
+  fn increasing(&self) -> bool {
+    matches!(self.status, StakeStatus::Activating | StakeStatus::Active)
+  }
+
+  /// Whether `merge_transient_stake` can settle it now.
+  fn settled(&self) -> bool {
+    matches!(self.status, StakeStatus::Active | StakeStatus::Inactive)
+  }
+}
+
+/// One of the pool's validators, as listed in `StakePool.validators`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoolValidator {
+  pub vote_account: Pubkey,
+  /// Delegated stake of the validator's stake account
+  pub stake: u64,
+  /// Deactivating or inactive once `remove_validator` has started
+  pub status: StakeStatus,
+  pub transient: Option<TransientStake>,
+}
+
+impl PoolValidator {
+  fn being_removed(&self) -> bool {
+    matches!(
+      self.status,
+      StakeStatus::Deactivating | StakeStatus::Inactive
+    )
+  }
+}
+
+/// The pool as the crank finds it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PoolState {
+  pub epoch: u64,
+  pub last_update_epoch: u64,
+  /// Reserve lamports above its rent-exempt minimum
+  pub reserve: u64,
+  pub validators: Vec<PoolValidator>,
+}
+
+/// A validator and its share of the pool's stake, as read from the weights file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Target {
+  pub vote_account: Pubkey,
+  pub weight: u64,
+}
+
+/// How a validator's delegated stake compares with its share.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Allocation {
+  pub vote_account: Pubkey,
+  pub weight: u64,
+  /// Stake of the validator's stake account plus stake being added to it
+  pub current: u64,
+  pub target: u64,
+}
+
+/// One pool instruction of a crank run.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operation {
+  UpdatePoolBalance,
+  MergeTransientStake {
+    vote_account: Pubkey,
+  },
+  /// Starts removing a validator by deactivating its stake, or completes the removal once the
+  /// stake has cooled down
+  RemoveValidator {
+    vote_account: Pubkey,
+  },
+  AddValidator {
+    vote_account: Pubkey,
+  },
+  DecreaseValidatorStake {
+    vote_account: Pubkey,
+    lamports: u64,
+  },
+  IncreaseValidatorStake {
+    vote_account: Pubkey,
+    lamports: u64,
+  },
+}
+
+impl fmt::Display for Operation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::UpdatePoolBalance => write!(f, "update the pool balance"),
+      Self::MergeTransientStake { vote_account } => {
+        write!(f, "merge the transient stake of {}", vote_account)
+      }
+      Self::RemoveValidator { vote_account } => write!(f, "remove validator {}", vote_account),
+      Self::AddValidator { vote_account } => write!(f, "add validator {}", vote_account),
+      Self::DecreaseValidatorStake {
+        vote_account,
+        lamports,
+      } => write!(
+        f,
+        "move {} lamports of stake from {} back to the reserve",
+        lamports, vote_account
+      ),
+      Self::IncreaseValidatorStake {
+        vote_account,
+        lamports,
+      } => write!(
+        f,
+        "stake {} lamports from the reserve with {}",
+        lamports, vote_account
+      ),
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plan {
+  /// Reserve, validator and transient stake, split between the targets
+  pub total: u64,
+  pub allocations: Vec<Allocation>,
+  pub operations: Vec<Operation>,
+}
+
+/// Plans the pool instructions moving `pool` towards the `targets` weights.
+///
+/// Stake only moves through a validator's transient stake account, which takes an epoch to warm
+/// up or cool down, so a rebalance spans several runs an epoch apart. A validator with transient
+/// stake in flight is left alone until `merge_transient_stake` has settled it. Validators missing
+/// from `targets` are removed and new ones added with the minimum delegation. Differences smaller
+/// than `minimum_delegation` are left alone, and the reserve pays `stake_rent` for every stake
+/// account it creates.
+pub fn plan(
+  pool: &PoolState,
+  targets: &[Target],
+  minimum_delegation: u64,
+  stake_rent: u64,
+) -> Plan {
+  let mut operations = Vec::new();
+  if pool.last_update_epoch < pool.epoch {
+    operations.push(Operation::UpdatePoolBalance);
+  }
+
+  let mut reserve = pool.reserve;
+  let mut total = pool.reserve;
+  let mut current: BTreeMap<Pubkey, u64> = BTreeMap::new();
+  // Validators whose stake can't be moved in this run
+  let mut busy = BTreeSet::new();
+  for validator in &pool.validators {
+    let vote_account = validator.vote_account;
+    total = total.saturating_add(validator.stake);
+    if !validator.being_removed() {
+      current.insert(vote_account, validator.stake);
+    }
+
+    if let Some(transient) = &validator.transient {
+      total = total.saturating_add(transient.stake);
+      if transient.increasing() {
+        *current.entry(vote_account).or_default() += transient.stake;
+      }
+      if transient.settled() {
+        operations.push(Operation::MergeTransientStake { vote_account });
+      }
+      busy.insert(vote_account);
+      continue;
+    }
+
+    let targeted = targets
+      .iter()
+      .any(|target| target.vote_account == vote_account);
+    // A removal completes once the validator's stake has cooled down
+    if validator.status == StakeStatus::Inactive || (!targeted && !validator.being_removed()) {
+      operations.push(Operation::RemoveValidator { vote_account });
+    }
+    if validator.being_removed() || !targeted {
+      busy.insert(vote_account);
+    }
+  }
+
+  let mut validator_count = pool.validators.len();
+  for target in targets {
+    let listed = pool
+      .validators
+      .iter()
+      .any(|validator| validator.vote_account == target.vote_account);
+    if listed || target.weight == 0 {
+      continue;
+    }
+    if validator_count >= MAX_VALIDATORS || reserve < stake_rent + minimum_delegation {
+      continue;
+    }
+    operations.push(Operation::AddValidator {
+      vote_account: target.vote_account,
+    });
+    reserve -= stake_rent + minimum_delegation;
+    validator_count += 1;
+    current.insert(target.vote_account, minimum_delegation);
+    busy.insert(target.vote_account);
+  }
+
+  let total_weight: u64 = targets.iter().map(|target| target.weight).sum();
+  let allocations: Vec<Allocation> = current
+    .into_iter()
+    .map(|(vote_account, current)| {
+      let weight = targets
+        .iter()
+        .find(|target| target.vote_account == vote_account)
+        .map_or(0, |target| target.weight);
+      let target = if total_weight == 0 {
+        0
+      } else {
+        (u128::from(total) * u128::from(weight) / u128::from(total_weight)) as u64
+      };
+      Allocation {
+        vote_account,
+        weight,
+        current,
+        target,
+      }
+    })
+    .collect();
+
+  let idle = |allocation: &&Allocation| !busy.contains(&allocation.vote_account);
+
+  // Surplus goes back to the reserve first, so it can fund the deficits in a later run
+  for allocation in allocations.iter().filter(idle) {
+    let surplus = allocation.current.saturating_sub(allocation.target);
+    // The validator's stake account has to keep the minimum delegation
+    let lamports = surplus.min(allocation.current.saturating_sub(minimum_delegation));
+    if lamports >= minimum_delegation && reserve >= stake_rent {
+      operations.push(Operation::DecreaseValidatorStake {
+        vote_account: allocation.vote_account,
+        lamports,
+      });
+      reserve -= stake_rent;
+    }
+  }
+
+  let mut deficits: Vec<&Allocation> = allocations
+    .iter()
+    .filter(idle)
+    .filter(|allocation| allocation.weight > 0 && allocation.target > allocation.current)
+    .collect();
+  deficits.sort_by_key(|allocation| Reverse(allocation.target - allocation.current));
+  for allocation in deficits {
+    let deficit = allocation.target - allocation.current;
+    let lamports = deficit.min(reserve.saturating_sub(stake_rent));
+    if lamports >= minimum_delegation {
+      operations.push(Operation::IncreaseValidatorStake {
+        vote_account: allocation.vote_account,
+        lamports,
+      });
+      reserve -= lamports + stake_rent;
+    }
+  }
+
+  Plan {
+    total,
+    allocations,
+    operations,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use proptest::prelude::*;
+
+  use super::*;
+
+  const MINIMUM: u64 = 1_000;
+  const RENT: u64 = 100;
+  const EPOCH: u64 = 10;
+
+  fn validators<const N: usize>() -> [Pubkey; N] {
+    let mut validators = [(); N].map(|_| Pubkey::new_unique());
+    validators.sort();
+    validators
+  }
+
+  fn validator(vote_account: Pubkey, stake: u64) -> PoolValidator {
+    PoolValidator {
+      vote_account,
+      stake,
+      status: StakeStatus::Active,
+      transient: None,
+    }
+  }
+
+  fn pool(reserve: u64, validators: Vec<PoolValidator>) -> PoolState {
+    PoolState {
+      epoch: EPOCH,
+      last_update_epoch: EPOCH,
+      reserve,
+      validators,
+    }
+  }
+
+  fn targets(weights: &[(Pubkey, u64)]) -> Vec<Target> {
+    weights
+      .iter()
+      .map(|&(vote_account, weight)| Target {
+        vote_account,
+        weight,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn balanced_pool_needs_nothing() {
+    let [a, b] = validators();
+    let pool = pool(500, vec![validator(a, 5_000), validator(b, 5_000)]);
+    let plan = plan(&pool, &targets(&[(a, 1), (b, 1)]), MINIMUM, RENT);
+
+    assert_eq!(plan.total, 10_500);
+    assert_eq!(plan.allocations[0].target, 5_250);
+    assert!(plan.operations.is_empty());
+  }
+
+  #[test]
+  fn stale_pool_is_updated_first() {
+    let [a] = validators();
+    let mut pool = pool(0, vec![validator(a, 5_000)]);
+    pool.last_update_epoch = EPOCH - 1;
+    let plan = plan(&pool, &targets(&[(a, 1)]), MINIMUM, RENT);
+
+    assert_eq!(plan.operations, [Operation::UpdatePoolBalance]);
+  }
+
+  #[test]
+  fn reserve_is_staked_with_the_validators_furthest_below_target() {
+    let [a, b] = validators();
+    let pool = pool(10_000, vec![validator(a, 4_000), validator(b, 1_000)]);
+    let plan = plan(&pool, &targets(&[(a, 1), (b, 1)]), MINIMUM, RENT);
+
+    // 15_000 in total: b is 6_500 short, a 3_500; the reserve covers b and what is left of a
+    assert_eq!(
+      plan.operations,
+      [
+        Operation::IncreaseValidatorStake {
+          vote_account: b,
+          lamports: 6_500,
+        },
+        Operation::IncreaseValidatorStake {
+          vote_account: a,
+          lamports: 3_300,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn surplus_returns_to_the_reserve() {
+    let [a, b] = validators();
+    let pool = pool(200, vec![validator(a, 9_000), validator(b, 1_000)]);
+    let plan = plan(&pool, &targets(&[(a, 1), (b, 3)]), MINIMUM, RENT);
+
+    // a holds 6_450 more than its quarter; b waits for that to cool down
+    assert_eq!(
+      plan.operations,
+      [Operation::DecreaseValidatorStake {
+        vote_account: a,
+        lamports: 6_450,
+      }]
+    );
+  }
+
+  #[test]
+  fn decreases_keep_the_minimum_delegation() {
+    let [a, b] = validators();
+    let pool = pool(200, vec![validator(a, 1_500), validator(b, 8_000)]);
+    let plan = plan(&pool, &targets(&[(a, 0), (b, 1)]), MINIMUM, RENT);
+
+    // a's whole 1_500 is surplus, but only 500 could leave and that is below the minimum
+    assert!(plan.operations.is_empty());
+  }
+
+  #[test]
+  fn transient_stake_is_merged_before_it_moves_again() {
+    let [a, b, c] = validators();
+    let mut increased = validator(a, 1_000);
+    increased.transient = Some(TransientStake {
+      stake: 4_000,
+      status: StakeStatus::Active,
+    });
+    let mut decreasing = validator(b, 1_000);
+    decreasing.transient = Some(TransientStake {
+      stake: 4_000,
+      status: StakeStatus::Deactivating,
+    });
+    let mut decreased = validator(c, 9_000);
+    decreased.transient = Some(TransientStake {
+      stake: 2_000,
+      status: StakeStatus::Inactive,
+    });
+    let pool = pool(10_000, vec![increased, decreasing, decreased]);
+    let plan = plan(&pool, &targets(&[(a, 1), (b, 1), (c, 1)]), MINIMUM, RENT);
+
+    assert_eq!(plan.total, 31_000);
+    // Increasing stake counts towards the validator, decreasing stake doesn't
+    let current: Vec<u64> = plan
+      .allocations
+      .iter()
+      .map(|allocation| allocation.current)
+      .collect();
+    assert_eq!(current, [5_000, 1_000, 9_000]);
+    assert_eq!(
+      plan.operations,
+      [
+        Operation::MergeTransientStake { vote_account: a },
+        Operation::MergeTransientStake { vote_account: c },
+      ]
+    );
+  }
+
+  #[test]
+  fn validators_leave_and_join_the_pool() {
+    let [a, b, c, d] = validators();
+    let mut cooling_down = validator(b, 2_000);
+    cooling_down.status = StakeStatus::Deactivating;
+    let mut cooled_down = validator(c, 2_000);
+    cooled_down.status = StakeStatus::Inactive;
+    let pool = pool(5_000, vec![validator(a, 3_000), cooling_down, cooled_down]);
+    let plan = plan(&pool, &targets(&[(d, 1)]), MINIMUM, RENT);
+
+    // a starts leaving, b is still on its way out, c is done, and d joins with the minimum
+    assert_eq!(
+      plan.operations,
+      [
+        Operation::RemoveValidator { vote_account: a },
+        Operation::RemoveValidator { vote_account: c },
+        Operation::AddValidator { vote_account: d },
+      ]
+    );
+    assert_eq!(
+      plan.allocations,
+      [
+        Allocation {
+          vote_account: a,
+          weight: 0,
+          current: 3_000,
+          target: 0,
+        },
+        Allocation {
+          vote_account: d,
+          weight: 1,
+          current: MINIMUM,
+          target: 12_000,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn new_validators_are_added_while_the_reserve_lasts() {
+    let [a, b, c] = validators();
+    let pool = pool(MINIMUM + RENT + 500, vec![validator(a, 5_000)]);
+    let plan = plan(&pool, &targets(&[(a, 1), (b, 1), (c, 1)]), MINIMUM, RENT);
+
+    // The reserve only funds b; what is left of it still pays for a's surplus to come back
+    assert_eq!(
+      plan.operations,
+      [
+        Operation::AddValidator { vote_account: b },
+        Operation::DecreaseValidatorStake {
+          vote_account: a,
+          lamports: 2_800,
+        },
+      ]
+    );
+  }
+
+  fn arbitrary_validator(vote_account: Pubkey) -> impl Strategy<Value = PoolValidator> {
+    let status = || {
+      prop_oneof![
+        Just(StakeStatus::Inactive),
+        Just(StakeStatus::Activating),
+        Just(StakeStatus::Active),
+        Just(StakeStatus::Deactivating),
+      ]
+    };
+    (
+      MINIMUM .. 100_000u64,
+      status(),
+      prop::option::of((MINIMUM .. 100_000u64, status())),
+    )
+      .prop_map(move |(stake, status, transient)| PoolValidator {
+        vote_account,
+        stake,
+        status,
+        transient: transient.map(|(stake, status)| TransientStake { stake, status }),
+      })
+  }
+
+  proptest! {
+    #[test]
+    fn operations_respect_the_minimum_delegation_and_the_reserve(
+      (pool, weights) in prop::collection::vec((0..10u64, any::<bool>()), 1..6)
+        .prop_flat_map(|weights| {
+          let accounts: Vec<Pubkey> = weights.iter().map(|_| Pubkey::new_unique()).collect();
+          let listed: Vec<_> = accounts
+            .iter()
+            .zip(&weights)
+            .filter(|(_, (_, listed))| *listed)
+            .map(|(vote_account, _)| arbitrary_validator(*vote_account))
+            .collect();
+          let weights: Vec<(Pubkey, u64)> = accounts
+            .into_iter()
+            .zip(weights)
+            .map(|(vote_account, (weight, _))| (vote_account, weight))
+            .collect();
+          ((0..200_000u64, listed).prop_map(|(reserve, validators)| pool(reserve, validators)),
+            Just(weights))
+        })
+    ) {
+      let plan = plan(&pool, &targets(&weights), MINIMUM, RENT);
+      let find = |vote_account: &Pubkey| {
+        pool.validators.iter().find(|validator| validator.vote_account == *vote_account)
+      };
+
+      let mut spent = 0;
+      let mut moved = BTreeSet::new();
+      for operation in &plan.operations {
+        match operation {
+          Operation::IncreaseValidatorStake { vote_account, lamports } => {
+            prop_assert!(*lamports >= MINIMUM);
+            prop_assert!(moved.insert(*vote_account));
+            if let Some(validator) = find(vote_account) {
+              prop_assert!(validator.transient.is_none() && !validator.being_removed());
+            }
+            spent += lamports + RENT;
+          }
+          Operation::DecreaseValidatorStake { vote_account, lamports } => {
+            let validator = find(vote_account).unwrap();
+            prop_assert!(validator.transient.is_none() && !validator.being_removed());
+            prop_assert!(moved.insert(*vote_account));
+            prop_assert!(*lamports >= MINIMUM);
+            prop_assert!(validator.stake - lamports >= MINIMUM);
+            spent += RENT;
+          }
+          Operation::AddValidator { vote_account } => {
+            prop_assert!(find(vote_account).is_none());
+            spent += MINIMUM + RENT;
+          }
+          Operation::MergeTransientStake { vote_account } => {
+            prop_assert!(find(vote_account).unwrap().transient.as_ref().unwrap().settled());
+          }
+          Operation::RemoveValidator { vote_account } => {
+            prop_assert!(find(vote_account).unwrap().transient.is_none());
+          }
+          Operation::UpdatePoolBalance => {}
+        }
+      }
+      prop_assert!(spent <= pool.reserve);
+
+      let targeted: u64 = plan.allocations.iter().map(|allocation| allocation.target).sum();
+      prop_assert!(targeted <= plan.total);
+    }
+  }
+}
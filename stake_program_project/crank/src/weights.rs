@@ -0,0 +1,112 @@
+use std::{collections::BTreeSet, fs, path::Path, str::FromStr};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::plan::Target;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WeightsFile {
+  validators: Vec<ValidatorWeight>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ValidatorWeight {
+  vote_account: String,
+  weight: u64,
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<Target>> {
+  let path = path.as_ref();
+  let contents =
+    fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+  parse(&contents).with_context(|| format!("Invalid weights file {}", path.display()))
+}
+
+/// Parses the target weights, one `[[validators]]` table per vote account.
+pub fn parse(contents: &str) -> Result<Vec<Target>> {
+  let file: WeightsFile = toml::from_str(contents)?;
+
+  let mut seen = BTreeSet::new();
+  let targets = file
+    .validators
+    .into_iter()
+    .map(|validator| {
+      let vote_account = Pubkey::from_str(&validator.vote_account)
+        .with_context(|| format!("Invalid vote account {}", validator.vote_account))?;
+      if !seen.insert(vote_account) {
+        bail!("Vote account {} is listed twice", vote_account);
+      }
+      Ok(Target {
+        vote_account,
+        weight: validator.weight,
+      })
+    })
+    .collect::<Result<Vec<_>>>()?;
+
+  if targets.iter().all(|target| target.weight == 0) {
+    bail!("At least one validator needs a weight above zero");
+  }
+  Ok(targets)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const VOTE_A: &str = "Vote111111111111111111111111111111111111111";
+  const VOTE_B: &str = "4Nd1mBQtrMJVYVfKf2PJy9NZUZdTAsp7D4xWLs4gDB4T";
+
+  #[test]
+  fn parses_weights() {
+    let targets = parse(&format!(
+      r#"
+        [[validators]]
+        vote_account = "{VOTE_A}"
+        weight = 3
+
+        [[validators]]
+        vote_account = "{VOTE_B}"
+        weight = 0
+      "#
+    ))
+    .unwrap();
+
+    assert_eq!(
+      targets,
+      [
+        Target {
+          vote_account: Pubkey::from_str(VOTE_A).unwrap(),
+          weight: 3,
+        },
+        Target {
+          vote_account: Pubkey::from_str(VOTE_B).unwrap(),
+          weight: 0,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn rejects_duplicates_and_zero_weights() {
+    let duplicate = format!(
+      r#"
+        [[validators]]
+        vote_account = "{VOTE_A}"
+        weight = 1
+
+        [[validators]]
+        vote_account = "{VOTE_A}"
+        weight = 2
+      "#
+    );
+    assert!(parse(&duplicate).is_err());
+
+    let all_zero = format!("[[validators]]\nvote_account = \"{VOTE_A}\"\nweight = 0\n");
+    assert!(parse(&all_zero).is_err());
+    assert!(parse("[[validators]]\nvote_account = \"not a key\"\nweight = 1\n").is_err());
+  }
+}
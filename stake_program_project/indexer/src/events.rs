@@ -0,0 +1,344 @@
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Value};
+use solana_sdk::pubkey::Pubkey;
+use stake_program_project::{
+  AuthorizeEvent, ConfigEvent, DeactivateEvent, DelegateEvent, MergeEvent, MoveLamportsEvent,
+  MoveStakeEvent, PointValueEvent, RewardEvent, SetLockupEvent, SplitEvent, StakeAuthorize,
+  StakeInitializeEvent, WithdrawEvent,
+};
+
+const PROGRAM_DATA: &str = "Program data: ";
+
+/// An event emitted by the stake program, flattened to what the indexer stores.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedEvent {
+  pub name: &'static str,
+  /// Every account the event is about, so the history of any of them includes it.
+  pub accounts: Vec<Pubkey>,
+  pub data: Value,
+}
+
+/// Decodes the events `program_id` emitted in a transaction's log messages.
+///
+/// `Program data:` lines are attributed to the program on top of the invocation stack, so events
+/// logged by other programs (including ones that CPI into the stake program) are skipped.
+pub fn parse_logs(logs: &[String], program_id: &Pubkey) -> Result<Vec<IndexedEvent>> {
+  let program_id = program_id.to_string();
+  let mut invocations: Vec<&str> = Vec::new();
+  let mut events = Vec::new();
+
+  for log in logs {
+    if let Some(data) = log.strip_prefix(PROGRAM_DATA) {
+      if invocations.last() == Some(&program_id.as_str()) {
+        let bytes = STANDARD
+          .decode(data)
+          .with_context(|| format!("Invalid event encoding: {}", data))?;
+        events.extend(decode_event(&bytes)?);
+      }
+    } else if let Some(rest) = log.strip_prefix("Program ") {
+      let mut words = rest.split_whitespace();
+      match (words.next(), words.next()) {
+        (Some(program), Some("invoke")) => invocations.push(program),
+        (Some(_), Some("success" | "failed:")) => {
+          invocations.pop();
+        }
+        _ => {}
+      }
+    }
+  }
+
+  Ok(events)
+}
+
+/// Decodes one event from its discriminator and borsh payload, or `None` if the discriminator
+/// belongs to an event this indexer does not know.
+pub fn decode_event(data: &[u8]) -> Result<Option<IndexedEvent>> {
+  if data.len() < 8 {
+    return Ok(None);
+  }
+  let (discriminator, payload) = data.split_at(8);
+
+  let event = if discriminator == StakeInitializeEvent::DISCRIMINATOR {
+    let event: StakeInitializeEvent = deserialize(payload)?;
+    IndexedEvent {
+      name: "StakeInitialize",
+      accounts: vec![event.stake_account],
+      data: json!({
+        "stake_account": event.stake_account.to_string(),
+        "staker": event.staker.to_string(),
+        "withdrawer": event.withdrawer.to_string(),
+        "timestamp": event.timestamp,
+      }),
+    }
+  } else if discriminator == AuthorizeEvent::DISCRIMINATOR {
+    let event: AuthorizeEvent = deserialize(payload)?;
+    IndexedEvent {
+      name: "Authorize",
+      accounts: vec![event.stake_account],
+      data: json!({
+        "stake_account": event.stake_account.to_string(),
+        "old_authority": event.old_authority.to_string(),
+        "new_authority": event.new_authority.to_string(),
+        "stake_authorize": match event.stake_authorize {
+          StakeAuthorize::Staker => "staker",
+          StakeAuthorize::Withdrawer => "withdrawer",
+        },
+      }),
+    }
+  } else if discriminator == DelegateEvent::DISCRIMINATOR {
+    let event: DelegateEvent = deserialize(payload)?;
+    IndexedEvent {
+      name: "Delegate",
+      accounts: vec![event.stake_account],
+      data: json!({
+        "stake_account": event.stake_account.to_string(),
+        "vote_account": event.vote_account.to_string(),
+        "stake": event.stake,
+        "activation_epoch": event.activation_epoch,
+      }),
+    }
+  } else if discriminator == SplitEvent::DISCRIMINATOR {
+    let event: SplitEvent = deserialize(payload)?;
+    IndexedEvent {
+      name: "Split",
+      accounts: vec![event.source_account, event.split_account],
+      data: json!({
+        "source_account": event.source_account.to_string(),
+        "split_account": event.split_account.to_string(),
+        "lamports": event.lamports,
+        "timestamp": event.timestamp,
+      }),
+    }
+  } else if discriminator == WithdrawEvent::DISCRIMINATOR {
+    let event: WithdrawEvent = deserialize(payload)?;
+    IndexedEvent {
+      name: "Withdraw",
+      accounts: vec![event.stake_account],
+      data: json!({
+        "stake_account": event.stake_account.to_string(),
+        "withdrawer": event.withdrawer.to_string(),
+        "to": event.to.to_string(),
+        "lamports": event.lamports,
+        "timestamp": event.timestamp,
+      }),
+    }
+  } else if discriminator == DeactivateEvent::DISCRIMINATOR {
+    let event: DeactivateEvent = deserialize(payload)?;
+    IndexedEvent {
+      name: "Deactivate",
+      accounts: vec![event.stake_account],
+      data: json!({
+        "stake_account": event.stake_account.to_string(),
+        "epoch": event.epoch,
+      }),
+    }
+  } else if discriminator == SetLockupEvent::DISCRIMINATOR {
+    let event: SetLockupEvent = deserialize(payload)?;
+    IndexedEvent {
+      name: "SetLockup",
+      accounts: vec![event.stake_account],
+      data: json!({
+        "stake_account": event.stake_account.to_string(),
+        "unix_timestamp": event.lockup.unix_timestamp,
+        "epoch": event.lockup.epoch,
+        "custodian": event.lockup.custodian.to_string(),
+        "timestamp": event.timestamp,
+      }),
+    }
+  } else if discriminator == MergeEvent::DISCRIMINATOR {
+    let event: MergeEvent = deserialize(payload)?;
+    IndexedEvent {
+      name: "Merge",
+      accounts: vec![event.source_account, event.dest_account],
+      data: json!({
+        "source_account": event.source_account.to_string(),
+        "dest_account": event.dest_account.to_string(),
+        "lamports": event.lamports,
+        "timestamp": event.timestamp,
+      }),
+    }
+  } else if discriminator == MoveStakeEvent::DISCRIMINATOR {
+    let event: MoveStakeEvent = deserialize(payload)?;
+    IndexedEvent {
+      name: "MoveStake",
+      accounts: vec![event.source_account, event.dest_account],
+      data: json!({
+        "source_account": event.source_account.to_string(),
+        "dest_account": event.dest_account.to_string(),
+        "lamports": event.lamports,
+        "timestamp": event.timestamp,
+      }),
+    }
+  } else if discriminator == MoveLamportsEvent::DISCRIMINATOR {
+    let event: MoveLamportsEvent = deserialize(payload)?;
+    IndexedEvent {
+      name: "MoveLamports",
+      accounts: vec![event.source_account, event.dest_account],
+      data: json!({
+        "source_account": event.source_account.to_string(),
+        "dest_account": event.dest_account.to_string(),
+        "lamports": event.lamports,
+        "timestamp": event.timestamp,
+      }),
+    }
+  } else if discriminator == ConfigEvent::DISCRIMINATOR {
+    let event: ConfigEvent = deserialize(payload)?;
+    IndexedEvent {
+      name: "Config",
+      accounts: vec![event.config],
+      data: json!({
+        "config": event.config.to_string(),
+        "admin": event.admin.to_string(),
+        "minimum_delegation": event.minimum_delegation,
+        "warmup_cooldown_rate": event.warmup_cooldown_rate,
+        "slash_penalty": event.slash_penalty,
+      }),
+    }
+  } else if discriminator == PointValueEvent::DISCRIMINATOR {
+    let event: PointValueEvent = deserialize(payload)?;
+    IndexedEvent {
+      name: "PointValue",
+      accounts: vec![event.reward_reserve],
+      data: json!({
+        "reward_reserve": event.reward_reserve.to_string(),
+        "rewards": event.point_value.rewards,
+        // u128 does not fit a JSON number
+        "points": event.point_value.points.to_string(),
+      }),
+    }
+  } else if discriminator == RewardEvent::DISCRIMINATOR {
+    let event: RewardEvent = deserialize(payload)?;
+    IndexedEvent {
+      name: "Reward",
+      accounts: vec![event.stake_account],
+      data: json!({
+        "stake_account": event.stake_account.to_string(),
+        "vote_account": event.vote_account.to_string(),
+        "staker_rewards": event.staker_rewards,
+        "voter_rewards": event.voter_rewards,
+        "credits_observed": event.credits_observed,
+        "epoch": event.epoch,
+      }),
+    }
+  } else {
+    return Ok(None);
+  };
+
+  Ok(Some(event))
+}
+
+fn deserialize<T: AnchorDeserialize>(payload: &[u8]) -> Result<T> {
+  T::try_from_slice(payload).map_err(|err| anyhow!("Malformed event: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+  use anchor_lang::Event;
+
+  use super::*;
+  use crate::fixtures::{fixture, lifecycle};
+
+  #[test]
+  fn decodes_each_event_in_a_transaction() {
+    let transactions = lifecycle();
+    let initialize = parse_logs(&transactions[0].logs, &stake_program_project::ID).unwrap();
+    assert_eq!(
+      initialize,
+      vec![IndexedEvent {
+        name: "StakeInitialize",
+        accounts: vec![fixture("stake_a")],
+        data: json!({
+          "stake_account": fixture("stake_a").to_string(),
+          "staker": fixture("staker").to_string(),
+          "withdrawer": fixture("withdrawer").to_string(),
+          "timestamp": 1_760_000_500,
+        }),
+      }]
+    );
+
+    let split = parse_logs(&transactions[5].logs, &stake_program_project::ID).unwrap();
+    assert_eq!(split.len(), 1);
+    assert_eq!(split[0].name, "Split");
+    assert_eq!(
+      split[0].accounts,
+      vec![fixture("stake_a"), fixture("stake_b")]
+    );
+    assert_eq!(split[0].data["lamports"], 4_000_000_000u64);
+  }
+
+  #[test]
+  fn skips_data_logged_by_other_programs() {
+    let transactions = lifecycle();
+    let cpi = transactions.last().unwrap();
+    let events = parse_logs(&cpi.logs, &stake_program_project::ID).unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].name, "SetLockup");
+    assert_eq!(events[0].data["epoch"], 10);
+    assert_eq!(
+      events[0].data["custodian"],
+      fixture("recipient").to_string()
+    );
+
+    // Indexing the other program sees only its own data, which is not a stake program event
+    assert!(parse_logs(&cpi.logs, &fixture("other_program"))
+      .unwrap()
+      .is_empty());
+  }
+
+  #[test]
+  fn round_trips_events_serialized_by_the_program() {
+    let event = RewardEvent {
+      stake_account: Pubkey::new_unique(),
+      vote_account: Pubkey::new_unique(),
+      staker_rewards: 7,
+      voter_rewards: 3,
+      credits_observed: 42,
+      epoch: 9,
+    };
+    let decoded = decode_event(&event.data()).unwrap().unwrap();
+    assert_eq!(decoded.name, "Reward");
+    assert_eq!(decoded.accounts, vec![event.stake_account]);
+    assert_eq!(decoded.data["credits_observed"], 42);
+
+    let logs = vec![
+      format!("Program {} invoke [1]", stake_program_project::ID),
+      format!("{}{}", PROGRAM_DATA, STANDARD.encode(event.data())),
+      format!("Program {} success", stake_program_project::ID),
+    ];
+    assert_eq!(
+      parse_logs(&logs, &stake_program_project::ID).unwrap(),
+      vec![decoded]
+    );
+  }
+
+  #[test]
+  fn decodes_the_config_slash_penalty() {
+    let event = ConfigEvent {
+      config: Pubkey::new_unique(),
+      admin: Pubkey::new_unique(),
+      minimum_delegation: 1_000,
+      warmup_cooldown_rate: 0.09,
+      slash_penalty: 12,
+    };
+    let decoded = decode_event(&event.data()).unwrap().unwrap();
+    assert_eq!(decoded.name, "Config");
+    assert_eq!(decoded.accounts, vec![event.config]);
+    assert_eq!(decoded.data["slash_penalty"], 12);
+    assert_eq!(decoded.data["minimum_delegation"], 1_000);
+  }
+
+  #[test]
+  fn ignores_unknown_discriminators_and_rejects_truncated_events() {
+    assert_eq!(decode_event(&[1; 40]).unwrap(), None);
+
+    let mut data = DeactivateEvent {
+      stake_account: Pubkey::new_unique(),
+      epoch: 4,
+    }
+    .data();
+    data.pop();
+    assert!(decode_event(&data).is_err());
+  }
+}
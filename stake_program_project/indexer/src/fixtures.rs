@@ -0,0 +1,20 @@
+//! Recorded stake program logs shared by the tests.
+
+use std::{collections::HashMap, str::FromStr};
+
+use solana_sdk::pubkey::Pubkey;
+
+use crate::source::LogRecord;
+
+/// An initialize, delegate, failed split, split, authorize, deactivate, merge and withdraw, and
+/// a lockup set through a CPI from another program.
+pub fn lifecycle() -> Vec<LogRecord> {
+  serde_json::from_str(include_str!("../fixtures/stake_lifecycle.json")).unwrap()
+}
+
+/// An account the lifecycle fixture refers to, by its name in `fixtures/accounts.json`.
+pub fn fixture(name: &str) -> Pubkey {
+  let accounts: HashMap<String, String> =
+    serde_json::from_str(include_str!("../fixtures/accounts.json")).unwrap();
+  Pubkey::from_str(&accounts[name]).unwrap()
+}
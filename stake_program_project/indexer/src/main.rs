@@ -0,0 +1,75 @@
+use std::str::FromStr;
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use store::EventStore;
+
+mod events;
+#[cfg(test)]
+mod fixtures;
+mod source;
+mod store;
+
+#[derive(Parser)]
+#[command(name = "stake-indexer")]
+#[command(about = "Indexes stake program events into SQLite", long_about = None)]
+struct Cli {
+  #[command(subcommand)]
+  command: Commands,
+
+  #[arg(short, long, default_value = "http://localhost:8899")]
+  rpc_url: String,
+
+  /// Websocket endpoint; derived from --rpc-url by default
+  #[arg(short, long)]
+  ws_url: Option<String>,
+
+  #[arg(short, long, default_value = "stake-events.db")]
+  database: String,
+
+  #[arg(long, default_value_t = stake_program_project::ID.to_string())]
+  program_id: String,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+  /// Index every transaction since the last backfill, then exit
+  Backfill,
+  /// Backfill, then keep indexing transactions as they are confirmed
+  Watch,
+  /// Print the indexed events involving an account, oldest first
+  History {
+    #[arg(long)]
+    account: String,
+  },
+}
+
+fn main() -> Result<()> {
+  let cli = Cli::parse();
+
+  let program_id = Pubkey::from_str(&cli.program_id)?;
+  let commitment = CommitmentConfig::confirmed();
+  let rpc = RpcClient::new_with_commitment(cli.rpc_url.clone(), commitment);
+  let mut store = EventStore::open(&cli.database)?;
+
+  match cli.command {
+    Commands::Backfill => {
+      let indexed = source::backfill(&rpc, &mut store, &program_id, commitment)?;
+      println!("Indexed {} events", indexed);
+    }
+    Commands::Watch => {
+      let ws_url = cli
+        .ws_url
+        .unwrap_or_else(|| cli.rpc_url.replacen("http", "ws", 1));
+      source::watch(&rpc, &ws_url, &mut store, &program_id, commitment)?;
+    }
+    Commands::History { account } => {
+      let history = store.history(&Pubkey::from_str(&account)?)?;
+      println!("{}", serde_json::to_string_pretty(&history)?);
+    }
+  }
+
+  Ok(())
+}
@@ -0,0 +1,204 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use solana_client::{
+  pubsub_client::PubsubClient,
+  rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient},
+  rpc_config::{RpcTransactionConfig, RpcTransactionLogsConfig, RpcTransactionLogsFilter},
+};
+use solana_sdk::{
+  commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature,
+  transaction::TransactionError,
+};
+use solana_transaction_status_client_types::UiTransactionEncoding;
+
+use crate::{
+  events::parse_logs,
+  store::{EventStore, IndexedTransaction},
+};
+
+/// The log messages of one transaction, as delivered by `logsSubscribe` or read back through
+/// `getTransaction`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogRecord {
+  pub signature: String,
+  pub slot: u64,
+  pub block_time: Option<i64>,
+  pub err: Option<TransactionError>,
+  pub logs: Vec<String>,
+}
+
+impl LogRecord {
+  /// Decodes the record's events, or `None` for a failed transaction whose events were rolled
+  /// back along with everything else it did.
+  pub fn index(&self, program_id: &Pubkey) -> Result<Option<IndexedTransaction>> {
+    if self.err.is_some() {
+      return Ok(None);
+    }
+    Ok(Some(IndexedTransaction {
+      signature: self.signature.clone(),
+      slot: self.slot,
+      block_time: self.block_time,
+      events: parse_logs(&self.logs, program_id)?,
+    }))
+  }
+}
+
+/// Indexes every transaction of `program_id` since the store's cursor (or since the program was
+/// deployed, on the first run), oldest first. Returns the number of new events.
+pub fn backfill(
+  rpc: &RpcClient,
+  store: &mut EventStore,
+  program_id: &Pubkey,
+  commitment: CommitmentConfig,
+) -> Result<usize> {
+  let until = store
+    .cursor()?
+    .map(|signature| Signature::from_str(&signature))
+    .transpose()?;
+
+  // getSignaturesForAddress pages from the newest signature backwards
+  let mut statuses = Vec::new();
+  let mut before = None;
+  loop {
+    let page = rpc.get_signatures_for_address_with_config(
+      program_id,
+      GetConfirmedSignaturesForAddress2Config {
+        before,
+        until,
+        limit: None,
+        commitment: Some(commitment),
+      },
+    )?;
+    let Some(last) = page.last() else {
+      break;
+    };
+    before = Some(Signature::from_str(&last.signature)?);
+    statuses.extend(page);
+  }
+
+  let mut indexed = 0;
+  for status in statuses.iter().rev() {
+    if status.err.is_none() {
+      let record = fetch_record(rpc, &status.signature, commitment)?;
+      if let Some(transaction) = record.index(program_id)? {
+        indexed += store.insert(&transaction)?;
+      }
+    }
+    store.set_cursor(&status.signature)?;
+  }
+
+  Ok(indexed)
+}
+
+fn fetch_record(
+  rpc: &RpcClient,
+  signature: &str,
+  commitment: CommitmentConfig,
+) -> Result<LogRecord> {
+  let transaction = rpc.get_transaction_with_config(
+    &Signature::from_str(signature)?,
+    RpcTransactionConfig {
+      encoding: Some(UiTransactionEncoding::Base64),
+      commitment: Some(commitment),
+      max_supported_transaction_version: Some(0),
+    },
+  )?;
+  let meta = transaction
+    .transaction
+    .meta
+    .ok_or_else(|| anyhow!("Transaction {} has no status meta", signature))?;
+
+  Ok(LogRecord {
+    signature: signature.to_owned(),
+    slot: transaction.slot,
+    block_time: transaction.block_time,
+    err: meta.err,
+    logs: Option::from(meta.log_messages).unwrap_or_default(),
+  })
+}
+
+/// Subscribes to the logs of transactions mentioning `program_id`, backfills what happened
+/// before the subscription started, then indexes live transactions until the connection drops.
+pub fn watch(
+  rpc: &RpcClient,
+  ws_url: &str,
+  store: &mut EventStore,
+  program_id: &Pubkey,
+  commitment: CommitmentConfig,
+) -> Result<()> {
+  // Subscribing first leaves no gap between the backfill and the live stream
+  let (_subscription, receiver) = PubsubClient::logs_subscribe(
+    ws_url,
+    RpcTransactionLogsFilter::Mentions(vec![program_id.to_string()]),
+    RpcTransactionLogsConfig {
+      commitment: Some(commitment),
+    },
+  )?;
+
+  let indexed = backfill(rpc, store, program_id, commitment)?;
+  println!("Backfilled {} events", indexed);
+
+  for response in receiver {
+    // Notifications carry no block time. A block whose time isn't known yet is stored without
+    // one, which a backfill overlapping it fills in
+    let record = LogRecord {
+      signature: response.value.signature,
+      slot: response.context.slot,
+      block_time: rpc.get_block_time(response.context.slot).ok(),
+      err: response.value.err,
+      logs: response.value.logs,
+    };
+    let indexed = index_live(store, program_id, &record)?;
+    if indexed > 0 {
+      println!("{}: {} events", record.signature, indexed);
+    }
+  }
+
+  Err(anyhow!("Log subscription closed"))
+}
+
+/// Indexes a transaction from the live subscription and moves the cursor past it, so a restart
+/// only backfills what came after.
+fn index_live(store: &mut EventStore, program_id: &Pubkey, record: &LogRecord) -> Result<usize> {
+  let indexed = match record.index(program_id)? {
+    Some(transaction) => store.insert(&transaction)?,
+    None => 0,
+  };
+  store.set_cursor(&record.signature)?;
+  Ok(indexed)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::lifecycle;
+
+  #[test]
+  fn live_transactions_move_the_cursor() {
+    let mut store = EventStore::open_in_memory().unwrap();
+    let records = lifecycle();
+
+    assert_eq!(
+      index_live(&mut store, &stake_program_project::ID, &records[0]).unwrap(),
+      1
+    );
+    assert_eq!(
+      store.cursor().unwrap().as_deref(),
+      Some(records[0].signature.as_str())
+    );
+
+    // A failed transaction has no events but is still behind the cursor
+    let failed = records.iter().find(|record| record.err.is_some()).unwrap();
+    assert_eq!(
+      index_live(&mut store, &stake_program_project::ID, failed).unwrap(),
+      0
+    );
+    assert_eq!(
+      store.cursor().unwrap().as_deref(),
+      Some(failed.signature.as_str())
+    );
+  }
+}
@@ -0,0 +1,243 @@
+use std::path::Path;
+
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::events::IndexedEvent;
+
+const SCHEMA: &str = "
+  CREATE TABLE IF NOT EXISTS events (
+    signature TEXT NOT NULL,
+    event_index INTEGER NOT NULL,
+    slot INTEGER NOT NULL,
+    block_time INTEGER,
+    name TEXT NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (signature, event_index)
+  );
+  CREATE TABLE IF NOT EXISTS event_accounts (
+    signature TEXT NOT NULL,
+    event_index INTEGER NOT NULL,
+    account TEXT NOT NULL,
+    PRIMARY KEY (account, signature, event_index)
+  );
+  CREATE TABLE IF NOT EXISTS cursor (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    signature TEXT NOT NULL
+  );
+";
+
+/// A successful transaction and the stake program events it emitted.
+#[derive(Debug, Clone)]
+pub struct IndexedTransaction {
+  pub signature: String,
+  pub slot: u64,
+  pub block_time: Option<i64>,
+  pub events: Vec<IndexedEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StoredEvent {
+  pub signature: String,
+  pub slot: u64,
+  pub block_time: Option<i64>,
+  pub name: String,
+  pub data: serde_json::Value,
+}
+
+pub struct EventStore {
+  conn: Connection,
+}
+
+impl EventStore {
+  pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+    Self::init(Connection::open(path)?)
+  }
+
+  #[cfg(test)]
+  pub fn open_in_memory() -> Result<Self> {
+    Self::init(Connection::open_in_memory()?)
+  }
+
+  fn init(conn: Connection) -> Result<Self> {
+    conn.execute_batch(SCHEMA)?;
+    Ok(Self { conn })
+  }
+
+  /// Stores a transaction's events, returning how many were new. Indexing the same transaction
+  /// twice (a backfill overlapping the live subscription) only fills in a block time the live
+  /// subscription didn't know.
+  pub fn insert(&mut self, transaction: &IndexedTransaction) -> Result<usize> {
+    let tx = self.conn.transaction()?;
+    let mut inserted = 0;
+    for (index, event) in transaction.events.iter().enumerate() {
+      let changed = tx.execute(
+        "INSERT OR IGNORE INTO events (signature, event_index, slot, block_time, name, data)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+          transaction.signature,
+          index as i64,
+          transaction.slot as i64,
+          transaction.block_time,
+          event.name,
+          event.data.to_string(),
+        ],
+      )?;
+      if changed == 0 {
+        tx.execute(
+          "UPDATE events SET block_time = COALESCE(block_time, ?3)
+           WHERE signature = ?1 AND event_index = ?2",
+          params![transaction.signature, index as i64, transaction.block_time],
+        )?;
+        continue;
+      }
+      inserted += 1;
+      for account in &event.accounts {
+        tx.execute(
+          "INSERT OR IGNORE INTO event_accounts (signature, event_index, account)
+           VALUES (?1, ?2, ?3)",
+          params![transaction.signature, index as i64, account.to_string()],
+        )?;
+      }
+    }
+    tx.commit()?;
+    Ok(inserted)
+  }
+
+  /// Every event involving `account`, oldest first.
+  pub fn history(&self, account: &Pubkey) -> Result<Vec<StoredEvent>> {
+    let mut statement = self.conn.prepare(
+      "SELECT events.signature, events.slot, events.block_time, events.name, events.data
+       FROM event_accounts
+       JOIN events USING (signature, event_index)
+       WHERE event_accounts.account = ?1
+       ORDER BY events.slot, events.rowid",
+    )?;
+    let rows = statement.query_map(params![account.to_string()], |row| {
+      Ok((
+        row.get::<_, String>(0)?,
+        row.get::<_, i64>(1)?,
+        row.get::<_, Option<i64>>(2)?,
+        row.get::<_, String>(3)?,
+        row.get::<_, String>(4)?,
+      ))
+    })?;
+
+    rows
+      .map(|row| {
+        let (signature, slot, block_time, name, data) = row?;
+        Ok(StoredEvent {
+          signature,
+          slot: slot as u64,
+          block_time,
+          name,
+          data: serde_json::from_str(&data)?,
+        })
+      })
+      .collect()
+  }
+
+  /// The newest signature indexed, by a backfill or the live subscription; the next backfill
+  /// stops there.
+  pub fn cursor(&self) -> Result<Option<String>> {
+    Ok(
+      self
+        .conn
+        .query_row("SELECT signature FROM cursor WHERE id = 0", [], |row| {
+          row.get(0)
+        })
+        .optional()?,
+    )
+  }
+
+  pub fn set_cursor(&self, signature: &str) -> Result<()> {
+    self.conn.execute(
+      "INSERT INTO cursor (id, signature) VALUES (0, ?1)
+       ON CONFLICT (id) DO UPDATE SET signature = excluded.signature",
+      params![signature],
+    )?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fixtures::{fixture, lifecycle};
+
+  fn index_lifecycle(store: &mut EventStore) -> usize {
+    lifecycle()
+      .iter()
+      .filter_map(|record| record.index(&stake_program_project::ID).unwrap())
+      .map(|transaction| store.insert(&transaction).unwrap())
+      .sum()
+  }
+
+  fn names(events: &[StoredEvent]) -> Vec<&str> {
+    events.iter().map(|event| event.name.as_str()).collect()
+  }
+
+  #[test]
+  fn answers_the_history_of_a_stake_account() {
+    let mut store = EventStore::open_in_memory().unwrap();
+    // The failed split is not indexed
+    assert_eq!(index_lifecycle(&mut store), 10);
+
+    let history = store.history(&fixture("stake_a")).unwrap();
+    assert_eq!(
+      names(&history),
+      ["StakeInitialize", "Delegate", "Split", "Merge", "SetLockup"]
+    );
+    assert_eq!(history[2].slot, 1012);
+    assert_eq!(history[2].block_time, Some(1_760_000_506));
+    assert_eq!(
+      history[2].data["split_account"],
+      fixture("stake_b").to_string()
+    );
+
+    assert_eq!(
+      names(&store.history(&fixture("stake_b")).unwrap()),
+      ["Split", "Authorize", "Deactivate", "Withdraw"]
+    );
+    assert!(store.history(&fixture("staker")).unwrap().is_empty());
+  }
+
+  #[test]
+  fn indexing_twice_adds_nothing() {
+    let mut store = EventStore::open_in_memory().unwrap();
+    index_lifecycle(&mut store);
+    assert_eq!(index_lifecycle(&mut store), 0);
+    assert_eq!(store.history(&fixture("stake_c")).unwrap().len(), 3);
+  }
+
+  #[test]
+  fn indexing_again_fills_in_the_block_time() {
+    let mut store = EventStore::open_in_memory().unwrap();
+    let record = &lifecycle()[0];
+    let mut transaction = record.index(&stake_program_project::ID).unwrap().unwrap();
+    let block_time = transaction.block_time;
+
+    // Indexed live before its block time was known
+    transaction.block_time = None;
+    assert_eq!(store.insert(&transaction).unwrap(), 1);
+    transaction.block_time = block_time;
+    assert_eq!(store.insert(&transaction).unwrap(), 0);
+    transaction.block_time = None;
+    assert_eq!(store.insert(&transaction).unwrap(), 0);
+
+    let history = store.history(&fixture("stake_a")).unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].block_time, Some(1_760_000_500));
+  }
+
+  #[test]
+  fn cursor_tracks_the_latest_backfilled_signature() {
+    let store = EventStore::open_in_memory().unwrap();
+    assert_eq!(store.cursor().unwrap(), None);
+    store.set_cursor("first").unwrap();
+    store.set_cursor("second").unwrap();
+    assert_eq!(store.cursor().unwrap().as_deref(), Some("second"));
+  }
+}
@@ -0,0 +1,1331 @@
+use anchor_lang::{
+  prelude::*,
+  solana_program::sysvar::stake_history,
+  system_program::{self, Transfer},
+};
+use anchor_spl::token::{self, Burn, Mint, MintTo, Token, TokenAccount};
+use stake_program_project::{
+  cpi::accounts as stake_accounts, program::StakeProgramProject, Authorized, Lockup, StakeAccount,
+  StakeAuthorize, StakeConfig, STAKE_CONFIG_SEED,
+};
+
+pub mod math;
+use math::{fee_pool_tokens, lamports_for_pool_tokens, pool_tokens_for_deposit};
+
+declare_id!("4hK35WYyGAJouXWNQab4pcqKbzmMSG4dj7mLMkYCwahY");
+
+// Wrapped like the stake program's `processor`, for the same deprecated `realloc`
+#[allow(deprecated)]
+mod processor {
+  use super::*;
+
+  #[program]
+  pub mod stake_pool {
+    use super::*;
+
+    pub fn initialize_pool(ctx: Context<InitializePool>, fee: Fee) -> Result<()> {
+      fee.validate()?;
+
+      // The reserve is a plain system account, so it has to stay rent exempt on its own
+      let reserve_rent = Rent::get()?.minimum_balance(0);
+      let shortfall = reserve_rent.saturating_sub(ctx.accounts.reserve.lamports());
+      if shortfall > 0 {
+        system_program::transfer(
+          CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            Transfer {
+              from: ctx.accounts.manager.to_account_info(),
+              to: ctx.accounts.reserve.to_account_info(),
+            },
+          ),
+          shortfall,
+        )?;
+      }
+
+      let pool = &mut ctx.accounts.pool;
+      pool.manager = ctx.accounts.manager.key();
+      pool.pool_mint = ctx.accounts.pool_mint.key();
+      pool.manager_fee_account = ctx.accounts.manager_fee_account.key();
+      pool.fee = fee;
+      pool.total_lamports = 0;
+      pool.last_update_epoch = Clock::get()?.epoch;
+      pool.validators = Vec::new();
+      pool.authority_bump = ctx.bumps.withdraw_authority;
+      pool.reserve_bump = ctx.bumps.reserve;
+
+      Ok(())
+    }
+
+    /// Creates the pool's stake account for `vote_account`, funded with the minimum delegation
+    /// from the reserve, and delegates it.
+    pub fn add_validator(ctx: Context<AddValidator>) -> Result<()> {
+      let pool_key = ctx.accounts.pool.key();
+      let vote_key = ctx.accounts.vote_account.key();
+      let pool = &ctx.accounts.pool;
+      require!(
+        pool.validators.len() < MAX_VALIDATORS,
+        PoolError::ValidatorListFull
+      );
+      require!(
+        !pool.validators.contains(&vote_key),
+        PoolError::ValidatorAlreadyAdded
+      );
+
+      let authority = ctx.accounts.withdraw_authority.key();
+      let authority_seeds: &[&[u8]] = &[AUTHORITY_SEED, pool_key.as_ref(), &[pool.authority_bump]];
+      let reserve_seeds: &[&[u8]] = &[RESERVE_SEED, pool_key.as_ref(), &[pool.reserve_bump]];
+      let validator_seeds: &[&[u8]] = &[
+        VALIDATOR_STAKE_SEED,
+        pool_key.as_ref(),
+        vote_key.as_ref(),
+        &[ctx.bumps.validator_stake],
+      ];
+
+      // The reserve pays the new stake account's rent as well as its delegation
+      let minimum_delegation = ctx.accounts.stake_config.minimum_delegation;
+      let stake_rent = Rent::get()?.minimum_balance(8 + StakeAccount::LEN);
+      require!(
+        reserve_available(&ctx.accounts.reserve)? >= stake_rent + minimum_delegation,
+        PoolError::InsufficientReserve
+      );
+
+      stake_program_project::cpi::initialize(
+        CpiContext::new_with_signer(
+          ctx.accounts.stake_program.to_account_info(),
+          stake_accounts::Initialize {
+            stake_account: ctx.accounts.validator_stake.to_account_info(),
+            payer: ctx.accounts.reserve.to_account_info(),
+            rent: ctx.accounts.rent.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+          },
+          &[reserve_seeds, validator_seeds],
+        ),
+        Authorized {
+          staker: authority,
+          withdrawer: authority,
+        },
+        Lockup::default(),
+      )?;
+
+      system_program::transfer(
+        CpiContext::new_with_signer(
+          ctx.accounts.system_program.to_account_info(),
+          Transfer {
+            from: ctx.accounts.reserve.to_account_info(),
+            to: ctx.accounts.validator_stake.to_account_info(),
+          },
+          &[reserve_seeds],
+        ),
+        minimum_delegation,
+      )?;
+
+      stake_program_project::cpi::delegate_stake(CpiContext::new_with_signer(
+        ctx.accounts.stake_program.to_account_info(),
+        stake_accounts::DelegateStake {
+          stake_account: ctx.accounts.validator_stake.to_account_info(),
+          vote_account: ctx.accounts.vote_account.to_account_info(),
+          staker: ctx.accounts.withdraw_authority.to_account_info(),
+          clock: ctx.accounts.clock.to_account_info(),
+          stake_history: ctx.accounts.stake_history.to_account_info(),
+          config: ctx.accounts.stake_config.to_account_info(),
+        },
+        &[authority_seeds],
+      ))?;
+
+      ctx.accounts.pool.validators.push(vote_key);
+
+      Ok(())
+    }
+
+    /// Moves `lamports` from the reserve into a new transient stake account delegated to the
+    /// validator. Once it is active, `merge_transient_stake` folds it into the validator's stake
+    /// account.
+    pub fn increase_validator_stake(
+      ctx: Context<IncreaseValidatorStake>,
+      lamports: u64,
+    ) -> Result<()> {
+      let pool = &ctx.accounts.pool;
+      let pool_key = pool.key();
+      let vote_key = ctx.accounts.vote_account.key();
+      check_validator_idle(
+        pool,
+        &vote_key,
+        &ctx.accounts.validator_stake,
+        &ctx.accounts.transient_stake,
+      )?;
+
+      let stake_rent = Rent::get()?.minimum_balance(8 + StakeAccount::LEN);
+      require!(
+        reserve_available(&ctx.accounts.reserve)?
+          >= lamports
+            .checked_add(stake_rent)
+            .ok_or(PoolError::CalculationOverflow)?,
+        PoolError::InsufficientReserve
+      );
+
+      let authority = ctx.accounts.withdraw_authority.key();
+      let authority_seeds: &[&[u8]] = &[AUTHORITY_SEED, pool_key.as_ref(), &[pool.authority_bump]];
+      let reserve_seeds: &[&[u8]] = &[RESERVE_SEED, pool_key.as_ref(), &[pool.reserve_bump]];
+      let transient_seeds: &[&[u8]] = &[
+        TRANSIENT_STAKE_SEED,
+        pool_key.as_ref(),
+        vote_key.as_ref(),
+        &[ctx.bumps.transient_stake],
+      ];
+
+      sweep_transient_stake(
+        &ctx.accounts.transient_stake,
+        &ctx.accounts.reserve,
+        &ctx.accounts.system_program,
+        transient_seeds,
+      )?;
+      stake_program_project::cpi::initialize(
+        CpiContext::new_with_signer(
+          ctx.accounts.stake_program.to_account_info(),
+          stake_accounts::Initialize {
+            stake_account: ctx.accounts.transient_stake.to_account_info(),
+            payer: ctx.accounts.reserve.to_account_info(),
+            rent: ctx.accounts.rent.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+          },
+          &[reserve_seeds, transient_seeds],
+        ),
+        Authorized {
+          staker: authority,
+          withdrawer: authority,
+        },
+        Lockup::default(),
+      )?;
+
+      system_program::transfer(
+        CpiContext::new_with_signer(
+          ctx.accounts.system_program.to_account_info(),
+          Transfer {
+            from: ctx.accounts.reserve.to_account_info(),
+            to: ctx.accounts.transient_stake.to_account_info(),
+          },
+          &[reserve_seeds],
+        ),
+        lamports,
+      )?;
+
+      stake_program_project::cpi::delegate_stake(CpiContext::new_with_signer(
+        ctx.accounts.stake_program.to_account_info(),
+        stake_accounts::DelegateStake {
+          stake_account: ctx.accounts.transient_stake.to_account_info(),
+          vote_account: ctx.accounts.vote_account.to_account_info(),
+          staker: ctx.accounts.withdraw_authority.to_account_info(),
+          clock: ctx.accounts.clock.to_account_info(),
+          stake_history: ctx.accounts.stake_history.to_account_info(),
+          config: ctx.accounts.stake_config.to_account_info(),
+        },
+        &[authority_seeds],
+      ))?;
+
+      Ok(())
+    }
+
+    /// Splits `lamports` of stake off the validator's stake account into a new transient stake
+    /// account and deactivates it. Once it has cooled down, `merge_transient_stake` returns it to
+    /// the reserve. The reserve pays the transient account's rent.
+    pub fn decrease_validator_stake(
+      ctx: Context<DecreaseValidatorStake>,
+      lamports: u64,
+    ) -> Result<()> {
+      let pool = &ctx.accounts.pool;
+      let pool_key = pool.key();
+      let vote_key = ctx.accounts.vote_account.key();
+      check_validator_idle(
+        pool,
+        &vote_key,
+        &ctx.accounts.validator_stake,
+        &ctx.accounts.transient_stake,
+      )?;
+
+      check_remaining_delegation(
+        &ctx.accounts.validator_stake,
+        lamports,
+        ctx.accounts.stake_config.minimum_delegation,
+      )?;
+
+      let stake_rent = ctx.accounts.validator_stake.meta.rent_exempt_reserve;
+      require!(
+        reserve_available(&ctx.accounts.reserve)? >= stake_rent,
+        PoolError::InsufficientReserve
+      );
+
+      let authority_seeds: &[&[u8]] = &[AUTHORITY_SEED, pool_key.as_ref(), &[pool.authority_bump]];
+      let reserve_seeds: &[&[u8]] = &[RESERVE_SEED, pool_key.as_ref(), &[pool.reserve_bump]];
+      let transient_seeds: &[&[u8]] = &[
+        TRANSIENT_STAKE_SEED,
+        pool_key.as_ref(),
+        vote_key.as_ref(),
+        &[ctx.bumps.transient_stake],
+      ];
+
+      sweep_transient_stake(
+        &ctx.accounts.transient_stake,
+        &ctx.accounts.reserve,
+        &ctx.accounts.system_program,
+        transient_seeds,
+      )?;
+      // The split account is created by the staker, which is the pool authority
+      system_program::transfer(
+        CpiContext::new_with_signer(
+          ctx.accounts.system_program.to_account_info(),
+          Transfer {
+            from: ctx.accounts.reserve.to_account_info(),
+            to: ctx.accounts.withdraw_authority.to_account_info(),
+          },
+          &[reserve_seeds],
+        ),
+        stake_rent,
+      )?;
+      stake_program_project::cpi::split(
+        CpiContext::new_with_signer(
+          ctx.accounts.stake_program.to_account_info(),
+          stake_accounts::Split {
+            source_account: ctx.accounts.validator_stake.to_account_info(),
+            split_account: ctx.accounts.transient_stake.to_account_info(),
+            staker: ctx.accounts.withdraw_authority.to_account_info(),
+            rent: ctx.accounts.rent.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            config: ctx.accounts.stake_config.to_account_info(),
+          },
+          &[authority_seeds, transient_seeds],
+        ),
+        lamports,
+      )?;
+      stake_program_project::cpi::deactivate(CpiContext::new_with_signer(
+        ctx.accounts.stake_program.to_account_info(),
+        stake_accounts::Deactivate {
+          stake_account: ctx.accounts.transient_stake.to_account_info(),
+          staker: ctx.accounts.withdraw_authority.to_account_info(),
+          clock: ctx.accounts.clock.to_account_info(),
+          stake_history: ctx.accounts.stake_history.to_account_info(),
+        },
+        &[authority_seeds],
+      ))?;
+
+      Ok(())
+    }
+
+    /// Settles a validator's transient stake account: stake added by `increase_validator_stake`
+    /// is merged into the validator's stake account once active, and stake taken away by
+    /// `decrease_validator_stake` is withdrawn to the reserve once inactive. Anyone can call it.
+    pub fn merge_transient_stake(ctx: Context<MergeTransientStake>) -> Result<()> {
+      let pool = &ctx.accounts.pool;
+      let pool_key = pool.key();
+      let authority_seeds: &[&[u8]] = &[AUTHORITY_SEED, pool_key.as_ref(), &[pool.authority_bump]];
+
+      let transient = &ctx.accounts.transient_stake;
+      if is_deactivated(transient) {
+        // The stake program refuses while any of it is still cooling down
+        stake_program_project::cpi::withdraw(
+          CpiContext::new_with_signer(
+            ctx.accounts.stake_program.to_account_info(),
+            stake_accounts::Withdraw {
+              stake_account: transient.to_account_info(),
+              withdrawer: ctx.accounts.withdraw_authority.to_account_info(),
+              to: ctx.accounts.reserve.to_account_info(),
+              clock: ctx.accounts.clock.to_account_info(),
+              stake_history: ctx.accounts.stake_history.to_account_info(),
+              custodian: None,
+            },
+            &[authority_seeds],
+          ),
+          transient.to_account_info().lamports(),
+        )?;
+      } else {
+        // The stake program refuses while any of it is still warming up
+        stake_program_project::cpi::merge(CpiContext::new_with_signer(
+          ctx.accounts.stake_program.to_account_info(),
+          stake_accounts::Merge {
+            source_account: transient.to_account_info(),
+            dest_account: ctx.accounts.validator_stake.to_account_info(),
+            staker: ctx.accounts.withdraw_authority.to_account_info(),
+            clock: ctx.accounts.clock.to_account_info(),
+            stake_history: ctx.accounts.stake_history.to_account_info(),
+          },
+          &[authority_seeds],
+        ))?;
+      }
+
+      Ok(())
+    }
+
+    /// Takes a validator out of the pool in two steps an epoch or more apart. The first call
+    /// deactivates the validator's stake account; once it has cooled down, the next call
+    /// withdraws it to the reserve and drops the validator from the list.
+    pub fn remove_validator(ctx: Context<RemoveValidator>) -> Result<()> {
+      let pool = &ctx.accounts.pool;
+      let pool_key = pool.key();
+      let vote_key = ctx.accounts.vote_account.key();
+      require!(
+        pool.validators.contains(&vote_key),
+        PoolError::UnknownValidator
+      );
+      require!(
+        is_idle(&ctx.accounts.transient_stake),
+        PoolError::TransientStakeInUse
+      );
+      let authority_seeds: &[&[u8]] = &[AUTHORITY_SEED, pool_key.as_ref(), &[pool.authority_bump]];
+      let transient_seeds: &[&[u8]] = &[
+        TRANSIENT_STAKE_SEED,
+        pool_key.as_ref(),
+        vote_key.as_ref(),
+        &[ctx.bumps.transient_stake],
+      ];
+      sweep_transient_stake(
+        &ctx.accounts.transient_stake,
+        &ctx.accounts.reserve,
+        &ctx.accounts.system_program,
+        transient_seeds,
+      )?;
+
+      let validator_stake = &ctx.accounts.validator_stake;
+      if !is_deactivated(validator_stake) {
+        return stake_program_project::cpi::deactivate(CpiContext::new_with_signer(
+          ctx.accounts.stake_program.to_account_info(),
+          stake_accounts::Deactivate {
+            stake_account: validator_stake.to_account_info(),
+            staker: ctx.accounts.withdraw_authority.to_account_info(),
+            clock: ctx.accounts.clock.to_account_info(),
+            stake_history: ctx.accounts.stake_history.to_account_info(),
+          },
+          &[authority_seeds],
+        ));
+      }
+
+      // The stake program refuses while any of it is still cooling down
+      stake_program_project::cpi::withdraw(
+        CpiContext::new_with_signer(
+          ctx.accounts.stake_program.to_account_info(),
+          stake_accounts::Withdraw {
+            stake_account: validator_stake.to_account_info(),
+            withdrawer: ctx.accounts.withdraw_authority.to_account_info(),
+            to: ctx.accounts.reserve.to_account_info(),
+            clock: ctx.accounts.clock.to_account_info(),
+            stake_history: ctx.accounts.stake_history.to_account_info(),
+            custodian: None,
+          },
+          &[authority_seeds],
+        ),
+        validator_stake.to_account_info().lamports(),
+      )?;
+      ctx
+        .accounts
+        .pool
+        .validators
+        .retain(|validator| *validator != vote_key);
+
+      Ok(())
+    }
+
+    pub fn deposit_sol(ctx: Context<DepositSol>, lamports: u64) -> Result<()> {
+      let pool = &ctx.accounts.pool;
+      pool.check_updated()?;
+      let pool_tokens =
+        pool_tokens_for_deposit(lamports, pool.total_lamports, ctx.accounts.pool_mint.supply)?;
+      require!(pool_tokens > 0, PoolError::DepositTooSmall);
+
+      system_program::transfer(
+        CpiContext::new(
+          ctx.accounts.system_program.to_account_info(),
+          Transfer {
+            from: ctx.accounts.depositor.to_account_info(),
+            to: ctx.accounts.reserve.to_account_info(),
+          },
+        ),
+        lamports,
+      )?;
+      mint_pool_tokens(
+        &ctx.accounts.pool,
+        &ctx.accounts.token_program,
+        &ctx.accounts.pool_mint,
+        &ctx.accounts.destination,
+        &ctx.accounts.withdraw_authority,
+        pool_tokens,
+      )?;
+
+      ctx.accounts.pool.record_deposit(lamports)?;
+      emit!(DepositEvent {
+        pool: ctx.accounts.pool.key(),
+        depositor: ctx.accounts.depositor.key(),
+        lamports,
+        pool_tokens,
+      });
+
+      Ok(())
+    }
+
+    /// Takes over a fully active stake account delegated to one of the pool's validators: the
+    /// depositor hands both authorities to the pool, which merges it into its own stake account.
+    pub fn deposit_stake(ctx: Context<DepositStake>) -> Result<()> {
+      let pool = &ctx.accounts.pool;
+      pool.check_updated()?;
+      require!(
+        pool.validators.contains(&ctx.accounts.vote_account.key()),
+        PoolError::UnknownValidator
+      );
+      let lamports = ctx.accounts.deposit_stake.to_account_info().lamports();
+      let pool_tokens =
+        pool_tokens_for_deposit(lamports, pool.total_lamports, ctx.accounts.pool_mint.supply)?;
+      require!(pool_tokens > 0, PoolError::DepositTooSmall);
+
+      let pool_key = pool.key();
+      let authority = ctx.accounts.withdraw_authority.key();
+      let authority_seeds: &[&[u8]] = &[AUTHORITY_SEED, pool_key.as_ref(), &[pool.authority_bump]];
+
+      for stake_authorize in [StakeAuthorize::Staker, StakeAuthorize::Withdrawer] {
+        stake_program_project::cpi::authorize(
+          CpiContext::new(
+            ctx.accounts.stake_program.to_account_info(),
+            stake_accounts::Authorize {
+              stake_account: ctx.accounts.deposit_stake.to_account_info(),
+              authority: ctx.accounts.depositor.to_account_info(),
+              clock: ctx.accounts.clock.to_account_info(),
+              custodian: None,
+            },
+          ),
+          authority,
+          stake_authorize,
+        )?;
+      }
+      stake_program_project::cpi::merge(CpiContext::new_with_signer(
+        ctx.accounts.stake_program.to_account_info(),
+        stake_accounts::Merge {
+          source_account: ctx.accounts.deposit_stake.to_account_info(),
+          dest_account: ctx.accounts.validator_stake.to_account_info(),
+          staker: ctx.accounts.withdraw_authority.to_account_info(),
+          clock: ctx.accounts.clock.to_account_info(),
+          stake_history: ctx.accounts.stake_history.to_account_info(),
+        },
+        &[authority_seeds],
+      ))?;
+      mint_pool_tokens(
+        &ctx.accounts.pool,
+        &ctx.accounts.token_program,
+        &ctx.accounts.pool_mint,
+        &ctx.accounts.destination,
+        &ctx.accounts.withdraw_authority,
+        pool_tokens,
+      )?;
+
+      ctx.accounts.pool.record_deposit(lamports)?;
+      emit!(DepositEvent {
+        pool: pool_key,
+        depositor: ctx.accounts.depositor.key(),
+        lamports,
+        pool_tokens,
+      });
+
+      Ok(())
+    }
+
+    /// Burns `pool_tokens` and splits their worth off a validator stake account into
+    /// `split_stake`, handed over to the withdrawer. The withdrawer pays the new account's rent.
+    pub fn withdraw_stake(ctx: Context<WithdrawStake>, pool_tokens: u64) -> Result<()> {
+      let pool = &ctx.accounts.pool;
+      pool.check_updated()?;
+      let lamports = lamports_for_pool_tokens(
+        pool_tokens,
+        pool.total_lamports,
+        ctx.accounts.pool_mint.supply,
+      )?;
+      check_remaining_delegation(
+        &ctx.accounts.validator_stake,
+        lamports,
+        ctx.accounts.stake_config.minimum_delegation,
+      )?;
+
+      let pool_key = pool.key();
+      let authority_seeds: &[&[u8]] = &[AUTHORITY_SEED, pool_key.as_ref(), &[pool.authority_bump]];
+
+      // The split account is created by the staker, which is the pool authority
+      system_program::transfer(
+        CpiContext::new(
+          ctx.accounts.system_program.to_account_info(),
+          Transfer {
+            from: ctx.accounts.withdrawer.to_account_info(),
+            to: ctx.accounts.withdraw_authority.to_account_info(),
+          },
+        ),
+        ctx.accounts.validator_stake.meta.rent_exempt_reserve,
+      )?;
+      stake_program_project::cpi::split(
+        CpiContext::new_with_signer(
+          ctx.accounts.stake_program.to_account_info(),
+          stake_accounts::Split {
+            source_account: ctx.accounts.validator_stake.to_account_info(),
+            split_account: ctx.accounts.split_stake.to_account_info(),
+            staker: ctx.accounts.withdraw_authority.to_account_info(),
+            rent: ctx.accounts.rent.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            config: ctx.accounts.stake_config.to_account_info(),
+          },
+          &[authority_seeds],
+        ),
+        lamports,
+      )?;
+      for stake_authorize in [StakeAuthorize::Staker, StakeAuthorize::Withdrawer] {
+        stake_program_project::cpi::authorize(
+          CpiContext::new_with_signer(
+            ctx.accounts.stake_program.to_account_info(),
+            stake_accounts::Authorize {
+              stake_account: ctx.accounts.split_stake.to_account_info(),
+              authority: ctx.accounts.withdraw_authority.to_account_info(),
+              clock: ctx.accounts.clock.to_account_info(),
+              custodian: None,
+            },
+            &[authority_seeds],
+          ),
+          ctx.accounts.withdrawer.key(),
+          stake_authorize,
+        )?;
+      }
+      burn_pool_tokens(
+        &ctx.accounts.token_program,
+        &ctx.accounts.pool_mint,
+        &ctx.accounts.source,
+        &ctx.accounts.withdrawer,
+        pool_tokens,
+      )?;
+
+      ctx.accounts.pool.record_withdrawal(lamports)?;
+      emit!(WithdrawEvent {
+        pool: pool_key,
+        withdrawer: ctx.accounts.withdrawer.key(),
+        lamports,
+        pool_tokens,
+      });
+
+      Ok(())
+    }
+
+    /// Burns `pool_tokens` and pays their worth out of the reserve.
+    pub fn withdraw_sol(ctx: Context<WithdrawSol>, pool_tokens: u64) -> Result<()> {
+      let pool = &ctx.accounts.pool;
+      pool.check_updated()?;
+      let lamports = lamports_for_pool_tokens(
+        pool_tokens,
+        pool.total_lamports,
+        ctx.accounts.pool_mint.supply,
+      )?;
+      require!(
+        reserve_available(&ctx.accounts.reserve)? >= lamports,
+        PoolError::InsufficientReserve
+      );
+
+      let pool_key = pool.key();
+      let reserve_seeds: &[&[u8]] = &[RESERVE_SEED, pool_key.as_ref(), &[pool.reserve_bump]];
+
+      burn_pool_tokens(
+        &ctx.accounts.token_program,
+        &ctx.accounts.pool_mint,
+        &ctx.accounts.source,
+        &ctx.accounts.withdrawer,
+        pool_tokens,
+      )?;
+      system_program::transfer(
+        CpiContext::new_with_signer(
+          ctx.accounts.system_program.to_account_info(),
+          Transfer {
+            from: ctx.accounts.reserve.to_account_info(),
+            to: ctx.accounts.destination.to_account_info(),
+          },
+          &[reserve_seeds],
+        ),
+        lamports,
+      )?;
+
+      ctx.accounts.pool.record_withdrawal(lamports)?;
+      emit!(WithdrawEvent {
+        pool: pool_key,
+        withdrawer: ctx.accounts.withdrawer.key(),
+        lamports,
+        pool_tokens,
+      });
+
+      Ok(())
+    }
+
+    /// Recounts the pool's lamports at the start of an epoch and pays the manager its fee on the
+    /// growth since the last update. Each validator's stake account and transient stake account
+    /// are passed as remaining accounts, in that order and in the order of `pool.validators`.
+    pub fn update_pool_balance(ctx: Context<UpdatePoolBalance>) -> Result<()> {
+      let pool = &ctx.accounts.pool;
+      let pool_key = pool.key();
+      require!(
+        ctx.remaining_accounts.len() == 2 * pool.validators.len(),
+        PoolError::ValidatorStakeMismatch
+      );
+
+      let mut total_lamports = reserve_available(&ctx.accounts.reserve)?;
+      for (vote_account, accounts) in pool
+        .validators
+        .iter()
+        .zip(ctx.remaining_accounts.chunks_exact(2))
+      {
+        let (validator_stake, transient_stake) = (&accounts[0], &accounts[1]);
+        require_keys_eq!(
+          validator_stake.key(),
+          validator_stake_address(&pool_key, vote_account),
+          PoolError::ValidatorStakeMismatch
+        );
+        require_keys_eq!(
+          transient_stake.key(),
+          transient_stake_address(&pool_key, vote_account),
+          PoolError::ValidatorStakeMismatch
+        );
+        total_lamports = total_lamports
+          .checked_add(validator_stake.lamports())
+          .and_then(|total| total.checked_add(transient_stake.lamports()))
+          .ok_or(PoolError::CalculationOverflow)?;
+      }
+
+      let rewards = total_lamports.saturating_sub(pool.total_lamports);
+      let fee_tokens = fee_pool_tokens(
+        rewards,
+        total_lamports,
+        ctx.accounts.pool_mint.supply,
+        &pool.fee,
+      )?;
+      if fee_tokens > 0 {
+        mint_pool_tokens(
+          &ctx.accounts.pool,
+          &ctx.accounts.token_program,
+          &ctx.accounts.pool_mint,
+          &ctx.accounts.manager_fee_account,
+          &ctx.accounts.withdraw_authority,
+          fee_tokens,
+        )?;
+      }
+
+      let epoch = Clock::get()?.epoch;
+      let pool = &mut ctx.accounts.pool;
+      pool.total_lamports = total_lamports;
+      pool.last_update_epoch = epoch;
+
+      emit!(PoolBalanceEvent {
+        pool: pool_key,
+        epoch,
+        total_lamports,
+        fee_pool_tokens: fee_tokens,
+      });
+
+      Ok(())
+    }
+  }
+}
+pub use processor::*;
+
+fn reserve_available(reserve: &AccountInfo) -> Result<u64> {
+  Ok(
+    reserve
+      .lamports()
+      .saturating_sub(Rent::get()?.minimum_balance(0)),
+  )
+}
+
+/// Whether one of the pool's stake accounts has been deactivated, by `decrease_validator_stake`
+/// for a transient account or by `remove_validator` for a validator's own. An account without a
+/// delegation has nothing left to cool down, so it counts as deactivated.
+fn is_deactivated(stake_account: &StakeAccount) -> bool {
+  stake_account
+    .stake
+    .as_ref()
+    .is_none_or(|stake| stake.delegation.deactivation_epoch != u64::MAX)
+}
+
+/// Whether a transient stake account is free to be created again. The stake program closes it
+/// back to the system program, and anyone can send lamports to the address after that, so its
+/// balance alone doesn't tell.
+fn is_idle(transient_stake: &AccountInfo) -> bool {
+  transient_stake.owner == &system_program::ID && transient_stake.data_is_empty()
+}
+
+/// Returns lamports sent to an idle transient stake account to the reserve.
+fn sweep_transient_stake<'info>(
+  transient_stake: &AccountInfo<'info>,
+  reserve: &AccountInfo<'info>,
+  system_program: &AccountInfo<'info>,
+  transient_seeds: &[&[u8]],
+) -> Result<()> {
+  let lamports = transient_stake.lamports();
+  if lamports == 0 {
+    return Ok(());
+  }
+  system_program::transfer(
+    CpiContext::new_with_signer(
+      system_program.clone(),
+      Transfer {
+        from: transient_stake.clone(),
+        to: reserve.clone(),
+      },
+      &[transient_seeds],
+    ),
+    lamports,
+  )
+}
+
+/// Checks that taking `lamports` of stake off a validator's stake account leaves it the minimum
+/// delegation. Splitting off all of it would leave the account undelegated, and the pool could
+/// neither merge stake back into it nor deactivate it.
+fn check_remaining_delegation(
+  validator_stake: &StakeAccount,
+  lamports: u64,
+  minimum_delegation: u64,
+) -> Result<()> {
+  let delegated = validator_stake
+    .stake
+    .as_ref()
+    .map_or(0, |stake| stake.delegation.stake);
+  require!(
+    delegated
+      .checked_sub(lamports)
+      .is_some_and(|remaining| remaining >= minimum_delegation),
+    PoolError::ValidatorStakeTooLow
+  );
+  Ok(())
+}
+
+/// Checks that the validator's stake can be moved: it is in the pool, not being removed, and has
+/// no transient stake in flight.
+fn check_validator_idle(
+  pool: &StakePool,
+  vote_account: &Pubkey,
+  validator_stake: &StakeAccount,
+  transient_stake: &AccountInfo,
+) -> Result<()> {
+  require!(
+    pool.validators.contains(vote_account),
+    PoolError::UnknownValidator
+  );
+  require!(
+    !is_deactivated(validator_stake),
+    PoolError::ValidatorBeingRemoved
+  );
+  require!(is_idle(transient_stake), PoolError::TransientStakeInUse);
+  Ok(())
+}
+
+fn mint_pool_tokens<'info>(
+  pool: &Account<'info, StakePool>,
+  token_program: &Program<'info, Token>,
+  pool_mint: &Account<'info, Mint>,
+  destination: &Account<'info, TokenAccount>,
+  withdraw_authority: &UncheckedAccount<'info>,
+  pool_tokens: u64,
+) -> Result<()> {
+  let pool_key = pool.key();
+  let authority_seeds: &[&[u8]] = &[AUTHORITY_SEED, pool_key.as_ref(), &[pool.authority_bump]];
+  token::mint_to(
+    CpiContext::new_with_signer(
+      token_program.to_account_info(),
+      MintTo {
+        mint: pool_mint.to_account_info(),
+        to: destination.to_account_info(),
+        authority: withdraw_authority.to_account_info(),
+      },
+      &[authority_seeds],
+    ),
+    pool_tokens,
+  )
+}
+
+fn burn_pool_tokens<'info>(
+  token_program: &Program<'info, Token>,
+  pool_mint: &Account<'info, Mint>,
+  source: &Account<'info, TokenAccount>,
+  owner: &Signer<'info>,
+  pool_tokens: u64,
+) -> Result<()> {
+  token::burn(
+    CpiContext::new(
+      token_program.to_account_info(),
+      Burn {
+        mint: pool_mint.to_account_info(),
+        from: source.to_account_info(),
+        authority: owner.to_account_info(),
+      },
+    ),
+    pool_tokens,
+  )
+}
+
+/// Address of the pool's stake account delegated to `vote_account`.
+pub fn validator_stake_address(pool: &Pubkey, vote_account: &Pubkey) -> Pubkey {
+  Pubkey::find_program_address(
+    &[VALIDATOR_STAKE_SEED, pool.as_ref(), vote_account.as_ref()],
+    &crate::ID,
+  )
+  .0
+}
+
+/// Address of the pool's transient stake account for `vote_account`, which carries stake between
+/// the reserve and the validator's stake account while it warms up or cools down.
+pub fn transient_stake_address(pool: &Pubkey, vote_account: &Pubkey) -> Pubkey {
+  Pubkey::find_program_address(
+    &[TRANSIENT_STAKE_SEED, pool.as_ref(), vote_account.as_ref()],
+    &crate::ID,
+  )
+  .0
+}
+
+#[derive(Accounts)]
+pub struct InitializePool<'info> {
+  #[account(init, payer = manager, space = 8 + StakePool::LEN)]
+  pub pool: Account<'info, StakePool>,
+  #[account(mut)]
+  pub manager: Signer<'info>,
+  /// CHECK: PDA that is staker and withdrawer of the pool's stake accounts and mints pool tokens
+  #[account(seeds = [AUTHORITY_SEED, pool.key().as_ref()], bump)]
+  pub withdraw_authority: UncheckedAccount<'info>,
+  #[account(mut, seeds = [RESERVE_SEED, pool.key().as_ref()], bump)]
+  pub reserve: SystemAccount<'info>,
+  #[account(
+    init,
+    payer = manager,
+    mint::decimals = 9,
+    mint::authority = withdraw_authority
+  )]
+  pub pool_mint: Account<'info, Mint>,
+  #[account(
+    init,
+    payer = manager,
+    seeds = [MANAGER_FEE_SEED, pool.key().as_ref()],
+    bump,
+    token::mint = pool_mint,
+    token::authority = manager
+  )]
+  pub manager_fee_account: Account<'info, TokenAccount>,
+  pub token_program: Program<'info, Token>,
+  pub system_program: Program<'info, System>,
+  pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct AddValidator<'info> {
+  #[account(mut, has_one = manager @ PoolError::UnauthorizedManager)]
+  pub pool: Account<'info, StakePool>,
+  pub manager: Signer<'info>,
+  /// CHECK: Pool authority PDA, signs as the new stake account's staker
+  #[account(seeds = [AUTHORITY_SEED, pool.key().as_ref()], bump = pool.authority_bump)]
+  pub withdraw_authority: UncheckedAccount<'info>,
+  #[account(mut, seeds = [RESERVE_SEED, pool.key().as_ref()], bump = pool.reserve_bump)]
+  pub reserve: SystemAccount<'info>,
+  /// CHECK: Created by the stake program's `initialize`
+  #[account(
+    mut,
+    seeds = [VALIDATOR_STAKE_SEED, pool.key().as_ref(), vote_account.key().as_ref()],
+    bump
+  )]
+  pub validator_stake: UncheckedAccount<'info>,
+  /// CHECK: Validated by the stake program's `delegate_stake`
+  pub vote_account: UncheckedAccount<'info>,
+  #[account(
+    seeds = [STAKE_CONFIG_SEED],
+    bump = stake_config.bump,
+    seeds::program = stake_program.key()
+  )]
+  pub stake_config: Account<'info, StakeConfig>,
+  pub clock: Sysvar<'info, Clock>,
+  /// CHECK: Stake history sysvar, passed through to the stake program
+  #[account(address = stake_history::ID)]
+  pub stake_history: UncheckedAccount<'info>,
+  pub stake_program: Program<'info, StakeProgramProject>,
+  pub rent: Sysvar<'info, Rent>,
+  pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct IncreaseValidatorStake<'info> {
+  #[account(has_one = manager @ PoolError::UnauthorizedManager)]
+  pub pool: Account<'info, StakePool>,
+  pub manager: Signer<'info>,
+  /// CHECK: Pool authority PDA, signs as the transient stake account's staker
+  #[account(seeds = [AUTHORITY_SEED, pool.key().as_ref()], bump = pool.authority_bump)]
+  pub withdraw_authority: UncheckedAccount<'info>,
+  #[account(mut, seeds = [RESERVE_SEED, pool.key().as_ref()], bump = pool.reserve_bump)]
+  pub reserve: SystemAccount<'info>,
+  #[account(
+    seeds = [VALIDATOR_STAKE_SEED, pool.key().as_ref(), vote_account.key().as_ref()],
+    bump
+  )]
+  pub validator_stake: Account<'info, StakeAccount>,
+  /// CHECK: Created by the stake program's `initialize`
+  #[account(
+    mut,
+    seeds = [TRANSIENT_STAKE_SEED, pool.key().as_ref(), vote_account.key().as_ref()],
+    bump
+  )]
+  pub transient_stake: UncheckedAccount<'info>,
+  /// CHECK: Validated by the stake program's `delegate_stake`
+  pub vote_account: UncheckedAccount<'info>,
+  #[account(
+    seeds = [STAKE_CONFIG_SEED],
+    bump = stake_config.bump,
+    seeds::program = stake_program.key()
+  )]
+  pub stake_config: Account<'info, StakeConfig>,
+  pub clock: Sysvar<'info, Clock>,
+  /// CHECK: Stake history sysvar, passed through to the stake program
+  #[account(address = stake_history::ID)]
+  pub stake_history: UncheckedAccount<'info>,
+  pub stake_program: Program<'info, StakeProgramProject>,
+  pub rent: Sysvar<'info, Rent>,
+  pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DecreaseValidatorStake<'info> {
+  #[account(has_one = manager @ PoolError::UnauthorizedManager)]
+  pub pool: Account<'info, StakePool>,
+  pub manager: Signer<'info>,
+  /// CHECK: Pool authority PDA, splits the stake and pays for the transient account
+  #[account(mut, seeds = [AUTHORITY_SEED, pool.key().as_ref()], bump = pool.authority_bump)]
+  pub withdraw_authority: UncheckedAccount<'info>,
+  #[account(mut, seeds = [RESERVE_SEED, pool.key().as_ref()], bump = pool.reserve_bump)]
+  pub reserve: SystemAccount<'info>,
+  #[account(
+    mut,
+    seeds = [VALIDATOR_STAKE_SEED, pool.key().as_ref(), vote_account.key().as_ref()],
+    bump
+  )]
+  pub validator_stake: Account<'info, StakeAccount>,
+  /// CHECK: Created by the stake program's `split`
+  #[account(
+    mut,
+    seeds = [TRANSIENT_STAKE_SEED, pool.key().as_ref(), vote_account.key().as_ref()],
+    bump
+  )]
+  pub transient_stake: UncheckedAccount<'info>,
+  /// CHECK: Only used to derive the validator's stake accounts
+  pub vote_account: UncheckedAccount<'info>,
+  #[account(
+    seeds = [STAKE_CONFIG_SEED],
+    bump = stake_config.bump,
+    seeds::program = stake_program.key()
+  )]
+  pub stake_config: Account<'info, StakeConfig>,
+  pub clock: Sysvar<'info, Clock>,
+  /// CHECK: Stake history sysvar, passed through to the stake program
+  #[account(address = stake_history::ID)]
+  pub stake_history: UncheckedAccount<'info>,
+  pub stake_program: Program<'info, StakeProgramProject>,
+  pub rent: Sysvar<'info, Rent>,
+  pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MergeTransientStake<'info> {
+  pub pool: Account<'info, StakePool>,
+  /// CHECK: Pool authority PDA, staker and withdrawer of both stake accounts
+  #[account(mut, seeds = [AUTHORITY_SEED, pool.key().as_ref()], bump = pool.authority_bump)]
+  pub withdraw_authority: UncheckedAccount<'info>,
+  #[account(mut, seeds = [RESERVE_SEED, pool.key().as_ref()], bump = pool.reserve_bump)]
+  pub reserve: SystemAccount<'info>,
+  #[account(
+    mut,
+    seeds = [VALIDATOR_STAKE_SEED, pool.key().as_ref(), vote_account.key().as_ref()],
+    bump
+  )]
+  pub validator_stake: Account<'info, StakeAccount>,
+  #[account(
+    mut,
+    seeds = [TRANSIENT_STAKE_SEED, pool.key().as_ref(), vote_account.key().as_ref()],
+    bump
+  )]
+  pub transient_stake: Account<'info, StakeAccount>,
+  /// CHECK: Only used to derive the validator's stake accounts
+  pub vote_account: UncheckedAccount<'info>,
+  pub clock: Sysvar<'info, Clock>,
+  /// CHECK: Stake history sysvar, passed through to the stake program
+  #[account(address = stake_history::ID)]
+  pub stake_history: UncheckedAccount<'info>,
+  pub stake_program: Program<'info, StakeProgramProject>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveValidator<'info> {
+  #[account(mut, has_one = manager @ PoolError::UnauthorizedManager)]
+  pub pool: Account<'info, StakePool>,
+  pub manager: Signer<'info>,
+  /// CHECK: Pool authority PDA, staker and withdrawer of the validator's stake account
+  #[account(mut, seeds = [AUTHORITY_SEED, pool.key().as_ref()], bump = pool.authority_bump)]
+  pub withdraw_authority: UncheckedAccount<'info>,
+  #[account(mut, seeds = [RESERVE_SEED, pool.key().as_ref()], bump = pool.reserve_bump)]
+  pub reserve: SystemAccount<'info>,
+  #[account(
+    mut,
+    seeds = [VALIDATOR_STAKE_SEED, pool.key().as_ref(), vote_account.key().as_ref()],
+    bump
+  )]
+  pub validator_stake: Account<'info, StakeAccount>,
+  /// CHECK: Has to be idle; lamports sent to it are swept to the reserve
+  #[account(
+    mut,
+    seeds = [TRANSIENT_STAKE_SEED, pool.key().as_ref(), vote_account.key().as_ref()],
+    bump
+  )]
+  pub transient_stake: UncheckedAccount<'info>,
+  /// CHECK: Only used to derive the validator's stake accounts
+  pub vote_account: UncheckedAccount<'info>,
+  pub clock: Sysvar<'info, Clock>,
+  /// CHECK: Stake history sysvar, passed through to the stake program
+  #[account(address = stake_history::ID)]
+  pub stake_history: UncheckedAccount<'info>,
+  pub stake_program: Program<'info, StakeProgramProject>,
+  pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositSol<'info> {
+  #[account(mut, has_one = pool_mint)]
+  pub pool: Account<'info, StakePool>,
+  #[account(mut)]
+  pub depositor: Signer<'info>,
+  #[account(mut, seeds = [RESERVE_SEED, pool.key().as_ref()], bump = pool.reserve_bump)]
+  pub reserve: SystemAccount<'info>,
+  /// CHECK: Pool authority PDA, mints the pool tokens
+  #[account(seeds = [AUTHORITY_SEED, pool.key().as_ref()], bump = pool.authority_bump)]
+  pub withdraw_authority: UncheckedAccount<'info>,
+  #[account(mut)]
+  pub pool_mint: Account<'info, Mint>,
+  #[account(mut, token::mint = pool_mint)]
+  pub destination: Account<'info, TokenAccount>,
+  pub token_program: Program<'info, Token>,
+  pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositStake<'info> {
+  #[account(mut, has_one = pool_mint)]
+  pub pool: Account<'info, StakePool>,
+  /// Staker and withdrawer of the deposited stake account
+  pub depositor: Signer<'info>,
+  #[account(mut)]
+  pub deposit_stake: Account<'info, StakeAccount>,
+  /// CHECK: Only used to derive the validator stake account; the merge checks the delegation
+  pub vote_account: UncheckedAccount<'info>,
+  #[account(
+    mut,
+    seeds = [VALIDATOR_STAKE_SEED, pool.key().as_ref(), vote_account.key().as_ref()],
+    bump
+  )]
+  pub validator_stake: Account<'info, StakeAccount>,
+  /// CHECK: Pool authority PDA, becomes the deposit's authority and mints the pool tokens
+  #[account(mut, seeds = [AUTHORITY_SEED, pool.key().as_ref()], bump = pool.authority_bump)]
+  pub withdraw_authority: UncheckedAccount<'info>,
+  #[account(mut)]
+  pub pool_mint: Account<'info, Mint>,
+  #[account(mut, token::mint = pool_mint)]
+  pub destination: Account<'info, TokenAccount>,
+  pub clock: Sysvar<'info, Clock>,
+  /// CHECK: Stake history sysvar, passed through to the stake program
+  #[account(address = stake_history::ID)]
+  pub stake_history: UncheckedAccount<'info>,
+  pub stake_program: Program<'info, StakeProgramProject>,
+  pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawStake<'info> {
+  #[account(mut, has_one = pool_mint)]
+  pub pool: Account<'info, StakePool>,
+  /// Owner of the burned pool tokens and new authority of the split stake account
+  #[account(mut)]
+  pub withdrawer: Signer<'info>,
+  #[account(mut, token::mint = pool_mint, token::authority = withdrawer)]
+  pub source: Account<'info, TokenAccount>,
+  /// CHECK: Only used to derive the validator stake account
+  pub vote_account: UncheckedAccount<'info>,
+  #[account(
+    mut,
+    seeds = [VALIDATOR_STAKE_SEED, pool.key().as_ref(), vote_account.key().as_ref()],
+    bump
+  )]
+  pub validator_stake: Account<'info, StakeAccount>,
+  /// CHECK: Created by the stake program's `split`
+  #[account(mut)]
+  pub split_stake: Signer<'info>,
+  /// CHECK: Pool authority PDA, splits the stake and pays for the new account
+  #[account(mut, seeds = [AUTHORITY_SEED, pool.key().as_ref()], bump = pool.authority_bump)]
+  pub withdraw_authority: UncheckedAccount<'info>,
+  #[account(mut)]
+  pub pool_mint: Account<'info, Mint>,
+  #[account(
+    seeds = [STAKE_CONFIG_SEED],
+    bump = stake_config.bump,
+    seeds::program = stake_program.key()
+  )]
+  pub stake_config: Account<'info, StakeConfig>,
+  pub clock: Sysvar<'info, Clock>,
+  pub rent: Sysvar<'info, Rent>,
+  pub stake_program: Program<'info, StakeProgramProject>,
+  pub token_program: Program<'info, Token>,
+  pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawSol<'info> {
+  #[account(mut, has_one = pool_mint)]
+  pub pool: Account<'info, StakePool>,
+  pub withdrawer: Signer<'info>,
+  #[account(mut, token::mint = pool_mint, token::authority = withdrawer)]
+  pub source: Account<'info, TokenAccount>,
+  #[account(mut, seeds = [RESERVE_SEED, pool.key().as_ref()], bump = pool.reserve_bump)]
+  pub reserve: SystemAccount<'info>,
+  /// CHECK: Receives the withdrawn lamports
+  #[account(mut)]
+  pub destination: UncheckedAccount<'info>,
+  #[account(mut)]
+  pub pool_mint: Account<'info, Mint>,
+  pub token_program: Program<'info, Token>,
+  pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePoolBalance<'info> {
+  #[account(mut, has_one = pool_mint, has_one = manager_fee_account)]
+  pub pool: Account<'info, StakePool>,
+  #[account(seeds = [RESERVE_SEED, pool.key().as_ref()], bump = pool.reserve_bump)]
+  pub reserve: SystemAccount<'info>,
+  /// CHECK: Pool authority PDA, mints the manager fee
+  #[account(seeds = [AUTHORITY_SEED, pool.key().as_ref()], bump = pool.authority_bump)]
+  pub withdraw_authority: UncheckedAccount<'info>,
+  #[account(mut)]
+  pub pool_mint: Account<'info, Mint>,
+  #[account(mut)]
+  pub manager_fee_account: Account<'info, TokenAccount>,
+  pub token_program: Program<'info, Token>,
+}
+
+pub const AUTHORITY_SEED: &[u8] = b"withdraw_authority";
+pub const RESERVE_SEED: &[u8] = b"reserve";
+pub const VALIDATOR_STAKE_SEED: &[u8] = b"validator_stake";
+pub const TRANSIENT_STAKE_SEED: &[u8] = b"transient_stake";
+pub const MANAGER_FEE_SEED: &[u8] = b"manager_fee";
+pub const MAX_VALIDATORS: usize = 32;
+
+/// Share of epoch rewards paid to the pool manager.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Fee {
+  pub numerator: u64,
+  pub denominator: u64,
+}
+
+impl Fee {
+  const LEN: usize = 8 + 8;
+
+  fn validate(&self) -> Result<()> {
+    require!(
+      self.denominator > 0 && self.numerator <= self.denominator,
+      PoolError::InvalidFee
+    );
+    Ok(())
+  }
+}
+
+#[account]
+pub struct StakePool {
+  pub manager: Pubkey,
+  pub pool_mint: Pubkey,
+  pub manager_fee_account: Pubkey,
+  pub fee: Fee,
+  /// Lamports backing the pool tokens as of `last_update_epoch`, adjusted by every deposit and
+  /// withdrawal since
+  pub total_lamports: u64,
+  pub last_update_epoch: u64,
+  /// Vote accounts the pool has a stake account for
+  pub validators: Vec<Pubkey>,
+  pub authority_bump: u8,
+  pub reserve_bump: u8,
+}
+
+impl StakePool {
+  const LEN: usize = 32 + 32 + 32 + Fee::LEN + 8 + 8 + 4 + 32 * MAX_VALIDATORS + 1 + 1;
+
+  /// Deposits and withdrawals are priced from `total_lamports`, which is only current once
+  /// `update_pool_balance` has run in this epoch.
+  fn check_updated(&self) -> Result<()> {
+    require!(
+      self.last_update_epoch == Clock::get()?.epoch,
+      PoolError::StalePoolBalance
+    );
+    Ok(())
+  }
+
+  fn record_deposit(&mut self, lamports: u64) -> Result<()> {
+    self.total_lamports = self
+      .total_lamports
+      .checked_add(lamports)
+      .ok_or(PoolError::CalculationOverflow)?;
+    Ok(())
+  }
+
+  fn record_withdrawal(&mut self, lamports: u64) -> Result<()> {
+    self.total_lamports = self
+      .total_lamports
+      .checked_sub(lamports)
+      .ok_or(PoolError::CalculationOverflow)?;
+    Ok(())
+  }
+}
+
+#[event]
+pub struct DepositEvent {
+  pub pool: Pubkey,
+  pub depositor: Pubkey,
+  pub lamports: u64,
+  pub pool_tokens: u64,
+}
+
+#[event]
+pub struct WithdrawEvent {
+  pub pool: Pubkey,
+  pub withdrawer: Pubkey,
+  pub lamports: u64,
+  pub pool_tokens: u64,
+}
+
+#[event]
+pub struct PoolBalanceEvent {
+  pub pool: Pubkey,
+  pub epoch: u64,
+  pub total_lamports: u64,
+  pub fee_pool_tokens: u64,
+}
+
+#[error_code]
+pub enum PoolError {
+  #[msg("Fee numerator must not exceed a non-zero denominator")]
+  InvalidFee,
+  #[msg("Only the pool manager can do this")]
+  UnauthorizedManager,
+  #[msg("The pool already has the maximum number of validators")]
+  ValidatorListFull,
+  #[msg("The validator is already in the pool")]
+  ValidatorAlreadyAdded,
+  #[msg("The validator is not in the pool")]
+  UnknownValidator,
+  #[msg("Validator stake accounts do not match the pool's validators")]
+  ValidatorStakeMismatch,
+  #[msg("The pool balance has not been updated this epoch")]
+  StalePoolBalance,
+  #[msg("The reserve does not hold enough lamports")]
+  InsufficientReserve,
+  #[msg("The deposit is worth less than one pool token")]
+  DepositTooSmall,
+  #[msg("The pool has no tokens outstanding")]
+  EmptyPool,
+  #[msg("Pool token calculation overflow")]
+  CalculationOverflow,
+  #[msg("The validator's transient stake account is still in use")]
+  TransientStakeInUse,
+  #[msg("The validator is being removed from the pool")]
+  ValidatorBeingRemoved,
+  #[msg("The validator's stake account has to keep the minimum delegation")]
+  ValidatorStakeTooLow,
+}
@@ -0,0 +1,114 @@
+use anchor_lang::prelude::*;
+
+use crate::{Fee, PoolError};
+
+/// Pool tokens minted for `lamports` deposited into a pool worth `total_lamports` with `supply`
+/// tokens outstanding. An empty pool mints one token per lamport.
+pub fn pool_tokens_for_deposit(lamports: u64, total_lamports: u64, supply: u64) -> Result<u64> {
+  if supply == 0 || total_lamports == 0 {
+    return Ok(lamports);
+  }
+  mul_div(lamports, supply, total_lamports)
+}
+
+/// Lamports `pool_tokens` are worth, rounded down so a withdrawal never takes more than its share.
+pub fn lamports_for_pool_tokens(pool_tokens: u64, total_lamports: u64, supply: u64) -> Result<u64> {
+  require!(supply > 0, PoolError::EmptyPool);
+  mul_div(pool_tokens, total_lamports, supply)
+}
+
+/// Pool tokens paying the manager its `fee` of `rewards`, sized so that after minting them to
+/// the manager they are worth exactly the fee out of the updated `total_lamports`.
+pub fn fee_pool_tokens(rewards: u64, total_lamports: u64, supply: u64, fee: &Fee) -> Result<u64> {
+  let fee_lamports = mul_div(rewards, fee.numerator, fee.denominator)?;
+  if fee_lamports == 0 || supply == 0 {
+    return Ok(0);
+  }
+  // A fee taking the whole pool leaves nothing to price the tokens against
+  match total_lamports.checked_sub(fee_lamports) {
+    Some(remaining) if remaining > 0 => mul_div(fee_lamports, supply, remaining),
+    _ => Ok(0),
+  }
+}
+
+fn mul_div(value: u64, numerator: u64, denominator: u64) -> Result<u64> {
+  let result = u128::from(value)
+    .checked_mul(u128::from(numerator))
+    .and_then(|product| product.checked_div(u128::from(denominator)))
+    .ok_or(PoolError::CalculationOverflow)?;
+  u64::try_from(result).map_err(|_| error!(PoolError::CalculationOverflow))
+}
+
+#[cfg(test)]
+mod tests {
+  use proptest::prelude::*;
+
+  use super::*;
+
+  const FEE: Fee = Fee {
+    numerator: 5,
+    denominator: 100,
+  };
+
+  #[test]
+  fn first_deposit_mints_one_token_per_lamport() {
+    assert_eq!(pool_tokens_for_deposit(1_000, 0, 0).unwrap(), 1_000);
+    assert!(lamports_for_pool_tokens(1_000, 0, 0).is_err());
+  }
+
+  #[test]
+  fn deposits_after_rewards_mint_fewer_tokens() {
+    // 100 tokens are worth 110 lamports after rewards
+    assert_eq!(pool_tokens_for_deposit(110, 110, 100).unwrap(), 100);
+    assert_eq!(lamports_for_pool_tokens(50, 110, 100).unwrap(), 55);
+  }
+
+  #[test]
+  fn fee_tokens_are_worth_the_fee() {
+    // 1000 lamports of rewards on a pool now worth 11_000, at 5%
+    let fee_tokens = fee_pool_tokens(1_000, 11_000, 10_000, &FEE).unwrap();
+    assert_eq!(fee_tokens, 45);
+    assert_eq!(
+      lamports_for_pool_tokens(fee_tokens, 11_000, 10_000 + fee_tokens).unwrap(),
+      49
+    );
+    assert_eq!(fee_pool_tokens(0, 11_000, 10_000, &FEE).unwrap(), 0);
+    assert_eq!(fee_pool_tokens(1_000, 1_000, 0, &FEE).unwrap(), 0);
+  }
+
+  proptest! {
+    #[test]
+    fn a_deposit_never_withdraws_more_than_it_put_in(
+      total_lamports in 1..u64::MAX / 4,
+      supply in 1..u64::MAX / 4,
+      lamports in 0..u64::MAX / 4,
+    ) {
+      let Ok(tokens) = pool_tokens_for_deposit(lamports, total_lamports, supply) else {
+        return Ok(());
+      };
+      let Some(new_supply) = supply.checked_add(tokens) else {
+        return Ok(());
+      };
+      let withdrawn = lamports_for_pool_tokens(tokens, total_lamports + lamports, new_supply);
+      prop_assert!(withdrawn.map_or(true, |withdrawn| withdrawn <= lamports));
+    }
+
+    #[test]
+    fn the_manager_never_gets_more_than_its_fee(
+      rewards in 0..u64::MAX / 4,
+      extra in 1..u64::MAX / 4,
+      supply in 1..u64::MAX / 4,
+    ) {
+      let total_lamports = rewards + extra;
+      let fee_lamports = u128::from(rewards) * u128::from(FEE.numerator) / u128::from(FEE.denominator);
+      let Ok(fee_tokens) = fee_pool_tokens(rewards, total_lamports, supply, &FEE) else {
+        return Ok(());
+      };
+      let Some(new_supply) = supply.checked_add(fee_tokens) else {
+        return Ok(());
+      };
+      let value = lamports_for_pool_tokens(fee_tokens, total_lamports, new_supply);
+      prop_assert!(value.map_or(true, |value| u128::from(value) <= fee_lamports));
+    }
+  }
+}
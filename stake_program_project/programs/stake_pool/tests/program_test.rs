@@ -0,0 +1,1158 @@
+//! End-to-end tests of the pool against the stake program in `solana-program-test`.
+//!
+//! Both programs are loaded compiled from `SBF_OUT_DIR`, which defaults to the workspace's
+//! `target/deploy`, so run `anchor build` first. `PROGRAM_TEST_BUILTIN=1 cargo test` runs them as
+//! native builtins instead, for machines without the SBF toolchain.
+
+// System instruction and vote state helpers are only reachable through deprecated re-exports
+#![allow(deprecated)]
+
+use anchor_lang::{
+  prelude::{AccountInfo, AccountMeta, Pubkey},
+  solana_program::{
+    instruction::{Instruction, InstructionError},
+    program_pack::Pack,
+    system_instruction, system_program,
+    sysvar::{
+      self,
+      stake_history::{StakeHistory, StakeHistoryEntry},
+    },
+    vote::{
+      program as vote_program,
+      state::{VoteState, VoteStateVersions},
+    },
+  },
+  AccountDeserialize, AccountSerialize, InstructionData, ToAccountMetas,
+};
+use anchor_spl::token::{spl_token, Mint, TokenAccount};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+  account::{Account, AccountSharedData},
+  bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+  entrypoint::ProgramResult,
+  native_token::LAMPORTS_PER_SOL,
+  signature::{Keypair, Signer},
+  transaction::{Transaction, TransactionError},
+};
+use stake_pool::{
+  math::{fee_pool_tokens, lamports_for_pool_tokens},
+  transient_stake_address, validator_stake_address, Fee, PoolError, StakePool, AUTHORITY_SEED,
+  MANAGER_FEE_SEED, RESERVE_SEED,
+};
+use stake_program_project::{
+  program_data_address, Authorized, Lockup, StakeAccount, StakeConfigArgs,
+  DEFAULT_MINIMUM_DELEGATION, DEFAULT_SLASH_PENALTY, NEW_WARMUP_COOLDOWN_RATE, STAKE_CONFIG_SEED,
+};
+
+const STAKE: u64 = 10 * LAMPORTS_PER_SOL;
+const ACTIVATION_EPOCH: u64 = 2;
+const ACTIVE_EPOCH: u64 = 4;
+const FEE: Fee = Fee {
+  numerator: 5,
+  denominator: 100,
+};
+
+// Only used as the `PROGRAM_TEST_BUILTIN` fallback. `entry` ties the account slice to the
+// lifetime of the accounts in it, which the builtin signature doesn't; a leaked copy of the slice
+// satisfies both
+fn process_pool_instruction(
+  program_id: &Pubkey,
+  accounts: &[AccountInfo],
+  data: &[u8],
+) -> ProgramResult {
+  let accounts = Box::leak(accounts.to_vec().into_boxed_slice());
+  stake_pool::entry(program_id, accounts, data)
+}
+
+fn process_stake_instruction(
+  program_id: &Pubkey,
+  accounts: &[AccountInfo],
+  data: &[u8],
+) -> ProgramResult {
+  let accounts = Box::leak(accounts.to_vec().into_boxed_slice());
+  stake_program_project::entry(program_id, accounts, data)
+}
+
+/// Cluster stake history in which the pool's and the user's delegations, made in
+/// `ACTIVATION_EPOCH`, are the only ones activating and are fully active by `ACTIVE_EPOCH`.
+fn cluster_stake_history() -> StakeHistory {
+  let mut history = StakeHistory::default();
+  for (epoch, effective, activating) in [
+    (
+      ACTIVATION_EPOCH,
+      100 * LAMPORTS_PER_SOL,
+      11 * LAMPORTS_PER_SOL,
+    ),
+    (
+      ACTIVATION_EPOCH + 1,
+      109 * LAMPORTS_PER_SOL,
+      2 * LAMPORTS_PER_SOL,
+    ),
+  ] {
+    history.add(
+      epoch,
+      StakeHistoryEntry {
+        effective,
+        activating,
+        deactivating: 0,
+      },
+    );
+  }
+  history
+}
+
+fn new_vote_account() -> Account {
+  let mut data = vec![0; VoteState::size_of()];
+  VoteState::serialize(
+    &VoteStateVersions::new_current(VoteState::default()),
+    &mut data,
+  )
+  .unwrap();
+
+  Account {
+    lamports: LAMPORTS_PER_SOL,
+    data,
+    owner: vote_program::ID,
+    executable: false,
+    rent_epoch: 0,
+  }
+}
+
+fn stake_config_address() -> Pubkey {
+  Pubkey::find_program_address(&[STAKE_CONFIG_SEED], &stake_program_project::ID).0
+}
+
+fn pool_address(seed: &[u8], pool: &Pubkey) -> Pubkey {
+  Pubkey::find_program_address(&[seed, pool.as_ref()], &stake_pool::ID).0
+}
+
+fn assert_pool_error(result: Result<(), BanksClientError>, expected: PoolError) {
+  match result.map_err(|error| error.unwrap()) {
+    Err(TransactionError::InstructionError(_, InstructionError::Custom(code))) => {
+      assert_eq!(code, u32::from(expected), "expected {expected:?}")
+    }
+    other => panic!("expected {expected:?}, got {other:?}"),
+  }
+}
+
+struct TestEnv {
+  context: ProgramTestContext,
+  vote_account: Pubkey,
+  stake_history: StakeHistory,
+  pool: Pubkey,
+  pool_mint: Pubkey,
+  manager: Keypair,
+}
+
+impl TestEnv {
+  /// Starts a cluster with the stake program configured and a pool created in
+  /// `ACTIVATION_EPOCH`.
+  async fn start() -> Self {
+    let builtin = std::env::var_os("PROGRAM_TEST_BUILTIN").is_some();
+    let mut program_test = ProgramTest::default();
+    program_test.prefer_bpf(!builtin);
+    program_test.add_program(
+      "stake_pool",
+      stake_pool::ID,
+      if builtin {
+        processor!(process_pool_instruction)
+      } else {
+        None
+      },
+    );
+    program_test.add_program(
+      "stake_program_project",
+      stake_program_project::ID,
+      if builtin {
+        processor!(process_stake_instruction)
+      } else {
+        None
+      },
+    );
+    let vote_account = Pubkey::new_unique();
+    program_test.add_account(vote_account, new_vote_account());
+    // Only the stake program's upgrade authority may create its config
+    let admin = Keypair::new();
+    program_test.add_account(
+      program_data_address(),
+      Account::new_data(
+        LAMPORTS_PER_SOL,
+        &UpgradeableLoaderState::ProgramData {
+          slot: 0,
+          upgrade_authority_address: Some(admin.pubkey()),
+        },
+        &bpf_loader_upgradeable::ID,
+      )
+      .unwrap(),
+    );
+    program_test.add_account(
+      admin.pubkey(),
+      Account::new(LAMPORTS_PER_SOL, 0, &system_program::ID),
+    );
+
+    let (pool, pool_mint) = (Keypair::new(), Keypair::new());
+    let mut env = Self {
+      context: program_test.start_with_context().await,
+      vote_account,
+      stake_history: cluster_stake_history(),
+      pool: pool.pubkey(),
+      pool_mint: pool_mint.pubkey(),
+      manager: Keypair::new(),
+    };
+
+    env
+      .send(
+        &[Instruction {
+          program_id: stake_program_project::ID,
+          accounts: stake_program_project::accounts::InitializeConfig {
+            config: stake_config_address(),
+            admin: admin.pubkey(),
+            program_data: program_data_address(),
+            system_program: system_program::ID,
+          }
+          .to_account_metas(None),
+          data: stake_program_project::instruction::InitializeConfig {
+            args: StakeConfigArgs {
+              minimum_delegation: DEFAULT_MINIMUM_DELEGATION,
+              warmup_cooldown_rate: NEW_WARMUP_COOLDOWN_RATE,
+              slash_penalty: DEFAULT_SLASH_PENALTY,
+            },
+          }
+          .data(),
+        }],
+        &[&admin],
+      )
+      .await
+      .unwrap();
+    env.warp_to_epoch(ACTIVATION_EPOCH).await;
+
+    let manager = env.manager.insecure_clone();
+    env.fund(manager.pubkey(), 10 * LAMPORTS_PER_SOL).await;
+    env
+      .process(
+        stake_pool::accounts::InitializePool {
+          pool: pool.pubkey(),
+          manager: manager.pubkey(),
+          withdraw_authority: env.withdraw_authority(),
+          reserve: env.reserve(),
+          pool_mint: pool_mint.pubkey(),
+          manager_fee_account: env.manager_fee_account(),
+          token_program: spl_token::ID,
+          system_program: system_program::ID,
+          rent: sysvar::rent::ID,
+        },
+        stake_pool::instruction::InitializePool { fee: FEE },
+        &[&pool, &manager, &pool_mint],
+      )
+      .await
+      .unwrap();
+
+    env
+  }
+
+  fn payer(&self) -> Keypair {
+    self.context.payer.insecure_clone()
+  }
+
+  fn withdraw_authority(&self) -> Pubkey {
+    pool_address(AUTHORITY_SEED, &self.pool)
+  }
+
+  fn reserve(&self) -> Pubkey {
+    pool_address(RESERVE_SEED, &self.pool)
+  }
+
+  fn manager_fee_account(&self) -> Pubkey {
+    pool_address(MANAGER_FEE_SEED, &self.pool)
+  }
+
+  fn validator_stake(&self) -> Pubkey {
+    validator_stake_address(&self.pool, &self.vote_account)
+  }
+
+  fn transient_stake(&self) -> Pubkey {
+    transient_stake_address(&self.pool, &self.vote_account)
+  }
+
+  /// The accounts `update_pool_balance` expects for the pool's one validator
+  fn validator_accounts(&self) -> [Pubkey; 2] {
+    [self.validator_stake(), self.transient_stake()]
+  }
+
+  async fn send(
+    &mut self,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+  ) -> Result<(), BanksClientError> {
+    let payer = self.payer();
+    let mut all_signers = vec![&payer];
+    all_signers.extend_from_slice(signers);
+
+    // A fresh blockhash keeps a retried instruction from being rejected as already processed
+    let blockhash = self.context.get_new_latest_blockhash().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(
+      instructions,
+      Some(&payer.pubkey()),
+      &all_signers,
+      blockhash,
+    );
+    self
+      .context
+      .banks_client
+      .process_transaction(transaction)
+      .await
+  }
+
+  async fn process(
+    &mut self,
+    accounts: impl ToAccountMetas,
+    data: impl InstructionData,
+    signers: &[&Keypair],
+  ) -> Result<(), BanksClientError> {
+    let instruction = Instruction {
+      program_id: stake_pool::ID,
+      accounts: accounts.to_account_metas(None),
+      data: data.data(),
+    };
+    self.send(&[instruction], signers).await
+  }
+
+  /// Warps to the first slot of `epoch`, then restores the test's stake history over the one the
+  /// bank computed from the (empty) native stake program.
+  async fn warp_to_epoch(&mut self, epoch: u64) {
+    self.context.warp_to_epoch(epoch).unwrap();
+    self.context.set_sysvar(&self.stake_history);
+  }
+
+  async fn fund(&mut self, address: Pubkey, lamports: u64) {
+    let payer = self.payer();
+    self
+      .send(
+        &[system_instruction::transfer(
+          &payer.pubkey(),
+          &address,
+          lamports,
+        )],
+        &[],
+      )
+      .await
+      .unwrap();
+  }
+
+  async fn account(&mut self, address: Pubkey) -> Account {
+    self
+      .context
+      .banks_client
+      .get_account(address)
+      .await
+      .unwrap()
+      .expect("account exists")
+  }
+
+  async fn lamports(&mut self, address: Pubkey) -> u64 {
+    self
+      .context
+      .banks_client
+      .get_balance(address)
+      .await
+      .unwrap()
+  }
+
+  async fn deserialize<T: AccountDeserialize>(&mut self, address: Pubkey) -> T {
+    let account = self.account(address).await;
+    T::try_deserialize(&mut account.data.as_slice()).unwrap()
+  }
+
+  async fn pool_state(&mut self) -> StakePool {
+    let pool = self.pool;
+    self.deserialize(pool).await
+  }
+
+  async fn supply(&mut self) -> u64 {
+    let pool_mint = self.pool_mint;
+    self.deserialize::<Mint>(pool_mint).await.supply
+  }
+
+  async fn token_balance(&mut self, token_account: Pubkey) -> u64 {
+    self.deserialize::<TokenAccount>(token_account).await.amount
+  }
+
+  /// Credits `lamports` to `address` out of thin air, as epoch rewards would.
+  async fn reward(&mut self, address: Pubkey, lamports: u64) {
+    let mut account = self.account(address).await;
+    account.lamports += lamports;
+    self
+      .context
+      .set_account(&address, &AccountSharedData::from(account));
+  }
+
+  async fn create_token_account(&mut self, owner: Pubkey) -> Pubkey {
+    let token_account = Keypair::new();
+    let payer = self.payer();
+    let rent = self.context.banks_client.get_rent().await.unwrap();
+    let create = system_instruction::create_account(
+      &payer.pubkey(),
+      &token_account.pubkey(),
+      rent.minimum_balance(spl_token::state::Account::LEN),
+      spl_token::state::Account::LEN as u64,
+      &spl_token::ID,
+    );
+    let initialize = spl_token::instruction::initialize_account3(
+      &spl_token::ID,
+      &token_account.pubkey(),
+      &self.pool_mint,
+      &owner,
+    )
+    .unwrap();
+
+    self
+      .send(&[create, initialize], &[&token_account])
+      .await
+      .unwrap();
+    token_account.pubkey()
+  }
+
+  /// Creates a stake account of `STAKE` owned by `owner` and delegates it to the test's vote
+  /// account.
+  async fn create_delegated_stake(&mut self, owner: &Keypair) -> Pubkey {
+    let stake_account = Keypair::new();
+    let payer = self.payer();
+    let initialize = Instruction {
+      program_id: stake_program_project::ID,
+      accounts: stake_program_project::accounts::Initialize {
+        stake_account: stake_account.pubkey(),
+        payer: payer.pubkey(),
+        rent: sysvar::rent::ID,
+        system_program: system_program::ID,
+      }
+      .to_account_metas(None),
+      data: stake_program_project::instruction::Initialize {
+        authorized: Authorized {
+          staker: owner.pubkey(),
+          withdrawer: owner.pubkey(),
+        },
+        lockup: Lockup::default(),
+      }
+      .data(),
+    };
+    let fund = system_instruction::transfer(&payer.pubkey(), &stake_account.pubkey(), STAKE);
+    let delegate = Instruction {
+      program_id: stake_program_project::ID,
+      accounts: stake_program_project::accounts::DelegateStake {
+        stake_account: stake_account.pubkey(),
+        vote_account: self.vote_account,
+        staker: owner.pubkey(),
+        clock: sysvar::clock::ID,
+        stake_history: sysvar::stake_history::ID,
+        config: stake_config_address(),
+      }
+      .to_account_metas(None),
+      data: stake_program_project::instruction::DelegateStake {}.data(),
+    };
+
+    self
+      .send(&[initialize, fund, delegate], &[&stake_account, owner])
+      .await
+      .unwrap();
+    stake_account.pubkey()
+  }
+
+  async fn add_validator(&mut self, manager: &Keypair) -> Result<(), BanksClientError> {
+    self
+      .process(
+        stake_pool::accounts::AddValidator {
+          pool: self.pool,
+          manager: manager.pubkey(),
+          withdraw_authority: self.withdraw_authority(),
+          reserve: self.reserve(),
+          validator_stake: self.validator_stake(),
+          vote_account: self.vote_account,
+          stake_config: stake_config_address(),
+          clock: sysvar::clock::ID,
+          stake_history: sysvar::stake_history::ID,
+          stake_program: stake_program_project::ID,
+          rent: sysvar::rent::ID,
+          system_program: system_program::ID,
+        },
+        stake_pool::instruction::AddValidator {},
+        &[manager],
+      )
+      .await
+  }
+
+  async fn deposit_sol(
+    &mut self,
+    depositor: &Keypair,
+    destination: Pubkey,
+    lamports: u64,
+  ) -> Result<(), BanksClientError> {
+    self
+      .process(
+        stake_pool::accounts::DepositSol {
+          pool: self.pool,
+          depositor: depositor.pubkey(),
+          reserve: self.reserve(),
+          withdraw_authority: self.withdraw_authority(),
+          pool_mint: self.pool_mint,
+          destination,
+          token_program: spl_token::ID,
+          system_program: system_program::ID,
+        },
+        stake_pool::instruction::DepositSol { lamports },
+        &[depositor],
+      )
+      .await
+  }
+
+  async fn increase_validator_stake(
+    &mut self,
+    manager: &Keypair,
+    lamports: u64,
+  ) -> Result<(), BanksClientError> {
+    self
+      .process(
+        stake_pool::accounts::IncreaseValidatorStake {
+          pool: self.pool,
+          manager: manager.pubkey(),
+          withdraw_authority: self.withdraw_authority(),
+          reserve: self.reserve(),
+          validator_stake: self.validator_stake(),
+          transient_stake: self.transient_stake(),
+          vote_account: self.vote_account,
+          stake_config: stake_config_address(),
+          clock: sysvar::clock::ID,
+          stake_history: sysvar::stake_history::ID,
+          stake_program: stake_program_project::ID,
+          rent: sysvar::rent::ID,
+          system_program: system_program::ID,
+        },
+        stake_pool::instruction::IncreaseValidatorStake { lamports },
+        &[manager],
+      )
+      .await
+  }
+
+  async fn decrease_validator_stake(
+    &mut self,
+    manager: &Keypair,
+    lamports: u64,
+  ) -> Result<(), BanksClientError> {
+    self
+      .process(
+        stake_pool::accounts::DecreaseValidatorStake {
+          pool: self.pool,
+          manager: manager.pubkey(),
+          withdraw_authority: self.withdraw_authority(),
+          reserve: self.reserve(),
+          validator_stake: self.validator_stake(),
+          transient_stake: self.transient_stake(),
+          vote_account: self.vote_account,
+          stake_config: stake_config_address(),
+          clock: sysvar::clock::ID,
+          stake_history: sysvar::stake_history::ID,
+          stake_program: stake_program_project::ID,
+          rent: sysvar::rent::ID,
+          system_program: system_program::ID,
+        },
+        stake_pool::instruction::DecreaseValidatorStake { lamports },
+        &[manager],
+      )
+      .await
+  }
+
+  async fn merge_transient_stake(&mut self) -> Result<(), BanksClientError> {
+    self
+      .process(
+        stake_pool::accounts::MergeTransientStake {
+          pool: self.pool,
+          withdraw_authority: self.withdraw_authority(),
+          reserve: self.reserve(),
+          validator_stake: self.validator_stake(),
+          transient_stake: self.transient_stake(),
+          vote_account: self.vote_account,
+          clock: sysvar::clock::ID,
+          stake_history: sysvar::stake_history::ID,
+          stake_program: stake_program_project::ID,
+        },
+        stake_pool::instruction::MergeTransientStake {},
+        &[],
+      )
+      .await
+  }
+
+  async fn remove_validator(&mut self, manager: &Keypair) -> Result<(), BanksClientError> {
+    self
+      .process(
+        stake_pool::accounts::RemoveValidator {
+          pool: self.pool,
+          manager: manager.pubkey(),
+          withdraw_authority: self.withdraw_authority(),
+          reserve: self.reserve(),
+          validator_stake: self.validator_stake(),
+          transient_stake: self.transient_stake(),
+          vote_account: self.vote_account,
+          clock: sysvar::clock::ID,
+          stake_history: sysvar::stake_history::ID,
+          stake_program: stake_program_project::ID,
+          system_program: system_program::ID,
+        },
+        stake_pool::instruction::RemoveValidator {},
+        &[manager],
+      )
+      .await
+  }
+
+  async fn delegated_stake(&mut self, stake_account: Pubkey) -> u64 {
+    self
+      .deserialize::<StakeAccount>(stake_account)
+      .await
+      .stake
+      .unwrap()
+      .delegation
+      .stake
+  }
+
+  async fn exists(&mut self, address: Pubkey) -> bool {
+    self
+      .context
+      .banks_client
+      .get_account(address)
+      .await
+      .unwrap()
+      .is_some()
+  }
+
+  async fn update_pool_balance(
+    &mut self,
+    validator_stakes: &[Pubkey],
+  ) -> Result<(), BanksClientError> {
+    let mut accounts = stake_pool::accounts::UpdatePoolBalance {
+      pool: self.pool,
+      reserve: self.reserve(),
+      withdraw_authority: self.withdraw_authority(),
+      pool_mint: self.pool_mint,
+      manager_fee_account: self.manager_fee_account(),
+      token_program: spl_token::ID,
+    }
+    .to_account_metas(None);
+    accounts.extend(
+      validator_stakes
+        .iter()
+        .map(|validator_stake| AccountMeta::new_readonly(*validator_stake, false)),
+    );
+
+    self
+      .send(
+        &[Instruction {
+          program_id: stake_pool::ID,
+          accounts,
+          data: stake_pool::instruction::UpdatePoolBalance {}.data(),
+        }],
+        &[],
+      )
+      .await
+  }
+}
+
+#[tokio::test]
+async fn pool_lifecycle() {
+  let mut env = TestEnv::start().await;
+  let manager = env.manager.insecure_clone();
+  let user = Keypair::new();
+  env.fund(user.pubkey(), 20 * LAMPORTS_PER_SOL).await;
+  let user_tokens = env.create_token_account(user.pubkey()).await;
+
+  // The first deposit mints one token per lamport and funds the validator's stake account
+  env
+    .deposit_sol(&user, user_tokens, 10 * LAMPORTS_PER_SOL)
+    .await
+    .unwrap();
+  assert_eq!(env.token_balance(user_tokens).await, 10 * LAMPORTS_PER_SOL);
+  env.add_validator(&manager).await.unwrap();
+
+  let validator_stake = env.validator_stake();
+  let state = env.deserialize::<StakeAccount>(validator_stake).await;
+  assert_eq!(
+    state.stake.unwrap().delegation.stake,
+    DEFAULT_MINIMUM_DELEGATION
+  );
+  assert_eq!(state.meta.authorized.staker, env.withdraw_authority());
+  let rent_exempt_reserve = state.meta.rent_exempt_reserve;
+  assert_eq!(env.pool_state().await.validators, [env.vote_account]);
+
+  let user_stake = env.create_delegated_stake(&user).await;
+  env.warp_to_epoch(ACTIVE_EPOCH).await;
+  env
+    .update_pool_balance(&env.validator_accounts())
+    .await
+    .unwrap();
+  // Moving lamports into the validator stake account is not a reward
+  let pool = env.pool_state().await;
+  assert_eq!(pool.total_lamports, 10 * LAMPORTS_PER_SOL);
+  assert_eq!(pool.last_update_epoch, ACTIVE_EPOCH);
+  let manager_fee_account = env.manager_fee_account();
+  assert_eq!(env.token_balance(manager_fee_account).await, 0);
+
+  // A stake deposit is merged into the pool's stake account for the same validator
+  let vote_account = env.vote_account;
+  env
+    .process(
+      stake_pool::accounts::DepositStake {
+        pool: env.pool,
+        depositor: user.pubkey(),
+        deposit_stake: user_stake,
+        vote_account,
+        validator_stake,
+        withdraw_authority: env.withdraw_authority(),
+        pool_mint: env.pool_mint,
+        destination: user_tokens,
+        clock: sysvar::clock::ID,
+        stake_history: sysvar::stake_history::ID,
+        stake_program: stake_program_project::ID,
+        token_program: spl_token::ID,
+      },
+      stake_pool::instruction::DepositStake {},
+      &[&user],
+    )
+    .await
+    .unwrap();
+  assert!(env
+    .context
+    .banks_client
+    .get_account(user_stake)
+    .await
+    .unwrap()
+    .is_none());
+  let deposited = STAKE + rent_exempt_reserve;
+  assert_eq!(
+    env.token_balance(user_tokens).await,
+    10 * LAMPORTS_PER_SOL + deposited
+  );
+  assert_eq!(
+    env
+      .deserialize::<StakeAccount>(validator_stake)
+      .await
+      .stake
+      .unwrap()
+      .delegation
+      .stake,
+    DEFAULT_MINIMUM_DELEGATION + STAKE
+  );
+
+  // Rewards raise the value of every token, and the manager is paid its share in new tokens
+  let total_before = env.pool_state().await.total_lamports;
+  let supply_before = env.supply().await;
+  env.reward(validator_stake, LAMPORTS_PER_SOL).await;
+  env
+    .update_pool_balance(&env.validator_accounts())
+    .await
+    .unwrap();
+
+  let total_lamports = env.pool_state().await.total_lamports;
+  assert_eq!(total_lamports, total_before + LAMPORTS_PER_SOL);
+  let fee_tokens = env.token_balance(manager_fee_account).await;
+  assert_eq!(
+    fee_tokens,
+    fee_pool_tokens(LAMPORTS_PER_SOL, total_lamports, supply_before, &FEE).unwrap()
+  );
+  let supply = env.supply().await;
+  let fee_value = lamports_for_pool_tokens(fee_tokens, total_lamports, supply).unwrap();
+  let fee_lamports = LAMPORTS_PER_SOL * FEE.numerator / FEE.denominator;
+  assert!(fee_value <= fee_lamports && fee_lamports - fee_value < 10);
+
+  // A SOL withdrawal pays out of the reserve at the new token value
+  let destination = Pubkey::new_unique();
+  let withdrawn = lamports_for_pool_tokens(LAMPORTS_PER_SOL, total_lamports, supply).unwrap();
+  assert!(withdrawn > LAMPORTS_PER_SOL);
+  env
+    .process(
+      stake_pool::accounts::WithdrawSol {
+        pool: env.pool,
+        withdrawer: user.pubkey(),
+        source: user_tokens,
+        reserve: env.reserve(),
+        destination,
+        pool_mint: env.pool_mint,
+        token_program: spl_token::ID,
+        system_program: system_program::ID,
+      },
+      stake_pool::instruction::WithdrawSol {
+        pool_tokens: LAMPORTS_PER_SOL,
+      },
+      &[&user],
+    )
+    .await
+    .unwrap();
+  assert_eq!(env.lamports(destination).await, withdrawn);
+  assert_eq!(env.supply().await, supply - LAMPORTS_PER_SOL);
+
+  // A stake withdrawal splits the tokens' worth off the validator stake account
+  let total_lamports = env.pool_state().await.total_lamports;
+  assert_eq!(total_lamports, total_before + LAMPORTS_PER_SOL - withdrawn);
+  let supply = env.supply().await;
+  let pool_tokens = 2 * LAMPORTS_PER_SOL;
+  let split_lamports = lamports_for_pool_tokens(pool_tokens, total_lamports, supply).unwrap();
+  let split_stake = Keypair::new();
+  env
+    .process(
+      stake_pool::accounts::WithdrawStake {
+        pool: env.pool,
+        withdrawer: user.pubkey(),
+        source: user_tokens,
+        vote_account,
+        validator_stake,
+        split_stake: split_stake.pubkey(),
+        withdraw_authority: env.withdraw_authority(),
+        pool_mint: env.pool_mint,
+        stake_config: stake_config_address(),
+        clock: sysvar::clock::ID,
+        rent: sysvar::rent::ID,
+        stake_program: stake_program_project::ID,
+        token_program: spl_token::ID,
+        system_program: system_program::ID,
+      },
+      stake_pool::instruction::WithdrawStake { pool_tokens },
+      &[&user, &split_stake],
+    )
+    .await
+    .unwrap();
+
+  let split = env.deserialize::<StakeAccount>(split_stake.pubkey()).await;
+  assert_eq!(split.meta.authorized.staker, user.pubkey());
+  assert_eq!(split.meta.authorized.withdrawer, user.pubkey());
+  let delegation = split.stake.unwrap().delegation;
+  assert_eq!(delegation.stake, split_lamports);
+  assert_eq!(delegation.voter_pubkey, vote_account);
+  assert_eq!(
+    env.lamports(split_stake.pubkey()).await,
+    split_lamports + rent_exempt_reserve
+  );
+  assert_eq!(
+    env.pool_state().await.total_lamports,
+    total_lamports - split_lamports
+  );
+  assert_eq!(env.supply().await, supply - pool_tokens);
+}
+
+#[tokio::test]
+async fn pool_rejects_stale_balances_and_strangers() {
+  let mut env = TestEnv::start().await;
+  let manager = env.manager.insecure_clone();
+  let user = Keypair::new();
+  env.fund(user.pubkey(), 20 * LAMPORTS_PER_SOL).await;
+  let user_tokens = env.create_token_account(user.pubkey()).await;
+
+  assert_pool_error(
+    env.add_validator(&user).await,
+    PoolError::UnauthorizedManager,
+  );
+  // The reserve has to cover the validator's minimum delegation
+  assert_pool_error(
+    env.add_validator(&manager).await,
+    PoolError::InsufficientReserve,
+  );
+  env
+    .deposit_sol(&user, user_tokens, 5 * LAMPORTS_PER_SOL)
+    .await
+    .unwrap();
+  env.add_validator(&manager).await.unwrap();
+  assert_pool_error(
+    env.add_validator(&manager).await,
+    PoolError::ValidatorAlreadyAdded,
+  );
+
+  env.warp_to_epoch(ACTIVATION_EPOCH + 1).await;
+  assert_pool_error(
+    env.deposit_sol(&user, user_tokens, LAMPORTS_PER_SOL).await,
+    PoolError::StalePoolBalance,
+  );
+  assert_pool_error(
+    env.update_pool_balance(&[]).await,
+    PoolError::ValidatorStakeMismatch,
+  );
+  assert_pool_error(
+    env.update_pool_balance(&[user_tokens]).await,
+    PoolError::ValidatorStakeMismatch,
+  );
+  let validator_stake = env.validator_stake();
+  assert_pool_error(
+    env
+      .update_pool_balance(&[validator_stake, user_tokens])
+      .await,
+    PoolError::ValidatorStakeMismatch,
+  );
+
+  env
+    .update_pool_balance(&env.validator_accounts())
+    .await
+    .unwrap();
+  env
+    .deposit_sol(&user, user_tokens, LAMPORTS_PER_SOL)
+    .await
+    .unwrap();
+  assert_eq!(env.token_balance(user_tokens).await, 6 * LAMPORTS_PER_SOL);
+}
+
+#[tokio::test]
+async fn validator_stake_moves_through_the_transient_account() {
+  let mut env = TestEnv::start().await;
+  let manager = env.manager.insecure_clone();
+  let user = Keypair::new();
+  env.fund(user.pubkey(), 20 * LAMPORTS_PER_SOL).await;
+  let user_tokens = env.create_token_account(user.pubkey()).await;
+  env
+    .deposit_sol(&user, user_tokens, 10 * LAMPORTS_PER_SOL)
+    .await
+    .unwrap();
+  env.add_validator(&manager).await.unwrap();
+  env.warp_to_epoch(ACTIVE_EPOCH).await;
+  env
+    .update_pool_balance(&env.validator_accounts())
+    .await
+    .unwrap();
+
+  let (validator_stake, transient_stake, reserve) =
+    (env.validator_stake(), env.transient_stake(), env.reserve());
+  let rent_exempt_reserve = env
+    .deserialize::<StakeAccount>(validator_stake)
+    .await
+    .meta
+    .rent_exempt_reserve;
+
+  // Reserve SOL is delegated through the transient account and merged once active
+  assert_pool_error(
+    env
+      .increase_validator_stake(&user, 3 * LAMPORTS_PER_SOL)
+      .await,
+    PoolError::UnauthorizedManager,
+  );
+  let reserve_before = env.lamports(reserve).await;
+  env
+    .increase_validator_stake(&manager, 3 * LAMPORTS_PER_SOL)
+    .await
+    .unwrap();
+  assert_eq!(
+    env.delegated_stake(transient_stake).await,
+    3 * LAMPORTS_PER_SOL
+  );
+  assert_eq!(
+    env.lamports(reserve).await,
+    reserve_before - 3 * LAMPORTS_PER_SOL - rent_exempt_reserve
+  );
+  assert_pool_error(
+    env
+      .increase_validator_stake(&manager, LAMPORTS_PER_SOL)
+      .await,
+    PoolError::TransientStakeInUse,
+  );
+  assert_pool_error(
+    env
+      .decrease_validator_stake(&manager, LAMPORTS_PER_SOL)
+      .await,
+    PoolError::TransientStakeInUse,
+  );
+  // Still activating
+  assert!(env.merge_transient_stake().await.is_err());
+
+  env.warp_to_epoch(ACTIVE_EPOCH + 1).await;
+  env.merge_transient_stake().await.unwrap();
+  assert!(!env.exists(transient_stake).await);
+  assert_eq!(
+    env.delegated_stake(validator_stake).await,
+    DEFAULT_MINIMUM_DELEGATION + 3 * LAMPORTS_PER_SOL
+  );
+  // Moving stake around is not a reward
+  env
+    .update_pool_balance(&env.validator_accounts())
+    .await
+    .unwrap();
+  assert_eq!(env.pool_state().await.total_lamports, 10 * LAMPORTS_PER_SOL);
+
+  // Stake taken off the validator cools down in the transient account and returns to the reserve
+  let reserve_before = env.lamports(reserve).await;
+  env
+    .decrease_validator_stake(&manager, 2 * LAMPORTS_PER_SOL)
+    .await
+    .unwrap();
+  let transient = env.deserialize::<StakeAccount>(transient_stake).await;
+  assert_eq!(
+    transient.stake.unwrap().delegation.stake,
+    2 * LAMPORTS_PER_SOL
+  );
+  env.warp_to_epoch(ACTIVE_EPOCH + 2).await;
+  env.merge_transient_stake().await.unwrap();
+  assert!(!env.exists(transient_stake).await);
+  assert_eq!(
+    env.lamports(reserve).await,
+    reserve_before + 2 * LAMPORTS_PER_SOL
+  );
+
+  // Removal deactivates the validator's stake first and withdraws it once it has cooled down
+  env.remove_validator(&manager).await.unwrap();
+  assert_eq!(env.pool_state().await.validators, [env.vote_account]);
+  assert_pool_error(
+    env
+      .increase_validator_stake(&manager, LAMPORTS_PER_SOL)
+      .await,
+    PoolError::ValidatorBeingRemoved,
+  );
+  assert!(env.remove_validator(&manager).await.is_err());
+
+  env.warp_to_epoch(ACTIVE_EPOCH + 3).await;
+  env.remove_validator(&manager).await.unwrap();
+  assert!(env.pool_state().await.validators.is_empty());
+  assert!(!env.exists(validator_stake).await);
+  env.update_pool_balance(&[]).await.unwrap();
+  assert_eq!(env.pool_state().await.total_lamports, 10 * LAMPORTS_PER_SOL);
+}
+
+#[tokio::test]
+async fn validator_stake_keeps_the_minimum_delegation() {
+  let mut env = TestEnv::start().await;
+  let manager = env.manager.insecure_clone();
+  let user = Keypair::new();
+  env.fund(user.pubkey(), 20 * LAMPORTS_PER_SOL).await;
+  let user_tokens = env.create_token_account(user.pubkey()).await;
+  env
+    .deposit_sol(&user, user_tokens, 10 * LAMPORTS_PER_SOL)
+    .await
+    .unwrap();
+  env.add_validator(&manager).await.unwrap();
+  env.warp_to_epoch(ACTIVE_EPOCH).await;
+  env
+    .update_pool_balance(&env.validator_accounts())
+    .await
+    .unwrap();
+
+  // The stake program would split off the whole delegation and leave the account undelegated
+  assert_pool_error(
+    env
+      .decrease_validator_stake(&manager, DEFAULT_MINIMUM_DELEGATION)
+      .await,
+    PoolError::ValidatorStakeTooLow,
+  );
+  let (vote_account, validator_stake) = (env.vote_account, env.validator_stake());
+  let split_stake = Keypair::new();
+  assert_pool_error(
+    env
+      .process(
+        stake_pool::accounts::WithdrawStake {
+          pool: env.pool,
+          withdrawer: user.pubkey(),
+          source: user_tokens,
+          vote_account,
+          validator_stake,
+          split_stake: split_stake.pubkey(),
+          withdraw_authority: env.withdraw_authority(),
+          pool_mint: env.pool_mint,
+          stake_config: stake_config_address(),
+          clock: sysvar::clock::ID,
+          rent: sysvar::rent::ID,
+          stake_program: stake_program_project::ID,
+          token_program: spl_token::ID,
+          system_program: system_program::ID,
+        },
+        stake_pool::instruction::WithdrawStake {
+          pool_tokens: DEFAULT_MINIMUM_DELEGATION,
+        },
+        &[&user, &split_stake],
+      )
+      .await,
+    PoolError::ValidatorStakeTooLow,
+  );
+  assert_eq!(
+    env.delegated_stake(validator_stake).await,
+    DEFAULT_MINIMUM_DELEGATION
+  );
+
+  // A validator stake account left undelegated has nothing to cool down and is removed at once
+  let mut account = env.account(validator_stake).await;
+  let mut state = StakeAccount::try_deserialize(&mut account.data.as_slice()).unwrap();
+  state.stake = None;
+  account.data.clear();
+  state.try_serialize(&mut account.data).unwrap();
+  env
+    .context
+    .set_account(&validator_stake, &AccountSharedData::from(account));
+  assert_pool_error(
+    env
+      .increase_validator_stake(&manager, LAMPORTS_PER_SOL)
+      .await,
+    PoolError::ValidatorBeingRemoved,
+  );
+  env.remove_validator(&manager).await.unwrap();
+  assert!(env.pool_state().await.validators.is_empty());
+  assert!(!env.exists(validator_stake).await);
+}
+
+#[tokio::test]
+async fn lamports_sent_to_the_transient_account_are_swept_to_the_reserve() {
+  let mut env = TestEnv::start().await;
+  let manager = env.manager.insecure_clone();
+  let user = Keypair::new();
+  env.fund(user.pubkey(), 20 * LAMPORTS_PER_SOL).await;
+  let user_tokens = env.create_token_account(user.pubkey()).await;
+  env
+    .deposit_sol(&user, user_tokens, 10 * LAMPORTS_PER_SOL)
+    .await
+    .unwrap();
+  env.add_validator(&manager).await.unwrap();
+  env.warp_to_epoch(ACTIVE_EPOCH).await;
+  env
+    .update_pool_balance(&env.validator_accounts())
+    .await
+    .unwrap();
+
+  let (validator_stake, transient_stake, reserve) =
+    (env.validator_stake(), env.transient_stake(), env.reserve());
+  let rent_exempt_reserve = env
+    .deserialize::<StakeAccount>(validator_stake)
+    .await
+    .meta
+    .rent_exempt_reserve;
+  let stray = env
+    .context
+    .banks_client
+    .get_rent()
+    .await
+    .unwrap()
+    .minimum_balance(0);
+
+  env.fund(transient_stake, stray).await;
+  let reserve_before = env.lamports(reserve).await;
+  env
+    .increase_validator_stake(&manager, 3 * LAMPORTS_PER_SOL)
+    .await
+    .unwrap();
+  assert_eq!(
+    env.delegated_stake(transient_stake).await,
+    3 * LAMPORTS_PER_SOL
+  );
+  assert_eq!(
+    env.lamports(reserve).await,
+    reserve_before + stray - 3 * LAMPORTS_PER_SOL - rent_exempt_reserve
+  );
+  env.warp_to_epoch(ACTIVE_EPOCH + 1).await;
+  env.merge_transient_stake().await.unwrap();
+
+  env.fund(transient_stake, stray).await;
+  env
+    .decrease_validator_stake(&manager, 2 * LAMPORTS_PER_SOL)
+    .await
+    .unwrap();
+  assert_eq!(
+    env.delegated_stake(transient_stake).await,
+    2 * LAMPORTS_PER_SOL
+  );
+  env.warp_to_epoch(ACTIVE_EPOCH + 2).await;
+  env.merge_transient_stake().await.unwrap();
+
+  env.fund(transient_stake, stray).await;
+  let reserve_before = env.lamports(reserve).await;
+  env.remove_validator(&manager).await.unwrap();
+  assert!(!env.exists(transient_stake).await);
+  assert_eq!(env.lamports(reserve).await, reserve_before + stray);
+  env.warp_to_epoch(ACTIVE_EPOCH + 3).await;
+  env.remove_validator(&manager).await.unwrap();
+  assert!(env.pool_state().await.validators.is_empty());
+}
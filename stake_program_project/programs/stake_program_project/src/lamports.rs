@@ -0,0 +1,222 @@
+use anchor_lang::{prelude::*, solana_program::sysvar::stake_history::StakeHistoryGetEntry};
+
+use crate::{Stake, StakeError, NEW_RATE_ACTIVATION_EPOCH};
+
+/// Lamports of `stake` that cannot be withdrawn in `epoch`.
+///
+/// Until deactivation the whole delegation is locked, including stake still warming up; after
+/// that only the part that hasn't cooled down yet.
+pub fn staked_lamports<T: StakeHistoryGetEntry>(
+  stake: Option<&Stake>,
+  epoch: u64,
+  stake_history: &T,
+) -> u64 {
+  match stake {
+    Some(stake) if epoch >= stake.delegation.deactivation_epoch => {
+      stake
+        .delegation
+        .stake(epoch, stake_history, NEW_RATE_ACTIVATION_EPOCH)
+    }
+    Some(stake) => stake.delegation.stake,
+    None => 0,
+  }
+}
+
+/// Lamports a stake account can delegate once its rent-exempt reserve is set aside.
+pub fn delegatable_lamports(stake_lamports: u64, rent_exempt_reserve: u64) -> Result<u64> {
+  stake_lamports
+    .checked_sub(rent_exempt_reserve)
+    .ok_or_else(|| error!(StakeError::BalanceBelowRentExemptReserve))
+}
+
+/// Lamports that can leave a stake account without touching `staked` lamports or the rent-exempt
+/// reserve.
+pub fn withdrawable_lamports(
+  stake_lamports: u64,
+  staked: u64,
+  rent_exempt_reserve: u64,
+) -> Result<u64> {
+  let unstaked = stake_lamports
+    .checked_sub(staked)
+    .ok_or(StakeError::BalanceBelowDelegatedStake)?;
+  delegatable_lamports(unstaked, rent_exempt_reserve)
+}
+
+/// Moves `lamports` between two accounts this program can debit.
+///
+/// `from` and `to` may be the same account, in which case its balance is left as it was.
+pub fn transfer_lamports(from: &AccountInfo, to: &AccountInfo, lamports: u64) -> Result<()> {
+  let from_lamports = from
+    .lamports()
+    .checked_sub(lamports)
+    .ok_or(StakeError::InsufficientFunds)?;
+  **from.try_borrow_mut_lamports()? = from_lamports;
+
+  // Read the destination only after the debit, so a transfer to the same account nets out
+  let to_lamports = to
+    .lamports()
+    .checked_add(lamports)
+    .ok_or(StakeError::LamportsOverflow)?;
+  **to.try_borrow_mut_lamports()? = to_lamports;
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use anchor_lang::solana_program::sysvar::stake_history::StakeHistory;
+  use proptest::prelude::*;
+
+  use super::*;
+  use crate::{Delegation, NEW_WARMUP_COOLDOWN_RATE};
+
+  fn error_code(result: Result<u64>) -> Option<u32> {
+    match result {
+      Err(Error::AnchorError(error)) => Some(error.error_code_number),
+      _ => None,
+    }
+  }
+
+  #[test]
+  fn staked_lamports_follow_cooldown() {
+    let history = StakeHistory::default();
+    let mut stake = Stake {
+      delegation: Delegation {
+        voter_pubkey: Pubkey::new_unique(),
+        stake: 1_000,
+        activation_epoch: 1,
+        deactivation_epoch: u64::MAX,
+        warmup_cooldown_rate: NEW_WARMUP_COOLDOWN_RATE,
+      },
+      credits_observed: 0,
+    };
+
+    assert_eq!(staked_lamports(None, 5, &history), 0);
+    // Activating stake is locked as well
+    assert_eq!(staked_lamports(Some(&stake), 1, &history), 1_000);
+
+    stake.delegation.deactivation_epoch = 5;
+    assert_eq!(staked_lamports(Some(&stake), 5, &history), 1_000);
+    // Without cluster history the stake cools down in a single epoch
+    assert_eq!(staked_lamports(Some(&stake), 6, &history), 0);
+  }
+
+  proptest! {
+    #[test]
+    fn withdrawable_never_dips_into_stake_or_reserve(
+      stake_lamports: u64,
+      staked: u64,
+      rent_exempt_reserve: u64,
+    ) {
+      match withdrawable_lamports(stake_lamports, staked, rent_exempt_reserve) {
+        Ok(withdrawable) => {
+          prop_assert_eq!(
+            u128::from(withdrawable) + u128::from(staked) + u128::from(rent_exempt_reserve),
+            u128::from(stake_lamports)
+          );
+        }
+        result if staked > stake_lamports => {
+          prop_assert_eq!(
+            error_code(result),
+            Some(u32::from(StakeError::BalanceBelowDelegatedStake))
+          );
+        }
+        result => {
+          prop_assert!(stake_lamports - staked < rent_exempt_reserve);
+          prop_assert_eq!(
+            error_code(result),
+            Some(u32::from(StakeError::BalanceBelowRentExemptReserve))
+          );
+        }
+      }
+    }
+
+    #[test]
+    fn delegatable_is_balance_above_reserve(stake_lamports: u64, rent_exempt_reserve: u64) {
+      let result = delegatable_lamports(stake_lamports, rent_exempt_reserve);
+      if stake_lamports >= rent_exempt_reserve {
+        prop_assert_eq!(result.unwrap(), stake_lamports - rent_exempt_reserve);
+      } else {
+        prop_assert_eq!(
+          error_code(result),
+          Some(u32::from(StakeError::BalanceBelowRentExemptReserve))
+        );
+      }
+    }
+
+    #[test]
+    fn transfers_conserve_lamports(mut from_lamports: u64, mut to_lamports: u64, lamports: u64) {
+      let (from_before, to_before) = (from_lamports, to_lamports);
+      let (from_key, to_key, owner) = (Pubkey::new_unique(), Pubkey::new_unique(), crate::ID);
+      let (mut from_data, mut to_data) = (vec![], vec![]);
+      let from = AccountInfo::new(
+        &from_key,
+        false,
+        true,
+        &mut from_lamports,
+        &mut from_data,
+        &owner,
+        false,
+        0,
+      );
+      let to = AccountInfo::new(
+        &to_key,
+        false,
+        true,
+        &mut to_lamports,
+        &mut to_data,
+        &owner,
+        false,
+        0,
+      );
+
+      let result = transfer_lamports(&from, &to, lamports);
+
+      match result {
+        Ok(()) => {
+          prop_assert_eq!(from.lamports(), from_before - lamports);
+          prop_assert_eq!(to.lamports(), to_before + lamports);
+        }
+        Err(_) if lamports > from_before => {
+          // Nothing moves without the funds for it
+          prop_assert_eq!(from.lamports(), from_before);
+          prop_assert_eq!(to.lamports(), to_before);
+        }
+        Err(_) => {
+          // The destination overflowed; the runtime rolls back the debit with the transaction
+          prop_assert!(to_before.checked_add(lamports).is_none());
+          prop_assert_eq!(to.lamports(), to_before);
+        }
+      }
+    }
+
+    #[test]
+    fn transfers_to_the_same_account_conserve_lamports(mut balance: u64, lamports: u64) {
+      let balance_before = balance;
+      let (key, owner) = (Pubkey::new_unique(), crate::ID);
+      let mut data = vec![];
+      let account = AccountInfo::new(
+        &key,
+        false,
+        true,
+        &mut balance,
+        &mut data,
+        &owner,
+        false,
+        0,
+      );
+
+      let result = transfer_lamports(&account, &account, lamports);
+
+      prop_assert_eq!(account.lamports(), balance_before);
+      if lamports > balance_before {
+        prop_assert_eq!(
+          error_code(result.map(|()| 0)),
+          Some(u32::from(StakeError::InsufficientFunds))
+        );
+      } else {
+        prop_assert!(result.is_ok());
+      }
+    }
+  }
+}
@@ -1,390 +1,963 @@
-use anchor_lang::{prelude::*, solana_program::stake_history::StakeHistory as SolanaStakeHistory};
+use anchor_lang::{
+  prelude::*,
+  solana_program::sysvar::stake_history::{self, StakeHistoryGetEntry},
+  system_program,
+};
+use solana_sdk_ids::bpf_loader_upgradeable;
+
+pub mod lamports;
+pub mod merge;
+pub mod rewards;
+pub mod split;
+pub mod stake_history_data;
+pub mod vote_state;
+use lamports::{delegatable_lamports, staked_lamports, transfer_lamports, withdrawable_lamports};
+use merge::{merge_stake, metas_can_merge, stake_weighted_credits_observed, MergeKind};
+use rewards::{calculate_stake_rewards, PointValue};
+use split::{split_stake, SPLIT_SEED};
+use stake_history_data::StakeHistoryData;
+use vote_state::{
+  acceptable_reference_epoch_credits, eligible_for_deactivate_delinquent, get_vote_state,
+};
 
 declare_id!("FPQBMD5Q5fRTqRt3ttb461VnzESGmJDHanas7VFgos9W");
 
-#[program]
-pub mod stake_program_project {
+// `#[program]` in anchor 0.31 expands to the deprecated `AccountInfo::realloc` in the IDL
+// handlers it generates next to the module, so the allow has to wrap both
+#[allow(deprecated)]
+mod processor {
   use super::*;
 
-  pub fn initialize(
-    ctx: Context<Initialize>,
-    authorized: Authorized,
-    lockup: Lockup,
-  ) -> Result<()> {
-    let clock = Clock::get()?;
-    let rent = Rent::get()?;
-
-    let data_len = ctx.accounts.stake_account.to_account_info().data_len();
-    let stake_account_key = ctx.accounts.stake_account.key();
+  #[program]
+  pub mod stake_program_project {
+    use super::*;
 
-    let stake_account = &mut ctx.accounts.stake_account;
-    stake_account.meta = Meta {
-      rent_exempt_reserve: rent.minimum_balance(data_len),
-      authorized,
-      lockup,
-    };
-    stake_account.stake = None;
-    stake_account.stake_flags = StakeFlags::empty();
+    pub fn initialize(
+      ctx: Context<Initialize>,
+      authorized: Authorized,
+      lockup: Lockup,
+    ) -> Result<()> {
+      initialize_stake_account(&mut ctx.accounts.stake_account, authorized, lockup)
+    }
 
-    emit!(StakeInitializeEvent {
-      stake_account: stake_account_key,
-      staker: authorized.staker,
-      withdrawer: authorized.withdrawer,
-      timestamp: clock.unix_timestamp,
-    });
+    pub fn initialize_checked(ctx: Context<InitializeChecked>) -> Result<()> {
+      let authorized = Authorized {
+        staker: ctx.accounts.staker.key(),
+        withdrawer: ctx.accounts.withdrawer.key(),
+      };
+
+      initialize_stake_account(
+        &mut ctx.accounts.stake_account,
+        authorized,
+        Lockup::default(),
+      )
+    }
 
-    Ok(())
-  }
+    pub fn authorize(
+      ctx: Context<Authorize>,
+      new_authority: Pubkey,
+      stake_authorize: StakeAuthorize,
+    ) -> Result<()> {
+      authorize_stake_account(
+        &mut ctx.accounts.stake_account,
+        ctx.accounts.authority.key(),
+        new_authority,
+        stake_authorize,
+        ctx
+          .accounts
+          .custodian
+          .as_ref()
+          .map(|custodian| custodian.key()),
+      )
+    }
 
-  pub fn authorize(
-    ctx: Context<Authorize>,
-    new_authority: Pubkey,
-    stake_authorize: StakeAuthorize,
-  ) -> Result<()> {
-    let stake_account = &mut ctx.accounts.stake_account;
+    pub fn authorize_checked(
+      ctx: Context<AuthorizeChecked>,
+      stake_authorize: StakeAuthorize,
+    ) -> Result<()> {
+      authorize_stake_account(
+        &mut ctx.accounts.stake_account,
+        ctx.accounts.authority.key(),
+        ctx.accounts.new_authority.key(),
+        stake_authorize,
+        ctx
+          .accounts
+          .custodian
+          .as_ref()
+          .map(|custodian| custodian.key()),
+      )
+    }
 
-    match stake_authorize {
-      StakeAuthorize::Staker => {
-        require!(
-          ctx.accounts.authority.key() == stake_account.meta.authorized.staker,
-          StakeError::UnauthorizedStaker
-        );
-        stake_account.meta.authorized.staker = new_authority;
-      }
-      StakeAuthorize::Withdrawer => {
-        require!(
-          ctx.accounts.authority.key() == stake_account.meta.authorized.withdrawer,
-          StakeError::UnauthorizedWithdrawer
-        );
-        stake_account.meta.authorized.withdrawer = new_authority;
-      }
+    pub fn authorize_with_seed(
+      ctx: Context<AuthorizeWithSeed>,
+      args: AuthorizeWithSeedArgs,
+    ) -> Result<()> {
+      let authority = seed_authority(
+        &ctx.accounts.base.key(),
+        &args.authority_seed,
+        &args.authority_owner,
+      )?;
+
+      authorize_stake_account(
+        &mut ctx.accounts.stake_account,
+        authority,
+        args.new_authority,
+        args.stake_authorize,
+        ctx
+          .accounts
+          .custodian
+          .as_ref()
+          .map(|custodian| custodian.key()),
+      )
     }
 
-    emit!(AuthorizeEvent {
-      stake_account: ctx.accounts.stake_account.key(),
-      old_authority: ctx.accounts.authority.key(),
-      new_authority,
-      stake_authorize,
-    });
+    pub fn delegate_stake(ctx: Context<DelegateStake>) -> Result<()> {
+      let vote_account = &ctx.accounts.vote_account;
+      let clock = Clock::get()?;
+      let stake_history_data = ctx.accounts.stake_history.try_borrow_data()?;
+      let stake_history = StakeHistoryData(&stake_history_data);
 
-    Ok(())
-  }
+      let stake_lamports = ctx.accounts.stake_account.to_account_info().lamports();
+      let stake_account_key = ctx.accounts.stake_account.key();
+      let stake_account = &mut ctx.accounts.stake_account;
 
-  pub fn delegate_stake(ctx: Context<DelegateStake>) -> Result<()> {
-    let vote_account = &ctx.accounts.vote_account;
-    let clock = Clock::get()?;
-    // In a real implementation, stake history would be passed as an account
-    // For now, we'll use a placeholder
+      require!(
+        ctx.accounts.staker.key() == stake_account.meta.authorized.staker,
+        StakeError::UnauthorizedStaker
+      );
 
-    let stake_lamports = ctx.accounts.stake_account.to_account_info().lamports();
-    let stake_account = &mut ctx.accounts.stake_account;
+      let vote_state = get_vote_state(vote_account)?;
 
-    require!(
-      ctx.accounts.staker.key() == stake_account.meta.authorized.staker,
-      StakeError::UnauthorizedStaker
-    );
+      // Stake that is still effective has to cool down before it can be delegated again
+      if let Some(stake) = &stake_account.stake {
+        require!(
+          stake
+            .delegation
+            .stake(clock.epoch, &stake_history, NEW_RATE_ACTIVATION_EPOCH)
+            == 0,
+          StakeError::TooSoonToRedelegate
+        );
+      }
 
-    let stake_amount = stake_lamports - stake_account.meta.rent_exempt_reserve;
+      let stake_amount =
+        delegatable_lamports(stake_lamports, stake_account.meta.rent_exempt_reserve)?;
 
-    require!(
-      stake_amount >= MINIMUM_DELEGATION,
-      StakeError::InsufficientStake
-    );
+      require!(
+        stake_amount >= ctx.accounts.config.minimum_delegation,
+        StakeError::InsufficientStake
+      );
+
+      // A redelegated account has to warm up again before it can be deactivated, so stake cannot
+      // be cycled between validators faster than it activates
+      if stake_account.stake.is_some() {
+        stake_account
+          .stake_flags
+          .set(StakeFlags::MUST_FULLY_ACTIVATE_BEFORE_DEACTIVATION_IS_PERMITTED);
+      }
+
+      stake_account.stake = Some(Stake {
+        delegation: Delegation {
+          voter_pubkey: vote_account.key(),
+          stake: stake_amount,
+          activation_epoch: clock.epoch,
+          deactivation_epoch: u64::MAX,
+          warmup_cooldown_rate: ctx.accounts.config.warmup_cooldown_rate,
+        },
+        credits_observed: vote_state.credits(),
+      });
 
-    stake_account.stake = Some(Stake {
-      delegation: Delegation {
-        voter_pubkey: vote_account.key(),
+      emit!(DelegateEvent {
+        stake_account: stake_account_key,
+        vote_account: vote_account.key(),
         stake: stake_amount,
         activation_epoch: clock.epoch,
-        deactivation_epoch: u64::MAX,
-        warmup_cooldown_rate: WARMUP_COOLDOWN_RATE,
-      },
-      credits_observed: 0, // Credits observed would come from vote account in real implementation
-    });
-
-    emit!(DelegateEvent {
-      stake_account: ctx.accounts.stake_account.key(),
-      vote_account: vote_account.key(),
-      stake: stake_amount,
-      activation_epoch: clock.epoch,
-    });
-
-    Ok(())
-  }
+      });
 
-  pub fn split(ctx: Context<Split>, lamports: u64) -> Result<()> {
-    let clock = Clock::get()?;
-    let rent = Rent::get()?;
+      Ok(())
+    }
 
-    let source_lamports = ctx.accounts.source_account.to_account_info().lamports();
-    let split_data_len = ctx.accounts.split_account.to_account_info().data_len();
-    let split_min_balance = rent.minimum_balance(split_data_len);
-    let source_account_key = ctx.accounts.source_account.key();
-    let split_account_key = ctx.accounts.split_account.key();
+    pub fn split(ctx: Context<Split>, lamports: u64) -> Result<()> {
+      require!(
+        ctx.accounts.staker.key() == ctx.accounts.source_account.meta.authorized.staker,
+        StakeError::UnauthorizedStaker
+      );
+
+      let source_lamports = ctx.accounts.source_account.to_account_info().lamports();
+      let split_account = split_stake(
+        &mut ctx.accounts.source_account,
+        source_lamports,
+        lamports,
+        ctx.accounts.config.minimum_delegation,
+      )?;
+      ctx.accounts.split_account.set_inner(split_account);
+
+      transfer_lamports(
+        &ctx.accounts.source_account.to_account_info(),
+        &ctx.accounts.split_account.to_account_info(),
+        lamports,
+      )?;
+
+      emit!(SplitEvent {
+        source_account: ctx.accounts.source_account.key(),
+        split_account: ctx.accounts.split_account.key(),
+        lamports,
+        timestamp: Clock::get()?.unix_timestamp,
+      });
 
-    let source_account = &mut ctx.accounts.source_account;
-    let split_account = &mut ctx.accounts.split_account;
+      Ok(())
+    }
 
-    require!(
-      ctx.accounts.staker.key() == source_account.meta.authorized.staker,
-      StakeError::UnauthorizedStaker
-    );
+    pub fn split_to_pda(ctx: Context<SplitToPda>, _index: u64, lamports: u64) -> Result<()> {
+      require!(
+        ctx.accounts.staker.key() == ctx.accounts.source_account.meta.authorized.staker,
+        StakeError::UnauthorizedStaker
+      );
+
+      let source_lamports = ctx.accounts.source_account.to_account_info().lamports();
+      let split_account = split_stake(
+        &mut ctx.accounts.source_account,
+        source_lamports,
+        lamports,
+        ctx.accounts.config.minimum_delegation,
+      )?;
+      ctx.accounts.split_account.set_inner(split_account);
+
+      transfer_lamports(
+        &ctx.accounts.source_account.to_account_info(),
+        &ctx.accounts.split_account.to_account_info(),
+        lamports,
+      )?;
+
+      emit!(SplitEvent {
+        source_account: ctx.accounts.source_account.key(),
+        split_account: ctx.accounts.split_account.key(),
+        lamports,
+        timestamp: Clock::get()?.unix_timestamp,
+      });
 
-    require!(
-      lamports + split_min_balance <= source_lamports,
-      StakeError::InsufficientFunds
-    );
+      Ok(())
+    }
 
-    require!(
-      source_lamports - lamports >= source_account.meta.rent_exempt_reserve,
-      StakeError::InsufficientFunds
-    );
+    pub fn split_with_seed(ctx: Context<SplitWithSeed>, seed: String, lamports: u64) -> Result<()> {
+      require!(
+        ctx.accounts.staker.key() == ctx.accounts.source_account.meta.authorized.staker,
+        StakeError::UnauthorizedStaker
+      );
+      let split_address = Pubkey::create_with_seed(&ctx.accounts.base.key(), &seed, &crate::ID)
+        .map_err(|_| error!(StakeError::InvalidSplitAddress))?;
+      require_keys_eq!(
+        ctx.accounts.split_account.key(),
+        split_address,
+        StakeError::InvalidSplitAddress
+      );
+
+      let space = 8 + StakeAccount::LEN;
+      system_program::create_account_with_seed(
+        CpiContext::new(
+          ctx.accounts.system_program.to_account_info(),
+          system_program::CreateAccountWithSeed {
+            from: ctx.accounts.staker.to_account_info(),
+            to: ctx.accounts.split_account.to_account_info(),
+            base: ctx.accounts.base.to_account_info(),
+          },
+        ),
+        &seed,
+        ctx.accounts.rent.minimum_balance(space),
+        space as u64,
+        &crate::ID,
+      )?;
+
+      let source_lamports = ctx.accounts.source_account.to_account_info().lamports();
+      let split_account = split_stake(
+        &mut ctx.accounts.source_account,
+        source_lamports,
+        lamports,
+        ctx.accounts.config.minimum_delegation,
+      )?;
+      split_account
+        .try_serialize(&mut &mut ctx.accounts.split_account.try_borrow_mut_data()?[..])?;
+
+      transfer_lamports(
+        &ctx.accounts.source_account.to_account_info(),
+        &ctx.accounts.split_account.to_account_info(),
+        lamports,
+      )?;
+
+      emit!(SplitEvent {
+        source_account: ctx.accounts.source_account.key(),
+        split_account: ctx.accounts.split_account.key(),
+        lamports,
+        timestamp: Clock::get()?.unix_timestamp,
+      });
 
-    split_account.meta = source_account.meta.clone();
+      Ok(())
+    }
 
-    if let Some(source_stake) = &mut source_account.stake {
-      let split_stake_amount =
-        (source_stake.delegation.stake as u128 * lamports as u128 / source_lamports as u128) as u64;
+    pub fn withdraw(ctx: Context<Withdraw>, lamports: u64) -> Result<()> {
+      let clock = Clock::get()?;
+      let stake_history_data = ctx.accounts.stake_history.try_borrow_data()?;
+      let stake_history = StakeHistoryData(&stake_history_data);
+
+      let stake_lamports = ctx.accounts.stake_account.to_account_info().lamports();
+      let custodian = ctx
+        .accounts
+        .custodian
+        .as_ref()
+        .map(|custodian| custodian.key());
+      let stake_account = &mut ctx.accounts.stake_account;
+
+      require!(
+        ctx.accounts.withdrawer.key() == stake_account.meta.authorized.withdrawer,
+        StakeError::UnauthorizedWithdrawer
+      );
+
+      stake_account
+        .meta
+        .lockup
+        .check_expired_or_custodian(&clock, custodian.as_ref())?;
+
+      let staked = staked_lamports(stake_account.stake.as_ref(), clock.epoch, &stake_history);
+
+      // Withdrawing everything, rent-exempt reserve included, closes the account
+      if lamports == stake_lamports {
+        require!(staked == 0, StakeError::StakeNotFullyInactive);
+
+        ctx
+          .accounts
+          .stake_account
+          .close(ctx.accounts.to.to_account_info())?;
+      } else {
+        let available_for_withdrawal = withdrawable_lamports(
+          stake_lamports,
+          staked,
+          stake_account.meta.rent_exempt_reserve,
+        )?;
+        require!(
+          lamports <= available_for_withdrawal,
+          StakeError::InsufficientFunds
+        );
 
-      source_stake.delegation.stake -= split_stake_amount;
+        transfer_lamports(
+          &ctx.accounts.stake_account.to_account_info(),
+          &ctx.accounts.to.to_account_info(),
+          lamports,
+        )?;
+      }
 
-      split_account.stake = Some(Stake {
-        delegation: Delegation {
-          voter_pubkey: source_stake.delegation.voter_pubkey,
-          stake: split_stake_amount,
-          activation_epoch: source_stake.delegation.activation_epoch,
-          deactivation_epoch: source_stake.delegation.deactivation_epoch,
-          warmup_cooldown_rate: source_stake.delegation.warmup_cooldown_rate,
-        },
-        credits_observed: source_stake.credits_observed,
+      emit!(WithdrawEvent {
+        stake_account: ctx.accounts.stake_account.key(),
+        withdrawer: ctx.accounts.withdrawer.key(),
+        to: ctx.accounts.to.key(),
+        lamports,
+        timestamp: clock.unix_timestamp,
       });
-    } else {
-      split_account.stake = None;
-    }
-
-    split_account.stake_flags = source_account.stake_flags;
 
-    **ctx
-      .accounts
-      .source_account
-      .to_account_info()
-      .try_borrow_mut_lamports()? -= lamports;
-    **ctx
-      .accounts
-      .split_account
-      .to_account_info()
-      .try_borrow_mut_lamports()? += lamports;
-
-    emit!(SplitEvent {
-      source_account: source_account_key,
-      split_account: split_account_key,
-      lamports,
-      timestamp: clock.unix_timestamp,
-    });
+      Ok(())
+    }
 
-    Ok(())
-  }
+    pub fn deactivate(ctx: Context<Deactivate>) -> Result<()> {
+      let stake_account_key = ctx.accounts.stake_account.key();
+      let stake_account = &mut ctx.accounts.stake_account;
+      let clock = Clock::get()?;
+      let stake_history_data = ctx.accounts.stake_history.try_borrow_data()?;
+      let stake_history = StakeHistoryData(&stake_history_data);
+
+      require!(
+        ctx.accounts.staker.key() == stake_account.meta.authorized.staker,
+        StakeError::UnauthorizedStaker
+      );
+
+      let mut stake_flags = stake_account.stake_flags;
+      if let Some(stake) = &mut stake_account.stake {
+        // Deactivating again would restart the cooldown
+        require!(
+          stake.delegation.deactivation_epoch == u64::MAX,
+          StakeError::AlreadyDeactivated
+        );
+        if stake_flags.contains(StakeFlags::MUST_FULLY_ACTIVATE_BEFORE_DEACTIVATION_IS_PERMITTED) {
+          let status = stake.delegation.stake_activating_and_deactivating(
+            clock.epoch,
+            &stake_history,
+            NEW_RATE_ACTIVATION_EPOCH,
+          );
+          require!(
+            status.activating == 0,
+            StakeError::MustFullyActivateBeforeDeactivation
+          );
+          stake_flags.remove(StakeFlags::MUST_FULLY_ACTIVATE_BEFORE_DEACTIVATION_IS_PERMITTED);
+        }
+
+        stake.delegation.deactivation_epoch = clock.epoch;
+        stake_account.stake_flags = stake_flags;
+
+        emit!(DeactivateEvent {
+          stake_account: stake_account_key,
+          epoch: clock.epoch,
+        });
+      }
 
-  pub fn withdraw(ctx: Context<Withdraw>, lamports: u64) -> Result<()> {
-    let clock = Clock::get()?;
-    // In a real implementation, stake history would be passed as an account
-    // For now, we'll use a placeholder
+      Ok(())
+    }
 
-    let stake_lamports = ctx.accounts.stake_account.to_account_info().lamports();
-    let stake_account = &mut ctx.accounts.stake_account;
+    pub fn deactivate_delinquent(ctx: Context<DeactivateDelinquent>) -> Result<()> {
+      let clock = Clock::get()?;
+      let stake_account_key = ctx.accounts.stake_account.key();
+      let stake_account = &mut ctx.accounts.stake_account;
+
+      let reference_vote_state = get_vote_state(&ctx.accounts.reference_vote_account)?;
+      require!(
+        acceptable_reference_epoch_credits(reference_vote_state.epoch_credits(), clock.epoch),
+        StakeError::InsufficientReferenceVotes
+      );
+
+      let stake = stake_account
+        .stake
+        .as_mut()
+        .ok_or(StakeError::StakeNotDelegated)?;
+      require_keys_eq!(
+        stake.delegation.voter_pubkey,
+        ctx.accounts.vote_account.key(),
+        StakeError::VoteAddressMismatch
+      );
+      require!(
+        stake.delegation.deactivation_epoch == u64::MAX,
+        StakeError::AlreadyDeactivated
+      );
+
+      let vote_state = get_vote_state(&ctx.accounts.vote_account)?;
+      require!(
+        eligible_for_deactivate_delinquent(vote_state.epoch_credits(), clock.epoch),
+        StakeError::MinimumDelinquentEpochsForDeactivationNotMet
+      );
 
-    require!(
-      ctx.accounts.withdrawer.key() == stake_account.meta.authorized.withdrawer,
-      StakeError::UnauthorizedWithdrawer
-    );
+      stake.delegation.deactivation_epoch = clock.epoch;
 
-    require!(
-      !stake_account.meta.lockup.is_in_force(&clock, None),
-      StakeError::LockupInForce
-    );
+      emit!(DeactivateEvent {
+        stake_account: stake_account_key,
+        epoch: clock.epoch,
+      });
 
-    let available_for_withdrawal = if let Some(stake) = &stake_account.stake {
-      let effective_stake = stake
-        .delegation
-        .stake_activating_and_deactivating(
-          clock.epoch,
-          None, // Stake history would be passed in real implementation
-          None,
-        )
-        .effective;
-
-      stake_lamports - effective_stake - stake_account.meta.rent_exempt_reserve
-    } else {
-      stake_lamports - stake_account.meta.rent_exempt_reserve
-    };
+      Ok(())
+    }
 
-    require!(
-      lamports <= available_for_withdrawal,
-      StakeError::InsufficientFunds
-    );
+    pub fn set_lockup(ctx: Context<SetLockup>, lockup: LockupArgs) -> Result<()> {
+      set_stake_lockup(
+        &mut ctx.accounts.stake_account,
+        ctx.accounts.custodian.key(),
+        lockup,
+      )
+    }
 
-    **ctx
-      .accounts
-      .stake_account
-      .to_account_info()
-      .try_borrow_mut_lamports()? -= lamports;
-    **ctx
-      .accounts
-      .to
-      .to_account_info()
-      .try_borrow_mut_lamports()? += lamports;
-
-    emit!(WithdrawEvent {
-      stake_account: ctx.accounts.stake_account.key(),
-      withdrawer: ctx.accounts.withdrawer.key(),
-      to: ctx.accounts.to.key(),
-      lamports,
-      timestamp: clock.unix_timestamp,
-    });
+    pub fn set_lockup_checked(
+      ctx: Context<SetLockupChecked>,
+      lockup: LockupCheckedArgs,
+    ) -> Result<()> {
+      let lockup = LockupArgs {
+        unix_timestamp: lockup.unix_timestamp,
+        epoch: lockup.epoch,
+        custodian: ctx
+          .accounts
+          .new_custodian
+          .as_ref()
+          .map(|custodian| custodian.key()),
+      };
+
+      set_stake_lockup(
+        &mut ctx.accounts.stake_account,
+        ctx.accounts.custodian.key(),
+        lockup,
+      )
+    }
 
-    Ok(())
-  }
+    pub fn merge(ctx: Context<Merge>) -> Result<()> {
+      let clock = Clock::get()?;
+      let stake_history_data = ctx.accounts.stake_history.try_borrow_data()?;
+      let stake_history = StakeHistoryData(&stake_history_data);
+
+      let source_lamports = ctx.accounts.source_account.to_account_info().lamports();
+
+      let source_account = &mut ctx.accounts.source_account;
+      let dest_account = &mut ctx.accounts.dest_account;
+
+      require!(
+        ctx.accounts.staker.key() == source_account.meta.authorized.staker,
+        StakeError::UnauthorizedStaker
+      );
+      require!(
+        ctx.accounts.staker.key() == dest_account.meta.authorized.staker,
+        StakeError::UnauthorizedStaker
+      );
+
+      require!(
+        metas_can_merge(&dest_account.meta, &source_account.meta, &clock),
+        StakeError::MergeMismatch
+      );
+
+      if let Some((stake, stake_flags)) = merge_stake(
+        dest_account,
+        source_account,
+        source_lamports,
+        &clock,
+        &stake_history,
+      )? {
+        dest_account.stake = Some(stake);
+        dest_account.stake_flags = stake_flags;
+      }
 
-  pub fn deactivate(ctx: Context<Deactivate>) -> Result<()> {
-    let stake_account = &mut ctx.accounts.stake_account;
-    let clock = Clock::get()?;
+      transfer_lamports(
+        &ctx.accounts.source_account.to_account_info(),
+        &ctx.accounts.dest_account.to_account_info(),
+        source_lamports,
+      )?;
+
+      emit!(MergeEvent {
+        source_account: ctx.accounts.source_account.key(),
+        dest_account: ctx.accounts.dest_account.key(),
+        lamports: source_lamports,
+        timestamp: clock.unix_timestamp,
+      });
 
-    require!(
-      ctx.accounts.staker.key() == stake_account.meta.authorized.staker,
-      StakeError::UnauthorizedStaker
-    );
+      Ok(())
+    }
 
-    if let Some(stake) = &mut stake_account.stake {
-      stake.delegation.deactivation_epoch = clock.epoch;
+    pub fn move_stake(ctx: Context<MoveStake>, lamports: u64) -> Result<()> {
+      let clock = Clock::get()?;
+      let minimum_delegation = ctx.accounts.config.minimum_delegation;
+      let stake_history_data = ctx.accounts.stake_history.try_borrow_data()?;
+      let stake_history = StakeHistoryData(&stake_history_data);
+
+      let source_account_key = ctx.accounts.source_account.key();
+      let dest_account_key = ctx.accounts.dest_account.key();
+
+      let source_account = &mut ctx.accounts.source_account;
+      let dest_account = &mut ctx.accounts.dest_account;
+
+      require!(
+        ctx.accounts.staker.key() == source_account.meta.authorized.staker,
+        StakeError::UnauthorizedStaker
+      );
+      require!(lamports > 0, StakeError::InvalidMoveAmount);
+      require!(
+        metas_can_merge(&dest_account.meta, &source_account.meta, &clock),
+        StakeError::MergeMismatch
+      );
+
+      // Only fully active stake can move, and only onto an idle account or a matching active one
+      require!(
+        MergeKind::get_if_mergeable(source_account, &clock, &stake_history)?
+          == MergeKind::FullyActive,
+        StakeError::MergeTransientStake
+      );
+      let dest_merge_kind = MergeKind::get_if_mergeable(dest_account, &clock, &stake_history)?;
+
+      let source_stake = source_account
+        .stake
+        .clone()
+        .ok_or(StakeError::StakeNotDelegated)?;
+      let remaining_stake = source_stake
+        .delegation
+        .stake
+        .checked_sub(lamports)
+        .ok_or(StakeError::InsufficientStake)?;
+      require!(
+        remaining_stake == 0 || remaining_stake >= minimum_delegation,
+        StakeError::InsufficientStake
+      );
+
+      match (dest_merge_kind, &mut dest_account.stake) {
+        (MergeKind::FullyActive, Some(dest_stake)) => {
+          require!(
+            dest_stake.delegation.voter_pubkey == source_stake.delegation.voter_pubkey,
+            StakeError::MergeMismatch
+          );
+          dest_stake.credits_observed =
+            stake_weighted_credits_observed(dest_stake, lamports, source_stake.credits_observed)
+              .ok_or(StakeError::InsufficientFunds)?;
+          dest_stake.delegation.stake = dest_stake
+            .delegation
+            .stake
+            .checked_add(lamports)
+            .ok_or(StakeError::InsufficientFunds)?;
+        }
+        (MergeKind::Inactive, _) => {
+          require!(
+            lamports >= minimum_delegation,
+            StakeError::InsufficientStake
+          );
+          dest_account.stake = Some(Stake {
+            delegation: Delegation {
+              stake: lamports,
+              ..source_stake.delegation
+            },
+            credits_observed: source_stake.credits_observed,
+          });
+        }
+        _ => return err!(StakeError::MergeTransientStake),
+      }
+      dest_account
+        .stake_flags
+        .set(StakeFlags::MUST_FULLY_ACTIVATE_BEFORE_DEACTIVATION_IS_PERMITTED);
+
+      if remaining_stake == 0 {
+        source_account.stake = None;
+      } else if let Some(stake) = &mut source_account.stake {
+        stake.delegation.stake = remaining_stake;
+      }
 
-      emit!(DeactivateEvent {
-        stake_account: ctx.accounts.stake_account.key(),
-        epoch: clock.epoch,
+      transfer_lamports(
+        &ctx.accounts.source_account.to_account_info(),
+        &ctx.accounts.dest_account.to_account_info(),
+        lamports,
+      )?;
+
+      emit!(MoveStakeEvent {
+        source_account: source_account_key,
+        dest_account: dest_account_key,
+        lamports,
+        timestamp: clock.unix_timestamp,
       });
+
+      Ok(())
     }
 
-    Ok(())
-  }
+    pub fn move_lamports(ctx: Context<MoveLamports>, lamports: u64) -> Result<()> {
+      let clock = Clock::get()?;
+      let stake_history_data = ctx.accounts.stake_history.try_borrow_data()?;
+      let stake_history = StakeHistoryData(&stake_history_data);
+
+      let source_lamports = ctx.accounts.source_account.to_account_info().lamports();
+      let source_account = &ctx.accounts.source_account;
+      let dest_account = &ctx.accounts.dest_account;
+
+      require!(
+        ctx.accounts.staker.key() == source_account.meta.authorized.staker,
+        StakeError::UnauthorizedStaker
+      );
+      require!(lamports > 0, StakeError::InvalidMoveAmount);
+      require!(
+        metas_can_merge(&dest_account.meta, &source_account.meta, &clock),
+        StakeError::MergeMismatch
+      );
+
+      let source_merge_kind = MergeKind::get_if_mergeable(source_account, &clock, &stake_history)?;
+      let dest_merge_kind = MergeKind::get_if_mergeable(dest_account, &clock, &stake_history)?;
+      require!(
+        source_merge_kind != MergeKind::ActivationEpoch
+          && dest_merge_kind != MergeKind::ActivationEpoch,
+        StakeError::MergeTransientStake
+      );
+
+      // Lamports that are neither delegated nor part of the rent-exempt reserve
+      let delegated_stake = match (source_merge_kind, &source_account.stake) {
+        (MergeKind::FullyActive, Some(stake)) => stake.delegation.stake,
+        _ => 0,
+      };
+      let free_lamports = source_lamports
+        .saturating_sub(delegated_stake)
+        .saturating_sub(source_account.meta.rent_exempt_reserve);
+      require!(lamports <= free_lamports, StakeError::InsufficientFunds);
+
+      transfer_lamports(
+        &ctx.accounts.source_account.to_account_info(),
+        &ctx.accounts.dest_account.to_account_info(),
+        lamports,
+      )?;
+
+      emit!(MoveLamportsEvent {
+        source_account: ctx.accounts.source_account.key(),
+        dest_account: ctx.accounts.dest_account.key(),
+        lamports,
+        timestamp: clock.unix_timestamp,
+      });
 
-  pub fn set_lockup(ctx: Context<SetLockup>, lockup: LockupArgs) -> Result<()> {
-    let clock = Clock::get()?;
-    let stake_account_key = ctx.accounts.stake_account.key();
+      Ok(())
+    }
 
-    let stake_account = &mut ctx.accounts.stake_account;
+    pub fn get_minimum_delegation(ctx: Context<GetMinimumDelegation>) -> Result<()> {
+      let min_delegation = ctx.accounts.config.minimum_delegation;
 
-    require!(
-      ctx.accounts.custodian.key() == stake_account.meta.lockup.custodian,
-      StakeError::UnauthorizedCustodian
-    );
+      anchor_lang::solana_program::program::set_return_data(&min_delegation.to_le_bytes());
 
-    if let Some(unix_timestamp) = lockup.unix_timestamp {
-      stake_account.meta.lockup.unix_timestamp = unix_timestamp;
-    }
-    if let Some(epoch) = lockup.epoch {
-      stake_account.meta.lockup.epoch = epoch;
-    }
-    if let Some(custodian) = lockup.custodian {
-      stake_account.meta.lockup.custodian = custodian;
+      Ok(())
     }
 
-    let lockup = stake_account.meta.lockup;
+    pub fn initialize_config(ctx: Context<InitializeConfig>, args: StakeConfigArgs) -> Result<()> {
+      let config = &mut ctx.accounts.config;
+      config.admin = ctx.accounts.admin.key();
+      config.minimum_delegation = args.minimum_delegation;
+      config.warmup_cooldown_rate = args.warmup_cooldown_rate;
+      config.slash_penalty = args.slash_penalty;
+      config.bump = ctx.bumps.config;
+      config.validate()?;
+
+      emit!(ConfigEvent {
+        config: config.key(),
+        admin: config.admin,
+        minimum_delegation: config.minimum_delegation,
+        warmup_cooldown_rate: config.warmup_cooldown_rate,
+        slash_penalty: config.slash_penalty,
+      });
 
-    emit!(SetLockupEvent {
-      stake_account: stake_account_key,
-      lockup,
-      timestamp: clock.unix_timestamp,
-    });
+      Ok(())
+    }
 
-    Ok(())
-  }
+    pub fn update_config(ctx: Context<UpdateConfig>, args: UpdateConfigArgs) -> Result<()> {
+      let config = &mut ctx.accounts.config;
+      if let Some(admin) = args.admin {
+        config.admin = admin;
+      }
+      if let Some(minimum_delegation) = args.minimum_delegation {
+        config.minimum_delegation = minimum_delegation;
+      }
+      if let Some(warmup_cooldown_rate) = args.warmup_cooldown_rate {
+        config.warmup_cooldown_rate = warmup_cooldown_rate;
+      }
+      if let Some(slash_penalty) = args.slash_penalty {
+        config.slash_penalty = slash_penalty;
+      }
+      config.validate()?;
+
+      emit!(ConfigEvent {
+        config: config.key(),
+        admin: config.admin,
+        minimum_delegation: config.minimum_delegation,
+        warmup_cooldown_rate: config.warmup_cooldown_rate,
+        slash_penalty: config.slash_penalty,
+      });
 
-  pub fn merge(ctx: Context<Merge>) -> Result<()> {
-    let clock = Clock::get()?;
-    // In a real implementation, stake history would be passed as an account
-    // For now, we'll use a placeholder
+      Ok(())
+    }
 
-    let source_lamports = ctx.accounts.source_account.to_account_info().lamports();
+    pub fn initialize_reward_reserve(
+      ctx: Context<InitializeRewardReserve>,
+      point_value: PointValue,
+    ) -> Result<()> {
+      let reward_reserve = &mut ctx.accounts.reward_reserve;
+      reward_reserve.authority = ctx.accounts.authority.key();
+      reward_reserve.point_value = point_value;
+      reward_reserve.bump = ctx.bumps.reward_reserve;
+
+      emit!(PointValueEvent {
+        reward_reserve: reward_reserve.key(),
+        point_value,
+      });
 
-    let source_account = &mut ctx.accounts.source_account;
-    let dest_account = &mut ctx.accounts.dest_account;
+      Ok(())
+    }
 
-    require!(
-      ctx.accounts.staker.key() == source_account.meta.authorized.staker,
-      StakeError::UnauthorizedStaker
-    );
-    require!(
-      ctx.accounts.staker.key() == dest_account.meta.authorized.staker,
-      StakeError::UnauthorizedStaker
-    );
+    pub fn set_point_value(ctx: Context<SetPointValue>, point_value: PointValue) -> Result<()> {
+      let reward_reserve = &mut ctx.accounts.reward_reserve;
+      reward_reserve.point_value = point_value;
 
-    require!(
-      source_account.meta.authorized == dest_account.meta.authorized,
-      StakeError::MergeMismatch
-    );
+      emit!(PointValueEvent {
+        reward_reserve: reward_reserve.key(),
+        point_value,
+      });
 
-    require!(
-      source_account.meta.lockup == dest_account.meta.lockup,
-      StakeError::MergeMismatch
-    );
+      Ok(())
+    }
 
-    match (&source_account.stake, &dest_account.stake) {
-      (Some(source_stake), Some(dest_stake)) => {
-        require!(
-          source_stake.delegation.voter_pubkey == dest_stake.delegation.voter_pubkey,
-          StakeError::MergeMismatch
-        );
+    pub fn redeem_rewards(ctx: Context<RedeemRewards>) -> Result<()> {
+      let clock = Clock::get()?;
+      let rent = Rent::get()?;
+      let stake_history_data = ctx.accounts.stake_history.try_borrow_data()?;
+      let stake_history = StakeHistoryData(&stake_history_data);
+      let vote_state = get_vote_state(&ctx.accounts.vote_account)?;
+
+      let reward_reserve_info = ctx.accounts.reward_reserve.to_account_info();
+      let available_rewards = reward_reserve_info
+        .lamports()
+        .saturating_sub(rent.minimum_balance(reward_reserve_info.data_len()));
+      let point_value = ctx.accounts.reward_reserve.point_value;
+      let stake_account_key = ctx.accounts.stake_account.key();
+      let vote_account_key = ctx.accounts.vote_account.key();
+
+      let stake = ctx
+        .accounts
+        .stake_account
+        .stake
+        .as_mut()
+        .ok_or(StakeError::StakeNotDelegated)?;
+
+      require_keys_eq!(
+        stake.delegation.voter_pubkey,
+        vote_account_key,
+        StakeError::InvalidVoteAccount
+      );
+
+      let rewards = calculate_stake_rewards(
+        stake,
+        vote_state.epoch_credits(),
+        clock.epoch,
+        vote_state.commission,
+        &point_value,
+        &stake_history,
+        NEW_RATE_ACTIVATION_EPOCH,
+      )?;
+      let total_rewards = rewards
+        .staker_rewards
+        .checked_add(rewards.voter_rewards)
+        .ok_or(StakeError::RewardCalculationOverflow)?;
+
+      require!(
+        total_rewards <= available_rewards,
+        StakeError::InsufficientRewardReserve
+      );
+
+      // As with native inflation rewards, the staker's share is delegated on top of the stake
+      stake.delegation.stake = stake
+        .delegation
+        .stake
+        .checked_add(rewards.staker_rewards)
+        .ok_or(StakeError::RewardCalculationOverflow)?;
+      stake.credits_observed = rewards.new_credits_observed;
+
+      transfer_lamports(
+        &reward_reserve_info,
+        &ctx.accounts.stake_account.to_account_info(),
+        rewards.staker_rewards,
+      )?;
+      transfer_lamports(
+        &reward_reserve_info,
+        &ctx.accounts.vote_account,
+        rewards.voter_rewards,
+      )?;
+
+      emit!(RewardEvent {
+        stake_account: stake_account_key,
+        vote_account: vote_account_key,
+        staker_rewards: rewards.staker_rewards,
+        voter_rewards: rewards.voter_rewards,
+        credits_observed: rewards.new_credits_observed,
+        epoch: clock.epoch,
+      });
 
-        require!(
-          source_stake.delegation.deactivation_epoch == u64::MAX
-            && dest_stake.delegation.deactivation_epoch == u64::MAX,
-          StakeError::MergeDeactivated
-        );
-      }
-      _ => return Err(StakeError::MergeTransientStake.into()),
+      Ok(())
     }
+  }
+}
+pub use processor::*;
+
+fn initialize_stake_account(
+  stake_account: &mut Account<StakeAccount>,
+  authorized: Authorized,
+  lockup: Lockup,
+) -> Result<()> {
+  let clock = Clock::get()?;
+  let rent = Rent::get()?;
+
+  let data_len = stake_account.to_account_info().data_len();
+  let stake_account_key = stake_account.key();
+
+  stake_account.meta = Meta {
+    rent_exempt_reserve: rent.minimum_balance(data_len),
+    authorized,
+    lockup,
+  };
+  stake_account.stake = None;
+  stake_account.stake_flags = StakeFlags::empty();
+
+  emit!(StakeInitializeEvent {
+    stake_account: stake_account_key,
+    staker: authorized.staker,
+    withdrawer: authorized.withdrawer,
+    timestamp: clock.unix_timestamp,
+  });
+
+  Ok(())
+}
 
-    if let Some(dest_stake) = &mut dest_account.stake {
-      if let Some(source_stake) = &source_account.stake {
-        dest_stake.delegation.stake = dest_stake
-          .delegation
-          .stake
-          .checked_add(source_stake.delegation.stake)
-          .ok_or(StakeError::InsufficientFunds)?;
-      }
+fn authorize_stake_account(
+  stake_account: &mut Account<StakeAccount>,
+  authority: Pubkey,
+  new_authority: Pubkey,
+  stake_authorize: StakeAuthorize,
+  custodian: Option<Pubkey>,
+) -> Result<()> {
+  match stake_authorize {
+    StakeAuthorize::Staker => {
+      require!(
+        authority == stake_account.meta.authorized.staker,
+        StakeError::UnauthorizedStaker
+      );
+      stake_account.meta.authorized.staker = new_authority;
+    }
+    StakeAuthorize::Withdrawer => {
+      require!(
+        authority == stake_account.meta.authorized.withdrawer,
+        StakeError::UnauthorizedWithdrawer
+      );
+      // Handing over the withdrawer would otherwise be a way around the lockup
+      stake_account
+        .meta
+        .lockup
+        .check_expired_or_custodian(&Clock::get()?, custodian.as_ref())?;
+      stake_account.meta.authorized.withdrawer = new_authority;
     }
+  }
 
-    **ctx
-      .accounts
-      .source_account
-      .to_account_info()
-      .try_borrow_mut_lamports()? = 0;
-    **ctx
-      .accounts
-      .dest_account
-      .to_account_info()
-      .try_borrow_mut_lamports()? += source_lamports;
-
-    emit!(MergeEvent {
-      source_account: ctx.accounts.source_account.key(),
-      dest_account: ctx.accounts.dest_account.key(),
-      lamports: source_lamports,
-      timestamp: clock.unix_timestamp,
-    });
+  emit!(AuthorizeEvent {
+    stake_account: stake_account.key(),
+    old_authority: authority,
+    new_authority,
+    stake_authorize,
+  });
 
-    Ok(())
-  }
+  Ok(())
+}
 
-  pub fn get_minimum_delegation(_ctx: Context<GetMinimumDelegation>) -> Result<()> {
-    let min_delegation = MINIMUM_DELEGATION;
+/// Address derived from `base`, `seed` and `owner` that `authorize_with_seed` checks against the
+/// current authority, matching `Pubkey::create_with_seed`.
+pub fn seed_authority(base: &Pubkey, seed: &str, owner: &Pubkey) -> Result<Pubkey> {
+  Pubkey::create_with_seed(base, seed, owner).map_err(|_| error!(StakeError::InvalidAuthoritySeed))
+}
+
+/// This program's program data account under the upgradeable loader. Its upgrade authority is
+/// the only key that can create the program-wide config and reward reserve.
+pub fn program_data_address() -> Pubkey {
+  Pubkey::find_program_address(&[crate::ID.as_ref()], &bpf_loader_upgradeable::ID).0
+}
 
-    anchor_lang::solana_program::program::set_return_data(&min_delegation.to_le_bytes());
+fn set_stake_lockup(
+  stake_account: &mut Account<StakeAccount>,
+  custodian: Pubkey,
+  lockup: LockupArgs,
+) -> Result<()> {
+  let clock = Clock::get()?;
 
-    Ok(())
+  require!(
+    custodian == stake_account.meta.lockup.custodian,
+    StakeError::UnauthorizedCustodian
+  );
+
+  if let Some(unix_timestamp) = lockup.unix_timestamp {
+    stake_account.meta.lockup.unix_timestamp = unix_timestamp;
+  }
+  if let Some(epoch) = lockup.epoch {
+    stake_account.meta.lockup.epoch = epoch;
   }
+  if let Some(custodian) = lockup.custodian {
+    stake_account.meta.lockup.custodian = custodian;
+  }
+
+  emit!(SetLockupEvent {
+    stake_account: stake_account.key(),
+    lockup: stake_account.meta.lockup,
+    timestamp: clock.unix_timestamp,
+  });
+
+  Ok(())
 }
 
-const MINIMUM_DELEGATION: u64 = 1_000_000_000; // 1 SOL
-const WARMUP_COOLDOWN_RATE: f64 = 0.09;
+/// Minimum delegation a new `StakeConfig` is usually initialized with
+pub const DEFAULT_MINIMUM_DELEGATION: u64 = 1_000_000_000; // 1 SOL
+
+/// Slash penalty a new `StakeConfig` is usually initialized with, as in the native config
+pub const DEFAULT_SLASH_PENALTY: u8 = 12;
+
+// The reduced warmup/cooldown rate is active from genesis on local validators and has long been
+// active on the public clusters, so every epoch we can see uses it
+pub const NEW_RATE_ACTIVATION_EPOCH: Option<u64> = Some(0);
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
 pub struct Authorized {
@@ -392,7 +965,7 @@ pub struct Authorized {
   pub withdrawer: Pubkey,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq)]
 pub struct Lockup {
   pub unix_timestamp: i64,
   pub epoch: u64,
@@ -406,6 +979,23 @@ impl Lockup {
     }
     self.unix_timestamp > clock.unix_timestamp || self.epoch > clock.epoch
   }
+
+  /// Fails while the lockup is in force, unless `custodian` is the lockup custodian.
+  pub fn check_expired_or_custodian(
+    &self,
+    clock: &Clock,
+    custodian: Option<&Pubkey>,
+  ) -> Result<()> {
+    if !self.is_in_force(clock, None) {
+      return Ok(());
+    }
+    require!(custodian.is_some(), StakeError::CustodianMissingSignature);
+    require!(
+      !self.is_in_force(clock, custodian),
+      StakeError::LockupInForce
+    );
+    Ok(())
+  }
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
@@ -415,6 +1005,35 @@ pub struct LockupArgs {
   pub custodian: Option<Pubkey>,
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct LockupCheckedArgs {
+  pub unix_timestamp: Option<i64>,
+  pub epoch: Option<u64>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct StakeConfigArgs {
+  pub minimum_delegation: u64,
+  pub warmup_cooldown_rate: f64,
+  pub slash_penalty: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct UpdateConfigArgs {
+  pub admin: Option<Pubkey>,
+  pub minimum_delegation: Option<u64>,
+  pub warmup_cooldown_rate: Option<f64>,
+  pub slash_penalty: Option<u8>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
+pub struct AuthorizeWithSeedArgs {
+  pub new_authority: Pubkey,
+  pub stake_authorize: StakeAuthorize,
+  pub authority_seed: String,
+  pub authority_owner: Pubkey,
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
 pub enum StakeAuthorize {
   Staker,
@@ -427,57 +1046,184 @@ pub struct Delegation {
   pub stake: u64,
   pub activation_epoch: u64,
   pub deactivation_epoch: u64,
+  /// Rate taken from the `StakeConfig` when delegated; see [`Delegation::warmup_cooldown_rate_at`]
   pub warmup_cooldown_rate: f64,
 }
 
+/// Rate at which stake warms up or cools down per epoch before the reduced rate takes effect.
+pub const DEFAULT_WARMUP_COOLDOWN_RATE: f64 = 0.25;
+/// Rate at which stake warms up or cools down per epoch once the reduced rate is active.
+pub const NEW_WARMUP_COOLDOWN_RATE: f64 = 0.09;
+
 impl Delegation {
-  pub fn stake_activating_and_deactivating(
+  /// Returns the warmup/cooldown rate in effect at `current_epoch`.
+  ///
+  /// Before `new_rate_activation_epoch` every delegation moves at the original 25% of the
+  /// cluster's effective stake per epoch, as in the native program. From then on it moves at the
+  /// rate the `StakeConfig` had when it was delegated. `None` means the switch has not happened.
+  pub fn warmup_cooldown_rate_at(
+    &self,
+    current_epoch: u64,
+    new_rate_activation_epoch: Option<u64>,
+  ) -> f64 {
+    if current_epoch < new_rate_activation_epoch.unwrap_or(u64::MAX) {
+      DEFAULT_WARMUP_COOLDOWN_RATE
+    } else {
+      self.warmup_cooldown_rate
+    }
+  }
+
+  pub fn is_bootstrap(&self) -> bool {
+    self.activation_epoch == u64::MAX
+  }
+
+  /// Effective stake at `target_epoch`.
+  pub fn stake<T: StakeHistoryGetEntry>(
+    &self,
+    target_epoch: u64,
+    history: &T,
+    new_rate_activation_epoch: Option<u64>,
+  ) -> u64 {
+    self
+      .stake_activating_and_deactivating(target_epoch, history, new_rate_activation_epoch)
+      .effective
+  }
+
+  pub fn stake_activating_and_deactivating<T: StakeHistoryGetEntry>(
     &self,
     target_epoch: u64,
-    history: Option<&SolanaStakeHistory>,
-    _new_rate_activation_epoch: Option<u64>,
+    history: &T,
+    new_rate_activation_epoch: Option<u64>,
   ) -> StakeActivationStatus {
-    let (effective_stake, activating_stake, deactivating_stake) =
-      self.stake_and_activating(target_epoch, history, _new_rate_activation_epoch);
+    // First, calculate the effective and activating stake
+    let (effective_stake, activating_stake) =
+      self.stake_and_activating(target_epoch, history, new_rate_activation_epoch);
 
-    StakeActivationStatus {
-      effective: effective_stake,
-      activating: activating_stake,
-      deactivating: deactivating_stake,
+    // Then deactivate some portion of it if necessary
+    if target_epoch < self.deactivation_epoch {
+      StakeActivationStatus {
+        effective: effective_stake,
+        activating: activating_stake,
+        deactivating: 0,
+      }
+    } else if target_epoch == self.deactivation_epoch {
+      // Only what has been activated can be deactivating
+      StakeActivationStatus {
+        effective: effective_stake,
+        activating: 0,
+        deactivating: effective_stake,
+      }
+    } else if let Some(mut prev_cluster_stake) = history.get_entry(self.deactivation_epoch) {
+      // Walk from the deactivation epoch to the target epoch. Each epoch this account cools down
+      // its share of the stake the whole cluster was allowed to deactivate in the previous epoch.
+      let mut prev_epoch = self.deactivation_epoch;
+      let mut current_effective_stake = effective_stake;
+      loop {
+        let current_epoch = prev_epoch + 1;
+        // Nothing was deactivating cluster-wide, so we must already be fully deactivated
+        if prev_cluster_stake.deactivating == 0 {
+          break;
+        }
+
+        let weight = current_effective_stake as f64 / prev_cluster_stake.deactivating as f64;
+        let rate = self.warmup_cooldown_rate_at(current_epoch, new_rate_activation_epoch);
+        let newly_not_effective_cluster_stake = prev_cluster_stake.effective as f64 * rate;
+        let newly_not_effective_stake =
+          ((weight * newly_not_effective_cluster_stake) as u64).max(1);
+
+        current_effective_stake = current_effective_stake.saturating_sub(newly_not_effective_stake);
+        if current_effective_stake == 0 || current_epoch >= target_epoch {
+          break;
+        }
+
+        match history.get_entry(current_epoch) {
+          Some(current_cluster_stake) => {
+            prev_epoch = current_epoch;
+            prev_cluster_stake = current_cluster_stake;
+          }
+          None => break,
+        }
+      }
+
+      StakeActivationStatus {
+        effective: current_effective_stake,
+        activating: 0,
+        deactivating: current_effective_stake,
+      }
+    } else {
+      // No history, or the deactivation epoch has dropped out of it: fully deactivated
+      StakeActivationStatus {
+        effective: 0,
+        activating: 0,
+        deactivating: 0,
+      }
     }
   }
 
-  fn stake_and_activating(
+  /// Returns `(effective, activating)` stake at `target_epoch`, ignoring deactivation.
+  fn stake_and_activating<T: StakeHistoryGetEntry>(
     &self,
     target_epoch: u64,
-    history: Option<&SolanaStakeHistory>,
-    _new_rate_activation_epoch: Option<u64>,
-  ) -> (u64, u64, u64) {
+    history: &T,
+    new_rate_activation_epoch: Option<u64>,
+  ) -> (u64, u64) {
     let delegated_stake = self.stake;
 
-    if self.activation_epoch == self.deactivation_epoch {
-      return (0, 0, 0);
-    }
-    if target_epoch == self.activation_epoch {
-      return (0, delegated_stake, 0);
-    }
-    if target_epoch < self.activation_epoch {
-      return (0, 0, 0);
-    }
-
-    let effective_stake = delegated_stake;
-    let mut activating_stake = 0;
-    let mut deactivating_stake = 0;
+    if self.is_bootstrap() {
+      // Fully effective immediately
+      (delegated_stake, 0)
+    } else if self.activation_epoch == self.deactivation_epoch {
+      // Deactivated in the same epoch it was activated, so it never became effective
+      (0, 0)
+    } else if target_epoch == self.activation_epoch {
+      (0, delegated_stake)
+    } else if target_epoch < self.activation_epoch {
+      (0, 0)
+    } else if let Some(mut prev_cluster_stake) = history.get_entry(self.activation_epoch) {
+      // Walk from the activation epoch to the target epoch. Each epoch this account warms up its
+      // share of the stake the whole cluster was allowed to activate in the previous epoch.
+      let mut prev_epoch = self.activation_epoch;
+      let mut current_effective_stake = 0;
+      loop {
+        let current_epoch = prev_epoch + 1;
+        // Nothing was activating cluster-wide, so we must already be fully effective
+        if prev_cluster_stake.activating == 0 {
+          break;
+        }
+
+        let remaining_activating_stake = delegated_stake - current_effective_stake;
+        let weight = remaining_activating_stake as f64 / prev_cluster_stake.activating as f64;
+        let rate = self.warmup_cooldown_rate_at(current_epoch, new_rate_activation_epoch);
+        let newly_effective_cluster_stake = prev_cluster_stake.effective as f64 * rate;
+        let newly_effective_stake = ((weight * newly_effective_cluster_stake) as u64).max(1);
+
+        current_effective_stake += newly_effective_stake;
+        if current_effective_stake >= delegated_stake {
+          current_effective_stake = delegated_stake;
+          break;
+        }
+
+        if current_epoch >= target_epoch || current_epoch >= self.deactivation_epoch {
+          break;
+        }
+
+        match history.get_entry(current_epoch) {
+          Some(current_cluster_stake) => {
+            prev_epoch = current_epoch;
+            prev_cluster_stake = current_cluster_stake;
+          }
+          None => break,
+        }
+      }
 
-    if target_epoch < self.deactivation_epoch {
-      activating_stake = delegated_stake;
-    } else if target_epoch == self.deactivation_epoch {
-      deactivating_stake = delegated_stake;
-    } else if let Some(_history) = history {
-      deactivating_stake = delegated_stake;
+      (
+        current_effective_stake,
+        delegated_stake - current_effective_stake,
+      )
+    } else {
+      // No history, or the activation epoch has dropped out of it: fully effective
+      (delegated_stake, 0)
     }
-
-    (effective_stake, activating_stake, deactivating_stake)
   }
 }
 
@@ -499,6 +1245,24 @@ impl StakeFlags {
   pub fn empty() -> Self {
     StakeFlags { flags: 0 }
   }
+
+  pub fn contains(&self, flag: u8) -> bool {
+    self.flags & flag == flag
+  }
+
+  pub fn set(&mut self, flag: u8) {
+    self.flags |= flag;
+  }
+
+  pub fn remove(&mut self, flag: u8) {
+    self.flags &= !flag;
+  }
+
+  pub fn union(self, other: Self) -> Self {
+    StakeFlags {
+      flags: self.flags | other.flags,
+    }
+  }
 }
 
 impl Default for StakeFlags {
@@ -521,22 +1285,68 @@ pub struct Initialize<'info> {
   pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct InitializeChecked<'info> {
+  #[account(
+        init,
+        payer = payer,
+        space = 8 + StakeAccount::LEN
+    )]
+  pub stake_account: Account<'info, StakeAccount>,
+  #[account(mut)]
+  pub payer: Signer<'info>,
+  /// CHECK: Only the withdrawer has to prove it can sign
+  pub staker: UncheckedAccount<'info>,
+  pub withdrawer: Signer<'info>,
+  pub rent: Sysvar<'info, Rent>,
+  pub system_program: Program<'info, System>,
+}
+
 #[derive(Accounts)]
 pub struct Authorize<'info> {
   #[account(mut)]
   pub stake_account: Account<'info, StakeAccount>,
   pub authority: Signer<'info>,
   pub clock: Sysvar<'info, Clock>,
+  /// Lockup custodian, needed while the lockup is in force
+  pub custodian: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct AuthorizeChecked<'info> {
+  #[account(mut)]
+  pub stake_account: Account<'info, StakeAccount>,
+  pub authority: Signer<'info>,
+  pub new_authority: Signer<'info>,
+  pub clock: Sysvar<'info, Clock>,
+  /// Lockup custodian, needed while the lockup is in force
+  pub custodian: Option<Signer<'info>>,
+}
+
+#[derive(Accounts)]
+pub struct AuthorizeWithSeed<'info> {
+  #[account(mut)]
+  pub stake_account: Account<'info, StakeAccount>,
+  /// Base of the seed-derived address holding the authority
+  pub base: Signer<'info>,
+  pub clock: Sysvar<'info, Clock>,
+  /// Lockup custodian, needed while the lockup is in force
+  pub custodian: Option<Signer<'info>>,
 }
 
 #[derive(Accounts)]
 pub struct DelegateStake<'info> {
   #[account(mut)]
   pub stake_account: Account<'info, StakeAccount>,
-  /// CHECK: Vote account is validated in instruction
+  /// CHECK: Owner and vote state are validated by `get_vote_state`
   pub vote_account: AccountInfo<'info>,
   pub staker: Signer<'info>,
   pub clock: Sysvar<'info, Clock>,
+  /// CHECK: Stake history sysvar, read in place by `StakeHistoryData`
+  #[account(address = stake_history::ID)]
+  pub stake_history: UncheckedAccount<'info>,
+  #[account(seeds = [STAKE_CONFIG_SEED], bump = config.bump)]
+  pub config: Account<'info, StakeConfig>,
 }
 
 #[derive(Accounts)]
@@ -553,6 +1363,45 @@ pub struct Split<'info> {
   pub staker: Signer<'info>,
   pub rent: Sysvar<'info, Rent>,
   pub system_program: Program<'info, System>,
+  #[account(seeds = [STAKE_CONFIG_SEED], bump = config.bump)]
+  pub config: Account<'info, StakeConfig>,
+}
+
+#[derive(Accounts)]
+#[instruction(index: u64)]
+pub struct SplitToPda<'info> {
+  #[account(mut)]
+  pub source_account: Account<'info, StakeAccount>,
+  #[account(
+    init,
+    payer = staker,
+    space = 8 + StakeAccount::LEN,
+    seeds = [SPLIT_SEED, source_account.key().as_ref(), &index.to_le_bytes()],
+    bump
+  )]
+  pub split_account: Account<'info, StakeAccount>,
+  #[account(mut)]
+  pub staker: Signer<'info>,
+  pub rent: Sysvar<'info, Rent>,
+  pub system_program: Program<'info, System>,
+  #[account(seeds = [STAKE_CONFIG_SEED], bump = config.bump)]
+  pub config: Account<'info, StakeConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SplitWithSeed<'info> {
+  #[account(mut)]
+  pub source_account: Account<'info, StakeAccount>,
+  /// CHECK: Created by the instruction at the address derived from `base` and the seed
+  #[account(mut)]
+  pub split_account: UncheckedAccount<'info>,
+  pub base: Signer<'info>,
+  #[account(mut)]
+  pub staker: Signer<'info>,
+  pub rent: Sysvar<'info, Rent>,
+  pub system_program: Program<'info, System>,
+  #[account(seeds = [STAKE_CONFIG_SEED], bump = config.bump)]
+  pub config: Account<'info, StakeConfig>,
 }
 
 #[derive(Accounts)]
@@ -565,6 +1414,11 @@ pub struct Withdraw<'info> {
   #[account(mut)]
   pub to: AccountInfo<'info>,
   pub clock: Sysvar<'info, Clock>,
+  /// CHECK: Stake history sysvar, read in place by `StakeHistoryData`
+  #[account(address = stake_history::ID)]
+  pub stake_history: UncheckedAccount<'info>,
+  /// Lockup custodian, allowing a withdrawal while the lockup is in force
+  pub custodian: Option<Signer<'info>>,
 }
 
 #[derive(Accounts)]
@@ -573,6 +1427,20 @@ pub struct Deactivate<'info> {
   pub stake_account: Account<'info, StakeAccount>,
   pub staker: Signer<'info>,
   pub clock: Sysvar<'info, Clock>,
+  /// CHECK: Stake history sysvar, read in place by `StakeHistoryData`
+  #[account(address = stake_history::ID)]
+  pub stake_history: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DeactivateDelinquent<'info> {
+  #[account(mut)]
+  pub stake_account: Account<'info, StakeAccount>,
+  /// CHECK: Owner and vote state are validated by `get_vote_state`
+  pub vote_account: AccountInfo<'info>,
+  /// CHECK: Owner and vote state are validated by `get_vote_state`
+  pub reference_vote_account: AccountInfo<'info>,
+  pub clock: Sysvar<'info, Clock>,
 }
 
 #[derive(Accounts)]
@@ -582,19 +1450,161 @@ pub struct SetLockup<'info> {
   pub custodian: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct SetLockupChecked<'info> {
+  #[account(mut)]
+  pub stake_account: Account<'info, StakeAccount>,
+  pub custodian: Signer<'info>,
+  pub new_custodian: Option<Signer<'info>>,
+}
+
 #[derive(Accounts)]
 pub struct Merge<'info> {
   #[account(mut, close = staker)]
   pub source_account: Account<'info, StakeAccount>,
+  #[account(
+        mut,
+        constraint = dest_account.key() != source_account.key() @ StakeError::MergeMismatch
+    )]
+  pub dest_account: Account<'info, StakeAccount>,
+  #[account(mut)]
+  pub staker: Signer<'info>,
+  pub clock: Sysvar<'info, Clock>,
+  /// CHECK: Stake history sysvar, read in place by `StakeHistoryData`
+  #[account(address = stake_history::ID)]
+  pub stake_history: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MoveStake<'info> {
   #[account(mut)]
+  pub source_account: Account<'info, StakeAccount>,
+  #[account(
+        mut,
+        constraint = dest_account.key() != source_account.key() @ StakeError::MergeMismatch
+    )]
   pub dest_account: Account<'info, StakeAccount>,
+  pub staker: Signer<'info>,
+  pub clock: Sysvar<'info, Clock>,
+  /// CHECK: Stake history sysvar, read in place by `StakeHistoryData`
+  #[account(address = stake_history::ID)]
+  pub stake_history: UncheckedAccount<'info>,
+  #[account(seeds = [STAKE_CONFIG_SEED], bump = config.bump)]
+  pub config: Account<'info, StakeConfig>,
+}
+
+#[derive(Accounts)]
+pub struct MoveLamports<'info> {
   #[account(mut)]
+  pub source_account: Account<'info, StakeAccount>,
+  #[account(
+        mut,
+        constraint = dest_account.key() != source_account.key() @ StakeError::MergeMismatch
+    )]
+  pub dest_account: Account<'info, StakeAccount>,
   pub staker: Signer<'info>,
   pub clock: Sysvar<'info, Clock>,
+  /// CHECK: Stake history sysvar, read in place by `StakeHistoryData`
+  #[account(address = stake_history::ID)]
+  pub stake_history: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetMinimumDelegation<'info> {
+  #[account(seeds = [STAKE_CONFIG_SEED], bump = config.bump)]
+  pub config: Account<'info, StakeConfig>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+  #[account(
+        init,
+        payer = admin,
+        space = 8 + StakeConfig::LEN,
+        seeds = [STAKE_CONFIG_SEED],
+        bump
+    )]
+  pub config: Account<'info, StakeConfig>,
+  #[account(mut)]
+  pub admin: Signer<'info>,
+  /// Only the program's upgrade authority can create the config
+  #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(admin.key())
+          @ StakeError::NotUpgradeAuthority
+    )]
+  pub program_data: Account<'info, ProgramData>,
+  pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+  #[account(
+        mut,
+        seeds = [STAKE_CONFIG_SEED],
+        bump = config.bump,
+        has_one = admin @ StakeError::UnauthorizedConfigAdmin
+    )]
+  pub config: Account<'info, StakeConfig>,
+  pub admin: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct GetMinimumDelegation {}
+pub struct InitializeRewardReserve<'info> {
+  #[account(
+        init,
+        payer = authority,
+        space = 8 + RewardReserve::LEN,
+        seeds = [REWARD_RESERVE_SEED],
+        bump
+    )]
+  pub reward_reserve: Account<'info, RewardReserve>,
+  #[account(mut)]
+  pub authority: Signer<'info>,
+  /// Only the program's upgrade authority can create the reserve
+  #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(authority.key())
+          @ StakeError::NotUpgradeAuthority
+    )]
+  pub program_data: Account<'info, ProgramData>,
+  pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPointValue<'info> {
+  #[account(
+        mut,
+        seeds = [REWARD_RESERVE_SEED],
+        bump = reward_reserve.bump,
+        has_one = authority @ StakeError::UnauthorizedRewardAuthority
+    )]
+  pub reward_reserve: Account<'info, RewardReserve>,
+  pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemRewards<'info> {
+  #[account(mut)]
+  pub stake_account: Account<'info, StakeAccount>,
+  /// CHECK: Owner and vote state are validated by `get_vote_state`
+  #[account(mut)]
+  pub vote_account: AccountInfo<'info>,
+  #[account(
+        mut,
+        seeds = [REWARD_RESERVE_SEED],
+        bump = reward_reserve.bump
+    )]
+  pub reward_reserve: Account<'info, RewardReserve>,
+  pub clock: Sysvar<'info, Clock>,
+  /// CHECK: Stake history sysvar, read in place by `StakeHistoryData`
+  #[account(address = stake_history::ID)]
+  pub stake_history: UncheckedAccount<'info>,
+}
 
 #[account]
 pub struct StakeAccount {
@@ -604,7 +1614,7 @@ pub struct StakeAccount {
 }
 
 impl StakeAccount {
-  const LEN: usize = Meta::LEN + 1 + Stake::LEN + 1;
+  pub const LEN: usize = Meta::LEN + 1 + Stake::LEN + 1;
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq)]
@@ -640,6 +1650,51 @@ impl Delegation {
   const LEN: usize = 32 + 8 + 8 + 8 + 8;
 }
 
+pub const STAKE_CONFIG_SEED: &[u8] = b"stake_config";
+
+/// Program-wide stake parameters, managed by `admin`.
+#[account]
+pub struct StakeConfig {
+  pub admin: Pubkey,
+  pub minimum_delegation: u64,
+  /// Share of the cluster's effective stake that new delegations warm up or cool down by per
+  /// epoch. Updating it leaves existing delegations at the rate they were made with.
+  pub warmup_cooldown_rate: f64,
+  /// Percentage of stake lost when slashed; stored for parity with the native config, nothing
+  /// slashes yet
+  pub slash_penalty: u8,
+  pub bump: u8,
+}
+
+impl StakeConfig {
+  const LEN: usize = 32 + 8 + 8 + 1 + 1;
+
+  fn validate(&self) -> Result<()> {
+    require!(
+      self.minimum_delegation > 0
+        && self.warmup_cooldown_rate > 0.0
+        && self.warmup_cooldown_rate <= 1.0
+        && self.slash_penalty <= 100,
+      StakeError::InvalidConfig
+    );
+    Ok(())
+  }
+}
+
+pub const REWARD_RESERVE_SEED: &[u8] = b"reward_reserve";
+
+/// Program-owned pool of lamports that `redeem_rewards` pays stake rewards from.
+#[account]
+pub struct RewardReserve {
+  pub authority: Pubkey,
+  pub point_value: PointValue,
+  pub bump: u8,
+}
+
+impl RewardReserve {
+  const LEN: usize = 32 + PointValue::LEN + 1;
+}
+
 #[event]
 pub struct StakeInitializeEvent {
   pub stake_account: Pubkey,
@@ -702,6 +1757,47 @@ pub struct MergeEvent {
   pub timestamp: i64,
 }
 
+#[event]
+pub struct MoveStakeEvent {
+  pub source_account: Pubkey,
+  pub dest_account: Pubkey,
+  pub lamports: u64,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct MoveLamportsEvent {
+  pub source_account: Pubkey,
+  pub dest_account: Pubkey,
+  pub lamports: u64,
+  pub timestamp: i64,
+}
+
+#[event]
+pub struct ConfigEvent {
+  pub config: Pubkey,
+  pub admin: Pubkey,
+  pub minimum_delegation: u64,
+  pub warmup_cooldown_rate: f64,
+  pub slash_penalty: u8,
+}
+
+#[event]
+pub struct PointValueEvent {
+  pub reward_reserve: Pubkey,
+  pub point_value: PointValue,
+}
+
+#[event]
+pub struct RewardEvent {
+  pub stake_account: Pubkey,
+  pub vote_account: Pubkey,
+  pub staker_rewards: u64,
+  pub voter_rewards: u64,
+  pub credits_observed: u64,
+  pub epoch: u64,
+}
+
 #[error_code]
 pub enum StakeError {
   #[msg("Insufficient stake amount")]
@@ -722,4 +1818,428 @@ pub enum StakeError {
   MergeDeactivated,
   #[msg("Cannot merge transient stakes")]
   MergeTransientStake,
+  #[msg("Stake is still effective and cannot be redelegated yet")]
+  TooSoonToRedelegate,
+  #[msg("Vote account is not a valid vote program account")]
+  InvalidVoteAccount,
+  #[msg("Stake account is not delegated")]
+  StakeNotDelegated,
+  #[msg("Reward calculation overflowed")]
+  RewardCalculationOverflow,
+  #[msg("Reward reserve cannot cover the rewards owed")]
+  InsufficientRewardReserve,
+  #[msg("Unauthorized reward reserve authority")]
+  UnauthorizedRewardAuthority,
+  #[msg("Move amount must be greater than zero")]
+  InvalidMoveAmount,
+  #[msg("Authority seed is too long or derives an invalid address")]
+  InvalidAuthoritySeed,
+  #[msg("Lockup is in force and the custodian did not sign")]
+  CustodianMissingSignature,
+  #[msg("Redelegated stake must fully activate before it can be deactivated")]
+  MustFullyActivateBeforeDeactivation,
+  #[msg("Reference vote account has not voted in each of the recent epochs")]
+  InsufficientReferenceVotes,
+  #[msg("Stake is not delegated to the given vote account")]
+  VoteAddressMismatch,
+  #[msg("Vote account has not been delinquent for long enough")]
+  MinimumDelinquentEpochsForDeactivationNotMet,
+  #[msg("Stake is already deactivated")]
+  AlreadyDeactivated,
+  #[msg("Split account is not the address derived from the base and seed")]
+  InvalidSplitAddress,
+  #[msg("Stake must be fully inactive before the account can be closed")]
+  StakeNotFullyInactive,
+  #[msg("Account balance is below its rent-exempt reserve")]
+  BalanceBelowRentExemptReserve,
+  #[msg("Account balance is below its delegated stake")]
+  BalanceBelowDelegatedStake,
+  #[msg("Lamport balance overflowed")]
+  LamportsOverflow,
+  #[msg(
+    "Config needs a positive minimum delegation, a rate in (0, 1] and a penalty of at most 100"
+  )]
+  InvalidConfig,
+  #[msg("Unauthorized config admin")]
+  UnauthorizedConfigAdmin,
+  #[msg("Signer is not the program's upgrade authority")]
+  NotUpgradeAuthority,
+}
+
+#[cfg(test)]
+mod tests {
+  use anchor_lang::solana_program::sysvar::stake_history::{StakeHistory, StakeHistoryEntry};
+
+  use super::*;
+
+  fn delegation(stake: u64, activation_epoch: u64, deactivation_epoch: u64) -> Delegation {
+    Delegation {
+      voter_pubkey: Pubkey::default(),
+      stake,
+      activation_epoch,
+      deactivation_epoch,
+      warmup_cooldown_rate: NEW_WARMUP_COOLDOWN_RATE,
+    }
+  }
+
+  fn entry(effective: u64, activating: u64, deactivating: u64) -> StakeHistoryEntry {
+    StakeHistoryEntry {
+      effective,
+      activating,
+      deactivating,
+    }
+  }
+
+  fn status(effective: u64, activating: u64, deactivating: u64) -> StakeActivationStatus {
+    StakeActivationStatus {
+      effective,
+      activating,
+      deactivating,
+    }
+  }
+
+  /// Builds the history a cluster made of `bootstrap` stake plus `delegations` would record.
+  fn history_from_delegations(
+    bootstrap: u64,
+    delegations: &[Delegation],
+    epochs: std::ops::Range<u64>,
+    new_rate_activation_epoch: Option<u64>,
+  ) -> StakeHistory {
+    let mut history = StakeHistory::default();
+    for epoch in epochs {
+      let entry = delegations
+        .iter()
+        .fold(entry(bootstrap, 0, 0), |acc, delegation| {
+          let status = delegation.stake_activating_and_deactivating(
+            epoch,
+            &history,
+            new_rate_activation_epoch,
+          );
+          entry(
+            acc.effective + status.effective,
+            acc.activating + status.activating,
+            acc.deactivating + status.deactivating,
+          )
+        });
+      history.add(epoch, entry);
+    }
+    history
+  }
+
+  #[test]
+  fn warmup_cooldown_rate_switches_at_activation_epoch() {
+    let delegation = Delegation {
+      warmup_cooldown_rate: 0.5,
+      ..delegation(1_000, 5, u64::MAX)
+    };
+    assert_eq!(
+      delegation.warmup_cooldown_rate_at(10, None),
+      DEFAULT_WARMUP_COOLDOWN_RATE
+    );
+    assert_eq!(
+      delegation.warmup_cooldown_rate_at(9, Some(10)),
+      DEFAULT_WARMUP_COOLDOWN_RATE
+    );
+    assert_eq!(delegation.warmup_cooldown_rate_at(10, Some(10)), 0.5);
+    assert_eq!(delegation.warmup_cooldown_rate_at(11, Some(10)), 0.5);
+  }
+
+  #[test]
+  fn stake_is_activating_in_activation_epoch() {
+    let history = StakeHistory::default();
+    let delegation = delegation(1_000, 5, u64::MAX);
+
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(4, &history, Some(0)),
+      status(0, 0, 0)
+    );
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(5, &history, Some(0)),
+      status(0, 1_000, 0)
+    );
+  }
+
+  #[test]
+  fn stake_deactivated_in_activation_epoch_never_activates() {
+    let mut history = StakeHistory::default();
+    history.add(5, entry(10_000, 1_000, 0));
+    let delegation = delegation(1_000, 5, 5);
+
+    for epoch in 4 .. 8 {
+      assert_eq!(
+        delegation.stake_activating_and_deactivating(epoch, &history, Some(0)),
+        status(0, 0, 0)
+      );
+    }
+  }
+
+  #[test]
+  fn bootstrap_stake_is_fully_effective() {
+    let history = StakeHistory::default();
+    let delegation = delegation(1_000, u64::MAX, u64::MAX);
+
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(0, &history, Some(0)),
+      status(1_000, 0, 0)
+    );
+  }
+
+  #[test]
+  fn stake_without_history_is_fully_effective_then_fully_deactivated() {
+    let history = StakeHistory::default();
+    let delegation = delegation(1_000, 5, 10);
+
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(6, &history, Some(0)),
+      status(1_000, 0, 0)
+    );
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(10, &history, Some(0)),
+      status(1_000, 0, 1_000)
+    );
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(11, &history, Some(0)),
+      status(0, 0, 0)
+    );
+  }
+
+  #[test]
+  fn warmup_uses_share_of_cluster_activation() {
+    // 100k of effective cluster stake lets 9k activate in the next epoch at the new rate. We own
+    // half of the activating stake, so we get half of that.
+    let mut history = StakeHistory::default();
+    history.add(5, entry(100_000, 20_000, 0));
+    let delegation = delegation(10_000, 5, u64::MAX);
+
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(6, &history, Some(0)),
+      status(4_500, 5_500, 0)
+    );
+    // Before the switch the old 25% rate applies
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(6, &history, None),
+      status(10_000, 0, 0)
+    );
+  }
+
+  #[test]
+  fn warmup_follows_the_delegated_rate() {
+    // Same cluster as above, but delegated while the config allowed 5% per epoch
+    let mut history = StakeHistory::default();
+    history.add(5, entry(100_000, 20_000, 0));
+    let delegation = Delegation {
+      warmup_cooldown_rate: 0.05,
+      ..delegation(10_000, 5, u64::MAX)
+    };
+
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(6, &history, Some(0)),
+      status(2_500, 7_500, 0)
+    );
+
+    // Cooldown moves at the same rate: 5k of the cluster may deactivate, we own an eighth of it
+    history.add(6, entry(100_000, 0, 20_000));
+    let delegation = Delegation {
+      deactivation_epoch: 6,
+      ..delegation
+    };
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(7, &history, Some(0)),
+      status(1_875, 0, 1_875)
+    );
+  }
+
+  #[test]
+  fn warmup_stops_when_history_runs_out() {
+    let mut history = StakeHistory::default();
+    history.add(5, entry(100_000, 20_000, 0));
+    let delegation = delegation(10_000, 5, u64::MAX);
+
+    // Epoch 6 is missing from the history, so nothing more is known past the first step
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(9, &history, Some(0)),
+      status(4_500, 5_500, 0)
+    );
+  }
+
+  #[test]
+  fn cooldown_uses_share_of_cluster_deactivation() {
+    let mut history = StakeHistory::default();
+    history.add(5, entry(100_000, 0, 10_000));
+    history.add(6, entry(91_000, 0, 1_000));
+    let delegation = delegation(10_000, u64::MAX, 5);
+
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(5, &history, Some(0)),
+      status(10_000, 0, 10_000)
+    );
+    // 9% of 100k leaves the cluster in epoch 6 and we are all of the deactivating stake
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(6, &history, Some(0)),
+      status(1_000, 0, 1_000)
+    );
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(7, &history, Some(0)),
+      status(0, 0, 0)
+    );
+  }
+
+  #[test]
+  fn cooldown_only_deactivates_effective_stake() {
+    let mut history = StakeHistory::default();
+    history.add(5, entry(100_000, 20_000, 0));
+    let delegation = delegation(10_000, 5, 6);
+
+    assert_eq!(
+      delegation.stake_activating_and_deactivating(6, &history, Some(0)),
+      status(4_500, 0, 4_500)
+    );
+  }
+
+  #[test]
+  fn stake_warms_up_and_cools_down_across_epochs() {
+    let bootstrap = 1_000_000;
+    let delegations = [delegation(500_000, 1, 20), delegation(300_000, 3, 25)];
+    let history = history_from_delegations(bootstrap, &delegations, 0 .. 40, Some(0));
+
+    for delegation in &delegations {
+      let mut prev = delegation.stake_activating_and_deactivating(0, &history, Some(0));
+      let mut fully_active = false;
+      for epoch in 1 .. 40 {
+        let current = delegation.stake_activating_and_deactivating(epoch, &history, Some(0));
+        if (delegation.activation_epoch .. delegation.deactivation_epoch).contains(&epoch) {
+          assert_eq!(current.effective + current.activating, delegation.stake);
+        }
+        if epoch < delegation.deactivation_epoch {
+          assert!(current.effective >= prev.effective);
+        } else {
+          assert!(current.effective <= prev.effective);
+          assert_eq!(current.effective, current.deactivating);
+        }
+        fully_active |= current.effective == delegation.stake;
+        prev = current;
+      }
+      assert!(fully_active);
+      assert_eq!(prev, status(0, 0, 0));
+    }
+
+    // Cluster-wide, no epoch activates or deactivates more than the rate allows
+    for epoch in 1 .. 40 {
+      let (prev, current) = (
+        history.get_entry(epoch - 1).unwrap(),
+        history.get_entry(epoch).unwrap(),
+      );
+      let max_delta =
+        (prev.effective as f64 * NEW_WARMUP_COOLDOWN_RATE) as u64 + delegations.len() as u64;
+      assert!(current.effective.abs_diff(prev.effective) <= max_delta);
+    }
+  }
+
+  #[test]
+  fn seed_authority_matches_create_with_seed() {
+    let base = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+
+    assert_eq!(
+      seed_authority(&base, "vault", &owner).unwrap(),
+      Pubkey::create_with_seed(&base, "vault", &owner).unwrap()
+    );
+    assert_ne!(
+      seed_authority(&base, "vault", &owner).unwrap(),
+      seed_authority(&base, "vault", &crate::ID).unwrap()
+    );
+    assert!(seed_authority(&base, &"x".repeat(33), &owner).is_err());
+  }
+
+  fn lockup() -> Lockup {
+    Lockup {
+      unix_timestamp: 1_000,
+      epoch: 10,
+      custodian: Pubkey::new_unique(),
+    }
+  }
+
+  fn clock(unix_timestamp: i64, epoch: u64) -> Clock {
+    Clock {
+      unix_timestamp,
+      epoch,
+      ..Clock::default()
+    }
+  }
+
+  fn assert_stake_error(result: Result<()>, expected: StakeError) {
+    match result {
+      Err(Error::AnchorError(error)) => assert_eq!(error.error_code_number, u32::from(expected)),
+      other => panic!("expected {:?}, got {:?}", expected, other),
+    }
+  }
+
+  #[test]
+  fn custodian_signature_allows_early_withdrawal() {
+    let lockup = lockup();
+
+    lockup
+      .check_expired_or_custodian(&clock(0, 0), Some(&lockup.custodian))
+      .unwrap();
+    // Only one of epoch and timestamp has passed
+    lockup
+      .check_expired_or_custodian(&clock(1_000, 5), Some(&lockup.custodian))
+      .unwrap();
+  }
+
+  #[test]
+  fn early_withdrawal_without_custodian_is_rejected() {
+    let lockup = lockup();
+
+    assert_stake_error(
+      lockup.check_expired_or_custodian(&clock(0, 0), None),
+      StakeError::CustodianMissingSignature,
+    );
+    assert_stake_error(
+      lockup.check_expired_or_custodian(&clock(0, 0), Some(&Pubkey::new_unique())),
+      StakeError::LockupInForce,
+    );
+  }
+
+  #[test]
+  fn expired_lockup_needs_no_custodian() {
+    let lockup = lockup();
+
+    lockup
+      .check_expired_or_custodian(&clock(1_000, 10), None)
+      .unwrap();
+    lockup
+      .check_expired_or_custodian(&clock(1_000, 10), Some(&Pubkey::new_unique()))
+      .unwrap();
+  }
+
+  fn config(minimum_delegation: u64, warmup_cooldown_rate: f64, slash_penalty: u8) -> StakeConfig {
+    StakeConfig {
+      admin: Pubkey::new_unique(),
+      minimum_delegation,
+      warmup_cooldown_rate,
+      slash_penalty,
+      bump: 255,
+    }
+  }
+
+  #[test]
+  fn config_validation() {
+    config(1, NEW_WARMUP_COOLDOWN_RATE, DEFAULT_SLASH_PENALTY)
+      .validate()
+      .unwrap();
+    config(DEFAULT_MINIMUM_DELEGATION, 1.0, 100)
+      .validate()
+      .unwrap();
+
+    for invalid in [
+      config(0, NEW_WARMUP_COOLDOWN_RATE, DEFAULT_SLASH_PENALTY),
+      config(1, 0.0, DEFAULT_SLASH_PENALTY),
+      config(1, 1.5, DEFAULT_SLASH_PENALTY),
+      config(1, f64::NAN, DEFAULT_SLASH_PENALTY),
+      config(1, NEW_WARMUP_COOLDOWN_RATE, 101),
+    ] {
+      assert_stake_error(invalid.validate(), StakeError::InvalidConfig);
+    }
+  }
 }
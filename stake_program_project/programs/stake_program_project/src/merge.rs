@@ -0,0 +1,373 @@
+use anchor_lang::{prelude::*, solana_program::sysvar::stake_history::StakeHistoryGetEntry};
+
+use crate::{
+  Delegation, Meta, Stake, StakeAccount, StakeError, StakeFlags, NEW_RATE_ACTIVATION_EPOCH,
+};
+
+/// Activation state of a stake account, as far as merging or moving stake is concerned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MergeKind {
+  /// Not delegated, or delegated and fully cooled down
+  Inactive,
+  /// Delegated in the current epoch, so nothing is effective yet
+  ActivationEpoch,
+  /// Delegated and fully effective
+  FullyActive,
+}
+
+impl MergeKind {
+  /// Classifies `stake_account`, failing with `MergeTransientStake` if its stake is partially
+  /// activated or deactivating.
+  pub fn get_if_mergeable<T: StakeHistoryGetEntry>(
+    stake_account: &StakeAccount,
+    clock: &Clock,
+    stake_history: &T,
+  ) -> Result<Self> {
+    let Some(stake) = &stake_account.stake else {
+      return Ok(Self::Inactive);
+    };
+
+    let status = stake.delegation.stake_activating_and_deactivating(
+      clock.epoch,
+      stake_history,
+      NEW_RATE_ACTIVATION_EPOCH,
+    );
+
+    match (status.effective, status.activating, status.deactivating) {
+      (0, 0, 0) => Ok(Self::Inactive),
+      (0, _, _) => Ok(Self::ActivationEpoch),
+      (_, 0, 0) => Ok(Self::FullyActive),
+      _ => err!(StakeError::MergeTransientStake),
+    }
+  }
+}
+
+/// Whether two accounts share the authorities and lockup needed to merge or move stake. Lockups
+/// only have to match while either of them is still in force.
+pub fn metas_can_merge(dest: &Meta, source: &Meta, clock: &Clock) -> bool {
+  dest.authorized == source.authorized
+    && (dest.lockup == source.lockup
+      || (!dest.lockup.is_in_force(clock, None) && !source.lockup.is_in_force(clock, None)))
+}
+
+fn delegations_can_merge(dest: &Delegation, source: &Delegation) -> Result<()> {
+  require_keys_eq!(
+    dest.voter_pubkey,
+    source.voter_pubkey,
+    StakeError::MergeMismatch
+  );
+  require!(
+    dest.deactivation_epoch == u64::MAX && source.deactivation_epoch == u64::MAX,
+    StakeError::MergeDeactivated
+  );
+  Ok(())
+}
+
+fn absorb_stake(
+  dest: &Stake,
+  absorbed_lamports: u64,
+  absorbed_credits_observed: u64,
+) -> Result<Stake> {
+  let mut stake = dest.clone();
+  stake.credits_observed =
+    stake_weighted_credits_observed(dest, absorbed_lamports, absorbed_credits_observed)
+      .ok_or(StakeError::InsufficientFunds)?;
+  stake.delegation.stake = stake
+    .delegation
+    .stake
+    .checked_add(absorbed_lamports)
+    .ok_or(StakeError::InsufficientFunds)?;
+  Ok(stake)
+}
+
+/// Stake and flags `dest` ends up with after absorbing `source`, which holds `source_lamports`.
+///
+/// Follows the native merge matrix:
+/// - inactive into inactive, or activating into inactive: `None`, only lamports move
+/// - inactive into activating: all of the source lamports are added to the activating stake
+/// - activating into activating: the source stake and its rent-exempt reserve are added
+/// - fully active into fully active: the source stake is added and `credits_observed` averaged
+///
+/// Every other combination fails with `MergeMismatch`.
+pub fn merge_stake<T: StakeHistoryGetEntry>(
+  dest: &StakeAccount,
+  source: &StakeAccount,
+  source_lamports: u64,
+  clock: &Clock,
+  stake_history: &T,
+) -> Result<Option<(Stake, StakeFlags)>> {
+  let dest_kind = MergeKind::get_if_mergeable(dest, clock, stake_history)?;
+  let source_kind = MergeKind::get_if_mergeable(source, clock, stake_history)?;
+  let stake_flags = dest.stake_flags.union(source.stake_flags);
+
+  match (dest_kind, &dest.stake, source_kind, &source.stake) {
+    (MergeKind::Inactive, _, MergeKind::Inactive | MergeKind::ActivationEpoch, _) => Ok(None),
+    (MergeKind::ActivationEpoch, Some(dest_stake), MergeKind::Inactive, _) => {
+      let mut stake = dest_stake.clone();
+      stake.delegation.stake = stake
+        .delegation
+        .stake
+        .checked_add(source_lamports)
+        .ok_or(StakeError::InsufficientFunds)?;
+      Ok(Some((stake, stake_flags)))
+    }
+    (
+      MergeKind::ActivationEpoch,
+      Some(dest_stake),
+      MergeKind::ActivationEpoch,
+      Some(source_stake),
+    ) => {
+      delegations_can_merge(&dest_stake.delegation, &source_stake.delegation)?;
+      let absorbed_lamports = source
+        .meta
+        .rent_exempt_reserve
+        .checked_add(source_stake.delegation.stake)
+        .ok_or(StakeError::InsufficientFunds)?;
+      let stake = absorb_stake(dest_stake, absorbed_lamports, source_stake.credits_observed)?;
+      Ok(Some((stake, stake_flags)))
+    }
+    (MergeKind::FullyActive, Some(dest_stake), MergeKind::FullyActive, Some(source_stake)) => {
+      delegations_can_merge(&dest_stake.delegation, &source_stake.delegation)?;
+      let stake = absorb_stake(
+        dest_stake,
+        source_stake.delegation.stake,
+        source_stake.credits_observed,
+      )?;
+      Ok(Some((stake, stake_flags)))
+    }
+    _ => err!(StakeError::MergeMismatch),
+  }
+}
+
+/// Averages `credits_observed` of `stake` and `absorbed_lamports` of stake observed at
+/// `absorbed_credits_observed`, weighted by stake.
+///
+/// The result is rounded up so merging can never earn fractional extra credits.
+pub fn stake_weighted_credits_observed(
+  stake: &Stake,
+  absorbed_lamports: u64,
+  absorbed_credits_observed: u64,
+) -> Option<u64> {
+  if stake.credits_observed == absorbed_credits_observed {
+    return Some(stake.credits_observed);
+  }
+
+  let total_stake = u128::from(stake.delegation.stake.checked_add(absorbed_lamports)?);
+  let stake_weighted_credits =
+    u128::from(stake.credits_observed).checked_mul(u128::from(stake.delegation.stake))?;
+  let absorbed_weighted_credits =
+    u128::from(absorbed_credits_observed).checked_mul(u128::from(absorbed_lamports))?;
+  let total_weighted_credits = stake_weighted_credits
+    .checked_add(absorbed_weighted_credits)?
+    .checked_add(total_stake)?
+    .checked_sub(1)?;
+
+  u64::try_from(total_weighted_credits.checked_div(total_stake)?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use anchor_lang::solana_program::sysvar::stake_history::StakeHistory;
+
+  use super::*;
+  use crate::{Authorized, Lockup, NEW_WARMUP_COOLDOWN_RATE};
+
+  const EPOCH: u64 = 5;
+  const RENT_EXEMPT_RESERVE: u64 = 2_282_880;
+
+  fn stake(amount: u64, credits_observed: u64) -> Stake {
+    Stake {
+      delegation: Delegation {
+        voter_pubkey: Pubkey::default(),
+        stake: amount,
+        activation_epoch: u64::MAX,
+        deactivation_epoch: u64::MAX,
+        warmup_cooldown_rate: NEW_WARMUP_COOLDOWN_RATE,
+      },
+      credits_observed,
+    }
+  }
+
+  #[test]
+  fn credits_observed_is_stake_weighted_and_rounded_up() {
+    assert_eq!(
+      stake_weighted_credits_observed(&stake(100, 10), 100, 10),
+      Some(10)
+    );
+    assert_eq!(
+      stake_weighted_credits_observed(&stake(100, 10), 300, 20),
+      Some(18)
+    );
+    // 11.5 rounds up
+    assert_eq!(
+      stake_weighted_credits_observed(&stake(100, 10), 100, 13),
+      Some(12)
+    );
+    assert_eq!(
+      stake_weighted_credits_observed(&stake(u64::MAX, 10), 1, 20),
+      None
+    );
+  }
+
+  fn meta() -> Meta {
+    Meta {
+      rent_exempt_reserve: RENT_EXEMPT_RESERVE,
+      authorized: Authorized {
+        staker: Pubkey::new_from_array([1; 32]),
+        withdrawer: Pubkey::new_from_array([2; 32]),
+      },
+      lockup: Lockup::default(),
+    }
+  }
+
+  fn account(stake: Option<Stake>) -> StakeAccount {
+    StakeAccount {
+      meta: meta(),
+      stake,
+      stake_flags: StakeFlags::empty(),
+    }
+  }
+
+  /// Stake of `amount` delegated in `activation_epoch`, which is fully active before `EPOCH` since
+  /// there is no stake history to rate limit it.
+  fn delegated(amount: u64, activation_epoch: u64, credits_observed: u64) -> Option<Stake> {
+    let mut stake = stake(amount, credits_observed);
+    stake.delegation.activation_epoch = activation_epoch;
+    Some(stake)
+  }
+
+  fn clock() -> Clock {
+    Clock {
+      epoch: EPOCH,
+      ..Clock::default()
+    }
+  }
+
+  fn merge(
+    dest: &StakeAccount,
+    source: &StakeAccount,
+    source_lamports: u64,
+  ) -> Result<Option<(Stake, StakeFlags)>> {
+    merge_stake(
+      dest,
+      source,
+      source_lamports,
+      &clock(),
+      &StakeHistory::default(),
+    )
+  }
+
+  fn assert_stake_error<T: std::fmt::Debug>(result: Result<T>, expected: StakeError) {
+    match result {
+      Err(Error::AnchorError(error)) => assert_eq!(error.error_code_number, u32::from(expected)),
+      other => panic!("expected {:?}, got {:?}", expected, other),
+    }
+  }
+
+  #[test]
+  fn inactive_into_inactive_only_moves_lamports() {
+    assert_eq!(
+      merge(&account(None), &account(None), 5_000_000_000).unwrap(),
+      None
+    );
+
+    let mut deactivated = delegated(1_000_000_000, 0, 0);
+    deactivated.as_mut().unwrap().delegation.deactivation_epoch = 1;
+    assert_eq!(
+      merge(&account(None), &account(deactivated), 5_000_000_000).unwrap(),
+      None
+    );
+  }
+
+  #[test]
+  fn inactive_into_activating_delegates_all_source_lamports() {
+    let (stake, _) = merge(
+      &account(delegated(2_000_000_000, EPOCH, 0)),
+      &account(None),
+      3_000_000_000,
+    )
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(stake.delegation.stake, 5_000_000_000);
+  }
+
+  #[test]
+  fn activating_into_activating_adds_stake_and_reserve() {
+    let mut source = account(delegated(1_000_000_000, EPOCH, 0));
+    source.stake_flags = StakeFlags {
+      flags: StakeFlags::MUST_FULLY_ACTIVATE_BEFORE_DEACTIVATION_IS_PERMITTED,
+    };
+
+    let (stake, stake_flags) = merge(
+      &account(delegated(2_000_000_000, EPOCH, 0)),
+      &source,
+      1_500_000_000 + RENT_EXEMPT_RESERVE,
+    )
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(stake.delegation.stake, 3_000_000_000 + RENT_EXEMPT_RESERVE);
+    assert_eq!(stake_flags, source.stake_flags);
+  }
+
+  #[test]
+  fn fully_active_merge_weights_credits_observed() {
+    let (stake, _) = merge(
+      &account(delegated(100, 0, 10)),
+      &account(delegated(300, 1, 20)),
+      1_000,
+    )
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(stake.delegation.stake, 400);
+    assert_eq!(stake.credits_observed, 18);
+  }
+
+  #[test]
+  fn mismatched_states_are_rejected() {
+    // Active into inactive, and activating into active
+    assert_stake_error(
+      merge(&account(None), &account(delegated(100, 0, 0)), 100),
+      StakeError::MergeMismatch,
+    );
+    assert_stake_error(
+      merge(
+        &account(delegated(100, 0, 0)),
+        &account(delegated(100, EPOCH, 0)),
+        100,
+      ),
+      StakeError::MergeMismatch,
+    );
+
+    let mut other_voter = delegated(100, 0, 0);
+    other_voter.as_mut().unwrap().delegation.voter_pubkey = Pubkey::new_unique();
+    assert_stake_error(
+      merge(&account(delegated(100, 0, 0)), &account(other_voter), 100),
+      StakeError::MergeMismatch,
+    );
+
+    let mut deactivating = delegated(100, 0, 0);
+    deactivating.as_mut().unwrap().delegation.deactivation_epoch = EPOCH;
+    assert_stake_error(
+      merge(&account(delegated(100, 0, 0)), &account(deactivating), 100),
+      StakeError::MergeTransientStake,
+    );
+  }
+
+  #[test]
+  fn expired_lockups_do_not_have_to_match() {
+    let mut dest = meta();
+    dest.lockup.epoch = EPOCH;
+    let mut source = meta();
+    source.lockup.custodian = Pubkey::new_unique();
+    assert!(metas_can_merge(&dest, &source, &clock()));
+
+    dest.lockup.epoch = EPOCH + 1;
+    assert!(!metas_can_merge(&dest, &source, &clock()));
+
+    source.authorized.staker = Pubkey::new_unique();
+    assert!(!metas_can_merge(&meta(), &source, &clock()));
+  }
+}
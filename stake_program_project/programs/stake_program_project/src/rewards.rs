@@ -0,0 +1,308 @@
+use anchor_lang::{prelude::*, solana_program::sysvar::stake_history::StakeHistoryGetEntry};
+
+use crate::{Stake, StakeError};
+
+/// Exchange rate between points (effective stake times vote credits) and reward lamports.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct PointValue {
+  pub rewards: u64,
+  pub points: u128,
+}
+
+impl PointValue {
+  pub const LEN: usize = 8 + 16;
+}
+
+/// Points earned since `credits_observed`, and the credits observed once they are redeemed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CalculatedStakePoints {
+  pub points: u128,
+  pub new_credits_observed: u64,
+}
+
+/// Rewards owed to a stake account, split between the staker and the validator.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CalculatedStakeRewards {
+  pub staker_rewards: u64,
+  pub voter_rewards: u64,
+  pub new_credits_observed: u64,
+}
+
+/// Sums, for every epoch the vote account earned credits in, the stake that was effective in that
+/// epoch times the credits earned since `credits_observed`.
+pub fn calculate_stake_points_and_credits<T: StakeHistoryGetEntry>(
+  stake: &Stake,
+  epoch_credits: &[(u64, u64, u64)],
+  stake_history: &T,
+  new_rate_activation_epoch: Option<u64>,
+) -> Result<CalculatedStakePoints> {
+  let credits_in_stake = stake.credits_observed;
+  let credits_in_vote = epoch_credits.last().map_or(0, |(_, credits, _)| *credits);
+
+  // Nothing new since we last looked, or the vote account was recreated with fewer credits than
+  // we saw; either way resync without rewards
+  if credits_in_vote <= credits_in_stake {
+    return Ok(CalculatedStakePoints {
+      points: 0,
+      new_credits_observed: credits_in_vote,
+    });
+  }
+
+  let mut points = 0u128;
+  let mut new_credits_observed = credits_in_stake;
+  for &(epoch, final_epoch_credits, initial_epoch_credits) in epoch_credits {
+    let stake_amount = stake
+      .delegation
+      .stake(epoch, stake_history, new_rate_activation_epoch);
+    let earned_credits = if credits_in_stake < initial_epoch_credits {
+      // The whole epoch happened after we last observed credits
+      final_epoch_credits.checked_sub(initial_epoch_credits)
+    } else if credits_in_stake < final_epoch_credits {
+      // Only part of the epoch is new to us
+      final_epoch_credits.checked_sub(new_credits_observed)
+    } else {
+      Some(0)
+    }
+    .ok_or(StakeError::InvalidVoteAccount)?;
+
+    new_credits_observed = new_credits_observed.max(final_epoch_credits);
+    points = u128::from(stake_amount)
+      .checked_mul(u128::from(earned_credits))
+      .and_then(|earned_points| points.checked_add(earned_points))
+      .ok_or(StakeError::RewardCalculationOverflow)?;
+  }
+
+  Ok(CalculatedStakePoints {
+    points,
+    new_credits_observed,
+  })
+}
+
+/// Splits `rewards` into `(voter, staker)` portions for a validator charging `commission` percent.
+pub fn commission_split(commission: u8, rewards: u64) -> (u64, u64) {
+  match commission.min(100) {
+    0 => (0, rewards),
+    100 => (rewards, 0),
+    split => {
+      let rewards = u128::from(rewards);
+      let voter = rewards * u128::from(split) / 100;
+      let staker = rewards * u128::from(100 - split) / 100;
+      (voter as u64, staker as u64)
+    }
+  }
+}
+
+/// Converts the points earned by `stake` in epochs before `current_epoch` into lamports at
+/// `point_value`, net of commission.
+pub fn calculate_stake_rewards<T: StakeHistoryGetEntry>(
+  stake: &Stake,
+  epoch_credits: &[(u64, u64, u64)],
+  current_epoch: u64,
+  commission: u8,
+  point_value: &PointValue,
+  stake_history: &T,
+  new_rate_activation_epoch: Option<u64>,
+) -> Result<CalculatedStakeRewards> {
+  let completed_epochs = epoch_credits.partition_point(|(epoch, ..)| *epoch < current_epoch);
+  let credits =
+    |epoch_credits: &[(u64, u64, u64)]| epoch_credits.last().map_or(0, |(_, credits, _)| *credits);
+  let epoch_credits = if credits(epoch_credits) < stake.credits_observed {
+    // The vote account was reset; resync to it without rewards
+    epoch_credits
+  } else if credits(&epoch_credits[.. completed_epochs]) <= stake.credits_observed {
+    // Nothing has been earned in a completed epoch since we last looked
+    return Ok(CalculatedStakeRewards {
+      staker_rewards: 0,
+      voter_rewards: 0,
+      new_credits_observed: stake.credits_observed,
+    });
+  } else {
+    // As with native rewards only completed epochs pay out, leaving the epoch in progress for a
+    // later redemption
+    &epoch_credits[.. completed_epochs]
+  };
+
+  let CalculatedStakePoints {
+    points,
+    new_credits_observed,
+  } = calculate_stake_points_and_credits(
+    stake,
+    epoch_credits,
+    stake_history,
+    new_rate_activation_epoch,
+  )?;
+
+  // A zero point value means no rewards are being paid out
+  let rewards = points
+    .checked_mul(u128::from(point_value.rewards))
+    .ok_or(StakeError::RewardCalculationOverflow)?
+    .checked_div(point_value.points)
+    .unwrap_or(0);
+  let rewards = u64::try_from(rewards).map_err(|_| StakeError::RewardCalculationOverflow)?;
+  let (voter_rewards, staker_rewards) = commission_split(commission, rewards);
+
+  Ok(CalculatedStakeRewards {
+    staker_rewards,
+    voter_rewards,
+    new_credits_observed,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use anchor_lang::solana_program::sysvar::stake_history::StakeHistory;
+
+  use super::*;
+  use crate::{Delegation, NEW_WARMUP_COOLDOWN_RATE};
+
+  fn stake(amount: u64, credits_observed: u64) -> Stake {
+    Stake {
+      delegation: Delegation {
+        voter_pubkey: Pubkey::default(),
+        stake: amount,
+        activation_epoch: u64::MAX,
+        deactivation_epoch: u64::MAX,
+        warmup_cooldown_rate: NEW_WARMUP_COOLDOWN_RATE,
+      },
+      credits_observed,
+    }
+  }
+
+  #[test]
+  fn points_only_count_credits_since_last_observed() {
+    let history = StakeHistory::default();
+    let epoch_credits = [(1, 100, 0), (2, 250, 100), (3, 300, 250)];
+
+    assert_eq!(
+      calculate_stake_points_and_credits(&stake(10, 0), &epoch_credits, &history, Some(0)).unwrap(),
+      CalculatedStakePoints {
+        points: 3_000,
+        new_credits_observed: 300,
+      }
+    );
+    // Halfway through epoch 2
+    assert_eq!(
+      calculate_stake_points_and_credits(&stake(10, 175), &epoch_credits, &history, Some(0))
+        .unwrap(),
+      CalculatedStakePoints {
+        points: 1_250,
+        new_credits_observed: 300,
+      }
+    );
+  }
+
+  #[test]
+  fn points_use_stake_effective_in_each_epoch() {
+    let history = StakeHistory::default();
+    let mut stake = stake(10, 0);
+    stake.delegation.activation_epoch = 2;
+    let epoch_credits = [(1, 100, 0), (2, 250, 100), (3, 300, 250)];
+
+    // Nothing is effective in epoch 1 and stake is still activating in epoch 2
+    assert_eq!(
+      calculate_stake_points_and_credits(&stake, &epoch_credits, &history, Some(0))
+        .unwrap()
+        .points,
+      500
+    );
+  }
+
+  #[test]
+  fn reset_vote_account_resyncs_credits_without_points() {
+    let history = StakeHistory::default();
+
+    assert_eq!(
+      calculate_stake_points_and_credits(&stake(10, 500), &[(1, 100, 0)], &history, Some(0))
+        .unwrap(),
+      CalculatedStakePoints {
+        points: 0,
+        new_credits_observed: 100,
+      }
+    );
+  }
+
+  #[test]
+  fn rewards_are_split_by_commission() {
+    let history = StakeHistory::default();
+    let point_value = PointValue {
+      rewards: 1,
+      points: 10,
+    };
+
+    assert_eq!(
+      calculate_stake_rewards(
+        &stake(1_000, 0),
+        &[(1, 100, 0)],
+        2,
+        10,
+        &point_value,
+        &history,
+        Some(0),
+      )
+      .unwrap(),
+      CalculatedStakeRewards {
+        staker_rewards: 9_000,
+        voter_rewards: 1_000,
+        new_credits_observed: 100,
+      }
+    );
+    assert_eq!(commission_split(0, 7), (0, 7));
+    assert_eq!(commission_split(100, 7), (7, 0));
+    assert_eq!(commission_split(150, 7), (7, 0));
+  }
+
+  #[test]
+  fn rewards_skip_the_epoch_in_progress() {
+    let history = StakeHistory::default();
+    let point_value = PointValue {
+      rewards: 1,
+      points: 1,
+    };
+    let epoch_credits = [(1, 100, 0), (2, 250, 100), (3, 300, 250)];
+    let rewards = |stake: &Stake, current_epoch| {
+      calculate_stake_rewards(
+        stake,
+        &epoch_credits,
+        current_epoch,
+        0,
+        &point_value,
+        &history,
+        Some(0),
+      )
+      .unwrap()
+    };
+
+    let mut stake = stake(10, 0);
+    assert_eq!(
+      rewards(&stake, 3),
+      CalculatedStakeRewards {
+        staker_rewards: 2_500,
+        voter_rewards: 0,
+        new_credits_observed: 250,
+      }
+    );
+
+    // Delegated partway through epoch 3, which pays out once it is over
+    stake.credits_observed = 270;
+    assert_eq!(
+      rewards(&stake, 3),
+      CalculatedStakeRewards {
+        staker_rewards: 0,
+        voter_rewards: 0,
+        new_credits_observed: 270,
+      }
+    );
+    assert_eq!(
+      rewards(&stake, 4),
+      CalculatedStakeRewards {
+        staker_rewards: 300,
+        voter_rewards: 0,
+        new_credits_observed: 300,
+      }
+    );
+
+    // A reset vote account still resyncs with an epoch in progress
+    stake.credits_observed = 500;
+    assert_eq!(rewards(&stake, 3).new_credits_observed, 300);
+  }
+}
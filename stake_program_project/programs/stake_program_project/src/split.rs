@@ -0,0 +1,236 @@
+use anchor_lang::prelude::*;
+
+use crate::{StakeAccount, StakeError};
+
+pub const SPLIT_SEED: &[u8] = b"split";
+
+/// Takes `lamports` out of `source`, which holds `source_lamports`, and returns the state of the
+/// account they are split into.
+///
+/// Delegated stake is split exactly: the new account is delegated `lamports` and the source keeps
+/// the rest. Both sides must keep at least `minimum_delegation`, unless the source hands over its
+/// whole delegation and is left undelegated.
+pub fn split_stake(
+  source: &mut StakeAccount,
+  source_lamports: u64,
+  lamports: u64,
+  minimum_delegation: u64,
+) -> Result<StakeAccount> {
+  require!(lamports > 0, StakeError::InsufficientFunds);
+  let remaining_lamports = source_lamports
+    .checked_sub(lamports)
+    .ok_or(StakeError::InsufficientFunds)?;
+  require!(
+    remaining_lamports >= source.meta.rent_exempt_reserve,
+    StakeError::InsufficientFunds
+  );
+
+  let split_stake = match &source.stake {
+    Some(source_stake) => {
+      let remaining_stake = source_stake
+        .delegation
+        .stake
+        .checked_sub(lamports)
+        .ok_or(StakeError::InsufficientStake)?;
+      require!(
+        lamports >= minimum_delegation,
+        StakeError::InsufficientStake
+      );
+      require!(
+        remaining_stake == 0 || remaining_stake >= minimum_delegation,
+        StakeError::InsufficientStake
+      );
+
+      let mut split_stake = source_stake.clone();
+      split_stake.delegation.stake = lamports;
+      source.stake = if remaining_stake == 0 {
+        None
+      } else {
+        let mut remaining = source_stake.clone();
+        remaining.delegation.stake = remaining_stake;
+        Some(remaining)
+      };
+      Some(split_stake)
+    }
+    None => None,
+  };
+
+  Ok(StakeAccount {
+    meta: source.meta.clone(),
+    stake: split_stake,
+    stake_flags: source.stake_flags,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use proptest::prelude::*;
+
+  use super::*;
+  use crate::{
+    Authorized, Delegation, Lockup, Meta, Stake, StakeFlags, DEFAULT_MINIMUM_DELEGATION,
+    NEW_WARMUP_COOLDOWN_RATE,
+  };
+
+  const MINIMUM_DELEGATION: u64 = DEFAULT_MINIMUM_DELEGATION;
+  const RENT_EXEMPT_RESERVE: u64 = 2_282_880;
+
+  fn account(delegated: Option<u64>) -> StakeAccount {
+    StakeAccount {
+      meta: Meta {
+        rent_exempt_reserve: RENT_EXEMPT_RESERVE,
+        authorized: Authorized {
+          staker: Pubkey::new_unique(),
+          withdrawer: Pubkey::new_unique(),
+        },
+        lockup: Lockup::default(),
+      },
+      stake: delegated.map(|stake| Stake {
+        delegation: Delegation {
+          voter_pubkey: Pubkey::new_unique(),
+          stake,
+          activation_epoch: 1,
+          deactivation_epoch: u64::MAX,
+          warmup_cooldown_rate: NEW_WARMUP_COOLDOWN_RATE,
+        },
+        credits_observed: 42,
+      }),
+      stake_flags: StakeFlags::empty(),
+    }
+  }
+
+  fn assert_stake_error(result: Result<StakeAccount>, expected: StakeError) {
+    match result {
+      Err(Error::AnchorError(error)) => assert_eq!(error.error_code_number, u32::from(expected)),
+      other => panic!("expected {:?}, got {:?}", expected, other.map(|_| ())),
+    }
+  }
+
+  #[test]
+  fn delegated_stake_is_split_exactly() {
+    let mut source = account(Some(5 * MINIMUM_DELEGATION));
+    let source_lamports = 5 * MINIMUM_DELEGATION + RENT_EXEMPT_RESERVE + 123;
+
+    let split = split_stake(
+      &mut source,
+      source_lamports,
+      2 * MINIMUM_DELEGATION,
+      MINIMUM_DELEGATION,
+    )
+    .unwrap();
+
+    let source_stake = source.stake.unwrap();
+    let split_stake = split.stake.unwrap();
+    assert_eq!(source_stake.delegation.stake, 3 * MINIMUM_DELEGATION);
+    assert_eq!(split_stake.delegation.stake, 2 * MINIMUM_DELEGATION);
+    assert_eq!(
+      split_stake.delegation.voter_pubkey,
+      source_stake.delegation.voter_pubkey
+    );
+    assert_eq!(split_stake.credits_observed, 42);
+    assert_eq!(split.meta, source.meta);
+  }
+
+  #[test]
+  fn splitting_all_stake_leaves_source_undelegated() {
+    let mut source = account(Some(2 * MINIMUM_DELEGATION));
+
+    let split = split_stake(
+      &mut source,
+      2 * MINIMUM_DELEGATION + RENT_EXEMPT_RESERVE,
+      2 * MINIMUM_DELEGATION,
+      MINIMUM_DELEGATION,
+    )
+    .unwrap();
+
+    assert_eq!(source.stake, None);
+    assert_eq!(
+      split.stake.unwrap().delegation.stake,
+      2 * MINIMUM_DELEGATION
+    );
+  }
+
+  #[test]
+  fn both_halves_keep_minimum_delegation() {
+    // Extra undelegated lamports, so only the stake amounts are in the way
+    let source_lamports = 3 * MINIMUM_DELEGATION + RENT_EXEMPT_RESERVE;
+
+    assert_stake_error(
+      split_stake(
+        &mut account(Some(2 * MINIMUM_DELEGATION)),
+        source_lamports,
+        MINIMUM_DELEGATION - 1,
+        MINIMUM_DELEGATION,
+      ),
+      StakeError::InsufficientStake,
+    );
+    assert_stake_error(
+      split_stake(
+        &mut account(Some(2 * MINIMUM_DELEGATION)),
+        source_lamports,
+        MINIMUM_DELEGATION + 1,
+        MINIMUM_DELEGATION,
+      ),
+      StakeError::InsufficientStake,
+    );
+    assert_stake_error(
+      split_stake(
+        &mut account(Some(2 * MINIMUM_DELEGATION)),
+        source_lamports,
+        2 * MINIMUM_DELEGATION + 1,
+        MINIMUM_DELEGATION,
+      ),
+      StakeError::InsufficientStake,
+    );
+  }
+
+  #[test]
+  fn undelegated_split_keeps_source_reserve() {
+    let mut source = account(None);
+
+    let split = split_stake(
+      &mut source,
+      RENT_EXEMPT_RESERVE + 100,
+      100,
+      MINIMUM_DELEGATION,
+    )
+    .unwrap();
+    assert_eq!(split.stake, None);
+
+    assert_stake_error(
+      split_stake(
+        &mut source,
+        RENT_EXEMPT_RESERVE + 100,
+        101,
+        MINIMUM_DELEGATION,
+      ),
+      StakeError::InsufficientFunds,
+    );
+  }
+
+  proptest! {
+    #[test]
+    fn random_splits_conserve_stake(
+      delegated in proptest::option::of(0 .. 4 * MINIMUM_DELEGATION),
+      extra_lamports in 0 .. MINIMUM_DELEGATION,
+      lamports in 0 .. 5 * MINIMUM_DELEGATION,
+    ) {
+      let mut source = account(delegated);
+      let source_lamports =
+        delegated.unwrap_or_default() + RENT_EXEMPT_RESERVE + extra_lamports;
+
+      let Ok(split) = split_stake(&mut source, source_lamports, lamports, MINIMUM_DELEGATION) else {
+        return Ok(());
+      };
+
+      let staked = |account: &StakeAccount| {
+        account.stake.as_ref().map_or(0, |stake| stake.delegation.stake)
+      };
+      prop_assert_eq!(staked(&source) + staked(&split), delegated.unwrap_or_default());
+      for stake in [&source.stake, &split.stake].into_iter().flatten() {
+        prop_assert!(stake.delegation.stake >= MINIMUM_DELEGATION);
+      }
+      prop_assert!(source_lamports - lamports >= RENT_EXEMPT_RESERVE);
+    }
+  }
+}
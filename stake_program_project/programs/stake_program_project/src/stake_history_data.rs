@@ -0,0 +1,96 @@
+use anchor_lang::solana_program::sysvar::stake_history::{StakeHistoryEntry, StakeHistoryGetEntry};
+
+const ENTRY_OFFSET: usize = 8;
+const ENTRY_SIZE: usize = 32;
+
+/// Stake history read straight from the sysvar account data.
+///
+/// The sysvar holds up to 512 entries (16KB), which is too much to deserialize on chain, so
+/// entries are looked up in place. The data is a bincode `Vec<(Epoch, StakeHistoryEntry)>`
+/// sorted from the newest epoch to the oldest.
+pub struct StakeHistoryData<'a>(pub &'a [u8]);
+
+impl StakeHistoryData<'_> {
+  fn read_u64(&self, offset: usize) -> Option<u64> {
+    let bytes = self.0.get(offset .. offset + 8)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+  }
+}
+
+impl StakeHistoryGetEntry for StakeHistoryData<'_> {
+  fn get_entry(&self, epoch: u64) -> Option<StakeHistoryEntry> {
+    let len = self.read_u64(0)? as usize;
+    let (mut low, mut high) = (0, len);
+
+    while low < high {
+      let mid = low + (high - low) / 2;
+      let offset = ENTRY_OFFSET + mid * ENTRY_SIZE;
+      let entry_epoch = self.read_u64(offset)?;
+
+      match entry_epoch.cmp(&epoch) {
+        std::cmp::Ordering::Equal => {
+          return Some(StakeHistoryEntry {
+            effective: self.read_u64(offset + 8)?,
+            activating: self.read_u64(offset + 16)?,
+            deactivating: self.read_u64(offset + 24)?,
+          });
+        }
+        // Newer entries come first, so older epochs are further along
+        std::cmp::Ordering::Greater => low = mid + 1,
+        std::cmp::Ordering::Less => high = mid,
+      }
+    }
+
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn serialize(entries: &[(u64, StakeHistoryEntry)]) -> Vec<u8> {
+    let mut data = (entries.len() as u64).to_le_bytes().to_vec();
+    for (epoch, entry) in entries {
+      data.extend_from_slice(&epoch.to_le_bytes());
+      data.extend_from_slice(&entry.effective.to_le_bytes());
+      data.extend_from_slice(&entry.activating.to_le_bytes());
+      data.extend_from_slice(&entry.deactivating.to_le_bytes());
+    }
+    data
+  }
+
+  #[test]
+  fn reads_entries_from_account_data() {
+    let entries: Vec<_> = (0 .. 10u64)
+      .rev()
+      .filter(|epoch| *epoch != 4)
+      .map(|epoch| {
+        (
+          epoch,
+          StakeHistoryEntry {
+            effective: epoch * 100,
+            activating: epoch * 10,
+            deactivating: epoch,
+          },
+        )
+      })
+      .collect();
+    let data = serialize(&entries);
+    let history = StakeHistoryData(&data);
+
+    for (epoch, entry) in &entries {
+      assert_eq!(history.get_entry(*epoch).as_ref(), Some(entry));
+    }
+    assert_eq!(history.get_entry(4), None);
+    assert_eq!(history.get_entry(10), None);
+  }
+
+  #[test]
+  fn truncated_data_has_no_entries() {
+    let data = serialize(&[(3, StakeHistoryEntry::with_effective(1))]);
+
+    assert_eq!(StakeHistoryData(&data[.. 20]).get_entry(3), None);
+    assert_eq!(StakeHistoryData(&[]).get_entry(3), None);
+  }
+}
@@ -0,0 +1,201 @@
+use anchor_lang::{
+  prelude::*,
+  solana_program::vote::{program as vote_program, state::VoteState},
+};
+
+use crate::StakeError;
+
+/// Checks that `vote_account` is owned by the vote program and deserializes its state.
+///
+/// The state is boxed because `VoteState` is too large for the SBF stack.
+pub fn get_vote_state(vote_account: &AccountInfo) -> Result<Box<VoteState>> {
+  require_keys_eq!(
+    *vote_account.owner,
+    vote_program::ID,
+    StakeError::InvalidVoteAccount
+  );
+
+  let mut vote_state = Box::<VoteState>::default();
+  VoteState::deserialize_into(&vote_account.try_borrow_data()?, &mut vote_state)
+    .map_err(|_| error!(StakeError::InvalidVoteAccount))?;
+
+  Ok(vote_state)
+}
+
+/// Number of epochs a vote account has to miss before stake delegated to it can be deactivated by
+/// anyone through `deactivate_delinquent`.
+pub const MINIMUM_DELINQUENT_EPOCHS_FOR_DEACTIVATION: usize = 5;
+
+/// Whether a reference vote account voted in each of the last
+/// `MINIMUM_DELINQUENT_EPOCHS_FOR_DEACTIVATION` epochs, up to and including `current_epoch`.
+pub fn acceptable_reference_epoch_credits(
+  epoch_credits: &[(u64, u64, u64)],
+  current_epoch: u64,
+) -> bool {
+  let Some(epoch_index) = epoch_credits
+    .len()
+    .checked_sub(MINIMUM_DELINQUENT_EPOCHS_FOR_DEACTIVATION)
+  else {
+    return false;
+  };
+
+  let mut epoch = current_epoch;
+  for (vote_epoch, ..) in epoch_credits[epoch_index ..].iter().rev() {
+    if *vote_epoch != epoch {
+      return false;
+    }
+    epoch = epoch.saturating_sub(1);
+  }
+  true
+}
+
+/// Whether a vote account has not voted in the last `MINIMUM_DELINQUENT_EPOCHS_FOR_DEACTIVATION`
+/// epochs.
+pub fn eligible_for_deactivate_delinquent(
+  epoch_credits: &[(u64, u64, u64)],
+  current_epoch: u64,
+) -> bool {
+  match epoch_credits.last() {
+    None => true,
+    Some((epoch, ..)) => current_epoch
+      .checked_sub(MINIMUM_DELINQUENT_EPOCHS_FOR_DEACTIVATION as u64)
+      .is_some_and(|minimum_epoch| *epoch <= minimum_epoch),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use anchor_lang::solana_program::vote::state::VoteStateVersions;
+
+  use super::*;
+
+  fn vote_account_data(epoch_credits: &[(u64, u64)]) -> Vec<u8> {
+    let mut vote_state = VoteState::default();
+    for (epoch, credits) in epoch_credits {
+      vote_state.increment_credits(*epoch, *credits);
+    }
+
+    let mut data = vec![0; VoteState::size_of()];
+    VoteState::serialize(&VoteStateVersions::new_current(vote_state), &mut data).unwrap();
+    data
+  }
+
+  fn assert_invalid_vote_account(result: Result<Box<VoteState>>) {
+    match result {
+      Err(Error::AnchorError(error)) => {
+        assert_eq!(error.error_name, "InvalidVoteAccount")
+      }
+      other => panic!("expected InvalidVoteAccount, got {:?}", other.map(|_| ())),
+    }
+  }
+
+  #[test]
+  fn reads_credits_from_vote_account() {
+    let key = Pubkey::new_unique();
+    let mut lamports = 1;
+    let mut data = vote_account_data(&[(1, 100), (2, 50), (3, 25)]);
+    let account = AccountInfo::new(
+      &key,
+      false,
+      false,
+      &mut lamports,
+      &mut data,
+      &vote_program::ID,
+      false,
+      0,
+    );
+
+    let vote_state = get_vote_state(&account).unwrap();
+
+    assert_eq!(vote_state.credits(), 175);
+    assert_eq!(
+      vote_state.epoch_credits(),
+      &vec![(1, 100, 0), (2, 150, 100), (3, 175, 150)]
+    );
+  }
+
+  #[test]
+  fn rejects_account_not_owned_by_vote_program() {
+    let key = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let mut lamports = 1;
+    let mut data = vote_account_data(&[(1, 100)]);
+    let account = AccountInfo::new(
+      &key,
+      false,
+      false,
+      &mut lamports,
+      &mut data,
+      &owner,
+      false,
+      0,
+    );
+
+    assert_invalid_vote_account(get_vote_state(&account));
+  }
+
+  #[test]
+  fn rejects_malformed_vote_state() {
+    let key = Pubkey::new_unique();
+    let mut lamports = 1;
+    let mut data = vec![0xff; 16];
+    let account = AccountInfo::new(
+      &key,
+      false,
+      false,
+      &mut lamports,
+      &mut data,
+      &vote_program::ID,
+      false,
+      0,
+    );
+
+    assert_invalid_vote_account(get_vote_state(&account));
+  }
+
+  fn epoch_credits(epochs: impl IntoIterator<Item = u64>) -> Vec<(u64, u64, u64)> {
+    epochs
+      .into_iter()
+      .map(|epoch| (epoch, epoch * 10 + 10, epoch * 10))
+      .collect()
+  }
+
+  #[test]
+  fn reference_must_have_voted_in_each_recent_epoch() {
+    assert!(acceptable_reference_epoch_credits(
+      &epoch_credits(0 .. 10),
+      9
+    ));
+    assert!(acceptable_reference_epoch_credits(
+      &epoch_credits(5 .. 10),
+      9
+    ));
+    // Too few epochs, a gap, or not voting in the current epoch
+    assert!(!acceptable_reference_epoch_credits(
+      &epoch_credits(6 .. 10),
+      9
+    ));
+    assert!(!acceptable_reference_epoch_credits(
+      &epoch_credits([3, 4, 5, 7, 8, 9]),
+      9
+    ));
+    assert!(!acceptable_reference_epoch_credits(
+      &epoch_credits(0 .. 9),
+      9
+    ));
+  }
+
+  #[test]
+  fn delinquent_after_minimum_epochs_without_votes() {
+    assert!(eligible_for_deactivate_delinquent(&[], 0));
+    assert!(eligible_for_deactivate_delinquent(
+      &epoch_credits(0 .. 5),
+      9
+    ));
+    assert!(!eligible_for_deactivate_delinquent(
+      &epoch_credits(0 .. 6),
+      9
+    ));
+    assert!(!eligible_for_deactivate_delinquent(&epoch_credits([0]), 4));
+  }
+}
@@ -0,0 +1,970 @@
+//! End-to-end tests running the program in `solana-program-test`.
+//!
+//! The suite loads the compiled `stake_program_project.so` from `SBF_OUT_DIR`, which defaults to
+//! the workspace's `target/deploy`, so run `anchor build` first. It needs neither a validator nor
+//! the network. Where the SBF toolchain isn't available, `PROGRAM_TEST_BUILTIN=1 cargo test` runs
+//! the crate as a native builtin instead, which skips SBF-only checks such as compute and stack
+//! limits.
+
+// Vote state and system instruction helpers are only reachable through deprecated re-exports
+#![allow(deprecated)]
+
+use anchor_lang::{
+  prelude::{AccountInfo, Clock, Pubkey},
+  solana_program::{
+    instruction::{Instruction, InstructionError},
+    system_instruction, system_program,
+    sysvar::{
+      self,
+      stake_history::{StakeHistory, StakeHistoryEntry},
+    },
+    vote::{
+      program as vote_program,
+      state::{VoteState, VoteStateVersions},
+    },
+  },
+  AccountDeserialize, InstructionData, ToAccountMetas,
+};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+  account::Account,
+  bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+  entrypoint::ProgramResult,
+  native_token::LAMPORTS_PER_SOL,
+  signature::{Keypair, Signer},
+  transaction::{Transaction, TransactionError},
+};
+use stake_program_project::{
+  accounts, instruction, program_data_address, rewards::PointValue, Authorized, Lockup, LockupArgs,
+  RewardReserve, StakeAccount, StakeActivationStatus, StakeAuthorize, StakeConfig, StakeConfigArgs,
+  StakeError, UpdateConfigArgs, DEFAULT_MINIMUM_DELEGATION, DEFAULT_SLASH_PENALTY,
+  NEW_RATE_ACTIVATION_EPOCH, NEW_WARMUP_COOLDOWN_RATE, REWARD_RESERVE_SEED, STAKE_CONFIG_SEED,
+};
+
+const STAKE: u64 = 10 * LAMPORTS_PER_SOL;
+const ACTIVATION_EPOCH: u64 = 2;
+const DEACTIVATION_EPOCH: u64 = 5;
+
+// Only used as the `PROGRAM_TEST_BUILTIN` fallback. `entry` ties the account slice to the
+// lifetime of the accounts in it, which the builtin signature doesn't; a leaked copy of the slice
+// satisfies both
+fn process_instruction(
+  program_id: &Pubkey,
+  accounts: &[AccountInfo],
+  data: &[u8],
+) -> ProgramResult {
+  let accounts = Box::leak(accounts.to_vec().into_boxed_slice());
+  stake_program_project::entry(program_id, accounts, data)
+}
+
+/// Cluster stake history in which a `STAKE` delegation made in `ACTIVATION_EPOCH` takes two
+/// epochs to warm up, and takes two epochs to cool down once deactivated in `DEACTIVATION_EPOCH`.
+fn cluster_stake_history() -> StakeHistory {
+  let mut history = StakeHistory::default();
+  for (epoch, effective, activating, deactivating) in [
+    (ACTIVATION_EPOCH, 100 * LAMPORTS_PER_SOL, STAKE, 0),
+    (
+      ACTIVATION_EPOCH + 1,
+      109 * LAMPORTS_PER_SOL,
+      LAMPORTS_PER_SOL,
+      0,
+    ),
+    (ACTIVATION_EPOCH + 2, 110 * LAMPORTS_PER_SOL, 0, 0),
+    (DEACTIVATION_EPOCH, 110 * LAMPORTS_PER_SOL, 0, STAKE),
+    (
+      DEACTIVATION_EPOCH + 1,
+      101 * LAMPORTS_PER_SOL,
+      0,
+      LAMPORTS_PER_SOL,
+    ),
+  ] {
+    history.add(
+      epoch,
+      StakeHistoryEntry {
+        effective,
+        activating,
+        deactivating,
+      },
+    );
+  }
+  history
+}
+
+fn new_vote_account() -> Account {
+  let mut data = vec![0; VoteState::size_of()];
+  VoteState::serialize(
+    &VoteStateVersions::new_current(VoteState::default()),
+    &mut data,
+  )
+  .unwrap();
+
+  Account {
+    lamports: LAMPORTS_PER_SOL,
+    data,
+    owner: vote_program::ID,
+    executable: false,
+    rent_epoch: 0,
+  }
+}
+
+/// Upgradeable loader program data naming `upgrade_authority`. The suite doesn't deploy through
+/// the upgradeable loader, so the account the admin instructions check is added by hand.
+fn program_data_account(upgrade_authority: Pubkey) -> Account {
+  Account::new_data(
+    LAMPORTS_PER_SOL,
+    &UpgradeableLoaderState::ProgramData {
+      slot: 0,
+      upgrade_authority_address: Some(upgrade_authority),
+    },
+    &bpf_loader_upgradeable::ID,
+  )
+  .unwrap()
+}
+
+fn reward_reserve_address() -> Pubkey {
+  Pubkey::find_program_address(&[REWARD_RESERVE_SEED], &stake_program_project::ID).0
+}
+
+fn config_address() -> Pubkey {
+  Pubkey::find_program_address(&[STAKE_CONFIG_SEED], &stake_program_project::ID).0
+}
+
+fn assert_stake_error(result: Result<(), BanksClientError>, expected: StakeError) {
+  match result.map_err(|error| error.unwrap()) {
+    Err(TransactionError::InstructionError(_, InstructionError::Custom(code))) => {
+      assert_eq!(code, u32::from(expected), "expected {expected:?}")
+    }
+    other => panic!("expected {expected:?}, got {other:?}"),
+  }
+}
+
+struct TestEnv {
+  context: ProgramTestContext,
+  vote_account: Pubkey,
+  stake_history: StakeHistory,
+  /// Deployer of the program, who creates the program-wide accounts
+  upgrade_authority: Keypair,
+}
+
+impl TestEnv {
+  async fn start() -> Self {
+    let mut env = Self::deploy().await;
+    let admin = env.upgrade_authority.insecure_clone();
+    env.initialize_config(&admin).await.unwrap();
+    env.warp_to_epoch(ACTIVATION_EPOCH).await;
+
+    env
+  }
+
+  /// Starts a cluster with the program deployed but no config created yet
+  async fn deploy() -> Self {
+    let builtin = std::env::var_os("PROGRAM_TEST_BUILTIN").is_some();
+    let mut program_test = ProgramTest::default();
+    program_test.prefer_bpf(!builtin);
+    program_test.add_program(
+      "stake_program_project",
+      stake_program_project::ID,
+      if builtin {
+        processor!(process_instruction)
+      } else {
+        None
+      },
+    );
+    let vote_account = Pubkey::new_unique();
+    program_test.add_account(vote_account, new_vote_account());
+    let upgrade_authority = Keypair::new();
+    program_test.add_account(
+      program_data_address(),
+      program_data_account(upgrade_authority.pubkey()),
+    );
+    program_test.add_account(
+      upgrade_authority.pubkey(),
+      Account::new(LAMPORTS_PER_SOL, 0, &system_program::ID),
+    );
+
+    Self {
+      context: program_test.start_with_context().await,
+      vote_account,
+      stake_history: cluster_stake_history(),
+      upgrade_authority,
+    }
+  }
+
+  async fn initialize_config(&mut self, admin: &Keypair) -> Result<(), BanksClientError> {
+    self
+      .process(
+        accounts::InitializeConfig {
+          config: config_address(),
+          admin: admin.pubkey(),
+          program_data: program_data_address(),
+          system_program: system_program::ID,
+        },
+        instruction::InitializeConfig {
+          args: StakeConfigArgs {
+            minimum_delegation: DEFAULT_MINIMUM_DELEGATION,
+            warmup_cooldown_rate: NEW_WARMUP_COOLDOWN_RATE,
+            slash_penalty: DEFAULT_SLASH_PENALTY,
+          },
+        },
+        &[admin],
+      )
+      .await
+  }
+
+  fn payer(&self) -> Keypair {
+    self.context.payer.insecure_clone()
+  }
+
+  async fn send(
+    &mut self,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+  ) -> Result<(), BanksClientError> {
+    let payer = self.payer();
+    let mut all_signers = vec![&payer];
+    all_signers.extend_from_slice(signers);
+
+    let transaction = Transaction::new_signed_with_payer(
+      instructions,
+      Some(&payer.pubkey()),
+      &all_signers,
+      self.context.last_blockhash,
+    );
+    self
+      .context
+      .banks_client
+      .process_transaction(transaction)
+      .await
+  }
+
+  async fn process(
+    &mut self,
+    accounts: impl ToAccountMetas,
+    data: impl InstructionData,
+    signers: &[&Keypair],
+  ) -> Result<(), BanksClientError> {
+    let instruction = Instruction {
+      program_id: stake_program_project::ID,
+      accounts: accounts.to_account_metas(None),
+      data: data.data(),
+    };
+    self.send(&[instruction], signers).await
+  }
+
+  /// Warps to the first slot of `epoch`, then restores the test's stake history over the one the
+  /// bank computed from the (empty) native stake program.
+  async fn warp_to_epoch(&mut self, epoch: u64) {
+    self.context.warp_to_epoch(epoch).unwrap();
+    self.context.set_sysvar(&self.stake_history);
+  }
+
+  async fn fund(&mut self, address: Pubkey, lamports: u64) {
+    let payer = self.payer();
+    self
+      .send(
+        &[system_instruction::transfer(
+          &payer.pubkey(),
+          &address,
+          lamports,
+        )],
+        &[],
+      )
+      .await
+      .unwrap();
+  }
+
+  async fn lamports(&mut self, address: Pubkey) -> u64 {
+    self
+      .context
+      .banks_client
+      .get_balance(address)
+      .await
+      .unwrap()
+  }
+
+  async fn stake_account(&mut self, address: Pubkey) -> StakeAccount {
+    let account = self
+      .context
+      .banks_client
+      .get_account(address)
+      .await
+      .unwrap()
+      .expect("stake account exists");
+    StakeAccount::try_deserialize(&mut account.data.as_slice()).unwrap()
+  }
+
+  async fn activation_status(&mut self, address: Pubkey) -> StakeActivationStatus {
+    let epoch = self.epoch().await;
+    let stake = self.stake_account(address).await.stake.unwrap();
+    stake.delegation.stake_activating_and_deactivating(
+      epoch,
+      &self.stake_history,
+      NEW_RATE_ACTIVATION_EPOCH,
+    )
+  }
+
+  async fn epoch(&mut self) -> u64 {
+    self
+      .context
+      .banks_client
+      .get_sysvar::<Clock>()
+      .await
+      .unwrap()
+      .epoch
+  }
+
+  /// Initializes a stake account holding `lamports` on top of its rent-exempt reserve.
+  async fn create_stake_account(
+    &mut self,
+    authorized: Authorized,
+    lockup: Lockup,
+    lamports: u64,
+  ) -> Pubkey {
+    let stake_account = Keypair::new();
+    let payer = self.payer();
+    let initialize = Instruction {
+      program_id: stake_program_project::ID,
+      accounts: accounts::Initialize {
+        stake_account: stake_account.pubkey(),
+        payer: payer.pubkey(),
+        rent: sysvar::rent::ID,
+        system_program: system_program::ID,
+      }
+      .to_account_metas(None),
+      data: instruction::Initialize { authorized, lockup }.data(),
+    };
+    let fund = system_instruction::transfer(&payer.pubkey(), &stake_account.pubkey(), lamports);
+
+    self
+      .send(&[initialize, fund], &[&stake_account])
+      .await
+      .unwrap();
+    stake_account.pubkey()
+  }
+
+  async fn delegate(
+    &mut self,
+    stake_account: Pubkey,
+    staker: &Keypair,
+  ) -> Result<(), BanksClientError> {
+    let vote_account = self.vote_account;
+    self
+      .process(
+        accounts::DelegateStake {
+          stake_account,
+          vote_account,
+          staker: staker.pubkey(),
+          clock: sysvar::clock::ID,
+          stake_history: sysvar::stake_history::ID,
+          config: config_address(),
+        },
+        instruction::DelegateStake {},
+        &[staker],
+      )
+      .await
+  }
+
+  async fn deactivate(
+    &mut self,
+    stake_account: Pubkey,
+    staker: &Keypair,
+  ) -> Result<(), BanksClientError> {
+    self
+      .process(
+        accounts::Deactivate {
+          stake_account,
+          staker: staker.pubkey(),
+          clock: sysvar::clock::ID,
+          stake_history: sysvar::stake_history::ID,
+        },
+        instruction::Deactivate {},
+        &[staker],
+      )
+      .await
+  }
+
+  async fn withdraw(
+    &mut self,
+    stake_account: Pubkey,
+    withdrawer: &Keypair,
+    to: Pubkey,
+    lamports: u64,
+    custodian: Option<&Keypair>,
+  ) -> Result<(), BanksClientError> {
+    let mut signers = vec![withdrawer];
+    signers.extend(custodian);
+    self
+      .process(
+        accounts::Withdraw {
+          stake_account,
+          withdrawer: withdrawer.pubkey(),
+          to,
+          clock: sysvar::clock::ID,
+          stake_history: sysvar::stake_history::ID,
+          custodian: custodian.map(Keypair::pubkey),
+        },
+        instruction::Withdraw { lamports },
+        &signers,
+      )
+      .await
+  }
+
+  async fn merge(
+    &mut self,
+    dest_account: Pubkey,
+    source_account: Pubkey,
+    staker: &Keypair,
+  ) -> Result<(), BanksClientError> {
+    self
+      .process(
+        accounts::Merge {
+          source_account,
+          dest_account,
+          staker: staker.pubkey(),
+          clock: sysvar::clock::ID,
+          stake_history: sysvar::stake_history::ID,
+        },
+        instruction::Merge {},
+        &[staker],
+      )
+      .await
+  }
+}
+
+fn authorized(staker: &Keypair, withdrawer: &Keypair) -> Authorized {
+  Authorized {
+    staker: staker.pubkey(),
+    withdrawer: withdrawer.pubkey(),
+  }
+}
+
+#[tokio::test]
+async fn initialize_and_authorize() {
+  let mut env = TestEnv::start().await;
+  let (staker, withdrawer, new_staker) = (Keypair::new(), Keypair::new(), Keypair::new());
+
+  let stake_account = env
+    .create_stake_account(authorized(&staker, &withdrawer), Lockup::default(), STAKE)
+    .await;
+  let state = env.stake_account(stake_account).await;
+  assert_eq!(state.meta.authorized, authorized(&staker, &withdrawer));
+  assert_eq!(state.stake, None);
+  assert_eq!(
+    env.lamports(stake_account).await,
+    state.meta.rent_exempt_reserve + STAKE
+  );
+
+  // The checked variant takes the withdrawer's signature instead of trusting the argument
+  let checked_account = Keypair::new();
+  let payer = env.payer();
+  env
+    .process(
+      accounts::InitializeChecked {
+        stake_account: checked_account.pubkey(),
+        payer: payer.pubkey(),
+        staker: staker.pubkey(),
+        withdrawer: withdrawer.pubkey(),
+        rent: sysvar::rent::ID,
+        system_program: system_program::ID,
+      },
+      instruction::InitializeChecked {},
+      &[&checked_account, &withdrawer],
+    )
+    .await
+    .unwrap();
+  assert_eq!(
+    env
+      .stake_account(checked_account.pubkey())
+      .await
+      .meta
+      .authorized,
+    authorized(&staker, &withdrawer)
+  );
+
+  let authorize = |authority: &Keypair, new_authority: &Keypair, stake_authorize| {
+    (
+      accounts::Authorize {
+        stake_account,
+        authority: authority.pubkey(),
+        clock: sysvar::clock::ID,
+        custodian: None,
+      },
+      instruction::Authorize {
+        new_authority: new_authority.pubkey(),
+        stake_authorize,
+      },
+    )
+  };
+
+  let (accounts, data) = authorize(&withdrawer, &new_staker, StakeAuthorize::Staker);
+  assert_stake_error(
+    env.process(accounts, data, &[&withdrawer]).await,
+    StakeError::UnauthorizedStaker,
+  );
+
+  let (accounts, data) = authorize(&staker, &new_staker, StakeAuthorize::Staker);
+  env.process(accounts, data, &[&staker]).await.unwrap();
+  assert_eq!(
+    env.stake_account(stake_account).await.meta.authorized,
+    authorized(&new_staker, &withdrawer)
+  );
+
+  // The old staker has lost its say over the account
+  assert_stake_error(
+    env.delegate(stake_account, &staker).await,
+    StakeError::UnauthorizedStaker,
+  );
+  env.delegate(stake_account, &new_staker).await.unwrap();
+
+  let new_withdrawer = Keypair::new();
+  let (accounts, data) = authorize(&withdrawer, &new_withdrawer, StakeAuthorize::Withdrawer);
+  env.process(accounts, data, &[&withdrawer]).await.unwrap();
+  assert_eq!(
+    env.stake_account(stake_account).await.meta.authorized,
+    authorized(&new_staker, &new_withdrawer)
+  );
+}
+
+#[tokio::test]
+async fn delegation_warms_up_and_cools_down_across_epochs() {
+  let mut env = TestEnv::start().await;
+  let (staker, withdrawer) = (Keypair::new(), Keypair::new());
+  let stake_account = env
+    .create_stake_account(authorized(&staker, &withdrawer), Lockup::default(), STAKE)
+    .await;
+  let rent_exempt_reserve = env
+    .stake_account(stake_account)
+    .await
+    .meta
+    .rent_exempt_reserve;
+
+  env.delegate(stake_account, &staker).await.unwrap();
+  let delegation = env
+    .stake_account(stake_account)
+    .await
+    .stake
+    .unwrap()
+    .delegation;
+  assert_eq!(delegation.stake, STAKE);
+  assert_eq!(delegation.voter_pubkey, env.vote_account);
+  assert_eq!(delegation.activation_epoch, ACTIVATION_EPOCH);
+  assert_eq!(
+    env.activation_status(stake_account).await,
+    StakeActivationStatus {
+      effective: 0,
+      activating: STAKE,
+      deactivating: 0,
+    }
+  );
+
+  // Most of the stake warms up in the first epoch, the rest in the second
+  env.warp_to_epoch(ACTIVATION_EPOCH + 1).await;
+  let status = env.activation_status(stake_account).await;
+  assert!(status.effective > 0 && status.activating > 0);
+  assert_eq!(status.effective + status.activating, STAKE);
+
+  env.warp_to_epoch(ACTIVATION_EPOCH + 2).await;
+  assert_eq!(
+    env.activation_status(stake_account).await,
+    StakeActivationStatus {
+      effective: STAKE,
+      activating: 0,
+      deactivating: 0,
+    }
+  );
+
+  // Delegated stake can't be withdrawn while it is active
+  let recipient = Pubkey::new_unique();
+  assert_stake_error(
+    env
+      .withdraw(stake_account, &withdrawer, recipient, 1, None)
+      .await,
+    StakeError::InsufficientFunds,
+  );
+
+  env.warp_to_epoch(DEACTIVATION_EPOCH).await;
+  env.deactivate(stake_account, &staker).await.unwrap();
+  assert_eq!(
+    env.activation_status(stake_account).await,
+    StakeActivationStatus {
+      effective: STAKE,
+      activating: 0,
+      deactivating: STAKE,
+    }
+  );
+  // Stake has to cool down before it can be delegated again, even to the same validator
+  assert_stake_error(
+    env.delegate(stake_account, &staker).await,
+    StakeError::TooSoonToRedelegate,
+  );
+
+  // Only the part that has cooled down can be withdrawn
+  env.warp_to_epoch(DEACTIVATION_EPOCH + 1).await;
+  // Deactivating again would restart the cooldown
+  assert_stake_error(
+    env.deactivate(stake_account, &staker).await,
+    StakeError::AlreadyDeactivated,
+  );
+  let status = env.activation_status(stake_account).await;
+  assert!(status.effective > 0 && status.effective < STAKE);
+  let stake_lamports = env.lamports(stake_account).await;
+  assert_stake_error(
+    env
+      .withdraw(stake_account, &withdrawer, recipient, stake_lamports, None)
+      .await,
+    StakeError::StakeNotFullyInactive,
+  );
+  let cooled_down = STAKE - status.effective;
+  env
+    .withdraw(stake_account, &withdrawer, recipient, cooled_down, None)
+    .await
+    .unwrap();
+  assert_eq!(env.lamports(recipient).await, cooled_down);
+
+  // Once inactive, withdrawing the whole balance closes the account
+  env.warp_to_epoch(DEACTIVATION_EPOCH + 2).await;
+  assert_eq!(
+    env.activation_status(stake_account).await,
+    StakeActivationStatus {
+      effective: 0,
+      activating: 0,
+      deactivating: 0,
+    }
+  );
+  let stake_lamports = env.lamports(stake_account).await;
+  assert_eq!(stake_lamports, status.effective + rent_exempt_reserve);
+  env
+    .withdraw(stake_account, &withdrawer, recipient, stake_lamports, None)
+    .await
+    .unwrap();
+  assert_eq!(env.lamports(recipient).await, STAKE + rent_exempt_reserve);
+  assert!(env
+    .context
+    .banks_client
+    .get_account(stake_account)
+    .await
+    .unwrap()
+    .is_none());
+}
+
+#[tokio::test]
+async fn split_divides_active_delegation() {
+  let mut env = TestEnv::start().await;
+  let (staker, withdrawer) = (Keypair::new(), Keypair::new());
+  let source_account = env
+    .create_stake_account(authorized(&staker, &withdrawer), Lockup::default(), STAKE)
+    .await;
+  env.delegate(source_account, &staker).await.unwrap();
+  env.warp_to_epoch(ACTIVATION_EPOCH + 2).await;
+  // The staker pays for the new account
+  env.fund(staker.pubkey(), LAMPORTS_PER_SOL).await;
+
+  let split_account = Keypair::new();
+  let split = |lamports| {
+    (
+      accounts::Split {
+        source_account,
+        split_account: split_account.pubkey(),
+        staker: staker.pubkey(),
+        rent: sysvar::rent::ID,
+        system_program: system_program::ID,
+        config: config_address(),
+      },
+      instruction::Split { lamports },
+    )
+  };
+
+  // Both halves have to keep the minimum delegation
+  let (accounts, data) = split(STAKE - DEFAULT_MINIMUM_DELEGATION + 1);
+  assert_stake_error(
+    env
+      .process(accounts, data, &[&staker, &split_account])
+      .await,
+    StakeError::InsufficientStake,
+  );
+
+  let (accounts, data) = split(4 * LAMPORTS_PER_SOL);
+  env
+    .process(accounts, data, &[&staker, &split_account])
+    .await
+    .unwrap();
+
+  let source = env.stake_account(source_account).await;
+  let split = env.stake_account(split_account.pubkey()).await;
+  assert_eq!(source.stake.unwrap().delegation.stake, 6 * LAMPORTS_PER_SOL);
+  assert_eq!(split.meta, source.meta);
+  let split_delegation = split.stake.unwrap().delegation;
+  assert_eq!(split_delegation.stake, 4 * LAMPORTS_PER_SOL);
+  assert_eq!(split_delegation.activation_epoch, ACTIVATION_EPOCH);
+  assert_eq!(
+    env.activation_status(split_account.pubkey()).await,
+    StakeActivationStatus {
+      effective: 4 * LAMPORTS_PER_SOL,
+      activating: 0,
+      deactivating: 0,
+    }
+  );
+  assert_eq!(
+    env.lamports(split_account.pubkey()).await,
+    source.meta.rent_exempt_reserve + 4 * LAMPORTS_PER_SOL
+  );
+}
+
+#[tokio::test]
+async fn merge_waits_for_transient_stake() {
+  let mut env = TestEnv::start().await;
+  let (staker, withdrawer) = (Keypair::new(), Keypair::new());
+  let dest_account = env
+    .create_stake_account(authorized(&staker, &withdrawer), Lockup::default(), STAKE)
+    .await;
+  env.delegate(dest_account, &staker).await.unwrap();
+  // An account can't be merged into itself
+  assert_stake_error(
+    env.merge(dest_account, dest_account, &staker).await,
+    StakeError::MergeMismatch,
+  );
+
+  env.warp_to_epoch(ACTIVATION_EPOCH + 1).await;
+  let source_account = env
+    .create_stake_account(authorized(&staker, &withdrawer), Lockup::default(), STAKE)
+    .await;
+  env.delegate(source_account, &staker).await.unwrap();
+
+  // The destination is halfway through its warmup
+  assert_stake_error(
+    env.merge(dest_account, source_account, &staker).await,
+    StakeError::MergeTransientStake,
+  );
+
+  env.warp_to_epoch(ACTIVATION_EPOCH + 2).await;
+  let dest_lamports = env.lamports(dest_account).await;
+  let source_lamports = env.lamports(source_account).await;
+  env
+    .merge(dest_account, source_account, &staker)
+    .await
+    .unwrap();
+
+  assert_eq!(
+    env
+      .stake_account(dest_account)
+      .await
+      .stake
+      .unwrap()
+      .delegation
+      .stake,
+    2 * STAKE
+  );
+  assert_eq!(
+    env.lamports(dest_account).await,
+    dest_lamports + source_lamports
+  );
+  assert!(env
+    .context
+    .banks_client
+    .get_account(source_account)
+    .await
+    .unwrap()
+    .is_none());
+}
+
+#[tokio::test]
+async fn lockup_holds_withdrawals_until_its_epoch() {
+  let mut env = TestEnv::start().await;
+  let (staker, withdrawer, custodian) = (Keypair::new(), Keypair::new(), Keypair::new());
+  let stake_account = env
+    .create_stake_account(
+      authorized(&staker, &withdrawer),
+      Lockup {
+        custodian: custodian.pubkey(),
+        ..Lockup::default()
+      },
+      STAKE,
+    )
+    .await;
+
+  let set_lockup = |custodian: &Keypair, epoch| {
+    (
+      accounts::SetLockup {
+        stake_account,
+        custodian: custodian.pubkey(),
+      },
+      instruction::SetLockup {
+        lockup: LockupArgs {
+          unix_timestamp: None,
+          epoch: Some(epoch),
+          custodian: None,
+        },
+      },
+    )
+  };
+
+  let (accounts, data) = set_lockup(&withdrawer, ACTIVATION_EPOCH + 2);
+  assert_stake_error(
+    env.process(accounts, data, &[&withdrawer]).await,
+    StakeError::UnauthorizedCustodian,
+  );
+  let (accounts, data) = set_lockup(&custodian, ACTIVATION_EPOCH + 2);
+  env.process(accounts, data, &[&custodian]).await.unwrap();
+  assert_eq!(
+    env.stake_account(stake_account).await.meta.lockup,
+    Lockup {
+      unix_timestamp: 0,
+      epoch: ACTIVATION_EPOCH + 2,
+      custodian: custodian.pubkey(),
+    }
+  );
+
+  let recipient = Pubkey::new_unique();
+  assert_stake_error(
+    env
+      .withdraw(
+        stake_account,
+        &withdrawer,
+        recipient,
+        LAMPORTS_PER_SOL,
+        None,
+      )
+      .await,
+    StakeError::CustodianMissingSignature,
+  );
+  // The custodian can let a withdrawal through early
+  env
+    .withdraw(
+      stake_account,
+      &withdrawer,
+      recipient,
+      LAMPORTS_PER_SOL,
+      Some(&custodian),
+    )
+    .await
+    .unwrap();
+
+  env.warp_to_epoch(ACTIVATION_EPOCH + 2).await;
+  env
+    .withdraw(
+      stake_account,
+      &withdrawer,
+      recipient,
+      LAMPORTS_PER_SOL,
+      None,
+    )
+    .await
+    .unwrap();
+  assert_eq!(env.lamports(recipient).await, 2 * LAMPORTS_PER_SOL);
+}
+
+#[tokio::test]
+async fn config_is_created_by_the_upgrade_authority() {
+  let mut env = TestEnv::deploy().await;
+  let impostor = Keypair::new();
+  env.fund(impostor.pubkey(), LAMPORTS_PER_SOL).await;
+
+  assert_stake_error(
+    env.initialize_config(&impostor).await,
+    StakeError::NotUpgradeAuthority,
+  );
+
+  let admin = env.upgrade_authority.insecure_clone();
+  env.initialize_config(&admin).await.unwrap();
+  let config = env
+    .context
+    .banks_client
+    .get_account(config_address())
+    .await
+    .unwrap()
+    .unwrap();
+  assert_eq!(
+    StakeConfig::try_deserialize(&mut config.data.as_slice())
+      .unwrap()
+      .admin,
+    admin.pubkey()
+  );
+}
+
+#[tokio::test]
+async fn slash_penalty_is_a_percentage() {
+  let mut env = TestEnv::start().await;
+  let admin = env.upgrade_authority.insecure_clone();
+  let update = |slash_penalty| {
+    (
+      accounts::UpdateConfig {
+        config: config_address(),
+        admin: admin.pubkey(),
+      },
+      instruction::UpdateConfig {
+        args: UpdateConfigArgs {
+          admin: None,
+          minimum_delegation: None,
+          warmup_cooldown_rate: None,
+          slash_penalty: Some(slash_penalty),
+        },
+      },
+    )
+  };
+
+  let (accounts, data) = update(101);
+  assert_stake_error(
+    env.process(accounts, data, &[&admin]).await,
+    StakeError::InvalidConfig,
+  );
+
+  let (accounts, data) = update(100);
+  env.process(accounts, data, &[&admin]).await.unwrap();
+  let config = env
+    .context
+    .banks_client
+    .get_account(config_address())
+    .await
+    .unwrap()
+    .unwrap();
+  let config = StakeConfig::try_deserialize(&mut config.data.as_slice()).unwrap();
+  assert_eq!(config.slash_penalty, 100);
+  assert_eq!(config.minimum_delegation, DEFAULT_MINIMUM_DELEGATION);
+}
+
+#[tokio::test]
+async fn reward_reserve_is_created_by_the_upgrade_authority() {
+  let mut env = TestEnv::start().await;
+  let impostor = Keypair::new();
+  env.fund(impostor.pubkey(), LAMPORTS_PER_SOL).await;
+
+  let initialize = |authority: &Keypair| {
+    (
+      accounts::InitializeRewardReserve {
+        reward_reserve: reward_reserve_address(),
+        authority: authority.pubkey(),
+        program_data: program_data_address(),
+        system_program: system_program::ID,
+      },
+      instruction::InitializeRewardReserve {
+        point_value: PointValue {
+          rewards: 1,
+          points: 1,
+        },
+      },
+    )
+  };
+
+  let (accounts, data) = initialize(&impostor);
+  assert_stake_error(
+    env.process(accounts, data, &[&impostor]).await,
+    StakeError::NotUpgradeAuthority,
+  );
+
+  let admin = env.upgrade_authority.insecure_clone();
+  let (accounts, data) = initialize(&admin);
+  env.process(accounts, data, &[&admin]).await.unwrap();
+  let reward_reserve = env
+    .context
+    .banks_client
+    .get_account(reward_reserve_address())
+    .await
+    .unwrap()
+    .unwrap();
+  assert_eq!(
+    RewardReserve::try_deserialize(&mut reward_reserve.data.as_slice())
+      .unwrap()
+      .authority,
+    admin.pubkey()
+  );
+}